@@ -0,0 +1,242 @@
+//! `pi` — drive a running pi-builder desktop app from scripts.
+//!
+//! ```text
+//! pi spawn --agent aider --repo .
+//! pi worktree list
+//! pi task enqueue -f task.md
+//! pi rpc --lsp
+//! ```
+//!
+//! Every subcommand is one request over the app's local socket (see
+//! `pi_builder_core::ipc`). `--json` prints the app's answer as-is. `pi rpc`
+//! instead relays JSON-RPC 2.0 between stdio and the app, for editors and
+//! other programs (see `pi_builder_core::rpc`).
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use pi_builder_core::ipc;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(name = "pi", version, about = "Script a running pi-builder desktop app")]
+struct Cli {
+    /// Print raw JSON instead of a summary.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start an agent session.
+    Spawn {
+        /// Agent profile id, e.g. `claude` or `aider`; defaults to the
+        /// repo's `default_agent`.
+        #[arg(long)]
+        agent: Option<String>,
+        /// Make this repo current first.
+        #[arg(long)]
+        repo: Option<PathBuf>,
+        /// Run in this worktree, creating it if needed.
+        #[arg(long)]
+        worktree: Option<String>,
+        #[arg(long)]
+        title: Option<String>,
+        /// Wait for a free slot when the app is at its session limit.
+        #[arg(long)]
+        queue: bool,
+        /// Keep the session running when the app exits (needs tmux).
+        #[arg(long)]
+        detached: bool,
+        /// Command to run instead of the profile's.
+        #[arg(last = true)]
+        cmd: Vec<String>,
+    },
+    /// Worktrees of the current repo.
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommand,
+    },
+    /// The task queue.
+    Task {
+        #[command(subcommand)]
+        command: TaskCommand,
+    },
+    /// Open sessions.
+    Sessions,
+    /// Relay JSON-RPC 2.0 between stdin/stdout and the app, one message
+    /// per line.
+    Rpc {
+        /// Frame messages with `Content-Length` headers, as in LSP.
+        #[arg(long)]
+        lsp: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommand {
+    List,
+}
+
+#[derive(Subcommand)]
+enum TaskCommand {
+    /// Queue a task whose prompt is the file's contents. The agent comes
+    /// from `--agent`, an `agent:` line in `---` front matter, or the
+    /// repo's `default_agent`.
+    Enqueue {
+        #[arg(short = 'f', long = "file")]
+        file: PathBuf,
+        #[arg(long)]
+        agent: Option<String>,
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    List,
+    Cancel {
+        task_id: String,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("pi: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let (result, summary): (Value, fn(&Value) -> String) = match cli.command {
+        Command::Spawn { agent, repo, worktree, title, queue, detached, cmd } => {
+            if let Some(repo) = &repo {
+                ipc::call("set_repo_path", json!({ "path": absolute(repo)? }))?;
+            }
+            let cmd = match &agent {
+                Some(agent) if cmd.is_empty() => profile_command(agent)?,
+                _ => cmd,
+            };
+            let args = json!({
+                "agent_id": agent.unwrap_or_default(),
+                "cmd": cmd,
+                "worktree": worktree,
+                "title": title,
+                "queue": queue,
+                "detached": detached,
+            });
+            (ipc::call("pty_spawn", json!({ "args": args }))?, spawned)
+        }
+        Command::Worktree { command: WorktreeCommand::List } => {
+            (ipc::call("worktree_list", Value::Null)?, worktrees)
+        }
+        Command::Task { command } => match command {
+            TaskCommand::Enqueue { file, agent, repo } => {
+                let text = std::fs::read_to_string(&file)
+                    .with_context(|| format!("read {}", file.display()))?;
+                let (front_agent, prompt) = split_front_matter(&text);
+                let agent = agent.or(front_agent).unwrap_or_default();
+                let repo = repo.as_deref().map(absolute).transpose()?;
+                let spec = json!({ "agent": agent, "prompt": prompt.trim(), "repo": repo });
+                (ipc::call("task_enqueue", json!({ "spec": spec }))?, task_line)
+            }
+            TaskCommand::List => (ipc::call("task_list", Value::Null)?, tasks),
+            TaskCommand::Cancel { task_id } => {
+                (ipc::call("task_cancel", json!({ "task_id": task_id }))?, |_| String::new())
+            }
+        },
+        Command::Sessions => (ipc::call("pty_list", Value::Null)?, sessions),
+        Command::Rpc { lsp } => return ipc::bridge_stdio(lsp),
+    };
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else {
+        let text = summary(&result);
+        if !text.is_empty() {
+            println!("{text}");
+        }
+    }
+    Ok(())
+}
+
+/// argv of an agent profile, as the app knows it.
+fn profile_command(agent: &str) -> Result<Vec<String>> {
+    let profiles = ipc::call("agent_profiles", Value::Null)?;
+    let profile = profiles
+        .as_array()
+        .and_then(|list| list.iter().find(|p| p["id"] == agent))
+        .with_context(|| format!("unknown agent profile: {agent}"))?;
+    Ok(serde_json::from_value(profile["command"].clone()).unwrap_or_default())
+}
+
+/// `agent:` from leading `---` front matter, and the rest of the text.
+fn split_front_matter(text: &str) -> (Option<String>, &str) {
+    let Some(rest) = text.strip_prefix("---\n") else { return (None, text) };
+    let Some((front, body)) = rest.split_once("\n---\n") else { return (None, text) };
+    let agent = front
+        .lines()
+        .find_map(|l| l.strip_prefix("agent:"))
+        .map(|a| a.trim().to_string());
+    (agent, body)
+}
+
+fn absolute(path: &Path) -> Result<String> {
+    let path = path.canonicalize().with_context(|| format!("resolve {}", path.display()))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+// ---------------------------------------------------------------------------
+// Summaries
+// ---------------------------------------------------------------------------
+
+fn spawned(r: &Value) -> String {
+    if let Some(queue_id) = r["queue_id"].as_str() {
+        return format!("queued as {queue_id}");
+    }
+    match r["worktree_path"].as_str() {
+        Some(path) => format!("{} in {path}", str_of(&r["session_id"])),
+        None => str_of(&r["session_id"]),
+    }
+}
+
+fn worktrees(list: &Value) -> String {
+    lines(list, |w| {
+        let dirty = if w["dirty"].as_bool() == Some(true) { " *" } else { "" };
+        format!(
+            "{}\t{}\t+{} -{}{dirty}\t{}",
+            str_of(&w["name"]),
+            str_of(&w["branch"]),
+            w["ahead"],
+            w["behind"],
+            str_of(&w["path"]),
+        )
+    })
+}
+
+fn tasks(list: &Value) -> String {
+    lines(list, task_line)
+}
+
+fn task_line(t: &Value) -> String {
+    format!("{}\t{}\t{}", str_of(&t["id"]), str_of(&t["status"]), str_of(&t["agent"]))
+}
+
+fn sessions(list: &Value) -> String {
+    lines(list, |s| {
+        format!(
+            "{}\t{}\t{}\t{}",
+            str_of(&s["sessionId"]),
+            str_of(&s["agentId"]),
+            str_of(&s["state"]),
+            s["title"].as_str().unwrap_or(""),
+        )
+    })
+}
+
+fn lines(list: &Value, line: impl Fn(&Value) -> String) -> String {
+    list.as_array().map(|l| l.iter().map(line).collect::<Vec<_>>().join("\n")).unwrap_or_default()
+}
+
+fn str_of(v: &Value) -> String {
+    v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string())
+}
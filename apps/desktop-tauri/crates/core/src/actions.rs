@@ -0,0 +1,221 @@
+//! Actions: named chains of backend steps for frontend shortcuts.
+//!
+//! An action is a list of steps run against one session: snapshot its
+//! worktree, type into it, interrupt it, wait. Actions are saved in
+//! `actions.json` in the config dir; the key bound to one is the
+//! frontend's business and is only stored here as `shortcut`. A shortcut
+//! makes a single `action_run` call and the backend runs the steps in
+//! order, so a lagging webview can't reorder or drop half of them.
+//!
+//! While a chain runs it holds its session, so pressing the shortcut again
+//! fails rather than interleaving a second chain with the first, and the
+//! caller holds the session's worktree lock (see `locks.rs`), so nothing
+//! rolls the worktree back between a snapshot and the steps after it. The
+//! first failing step ends the chain; the result says how far it got.
+
+use crate::error::{ErrorCode, PiError};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Longest `wait` step.
+pub const MAX_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActionStep {
+    /// Snapshot the session's worktree, labelled with `label` or the
+    /// action's name.
+    Snapshot {
+        #[serde(default)]
+        label: Option<String>,
+    },
+    /// Type `text` into the session through its guard, then Enter unless
+    /// `enter` is false.
+    Send {
+        text: String,
+        #[serde(default = "default_enter")]
+        enter: bool,
+    },
+    /// Ctrl-C.
+    Interrupt,
+    /// Pause before the next step, at most `MAX_WAIT`.
+    Wait { ms: u64 },
+}
+
+fn default_enter() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Action {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Key the frontend binds it to, as the frontend writes it.
+    #[serde(default)]
+    pub shortcut: Option<String>,
+    pub steps: Vec<ActionStep>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepResult {
+    pub index: usize,
+    pub ok: bool,
+    pub error: Option<PiError>,
+    /// Snapshot a `snapshot` step took.
+    pub snapshot_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionRun {
+    pub name: String,
+    pub session_id: String,
+    /// Every step ran and succeeded.
+    pub completed: bool,
+    /// Steps that ran, the last one failed unless `completed`.
+    pub steps: Vec<StepResult>,
+}
+
+/// Saved actions, by name.
+pub struct ActionStore {
+    path: PathBuf,
+    actions: Mutex<Vec<Action>>,
+}
+
+impl ActionStore {
+    pub fn load(path: PathBuf) -> Self {
+        let actions = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, actions: Mutex::new(actions) }
+    }
+
+    pub fn list(&self) -> Vec<Action> {
+        let mut actions = self.actions.lock().unwrap().clone();
+        actions.sort_by(|a, b| a.name.cmp(&b.name));
+        actions
+    }
+
+    pub fn get(&self, name: &str) -> Result<Action> {
+        let actions = self.actions.lock().unwrap();
+        let found = actions.iter().find(|a| a.name == name).cloned();
+        let not_found = || PiError::new(ErrorCode::NotFound, format!("no action named {name}"));
+        Ok(found.ok_or_else(not_found)?)
+    }
+
+    /// Save `action`, replacing one of the same name. Fails if another
+    /// action has its shortcut.
+    pub fn save(&self, mut action: Action) -> Result<Action> {
+        action.name = action.name.trim().to_string();
+        action.shortcut = action.shortcut.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        validate(&action)?;
+        {
+            let mut actions = self.actions.lock().unwrap();
+            if let Some(shortcut) = &action.shortcut {
+                let taken = actions
+                    .iter()
+                    .find(|a| a.name != action.name && a.shortcut.as_ref() == Some(shortcut));
+                if let Some(other) = taken {
+                    let message = format!("{shortcut} is already bound to {}", other.name);
+                    return Err(PiError::invalid_input(message).into());
+                }
+            }
+            actions.retain(|a| a.name != action.name);
+            actions.push(action.clone());
+        }
+        self.save_file()?;
+        Ok(action)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.actions.lock().unwrap().retain(|a| a.name != name);
+        self.save_file()
+    }
+
+    fn save_file(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.actions.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write actions")?;
+        Ok(())
+    }
+}
+
+pub fn actions_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("actions.json")
+}
+
+fn validate(action: &Action) -> Result<()> {
+    if action.name.is_empty() {
+        return Err(PiError::invalid_input("action name is empty").into());
+    }
+    if action.steps.is_empty() {
+        return Err(PiError::invalid_input("action has no steps").into());
+    }
+    for step in &action.steps {
+        match step {
+            ActionStep::Send { text, enter: false } if text.is_empty() => {
+                return Err(PiError::invalid_input("send step has no text").into());
+            }
+            ActionStep::Wait { ms } if Duration::from_millis(*ms) > MAX_WAIT => {
+                let message = format!("wait is over {}s", MAX_WAIT.as_secs());
+                return Err(PiError::invalid_input(message).into());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Sessions an action is running in.
+#[derive(Default)]
+pub struct Runs {
+    sessions: Mutex<HashSet<String>>,
+}
+
+impl Runs {
+    /// Run `action`'s steps in `session_id` in order with `step`, which
+    /// returns the id of a snapshot it took. Fails if an action is already
+    /// running there.
+    pub fn run(
+        &self,
+        action: &Action,
+        session_id: &str,
+        mut step: impl FnMut(&ActionStep) -> Result<Option<String>>,
+    ) -> Result<ActionRun> {
+        if !self.sessions.lock().unwrap().insert(session_id.to_string()) {
+            let message = "an action is already running in this session";
+            return Err(PiError::invalid_input(message).into());
+        }
+        let mut steps = Vec::new();
+        for (index, s) in action.steps.iter().enumerate() {
+            let result = match step(s) {
+                Ok(snapshot_id) => StepResult { index, ok: true, error: None, snapshot_id },
+                Err(e) => StepResult { index, ok: false, error: Some(e.into()), snapshot_id: None },
+            };
+            let ok = result.ok;
+            steps.push(result);
+            if !ok {
+                break;
+            }
+        }
+        self.sessions.lock().unwrap().remove(session_id);
+        Ok(ActionRun {
+            name: action.name.clone(),
+            session_id: session_id.to_string(),
+            completed: steps.len() == action.steps.len() && steps.iter().all(|s| s.ok),
+            steps,
+        })
+    }
+}
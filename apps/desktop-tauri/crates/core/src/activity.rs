@@ -0,0 +1,142 @@
+//! Whether a session is working, waiting on the user, or quiet.
+//!
+//! The reader feeds output into an `Activity`; a watcher thread ticks it.
+//! A session is `running` while it prints. Once it has been quiet for a
+//! moment with the cursor left on an unterminated line that looks like a
+//! prompt (a shell PS1, a `[y/N]` confirmation, a trailing question), it is
+//! `awaiting_input`. After the idle period with no prompt it is `idle`.
+//!
+//! Detection is heuristic: the last line is rebuilt from the raw stream (see
+//! `scrollback::LineTracker`), which is enough to follow readline redraws
+//! and agent spinners.
+
+use crate::scrollback::LineTracker;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// Quiet time before an unterminated prompt-like line counts as a prompt.
+/// Agents often pause briefly mid-line while streaming.
+const PROMPT_SETTLE: Duration = Duration::from_millis(750);
+
+/// Quiet time before the state becomes `idle` when no idle period is
+/// configured for notifications.
+const DEFAULT_IDLE: Duration = Duration::from_secs(60);
+
+/// Longest tail of the current line kept for matching.
+const MAX_LINE: usize = 512;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    #[default]
+    Running,
+    AwaitingInput,
+    Idle,
+}
+
+/// Result of a watcher tick.
+#[derive(Debug, Default)]
+pub struct Tick {
+    /// New state, if it changed.
+    pub state: Option<SessionState>,
+    /// The configured idle period just elapsed; emit `pty://idle`.
+    pub went_idle: bool,
+}
+
+pub struct Activity {
+    pub state: SessionState,
+    /// `None` until the first output.
+    last_output: Option<Instant>,
+    idle_notified: bool,
+    line: LineTracker,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            state: SessionState::default(),
+            last_output: None,
+            idle_notified: false,
+            line: LineTracker::new(MAX_LINE),
+        }
+    }
+}
+
+impl Activity {
+    /// Record output. Returns the new state if it changed.
+    pub fn output(&mut self, bytes: &[u8]) -> Option<SessionState> {
+        self.line.feed(bytes, |_| {});
+        self.last_output = Some(Instant::now());
+        self.idle_notified = false;
+        self.set(SessionState::Running)
+    }
+
+    /// Advance the state machine; `idle_after` is the notification idle
+    /// period, if any.
+    pub fn tick(&mut self, idle_after: Option<Duration>) -> Tick {
+        let Some(last) = self.last_output else { return Tick::default() };
+        let quiet = last.elapsed();
+        let mut tick = Tick::default();
+        if let Some(after) = idle_after {
+            if !self.idle_notified && quiet >= after {
+                self.idle_notified = true;
+                tick.went_idle = true;
+            }
+        }
+        let prompt = quiet >= PROMPT_SETTLE && looks_like_prompt(&self.line.text());
+        let next = match self.state {
+            SessionState::Running if prompt => SessionState::AwaitingInput,
+            SessionState::Running if quiet >= idle_after.unwrap_or(DEFAULT_IDLE) => {
+                SessionState::Idle
+            }
+            state => state,
+        };
+        tick.state = self.set(next);
+        tick
+    }
+
+    /// Time since the last output; `None` before the first.
+    pub fn quiet_for(&self) -> Option<Duration> {
+        self.last_output.map(|t| t.elapsed())
+    }
+
+    /// The unterminated line the cursor is on, escape sequences removed.
+    pub fn current_line(&self) -> String {
+        self.line.text()
+    }
+
+    fn set(&mut self, state: SessionState) -> Option<SessionState> {
+        (self.state != state).then(|| {
+            self.state = state;
+            state
+        })
+    }
+}
+
+fn looks_like_prompt(line: &str) -> bool {
+    !line.is_empty() && prompt_patterns().iter().any(|re| re.is_match(line))
+}
+
+/// Endings of lines that wait for the user.
+fn prompt_patterns() -> &'static [Regex] {
+    static PATTERNS: OnceLock<Vec<Regex>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            // Shell prompts: `user@host:~/src$`, `#`, `%`, `>`, starship/p10k
+            r"[$#%>❯➜»]$",
+            // Confirmations: `[y/N]`, `(yes/no)`, `[Y/n/a]`
+            r"(?i)[\[(]\s*y(es)?\s*/\s*n(o)?\b[^\])]*[\])]\s*[:?]?$",
+            // Questions and `Password:`-style fields
+            r"\?$",
+            r"(?i)(password|passphrase|username|token)[^:]*:$",
+            r"(?i)press (enter|return|any key)",
+        ]
+        .iter()
+        .map(|p| Regex::new(p).expect("prompt pattern"))
+        .collect()
+    })
+}
@@ -0,0 +1,97 @@
+//! Agent profiles — how to launch each CLI coding agent.
+//!
+//! Built-in profiles cover the common CLIs; profiles in settings with the
+//! same id replace them.
+
+use crate::{ssh::SshTarget, usage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How a task prompt reaches the agent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptMode {
+    /// Appended as the final argv element.
+    #[default]
+    Arg,
+    /// Typed into the PTY after spawn, followed by Enter.
+    Stdin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentProfile {
+    pub id: String,
+    /// argv; empty means the default shell.
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub prompt_mode: PromptMode,
+    /// Run this agent on a remote host instead of locally.
+    #[serde(default)]
+    pub ssh: Option<SshTarget>,
+    /// Regexes for token/cost lines in the agent's output (see `usage.rs`).
+    /// Empty uses the built-in ones for this id.
+    #[serde(default)]
+    pub usage_patterns: Vec<String>,
+}
+
+impl AgentProfile {
+    fn builtin(id: &str, command: &[&str], prompt_mode: PromptMode) -> Self {
+        Self {
+            id: id.into(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+            env: HashMap::new(),
+            prompt_mode,
+            ssh: None,
+            usage_patterns: Vec::new(),
+        }
+    }
+
+    /// Usage patterns, falling back to the built-ins for this id.
+    pub fn usage_patterns(&self) -> Vec<String> {
+        if self.usage_patterns.is_empty() {
+            usage::builtin_patterns(&self.id)
+        } else {
+            self.usage_patterns.clone()
+        }
+    }
+
+    /// argv for launching this agent with an optional prompt.
+    pub fn argv(&self, prompt: Option<&str>) -> Vec<String> {
+        let mut argv = self.command.clone();
+        if let (PromptMode::Arg, Some(p)) = (self.prompt_mode, prompt) {
+            argv.push(p.to_string());
+        }
+        argv
+    }
+
+    pub fn env_pairs(&self) -> Vec<(String, String)> {
+        self.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+pub fn builtin_profiles() -> Vec<AgentProfile> {
+    vec![
+        AgentProfile::builtin("claude", &["claude"], PromptMode::Arg),
+        AgentProfile::builtin("codex", &["codex"], PromptMode::Arg),
+        AgentProfile::builtin("aider", &["aider", "--message"], PromptMode::Arg),
+        AgentProfile::builtin("gemini", &["gemini", "-i"], PromptMode::Arg),
+        AgentProfile::builtin("pi", &["pi"], PromptMode::Arg),
+        AgentProfile::builtin("shell", &[], PromptMode::Stdin),
+    ]
+}
+
+/// All profiles, custom ones replacing built-ins with the same id.
+pub fn all_profiles(custom: &[AgentProfile]) -> Vec<AgentProfile> {
+    let mut profiles: Vec<AgentProfile> = builtin_profiles()
+        .into_iter()
+        .filter(|b| !custom.iter().any(|c| c.id == b.id))
+        .collect();
+    profiles.extend(custom.iter().cloned());
+    profiles
+}
+
+pub fn resolve(id: &str, custom: &[AgentProfile]) -> Option<AgentProfile> {
+    all_profiles(custom).into_iter().find(|p| p.id == id)
+}
@@ -0,0 +1,347 @@
+//! Annotations: things worth clicking in agent output.
+//!
+//! Each complete output line, ANSI stripped and redacted, goes through a
+//! list of annotators. The built-in ones find `file:line[:col]` references,
+//! compiler errors and warnings (rustc, gcc/clang, tsc), failing tests and
+//! their summaries (cargo, pytest, jest/vitest, go) and URLs. Spans may
+//! overlap: a compiler error's location is also a file reference.
+//!
+//! Sessions emit what the annotators find as `pty://annotation/<id>`.
+//! `lineStart`/`lineEnd` are byte offsets of the line in the session's
+//! output stream, counting from its first byte, raw escapes included;
+//! `start`/`end` are byte offsets of the span in the stripped line text.
+//! The event can arrive before the data event carrying the line.
+
+use crate::scrollback::LineTracker;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    ops::Range,
+    sync::{Arc, OnceLock},
+};
+
+/// Longest line scanned; longer lines are cut.
+const MAX_LINE: usize = 4096;
+
+/// Path of a source file: optional `./`, `../` or `/`, segments, and an
+/// extension, which keeps host names and ratios out.
+const PATH: &str = r"(?:\.{1,2}/|/)?(?:[\w@.+\-]+/)*[\w@+\-][\w@.+\-]*\.[A-Za-z]\w{0,9}";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Meaning {
+    FileRef {
+        path: String,
+        line: u32,
+        column: Option<u32>,
+    },
+    CompilerError {
+        /// `error` or `warning`.
+        severity: String,
+        message: String,
+        path: Option<String>,
+        line: Option<u32>,
+        column: Option<u32>,
+    },
+    /// A failing test, or a summary line reporting failures.
+    TestFailure {
+        test: Option<String>,
+        summary: Option<String>,
+    },
+    Url {
+        url: String,
+    },
+}
+
+/// One span an annotator recognised in a line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+    /// Byte range in the line.
+    pub range: Range<usize>,
+    pub meaning: Meaning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    #[serde(flatten)]
+    pub meaning: Meaning,
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub line_start: u64,
+    pub line_end: u64,
+}
+
+/// Recognises something in output lines. Annotators see every line of a
+/// session in order, so they may keep state across lines.
+pub trait Annotator: Send {
+    fn scan(&mut self, line: &str, found: &mut Vec<Found>);
+}
+
+/// Makes a fresh set of annotators for each session.
+pub type AnnotatorSet = Arc<dyn Fn() -> Vec<Box<dyn Annotator>> + Send + Sync>;
+
+/// The built-in annotators.
+pub fn builtin() -> Vec<Box<dyn Annotator>> {
+    vec![
+        Box::new(FileRefs),
+        Box::new(CompilerErrors::default()),
+        Box::new(TestFailures),
+        Box::new(Urls),
+    ]
+}
+
+pub fn builtin_set() -> AnnotatorSet {
+    Arc::new(builtin)
+}
+
+/// Splits a session's output into lines and runs the annotators on them.
+pub struct OutputAnnotator {
+    annotators: Vec<Box<dyn Annotator>>,
+    lines: LineTracker,
+    /// Stream offset of the next byte fed.
+    pos: u64,
+    /// Stream offset where the current line began.
+    line_start: u64,
+}
+
+impl OutputAnnotator {
+    pub fn new(annotators: Vec<Box<dyn Annotator>>) -> Self {
+        Self { annotators, lines: LineTracker::new(MAX_LINE), pos: 0, line_start: 0 }
+    }
+
+    /// Feed output bytes; returns annotations of the lines they complete.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Annotation> {
+        let mut out = Vec::new();
+        // Split after each newline so every finished line knows where it ends
+        for chunk in bytes.split_inclusive(|&b| b == b'\n') {
+            self.pos += chunk.len() as u64;
+            let mut done = Vec::new();
+            self.lines.feed(chunk, |line| done.push(line));
+            if !chunk.ends_with(b"\n") {
+                continue;
+            }
+            for line in done {
+                self.scan(&line, &mut out);
+            }
+            self.line_start = self.pos;
+        }
+        out
+    }
+
+    fn scan(&mut self, line: &str, out: &mut Vec<Annotation>) {
+        if line.trim().is_empty() {
+            // Still let stateful annotators see the break
+            for a in &mut self.annotators {
+                a.scan(line, &mut Vec::new());
+            }
+            return;
+        }
+        let mut found = Vec::new();
+        for a in &mut self.annotators {
+            a.scan(line, &mut found);
+        }
+        out.extend(found.into_iter().map(|f| Annotation {
+            text: line[f.range.clone()].to_string(),
+            start: f.range.start,
+            end: f.range.end,
+            meaning: f.meaning,
+            line_start: self.line_start,
+            line_end: self.pos,
+        }));
+    }
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: impl FnOnce() -> String) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(&pattern()).expect("valid annotator regex"))
+}
+
+fn number(m: Option<regex::Match>) -> Option<u32> {
+    m.and_then(|m| m.as_str().parse().ok())
+}
+
+/// `path:line[:col]` and tsc's `path(line,col)`.
+pub struct FileRefs;
+
+impl Annotator for FileRefs {
+    fn scan(&mut self, line: &str, found: &mut Vec<Found>) {
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = regex(&RE, || {
+            format!(r#"(?:^|[\s(\[<'"`=])({PATH})(?::(\d+)(?::(\d+))?|\((\d+),(\d+)\))"#)
+        });
+        for caps in re.captures_iter(line) {
+            let path = caps.get(1).expect("group 1 always matches");
+            let whole = caps.get(0).expect("group 0 always matches");
+            let (line_no, column) = match number(caps.get(2)) {
+                Some(n) => (n, number(caps.get(3))),
+                None => match number(caps.get(4)) {
+                    Some(n) => (n, number(caps.get(5))),
+                    None => continue,
+                },
+            };
+            found.push(Found {
+                range: path.start()..whole.end(),
+                meaning: Meaning::FileRef {
+                    path: path.as_str().to_string(),
+                    line: line_no,
+                    column,
+                },
+            });
+        }
+    }
+}
+
+/// Compiler diagnostics. rustc puts the location on a `-->` line after the
+/// message, so the message is remembered until then.
+#[derive(Default)]
+pub struct CompilerErrors {
+    /// Severity and message of a rustc diagnostic awaiting its location.
+    pending: Option<(String, String)>,
+}
+
+impl Annotator for CompilerErrors {
+    fn scan(&mut self, line: &str, found: &mut Vec<Found>) {
+        static RUSTC: OnceLock<Regex> = OnceLock::new();
+        static ARROW: OnceLock<Regex> = OnceLock::new();
+        static GCC: OnceLock<Regex> = OnceLock::new();
+        static TSC: OnceLock<Regex> = OnceLock::new();
+        let rustc = regex(&RUSTC, || r"^(error|warning)(?:\[\w+\])?: (.+)$".into());
+        let arrow = regex(&ARROW, || format!(r"^\s*--> ({PATH}):(\d+):(\d+)"));
+        let gcc = regex(&GCC, || {
+            format!(r"^({PATH}):(\d+):(?:(\d+):)? (?:fatal )?(error|warning): (.+)$")
+        });
+        let tsc = regex(&TSC, || format!(r"^({PATH})\((\d+),(\d+)\): (error|warning) (.+)$"));
+
+        if line.trim().is_empty() {
+            self.pending = None;
+            return;
+        }
+        if let Some(caps) = rustc.captures(line) {
+            // Counts like "warning: 3 warnings emitted" have no location
+            let message = caps[2].to_string();
+            if message.ends_with(" emitted") || message.starts_with("aborting due to") {
+                self.pending = None;
+            } else {
+                self.pending = Some((caps[1].to_string(), message));
+            }
+            return;
+        }
+        if let Some(caps) = arrow.captures(line) {
+            if let Some((severity, message)) = self.pending.take() {
+                let path = caps.get(1).expect("group 1 always matches");
+                found.push(Found {
+                    range: path.start()..caps.get(0).expect("group 0 always matches").end(),
+                    meaning: Meaning::CompilerError {
+                        severity,
+                        message,
+                        path: Some(path.as_str().to_string()),
+                        line: number(caps.get(2)),
+                        column: number(caps.get(3)),
+                    },
+                });
+            }
+            return;
+        }
+        for (re, col, sev, msg) in [(gcc, 3, 4, 5), (tsc, 3, 4, 5)] {
+            if let Some(caps) = re.captures(line) {
+                found.push(Found {
+                    range: 0..line.len(),
+                    meaning: Meaning::CompilerError {
+                        severity: caps[sev].to_string(),
+                        message: caps[msg].to_string(),
+                        path: Some(caps[1].to_string()),
+                        line: number(caps.get(2)),
+                        column: number(caps.get(col)),
+                    },
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// Failing tests and failure summaries.
+pub struct TestFailures;
+
+impl Annotator for TestFailures {
+    fn scan(&mut self, line: &str, found: &mut Vec<Found>) {
+        static TESTS: OnceLock<Vec<Regex>> = OnceLock::new();
+        static SUMMARIES: OnceLock<Vec<Regex>> = OnceLock::new();
+        let compile = |patterns: &[&str]| -> Vec<Regex> {
+            patterns.iter().map(|p| Regex::new(p).expect("valid annotator regex")).collect()
+        };
+        // Group 1 is the test name
+        let tests = TESTS.get_or_init(|| {
+            compile(&[
+                // cargo test
+                r"^test (\S+) \.\.\. FAILED$",
+                // pytest -rf
+                r"^FAILED (\S+)",
+                // jest, vitest
+                r"^\s*[✕×] (.+?)(?: \(\d+(?:\.\d+)? ?m?s\))?$",
+                // go test
+                r"^\s*--- FAIL: (\S+)",
+            ])
+        });
+        // Group 1 is the summary
+        let summaries = SUMMARIES.get_or_init(|| {
+            compile(&[
+                r"^test result: (FAILED\..*)$",
+                r"^=+ (.*\bfailed\b.*?) =+$",
+                r"^Tests:\s+(.*\bfailed\b.*)$",
+                r"^(FAIL\s+\S+.*)$",
+            ])
+        });
+        for re in tests {
+            if let Some(caps) = re.captures(line) {
+                let test = caps.get(1).expect("group 1 always matches");
+                found.push(Found {
+                    range: test.range(),
+                    meaning: Meaning::TestFailure {
+                        test: Some(test.as_str().to_string()),
+                        summary: None,
+                    },
+                });
+                return;
+            }
+        }
+        for re in summaries {
+            if let Some(caps) = re.captures(line) {
+                let summary = caps.get(1).expect("group 1 always matches");
+                found.push(Found {
+                    range: summary.range(),
+                    meaning: Meaning::TestFailure {
+                        test: None,
+                        summary: Some(summary.as_str().trim().to_string()),
+                    },
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// `http(s)://` URLs, without trailing punctuation.
+pub struct Urls;
+
+impl Annotator for Urls {
+    fn scan(&mut self, line: &str, found: &mut Vec<Found>) {
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = regex(&RE, || r#"https?://[^\s<>"'`]+"#.into());
+        for m in re.find_iter(line) {
+            let mut url = m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            // A closing bracket belongs to the URL only if it opened one
+            for (open, close) in [('(', ')'), ('[', ']')] {
+                while url.ends_with(close) && url.matches(close).count() > url.matches(open).count()
+                {
+                    url = &url[..url.len() - 1];
+                }
+            }
+            found.push(Found {
+                range: m.start()..m.start() + url.len(),
+                meaning: Meaning::Url { url: url.to_string() },
+            });
+        }
+    }
+}
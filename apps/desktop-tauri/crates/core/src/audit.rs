@@ -0,0 +1,429 @@
+//! Audit log of invoked commands.
+//!
+//! Every command that changes something, whether invoked by the webview or
+//! over the CLI socket, is appended to `audit.jsonl` in the config dir with
+//! a timestamp, where it came from, its arguments and the session it
+//! concerns. Read-only commands aren't recorded. The file is never
+//! rewritten or compacted.
+//!
+//! Arguments are recorded minus secrets: values under secret-looking keys,
+//! env values and `secret_set`'s value are masked, as are values of
+//! secret-looking flags in command lines (`--api-key=...`, `--token ...`).
+//! Typed input and pastes aren't recorded as text: they are summed per
+//! session into one `pty_input` or `pty_paste` entry per batch of up to 10
+//! seconds with the number of writes and bytes.
+//!
+//! Session starts and exits and review changes, merges included, are
+//! recorded from their events (`source` is `event`), which ties a
+//! `pty_spawn` call to the id of the session it started.
+
+use crate::{
+    events::{EventSink, SharedSink},
+    redact::{self, MASK},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Commands that only read state.
+const READ_ONLY: &[&str] = &[
+    "settings_get",
+    "pty_queue_list",
+    "system_metrics",
+    "doctor",
+    "pty_protocol",
+    "pty_ack",
+    "pty_resize",
+    "pty_search",
+    "pty_screen",
+    "pty_export_text",
+    "pty_stats",
+    "usage_report",
+    "session_history",
+    "pty_list",
+    "pty_pipes",
+    "pty_process_tree",
+    "pty_log_path",
+    "get_log_settings",
+    "pty_recordings",
+    "pty_previous_sessions",
+    "pty_launch_snapshot",
+    "pty_input_history",
+    "pty_input_history_search",
+    "worktree_list",
+    "worktree_status",
+    "worktree_disk_usage",
+    "worktree_scale",
+    "worktree_snapshots",
+    "worktree_log",
+    "worktree_blame",
+    "worktree_diff",
+    "worktree_compare",
+    "worktree_staged_diff",
+    "repo_config",
+    "worktree_violations",
+    "worktree_checks",
+    "get_worktree_root",
+    "repo_search",
+    "repo_search_cancel",
+    "repo_stash_list",
+    "repo_branches",
+    "fs_read_file",
+    "fs_list_dir",
+    "fs_stat",
+    "worktree_tree",
+    "review_list",
+    "review_merge_message",
+    "review_comment_list",
+    "merge_queue_list",
+    "macro_list",
+    "action_list",
+    "github_fetch_issue",
+    "github_fetch_pr",
+    "task_list",
+    "task_timeline",
+    "get_shell_config",
+    "get_hooks",
+    "get_notification_prefs",
+    "agent_profiles",
+    "terminal_profile_list",
+    "secret_list",
+    "webhook_list",
+    "events_since",
+    "get_repo_path",
+    "audit_query",
+];
+
+/// Input commands summed into batches; their `data` is never recorded.
+const INPUT: &[&str] = &["pty_input", "pty_paste"];
+
+/// Longest span of one input batch.
+const INPUT_BATCH_MS: u64 = 10_000;
+
+/// Event name prefixes recorded.
+const AUDITED_EVENTS: &[&str] = &["pty://spawned/", "pty://exit/", "review://changed"];
+
+/// Entries returned by a query when it sets no limit.
+const DEFAULT_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSource {
+    Webview,
+    Cli,
+    /// Recorded from a backend event rather than a call.
+    Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Unix millis; the first write of an input batch.
+    pub at: u64,
+    pub source: AuditSource,
+    /// Command name, or event name for `event` entries.
+    pub command: String,
+    pub session_id: Option<String>,
+    /// Arguments minus secrets; for input batches, `writes` and `bytes`.
+    pub args: Value,
+}
+
+/// Entries recorded within `[since, until)`, in Unix millis; open-ended
+/// when unset.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AuditFilter {
+    /// Command name, or a prefix ending in `_` or `/` (`worktree_`,
+    /// `pty://`).
+    pub command: Option<String>,
+    pub session_id: Option<String>,
+    pub source: Option<AuditSource>,
+    /// Most recent entries returned; 1000 by default.
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        let command = self.command.as_deref().map_or(true, |c| {
+            entry.command == c || (c.ends_with(['_', '/']) && entry.command.starts_with(c))
+        });
+        command
+            && self.session_id.as_ref().map_or(true, |s| entry.session_id.as_ref() == Some(s))
+            && self.source.map_or(true, |s| entry.source == s)
+    }
+}
+
+struct Batch {
+    at: u64,
+    source: AuditSource,
+    writes: u64,
+    bytes: u64,
+}
+
+struct Inner {
+    file: Option<File>,
+    /// Open input batches by command and session.
+    batches: HashMap<(String, String), Batch>,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf) -> Self {
+        let file = open_append(&path).map_err(|e| log::warn!("audit log: {e:#}")).ok();
+        Self { path, inner: Mutex::new(Inner { file, batches: HashMap::new() }) }
+    }
+
+    /// Record a call of `command` with its arguments, unless it is
+    /// read-only.
+    pub fn record(&self, source: AuditSource, command: &str, args: &Value) {
+        if READ_ONLY.contains(&command) {
+            return;
+        }
+        let now = now_ms();
+        let session_id = session_of(args);
+        let mut inner = self.inner.lock().unwrap();
+        inner.flush_batches(|b| now.saturating_sub(b.at) >= INPUT_BATCH_MS);
+        if INPUT.contains(&command) {
+            if let Some(session_id) = session_id {
+                let bytes = args.get("data").and_then(Value::as_str).map_or(0, |s| s.len() as u64);
+                let batch = inner
+                    .batches
+                    .entry((command.to_string(), session_id))
+                    .or_insert(Batch { at: now, source, writes: 0, bytes: 0 });
+                batch.writes += 1;
+                batch.bytes += bytes;
+                return;
+            }
+        }
+        let mut args = sanitize(args);
+        if command == "secret_set" {
+            if let Some(value) = args.get_mut("value") {
+                *value = Value::String(MASK.into());
+            }
+        }
+        inner.write(&AuditEntry {
+            at: now,
+            source,
+            command: command.to_string(),
+            session_id,
+            args,
+        });
+    }
+
+    /// Record an event, if it is one that is audited.
+    pub fn record_event(&self, event: &str, payload: &Value) {
+        if !AUDITED_EVENTS.iter().any(|p| event.starts_with(p)) {
+            return;
+        }
+        let session_id = event
+            .strip_prefix("pty://")
+            .and_then(|rest| rest.split_once('/'))
+            .map(|(_, id)| id.to_string());
+        let mut inner = self.inner.lock().unwrap();
+        inner.write(&AuditEntry {
+            at: now_ms(),
+            source: AuditSource::Event,
+            command: event.split('/').take(3).collect::<Vec<_>>().join("/"),
+            session_id,
+            args: sanitize(payload),
+        });
+    }
+
+    /// Write out open input batches (at shutdown and before queries).
+    pub fn flush(&self) {
+        self.inner.lock().unwrap().flush_batches(|_| true);
+    }
+
+    /// The most recent entries within `range` matching `filter`, oldest
+    /// first. Unreadable lines are skipped.
+    pub fn query(&self, range: AuditRange, filter: &AuditFilter) -> Vec<AuditEntry> {
+        self.flush();
+        let Ok(file) = File::open(&self.path) else { return Vec::new() };
+        let mut entries: Vec<AuditEntry> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+            .filter(|e| {
+                !range.since.is_some_and(|s| e.at < s) && !range.until.is_some_and(|u| e.at >= u)
+            })
+            .filter(|e| filter.matches(e))
+            .collect();
+        // Batches are written when they close, after later entries
+        entries.sort_by_key(|e| e.at);
+        let limit = filter.limit.unwrap_or(DEFAULT_LIMIT);
+        entries.split_off(entries.len().saturating_sub(limit))
+    }
+}
+
+impl Inner {
+    fn write(&mut self, entry: &AuditEntry) {
+        let Some(file) = self.file.as_mut() else { return };
+        match serde_json::to_string(entry) {
+            Ok(line) => {
+                if let Err(e) = writeln!(file, "{line}") {
+                    log::warn!("write audit log: {e}");
+                }
+            }
+            Err(e) => log::warn!("audit entry: {e}"),
+        }
+    }
+
+    fn flush_batches(&mut self, due: impl Fn(&Batch) -> bool) {
+        let keys: Vec<_> =
+            self.batches.iter().filter(|(_, b)| due(b)).map(|(k, _)| k.clone()).collect();
+        for key in keys {
+            let Some(batch) = self.batches.remove(&key) else { continue };
+            let (command, session_id) = key;
+            self.write(&AuditEntry {
+                at: batch.at,
+                source: batch.source,
+                command,
+                session_id: Some(session_id),
+                args: serde_json::json!({ "writes": batch.writes, "bytes": batch.bytes }),
+            });
+        }
+    }
+}
+
+/// Sink that records audited events before passing them on.
+pub struct AuditSink {
+    inner: SharedSink,
+    log: Arc<AuditLog>,
+}
+
+impl AuditSink {
+    pub fn new(inner: SharedSink, log: Arc<AuditLog>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl EventSink for AuditSink {
+    fn emit(&self, event: &str, payload: Value) {
+        self.log.record_event(event, &payload);
+        self.inner.emit(event, payload);
+    }
+}
+
+/// Location of the audit log inside the app config dir.
+pub fn audit_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("audit.jsonl")
+}
+
+/// The session an invocation concerns: a `sessionId` argument, at the top
+/// level or one down (`pty_spawn`'s `args`).
+fn session_of(args: &Value) -> Option<String> {
+    let find = |v: &Value| {
+        ["sessionId", "session_id"].iter().find_map(|k| v.get(k)?.as_str().map(String::from))
+    };
+    find(args).or_else(|| args.as_object()?.values().find_map(find))
+}
+
+/// `args` with secrets masked.
+fn sanitize(args: &Value) -> Value {
+    match args {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (key, value) in map {
+                let value = if redact::is_secret_name(key) {
+                    Value::String(MASK.into())
+                } else if key == "env" {
+                    mask_env(value)
+                } else if key == "cmd" || key == "argv" {
+                    mask_argv(value)
+                } else {
+                    sanitize(value)
+                };
+                out.insert(key.clone(), value);
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sanitize).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Env as a map or as `[name, value]` pairs, with every value masked.
+fn mask_env(env: &Value) -> Value {
+    match env {
+        Value::Object(map) => {
+            Value::Object(map.keys().map(|k| (k.clone(), Value::String(MASK.into()))).collect())
+        }
+        Value::Array(pairs) => Value::Array(
+            pairs
+                .iter()
+                .map(|pair| match pair.get(0) {
+                    Some(name) => serde_json::json!([name, MASK]),
+                    None => Value::String(MASK.into()),
+                })
+                .collect(),
+        ),
+        _ => Value::String(MASK.into()),
+    }
+}
+
+/// A command line with the values of secret-looking flags masked.
+fn mask_argv(argv: &Value) -> Value {
+    let Some(items) = argv.as_array() else { return sanitize(argv) };
+    let mut out = Vec::with_capacity(items.len());
+    let mut mask_next = false;
+    for item in items {
+        let Some(arg) = item.as_str() else {
+            out.push(sanitize(item));
+            continue;
+        };
+        if std::mem::take(&mut mask_next) {
+            out.push(Value::String(MASK.into()));
+            continue;
+        }
+        let Some(flag) = arg.strip_prefix('-').map(|f| f.trim_start_matches('-')) else {
+            out.push(item.clone());
+            continue;
+        };
+        match flag.split_once('=') {
+            Some((name, _)) if is_secret_flag(name) => {
+                let name_end = arg.len() - flag.len() + name.len();
+                out.push(Value::String(format!("{}={MASK}", &arg[..name_end])));
+            }
+            None if is_secret_flag(flag) => {
+                mask_next = true;
+                out.push(item.clone());
+            }
+            _ => out.push(item.clone()),
+        }
+    }
+    Value::Array(out)
+}
+
+fn is_secret_flag(name: &str) -> bool {
+    redact::is_secret_name(&name.replace('-', "_"))
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("create config dir")?;
+    }
+    OpenOptions::new().create(true).append(true).open(path).context("open audit log")
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,133 @@
+//! Keeping worktrees up with the base branch as it moves upstream.
+//!
+//! Each background fetch (see `remote.rs`) compares the upstream of the
+//! main checkout's branch with where it was before the fetch. When it
+//! moved, `repo://base-updated` carries the old and new tips and every
+//! worktree's ahead/behind against the new one, so long-running agents
+//! drifting behind show up before a merge conflicts.
+//!
+//! With `auto_rebase_worktrees` set, worktrees behind the new tip are
+//! rebased onto it first. Only clean ones are touched: a worktree with
+//! uncommitted changes, another git operation in progress, or held by
+//! another pi-builder operation is skipped, and a rebase that conflicts is
+//! aborted, leaving the worktree as it was.
+
+use crate::{
+    locks::WorktreeLocks,
+    rebase::{self, RebaseStatus},
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{Oid, Repository};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoRebase {
+    Rebased,
+    /// Conflicted and was aborted.
+    Conflict,
+    /// Dirty, mid-operation or busy.
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorktreeSync {
+    pub name: String,
+    /// Commits on the worktree's HEAD that aren't on the new tip.
+    pub upstream_ahead: usize,
+    /// Commits on the new tip the worktree doesn't have.
+    pub upstream_behind: usize,
+    /// What auto-rebase did, when it tried.
+    pub rebase: Option<AutoRebase>,
+}
+
+/// Payload of `repo://base-updated`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseUpdate {
+    pub repo: String,
+    /// Upstream of the main checkout's branch, e.g. `origin/main`.
+    pub upstream: String,
+    pub from: String,
+    pub to: String,
+    pub worktrees: Vec<WorktreeSync>,
+}
+
+/// Upstream of the main checkout's branch and its commit, if it has one.
+pub fn tip(repo_path: &str) -> Option<(String, Oid)> {
+    worktree::upstream_of(&Repository::open(repo_path).ok()?)
+}
+
+/// Report the worktrees of `repo_path` against the new upstream tip `to`,
+/// rebasing the clean ones that are behind onto it when `auto_rebase`,
+/// signing the rebased commits with `sign`.
+pub fn update(
+    repo_path: &str,
+    upstream: String,
+    (from, to): (Oid, Oid),
+    auto_rebase: bool,
+    sign: bool,
+    locks: &WorktreeLocks,
+) -> Result<BaseUpdate> {
+    let main = Repository::open(repo_path).context("open repo")?;
+    let mut worktrees = Vec::new();
+    for name in main.worktrees()?.iter().flatten() {
+        let Ok(mut counts) = divergence(&main, name, to) else { continue };
+        let mut rebase = None;
+        if auto_rebase && counts.1 > 0 {
+            let outcome = rebase_one(&main, repo_path, name, to, sign, locks);
+            if outcome == AutoRebase::Rebased {
+                counts = divergence(&main, name, to).unwrap_or(counts);
+            }
+            rebase = Some(outcome);
+        }
+        worktrees.push(WorktreeSync {
+            name: name.to_string(),
+            upstream_ahead: counts.0,
+            upstream_behind: counts.1,
+            rebase,
+        });
+    }
+    Ok(BaseUpdate {
+        repo: repo_path.to_string(),
+        upstream,
+        from: from.to_string(),
+        to: to.to_string(),
+        worktrees,
+    })
+}
+
+fn rebase_one(
+    main: &Repository,
+    repo_path: &str,
+    name: &str,
+    to: Oid,
+    sign: bool,
+    locks: &WorktreeLocks,
+) -> AutoRebase {
+    let Ok(_lock) = locks.worktree(repo_path, name, "auto rebase") else {
+        return AutoRebase::Skipped;
+    };
+    match rebase::rebase_onto(main, name, to, sign) {
+        Ok(outcome) if outcome.status == RebaseStatus::Conflict => {
+            if let Err(e) = rebase::abort(repo_path, name) {
+                log::warn!("aborting auto rebase of {name}: {e:#}");
+            }
+            AutoRebase::Conflict
+        }
+        Ok(_) => AutoRebase::Rebased,
+        Err(e) => {
+            log::debug!("auto rebase of {name} skipped: {e:#}");
+            AutoRebase::Skipped
+        }
+    }
+}
+
+/// Worktree `name`'s HEAD ahead/behind `to`.
+fn divergence(main: &Repository, name: &str, to: Oid) -> Result<(usize, usize)> {
+    let repo = worktree::open_in(main, name)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    Ok(repo.graph_ahead_behind(head, to)?)
+}
@@ -0,0 +1,146 @@
+//! Branches of the main checkout, and switching between them.
+//!
+//! New worktrees fork off the main checkout's HEAD and every worktree's
+//! ahead/behind is counted against it, so the branch checked out there is
+//! the base the agents work from. `list` shows the local and remote
+//! branches to pick from; `checkout` switches to one.
+//!
+//! Checkout refuses a main checkout with uncommitted changes (stash them
+//! first, see `stash.rs`) or mid-operation, and a branch another worktree
+//! has checked out. A remote branch with no local branch of the same name
+//! gets one that tracks it.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{build::CheckoutBuilder, Branch, BranchType, Repository, RepositoryState};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastCommit {
+    pub sha: String,
+    pub summary: String,
+    pub author: String,
+    /// Unix seconds.
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchInfo {
+    /// `main`, or `origin/main` for a remote branch.
+    pub name: String,
+    pub remote: bool,
+    /// Checked out in the main checkout.
+    pub current: bool,
+    /// Upstream of a local branch, e.g. `origin/main`.
+    pub upstream: Option<String>,
+    /// Commits not on the upstream; 0 without one.
+    pub ahead: usize,
+    /// Upstream commits not on the branch.
+    pub behind: usize,
+    pub last_commit: Option<LastCommit>,
+}
+
+/// Local branches, then remote ones, each by name. `origin/HEAD` and the
+/// like are left out.
+pub fn list(repo_path: &str) -> Result<Vec<BranchInfo>> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let mut branches = Vec::new();
+    for kind in [BranchType::Local, BranchType::Remote] {
+        let mut found = Vec::new();
+        for branch in repo.branches(Some(kind))? {
+            let (branch, _) = branch?;
+            if branch.get().symbolic_target().is_some() {
+                continue;
+            }
+            let Some(name) = branch.name()?.map(str::to_string) else { continue };
+            found.push(info(&repo, &branch, name, kind == BranchType::Remote));
+        }
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        branches.extend(found);
+    }
+    Ok(branches)
+}
+
+/// Check out `name`, a local branch or a remote one, in the main checkout.
+/// Returns the local branch now checked out.
+pub fn checkout(repo_path: &str, name: &str) -> Result<BranchInfo> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    if repo.state() != RepositoryState::Clean {
+        return Err(PiError::invalid_input("main checkout is in the middle of an operation").into());
+    }
+    if worktree::is_dirty(&repo) {
+        let message = "main checkout has uncommitted changes; stash them first";
+        return Err(PiError::invalid_input(message).into());
+    }
+    let branch = match repo.find_branch(name, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(_) => track(&repo, name)?,
+    };
+    let local = branch.name()?.context("branch name isn't UTF-8")?.to_string();
+    if let Some(worktree) = checked_out_in(&repo, &local)? {
+        let message = format!("{local} is checked out in worktree {worktree}");
+        return Err(PiError::invalid_input(message).into());
+    }
+    let refname = branch.get().name().context("branch ref isn't UTF-8")?.to_string();
+    let target = branch.get().peel(git2::ObjectType::Commit)?;
+    repo.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))?;
+    repo.set_head(&refname)?;
+    let branch = repo.find_branch(&local, BranchType::Local)?;
+    Ok(info(&repo, &branch, local, false))
+}
+
+/// Create a local branch tracking the remote branch `name`.
+fn track<'r>(repo: &'r Repository, name: &str) -> Result<Branch<'r>> {
+    let not_found = || PiError::new(ErrorCode::NotFound, format!("no branch {name}"));
+    let remote = repo.find_branch(name, BranchType::Remote).map_err(|_| not_found())?;
+    let local = name.split_once('/').map(|(_, rest)| rest).filter(|s| !s.is_empty());
+    let local = local.ok_or_else(not_found)?;
+    if repo.find_branch(local, BranchType::Local).is_ok() {
+        let message = format!("local branch {local} exists; check it out instead of {name}");
+        return Err(PiError::invalid_input(message).into());
+    }
+    let commit = remote.get().peel_to_commit()?;
+    let mut branch = repo.branch(local, &commit, false)?;
+    branch.set_upstream(Some(name))?;
+    Ok(branch)
+}
+
+/// Worktree that has `branch` checked out, if any.
+fn checked_out_in(repo: &Repository, branch: &str) -> Result<Option<String>> {
+    for name in repo.worktrees()?.iter().flatten() {
+        let Ok(wt_repo) = worktree::open_in(repo, name) else { continue };
+        let head = wt_repo.head().ok();
+        if head.as_ref().is_some_and(|h| h.is_branch() && h.shorthand() == Some(branch)) {
+            return Ok(Some(name.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn info(repo: &Repository, branch: &Branch, name: String, remote: bool) -> BranchInfo {
+    let tip = branch.get().peel_to_commit().ok();
+    let upstream = if remote { None } else { branch.upstream().ok() };
+    let (ahead, behind) = match (&tip, upstream.as_ref().and_then(|u| u.get().target())) {
+        (Some(tip), Some(up)) => repo.graph_ahead_behind(tip.id(), up).unwrap_or((0, 0)),
+        _ => (0, 0),
+    };
+    BranchInfo {
+        current: !remote && branch.is_head(),
+        upstream: upstream.and_then(|u| u.name().ok().flatten().map(str::to_string)),
+        ahead,
+        behind,
+        last_commit: tip.map(|c| LastCommit {
+            sha: c.id().to_string(),
+            summary: c.summary().unwrap_or_default().to_string(),
+            author: c.author().name().unwrap_or_default().to_string(),
+            timestamp: c.time().seconds(),
+        }),
+        name,
+        remote,
+    }
+}
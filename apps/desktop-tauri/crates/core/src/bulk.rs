@@ -0,0 +1,131 @@
+//! One operation across many worktrees.
+//!
+//! Names are shared out to a few worker threads, each of which opens the
+//! repository once and works through its share. Every name gets its own
+//! result; one failure doesn't stop the rest.
+
+use crate::{
+    error::PiError,
+    locks::WorktreeLocks,
+    rebase::{self, RebaseOutcome},
+    worktree::{self, WorktreeInfo},
+};
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// Upper bound on worker threads; git is mostly disk-bound past this.
+const MAX_WORKERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOp {
+    Remove,
+    Rebase,
+    /// Recompute branch, divergence and dirty state.
+    Status,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItem {
+    pub name: String,
+    pub ok: bool,
+    pub error: Option<PiError>,
+    /// For `status`.
+    pub info: Option<WorktreeInfo>,
+    /// For `rebase`.
+    pub rebase: Option<RebaseOutcome>,
+}
+
+/// Run `op` on every worktree in `names`. `on_item` is called from worker
+/// threads as each one finishes, with the number finished so far. Results
+/// come back in the order of `names`. Removals and rebases take each
+/// worktree's lock in `locks`; a busy one fails. Rebased commits are signed
+/// with `sign`.
+pub fn run(
+    repo_path: &str,
+    op: BulkOp,
+    names: &[String],
+    locks: &WorktreeLocks,
+    sign: bool,
+    on_item: impl Fn(&BulkItem, usize) + Sync,
+) -> Result<Vec<BulkItem>> {
+    // Fail fast on a bad repo instead of once per name
+    Repository::open(repo_path).context("open repo")?;
+
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BulkItem>>> =
+        Mutex::new(names.iter().map(|_| None).collect());
+    let workers = names.len().min(MAX_WORKERS).min(
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    );
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let repo = Repository::open(repo_path).map_err(PiError::from);
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(name) = names.get(i) else { break };
+                    let item = match &repo {
+                        Ok(repo) => run_one(repo_path, repo, op, name, locks, sign),
+                        Err(e) => failed(name, e.clone()),
+                    };
+                    on_item(&item, done.fetch_add(1, Ordering::SeqCst) + 1);
+                    results.lock().unwrap()[i] = Some(item);
+                }
+            });
+        }
+    });
+
+    Ok(results.into_inner().unwrap().into_iter().flatten().collect())
+}
+
+fn run_one(
+    repo_path: &str,
+    repo: &Repository,
+    op: BulkOp,
+    name: &str,
+    locks: &WorktreeLocks,
+    sign: bool,
+) -> BulkItem {
+    let mut item = BulkItem {
+        name: name.to_string(),
+        ok: true,
+        error: None,
+        info: None,
+        rebase: None,
+    };
+    let result = match op {
+        BulkOp::Remove => locks
+            .worktree(repo_path, name, "remove")
+            .and_then(|_lock| worktree::remove_in(repo, name)),
+        BulkOp::Rebase => locks
+            .worktree(repo_path, name, "rebase")
+            .and_then(|_lock| rebase::rebase_in(repo, name, sign))
+            .map(|o| item.rebase = Some(o)),
+        BulkOp::Status => worktree::worktree_info(repo, name).map(|i| item.info = Some(i)),
+    };
+    if let Err(e) = result {
+        return failed(name, e.into());
+    }
+    item
+}
+
+fn failed(name: &str, error: PiError) -> BulkItem {
+    BulkItem {
+        name: name.to_string(),
+        ok: false,
+        error: Some(error),
+        info: None,
+        rebase: None,
+    }
+}
@@ -0,0 +1,146 @@
+//! Named checks run in a worktree: tests, lint, build.
+//!
+//! A repo lists its checks under `[checks]` in `.pi-builder.toml`, a name
+//! per shell command. The merge check (see `mergequeue.rs`) is also
+//! available as `merge` unless the repo names a check that.
+//!
+//! `worktree_run_check` runs one in a worktree as a session with agent id
+//! `check`, tagged `check` and `check:<name>`, so its output is a terminal
+//! like any other. When it exits, `check://result` reports the exit code
+//! and whether it passed (exit 0).
+
+use crate::{
+    error::{ErrorCode, PiError},
+    events::SharedSink,
+    mergequeue,
+    pty::{PtyManager, SpawnOptions},
+    repo_config::RepoConfig,
+    settings::{Settings, TerminalSize},
+    worktree,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// Agent id of check sessions, so the UI can label them.
+pub const CHECK_AGENT_ID: &str = "check";
+
+/// Name the merge check is available under.
+pub const MERGE_CHECK: &str = "merge";
+
+/// Payload of `check://result`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    pub repo: String,
+    pub worktree: String,
+    pub check: String,
+    pub session_id: String,
+    pub exit_code: u32,
+    pub passed: bool,
+    pub duration_ms: u64,
+    /// Unix millis.
+    pub finished_at: u64,
+}
+
+/// Fail unless every check has a usable name and a command.
+pub fn validate(checks: &BTreeMap<String, String>) -> Result<()> {
+    for (name, command) in checks {
+        let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if name.is_empty() || !valid {
+            let message = format!("check name {name:?} may only use letters, digits, - _ .");
+            return Err(PiError::invalid_input(message).into());
+        }
+        if command.trim().is_empty() {
+            return Err(PiError::invalid_input(format!("check {name} has no command")).into());
+        }
+    }
+    Ok(())
+}
+
+/// The repo's checks, by name.
+pub fn list(repo: &str, settings: &Settings) -> BTreeMap<String, String> {
+    let mut checks = RepoConfig::load_or_default(Path::new(repo)).checks;
+    if !checks.contains_key(MERGE_CHECK) {
+        if let Some(command) = mergequeue::check_command(repo, settings) {
+            checks.insert(MERGE_CHECK.to_string(), command);
+        }
+    }
+    checks
+}
+
+/// The command of check `name`.
+pub fn command(repo: &str, settings: &Settings, name: &str) -> Result<String> {
+    let mut checks = list(repo, settings);
+    checks.remove(name).ok_or_else(|| {
+        let known = checks.keys().cloned().collect::<Vec<_>>().join(", ");
+        let message = if known.is_empty() {
+            format!("no check {name}; add one under [checks] in .pi-builder.toml")
+        } else {
+            format!("no check {name}; the repo has {known}")
+        };
+        PiError::new(ErrorCode::NotFound, message).into()
+    })
+}
+
+/// Start check `name` running `command` in `worktree` of `repo`. Returns
+/// the session id; `check://result` follows when the session exits.
+pub fn spawn(
+    pty: &Mutex<PtyManager>,
+    events: SharedSink,
+    repo: &str,
+    worktree: &str,
+    name: &str,
+    command: &str,
+    size: TerminalSize,
+) -> Result<String> {
+    let path = worktree::worktree_path(repo, worktree)?;
+    let argv = if cfg!(windows) {
+        vec!["cmd.exe".into(), "/C".into(), command.to_string()]
+    } else {
+        vec!["sh".into(), "-c".into(), command.to_string()]
+    };
+    // Known up front so the exit hook can name its session
+    let session_id = Uuid::new_v4().to_string();
+    let mut opts = SpawnOptions::new(CHECK_AGENT_ID, argv);
+    opts.session_id = Some(session_id.clone());
+    opts.cwd = Some(path.to_string_lossy().into_owned());
+    opts.cols = size.cols;
+    opts.rows = size.rows;
+    opts.repo = Some(repo.to_string());
+    opts.meta.title = Some(format!("{name} {worktree}"));
+    opts.meta.set_tags(vec!["check".into(), format!("check:{name}")]);
+    let started = Instant::now();
+    let result = CheckResult {
+        repo: repo.to_string(),
+        worktree: worktree.to_string(),
+        check: name.to_string(),
+        session_id,
+        exit_code: 0,
+        passed: false,
+        duration_ms: 0,
+        finished_at: 0,
+    };
+    let sink = events.clone();
+    opts.on_exit = Some(Box::new(move |code| {
+        let result = CheckResult {
+            exit_code: code,
+            passed: code == 0,
+            duration_ms: started.elapsed().as_millis() as u64,
+            finished_at: now_ms(),
+            ..result
+        };
+        sink.emit("check://result", serde_json::to_value(result).unwrap_or_default());
+    }));
+    pty.lock().unwrap().spawn(opts, events)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
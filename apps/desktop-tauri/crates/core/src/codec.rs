@@ -0,0 +1,93 @@
+//! PTY data framing.
+//!
+//! `pty://data` events carry a protocol version and an explicit encoding.
+//! `utf8` (the default, protocol v1 compatible) decodes text without
+//! splitting multi-byte characters across events; `base64` passes raw bytes
+//! through untouched for zmodem-style transfers and dense ANSI art.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Current `pty://data` payload version.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+#[derive(Serialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+    pub encodings: Vec<DataEncoding>,
+}
+
+pub fn protocol_info() -> ProtocolInfo {
+    ProtocolInfo {
+        version: PROTOCOL_VERSION,
+        encodings: vec![DataEncoding::Utf8, DataEncoding::Base64],
+    }
+}
+
+/// Stateful encoder for one session's output stream.
+#[derive(Default)]
+pub struct Encoder {
+    encoding: DataEncoding,
+    /// Trailing bytes of an incomplete UTF-8 sequence from the last chunk.
+    carry: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(encoding: DataEncoding) -> Self {
+        Self { encoding, carry: Vec::new() }
+    }
+
+    pub fn encoding(&self) -> DataEncoding {
+        self.encoding
+    }
+
+    pub fn encode(&mut self, bytes: &[u8]) -> String {
+        match self.encoding {
+            DataEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+            DataEncoding::Utf8 => {
+                let mut data = std::mem::take(&mut self.carry);
+                data.extend_from_slice(bytes);
+                let keep = incomplete_tail_len(&data);
+                self.carry = data.split_off(data.len() - keep);
+                String::from_utf8_lossy(&data).into_owned()
+            }
+        }
+    }
+
+    /// Emit any carried bytes at end of stream.
+    pub fn flush(&mut self) -> String {
+        let rest = std::mem::take(&mut self.carry);
+        match self.encoding {
+            DataEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(rest),
+            DataEncoding::Utf8 => String::from_utf8_lossy(&rest).into_owned(),
+        }
+    }
+}
+
+/// Number of bytes at the end of `data` that start a UTF-8 sequence not yet
+/// complete. Invalid bytes elsewhere are left for lossy decoding.
+fn incomplete_tail_len(data: &[u8]) -> usize {
+    let start = data.len().saturating_sub(3);
+    (start..data.len())
+        .find(|&i| sequence_len(data[i]) > data.len() - i)
+        .map(|i| data.len() - i)
+        .unwrap_or(0)
+}
+
+/// Length of the UTF-8 sequence introduced by lead byte `b`.
+fn sequence_len(b: u8) -> usize {
+    match b {
+        0xF0..=0xF7 => 4,
+        0xE0..=0xEF => 3,
+        0xC0..=0xDF => 2,
+        _ => 1,
+    }
+}
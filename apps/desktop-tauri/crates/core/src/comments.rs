@@ -0,0 +1,171 @@
+//! Inline review comments on agent diffs.
+//!
+//! Reviewers leave notes on lines of a worktree's diff while deciding
+//! whether to merge or discard it. Comments are kept locally in
+//! `review_comments.json` in the config dir, per repo and worktree, and
+//! outlive the worktree so a merged branch's notes can still be read.
+//! Each records the worktree HEAD it was written against, so a note on a
+//! line that has since changed can be told apart.
+//!
+//! `markdown` renders a worktree's comments grouped by file, for a PR
+//! description or a file written when the branch is merged.
+
+use crate::error::PiError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewComment {
+    pub id: String,
+    pub worktree: String,
+    /// Path relative to the worktree.
+    pub file: String,
+    /// 1-based line in the worktree's version of `file`.
+    pub line: u32,
+    pub body: String,
+    /// Worktree HEAD the comment was written against.
+    pub head: Option<String>,
+    /// Unix millis.
+    pub created_at: u64,
+}
+
+/// Comments per repo path, then per worktree name.
+type Comments = HashMap<String, HashMap<String, Vec<ReviewComment>>>;
+
+pub struct CommentStore {
+    path: PathBuf,
+    comments: Mutex<Comments>,
+}
+
+impl CommentStore {
+    /// Load `path`; a missing or unreadable file starts empty.
+    pub fn load(path: PathBuf) -> Self {
+        let comments = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, comments: Mutex::new(comments) }
+    }
+
+    /// Comments on `worktree`, by file, then line, then age.
+    pub fn list(&self, repo: &str, worktree: &str) -> Vec<ReviewComment> {
+        let mut list = self
+            .comments
+            .lock()
+            .unwrap()
+            .get(repo)
+            .and_then(|w| w.get(worktree).cloned())
+            .unwrap_or_default();
+        list.sort_by(|a, b| (&a.file, a.line, a.created_at).cmp(&(&b.file, b.line, b.created_at)));
+        list
+    }
+
+    pub fn add(
+        &self,
+        repo: &str,
+        worktree: &str,
+        file: &str,
+        line: u32,
+        body: &str,
+        head: Option<String>,
+    ) -> Result<ReviewComment> {
+        let file = file.trim().trim_start_matches("./");
+        if file.is_empty() || Path::new(file).is_absolute() || file.split('/').any(|p| p == "..") {
+            let message = "comment file must be a path in the worktree";
+            return Err(PiError::invalid_input(message).into());
+        }
+        if line == 0 {
+            return Err(PiError::invalid_input("comment line starts at 1").into());
+        }
+        if body.trim().is_empty() {
+            return Err(PiError::invalid_input("comment is empty").into());
+        }
+        let comment = ReviewComment {
+            id: Uuid::new_v4().to_string(),
+            worktree: worktree.to_string(),
+            file: file.to_string(),
+            line,
+            body: body.trim_end().to_string(),
+            head,
+            created_at: now_ms(),
+        };
+        self.comments
+            .lock()
+            .unwrap()
+            .entry(repo.to_string())
+            .or_default()
+            .entry(worktree.to_string())
+            .or_default()
+            .push(comment.clone());
+        self.save()?;
+        Ok(comment)
+    }
+
+    /// Delete comment `id` on `worktree`; returns whether it existed.
+    pub fn delete(&self, repo: &str, worktree: &str, id: &str) -> Result<bool> {
+        let removed = {
+            let mut comments = self.comments.lock().unwrap();
+            let Some(list) = comments.get_mut(repo).and_then(|w| w.get_mut(worktree)) else {
+                return Ok(false);
+            };
+            let before = list.len();
+            list.retain(|c| c.id != id);
+            list.len() != before
+        };
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.comments.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write review comments")?;
+        Ok(())
+    }
+}
+
+/// Location of the review comments inside the app config dir.
+pub fn comments_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("review_comments.json")
+}
+
+/// `comments` as Markdown: a heading, then a section per file with a
+/// bullet per comment. Empty without comments.
+pub fn markdown(worktree: &str, comments: &[ReviewComment]) -> String {
+    if comments.is_empty() {
+        return String::new();
+    }
+    let mut by_file: BTreeMap<&str, Vec<&ReviewComment>> = BTreeMap::new();
+    for comment in comments {
+        by_file.entry(&comment.file).or_default().push(comment);
+    }
+    let mut out = format!("## Review notes on `{worktree}`\n");
+    for (file, comments) in by_file {
+        out.push_str(&format!("\n### `{file}`\n\n"));
+        for comment in comments {
+            // Continuation lines are indented to stay inside the bullet
+            let body = comment.body.replace('\n', "\n  ");
+            out.push_str(&format!("- **L{}**: {body}\n", comment.line));
+        }
+    }
+    out
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,146 @@
+//! Comparing two agent branches.
+//!
+//! When two agents take on the same task, their branches are lined up
+//! against their common base, as `git diff a...b` does: every file either
+//! changed since the base is listed with how each branch changed it. A file
+//! both changed is an overlap, and an overlap git can't merge cleanly is a
+//! conflict; landing one branch would make the other's change to it
+//! conflict. The direct diff from one branch to the other comes along for
+//! side-by-side review. Only committed work is compared.
+
+use crate::{
+    staging::{self, FileDiff},
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{Delta, Oid, Patch, Repository, Tree};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchChange {
+    pub kind: ChangeKind,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileComparison {
+    pub path: String,
+    /// How each branch changed the file since the base; `None` if it didn't.
+    pub a: Option<BranchChange>,
+    pub b: Option<BranchChange>,
+    /// Both branches changed the file.
+    pub overlap: bool,
+    /// Both changed it and the changes don't merge cleanly.
+    pub conflict: bool,
+    /// Both ended up with the same content.
+    pub identical: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Comparison {
+    /// Merge base of the two branches.
+    pub base: String,
+    pub head_a: String,
+    pub head_b: String,
+    /// Files changed on either side since the base, by path.
+    pub files: Vec<FileComparison>,
+    /// From `a` to `b`.
+    pub diff: Vec<FileDiff>,
+}
+
+/// Compare the branches checked out in worktrees `a` and `b` of `main`.
+pub fn compare(main: &Repository, a: &str, b: &str) -> Result<Comparison> {
+    let head_a = head(main, a)?;
+    let head_b = head(main, b)?;
+    let base = main.merge_base(head_a, head_b).context("branches share no history")?;
+    let tree = |id| main.find_commit(id).and_then(|c| c.tree());
+    let (base_tree, tree_a, tree_b) = (tree(base)?, tree(head_a)?, tree(head_b)?);
+
+    let changes_a = changes(main, &base_tree, &tree_a)?;
+    let changes_b = changes(main, &base_tree, &tree_b)?;
+    let conflicts: HashSet<String> = main
+        .merge_trees(&base_tree, &tree_a, &tree_b, None)?
+        .conflicts()?
+        .flatten()
+        .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+        .map(|e| String::from_utf8_lossy(&e.path).to_string())
+        .collect();
+
+    let mut paths: Vec<&String> = changes_a.keys().chain(changes_b.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    let files = paths
+        .into_iter()
+        .map(|path| {
+            let (a, b) = (changes_a.get(path).cloned(), changes_b.get(path).cloned());
+            let overlap = a.is_some() && b.is_some();
+            let identical = overlap && entry_id(&tree_a, path) == entry_id(&tree_b, path);
+            FileComparison {
+                path: path.clone(),
+                conflict: overlap && conflicts.contains(path),
+                a,
+                b,
+                overlap,
+                identical,
+            }
+        })
+        .collect();
+
+    let diff = main.diff_tree_to_tree(Some(&tree_a), Some(&tree_b), None)?;
+    Ok(Comparison {
+        base: base.to_string(),
+        head_a: head_a.to_string(),
+        head_b: head_b.to_string(),
+        files,
+        diff: staging::file_diffs(main, &diff)?,
+    })
+}
+
+fn head(main: &Repository, name: &str) -> Result<Oid> {
+    let repo = worktree::open_in(main, name)?;
+    let id = repo.head()?.peel_to_commit()?.id();
+    Ok(id)
+}
+
+/// Files changed from `base` to `tree`, with their line counts.
+pub(crate) fn changes(
+    repo: &Repository,
+    base: &Tree,
+    tree: &Tree,
+) -> Result<BTreeMap<String, BranchChange>> {
+    let diff = repo.diff_tree_to_tree(Some(base), Some(tree), None)?;
+    let mut changes = BTreeMap::new();
+    for idx in 0..diff.deltas().len() {
+        let Some(patch) = Patch::from_diff(&diff, idx)? else { continue };
+        let delta = patch.delta();
+        let kind = match delta.status() {
+            Delta::Added => ChangeKind::Added,
+            Delta::Deleted => ChangeKind::Deleted,
+            _ => ChangeKind::Modified,
+        };
+        let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+            continue;
+        };
+        let (_, additions, deletions) = patch.line_stats()?;
+        let path = path.to_string_lossy().replace('\\', "/");
+        changes.insert(path, BranchChange { kind, additions, deletions });
+    }
+    Ok(changes)
+}
+
+fn entry_id(tree: &Tree, path: &str) -> Option<Oid> {
+    tree.get_path(std::path::Path::new(path)).ok().map(|e| e.id())
+}
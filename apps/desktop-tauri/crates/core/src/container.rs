@@ -0,0 +1,121 @@
+//! Container execution backend (Docker or Podman).
+//!
+//! The session's PTY runs `docker run -it` (or `podman run -it`), so the
+//! agent gets a real TTY inside the container while output streams through
+//! the usual reader thread. The session cwd — normally the agent worktree —
+//! is bind-mounted at `workdir`; nothing else from the host is visible
+//! unless listed in `mounts`. Killing the session force-removes the
+//! container, since the CLI going away doesn't stop it.
+
+use crate::target::Launch;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    fn program(self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Podman => "podman",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerSpec {
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
+    pub image: String,
+    /// Extra bind mounts besides the session cwd.
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    /// Variables set inside the container, on top of the session env.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Where the session cwd is mounted and the command starts.
+    #[serde(default = "default_workdir")]
+    pub workdir: String,
+    /// `--network` value, e.g. `none` to cut the agent off entirely.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Extra `run` arguments, inserted before the image.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+fn default_workdir() -> String {
+    "/workspace".into()
+}
+
+impl ContainerSpec {
+    /// Build the `run` command line. An empty `cmd` runs the image's default
+    /// command. Session env is forwarded by name (`-e KEY`) so values never
+    /// appear in the process list.
+    pub fn launch(
+        &self,
+        cmd: Vec<String>,
+        cwd: Option<String>,
+        env: &[(String, String)],
+    ) -> Launch {
+        let program = self.runtime.program();
+        let name = format!("pi-builder-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let mut argv: Vec<String> = vec![
+            program.into(),
+            "run".into(),
+            "--rm".into(),
+            "-it".into(),
+            "--init".into(),
+            "--name".into(),
+            name.clone(),
+        ];
+        if let Some(dir) = &cwd {
+            argv.push("-v".into());
+            argv.push(format!("{dir}:{}", self.workdir));
+            argv.push("-w".into());
+            argv.push(self.workdir.clone());
+        }
+        for m in &self.mounts {
+            argv.push("-v".into());
+            let ro = if m.readonly { ":ro" } else { "" };
+            argv.push(format!("{}:{}{ro}", m.source, m.target));
+        }
+        for (key, _) in env {
+            argv.push("-e".into());
+            argv.push(key.clone());
+        }
+        for (key, value) in &self.env {
+            argv.push("-e".into());
+            argv.push(format!("{key}={value}"));
+        }
+        if let Some(network) = &self.network {
+            argv.push("--network".into());
+            argv.push(network.clone());
+        }
+        argv.extend(self.args.iter().cloned());
+        argv.push(self.image.clone());
+        argv.extend(cmd);
+
+        Launch {
+            cmd: argv,
+            cwd,
+            env: Vec::new(),
+            cleanup: Some(vec![program.into(), "rm".into(), "-f".into(), name]),
+        }
+    }
+}
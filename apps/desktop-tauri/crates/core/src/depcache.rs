@@ -0,0 +1,187 @@
+//! Sharing dependency and build caches between worktrees.
+//!
+//! Every worktree installing its own `node_modules`, building its own
+//! `target/` and creating its own `.venv` makes parallel agents slow to
+//! start and heavy on disk. Per-repo rules in settings say how each cache
+//! directory of the main checkout is reused by new worktrees:
+//!
+//! - `symlink`: the worktree's directory is a link to the main checkout's.
+//!   Cheap, but installs in one worktree change all of them.
+//! - `hardlink`: the directory tree is recreated with every file
+//!   hard-linked, the way pnpm links from its store. Worktrees can add
+//!   packages independently; files nobody changes are stored once. Needs
+//!   the worktree on the same filesystem as the main checkout.
+//! - `shared_dir`: nothing is linked; sessions in the worktree get `env`
+//!   (e.g. `CARGO_TARGET_DIR`) pointing at the main checkout's directory.
+//!
+//! Rules run after the worktree is checked out and before the setup hook,
+//! so setup scripts see the linked directories.
+
+use crate::error::PiError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Component, Path},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStrategy {
+    Symlink,
+    Hardlink,
+    SharedDir,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheRule {
+    /// Directory relative to the checkout root, e.g. `node_modules`.
+    pub path: String,
+    pub strategy: CacheStrategy,
+    /// Variable pointing tools at the shared directory; required for
+    /// `shared_dir`.
+    #[serde(default)]
+    pub env: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheLinkReport {
+    pub path: String,
+    pub strategy: CacheStrategy,
+    pub linked: bool,
+    /// Files hard-linked, for `hardlink`.
+    pub files: u64,
+    /// Why the rule was skipped or failed.
+    pub error: Option<String>,
+}
+
+/// Reject rules that can't work before they are saved.
+pub fn validate(rules: &[CacheRule]) -> Result<()> {
+    for rule in rules {
+        let path = Path::new(&rule.path);
+        let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if rule.path.is_empty() || !plain {
+            return Err(PiError::invalid_input(format!(
+                "cache path must be relative and inside the checkout: {}",
+                rule.path
+            ))
+            .into());
+        }
+        if rule.strategy == CacheStrategy::SharedDir && rule.env.is_none() {
+            return Err(PiError::invalid_input(format!(
+                "shared_dir rule for {} needs an env variable",
+                rule.path
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Apply `rules` to a freshly created worktree. A directory the main
+/// checkout doesn't have yet, or the worktree already has (checked in), is
+/// skipped.
+pub fn apply(main: &Path, worktree: &Path, rules: &[CacheRule]) -> Vec<CacheLinkReport> {
+    rules
+        .iter()
+        .filter(|r| r.strategy != CacheStrategy::SharedDir)
+        .map(|rule| {
+            let mut report = CacheLinkReport {
+                path: rule.path.clone(),
+                strategy: rule.strategy,
+                linked: false,
+                files: 0,
+                error: None,
+            };
+            let src = main.join(&rule.path);
+            let dst = worktree.join(&rule.path);
+            if !src.is_dir() {
+                report.error = Some("not present in the main checkout".into());
+                return report;
+            }
+            if fs::symlink_metadata(&dst).is_ok() {
+                report.error = Some("already exists in the worktree".into());
+                return report;
+            }
+            let result = match rule.strategy {
+                CacheStrategy::Symlink => symlink_dir(&src, &dst),
+                CacheStrategy::Hardlink => link_tree(&src, &dst, &mut report.files).inspect_err(|_| {
+                    // Leave nothing half-linked behind for the setup hook
+                    let _ = fs::remove_dir_all(&dst);
+                }),
+                CacheStrategy::SharedDir => unreachable!(),
+            };
+            match result {
+                Ok(()) => report.linked = true,
+                Err(e) => {
+                    log::warn!("share {} with {}: {e:#}", rule.path, worktree.display());
+                    report.error = Some(format!("{e:#}"));
+                }
+            }
+            report
+        })
+        .collect()
+}
+
+/// Environment for sessions in a worktree of `main`, from its `shared_dir`
+/// rules.
+pub fn shared_env(main: &Path, rules: &[CacheRule]) -> Vec<(String, String)> {
+    rules
+        .iter()
+        .filter(|r| r.strategy == CacheStrategy::SharedDir)
+        .filter_map(|r| {
+            let dir = main.join(&r.path);
+            Some((r.env.clone()?, dir.to_string_lossy().to_string()))
+        })
+        .collect()
+}
+
+/// Recreate `src` at `dst`, hard-linking files and copying symlinks as-is.
+fn link_tree(src: &Path, dst: &Path, files: &mut u64) -> Result<()> {
+    fs::create_dir(dst).with_context(|| format!("create {}", dst.display()))?;
+    for entry in fs::read_dir(src).with_context(|| format!("read {}", src.display()))? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            link_tree(&from, &to, files)?;
+        } else if kind.is_symlink() {
+            // pnpm's node_modules is mostly relative symlinks into `.pnpm`
+            let target = fs::read_link(&from)?;
+            if from.is_dir() {
+                symlink_dir(&target, &to)?;
+            } else {
+                symlink_file(&target, &to)?;
+            }
+        } else {
+            fs::hard_link(&from, &to).with_context(|| {
+                format!("hard-link {} (is the worktree on another filesystem?)", from.display())
+            })?;
+            *files += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link).with_context(|| format!("link {}", link.display()))
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> Result<()> {
+    symlink_dir(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(target: &Path, link: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+        .with_context(|| format!("link {}", link.display()))
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+        .with_context(|| format!("link {}", link.display()))
+}
@@ -0,0 +1,188 @@
+//! Detached sessions: agents that outlive the app.
+//!
+//! A detached session runs under its own tmux server (`tmux -L
+//! pi-builder-<id>`) and its PTY only holds a tmux client. When the app
+//! exits the client goes, the server and the agent stay, and on the next
+//! launch the host attaches a new client to every detached session in
+//! `sessions.json` whose server is still up, under the same session id.
+//!
+//! A server per session starts from the session's env, so values don't
+//! appear on a command line. tmux runs without its status bar. On attach it
+//! redraws the screen, which stands in for output missed while the app was
+//! closed; scrollback from before is gone. Killing the session kills its
+//! server. The exit code reported is the tmux client's, not the agent's.
+//!
+//! With the `tmux` backend (see `SessionBackend`, chosen per terminal
+//! profile) a session is instead a window of tmux session `pi` on the
+//! user's default tmux server, so `tmux attach -t pi` from any terminal
+//! shows every such session, one window each. The window is created in its
+//! own tmux session `pi-<id>` and linked into `pi`; the app's client
+//! attaches to `pi-<id>`, so it stays on that window whatever other clients
+//! select. The window's env goes to tmux as `-e` arguments (tmux 3.2 or
+//! later). These sessions are detached too: they outlive the app and are
+//! attached again on the next launch.
+
+use crate::{error::PiError, target::Launch};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, process::Command};
+
+/// tmux session the `tmux` backend's windows are linked into.
+pub const SHARED_SESSION: &str = "pi";
+
+/// What a session's terminal runs under.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionBackend {
+    /// A PTY of the app's own; with `detached`, a tmux server of its own.
+    #[default]
+    Pty,
+    /// A window of the shared `pi` tmux session.
+    Tmux,
+}
+
+const CONFIG: &str = "\
+set -g status off
+set -sg escape-time 0
+set -g default-terminal xterm-256color
+";
+
+fn socket(session_id: &str) -> String {
+    format!("pi-builder-{session_id}")
+}
+
+pub fn available() -> bool {
+    Command::new("tmux").arg("-V").output().is_ok_and(|o| o.status.success())
+}
+
+/// Whether the session's tmux server is still running it.
+pub fn is_running(session_id: &str) -> bool {
+    Command::new("tmux")
+        .args(["-L", &socket(session_id), "has-session"])
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Command line for a new detached session running `cmd` (the user's shell
+/// if empty), or, with `attach`, for a client of a surviving one.
+pub fn launch(
+    session_id: &str,
+    cmd: &[String],
+    cwd: Option<String>,
+    attach: bool,
+) -> Result<Launch> {
+    if !available() {
+        return Err(PiError::invalid_input("detached sessions need tmux on the PATH").into());
+    }
+    let socket = socket(session_id);
+    let mut argv: Vec<String> = vec!["tmux".into(), "-L".into(), socket.clone()];
+    if attach {
+        if !is_running(session_id) {
+            return Err(PiError::invalid_input("detached session is no longer running").into());
+        }
+        argv.push("attach-session".into());
+    } else {
+        argv.extend(["-f".into(), config_file()?.to_string_lossy().into_owned()]);
+        argv.push("new-session".into());
+        argv.extend(cmd.iter().cloned());
+    }
+    let cleanup = vec!["tmux".into(), "-L".into(), socket, "kill-server".into()];
+    Ok(Launch { cmd: argv, cwd, env: Vec::new(), cleanup: Some(cleanup) })
+}
+
+fn shared_name(session_id: &str) -> String {
+    format!("{SHARED_SESSION}-{session_id}")
+}
+
+/// Whether the session's window in the shared tmux session is still there.
+pub fn is_running_shared(session_id: &str) -> bool {
+    tmux(&["has-session", "-t", &format!("={}", shared_name(session_id))]).is_ok()
+}
+
+/// Command line for a client of a session's window in the shared tmux
+/// session, creating the window first running `cmd` (the user's shell if
+/// empty) unless `attach`.
+pub fn launch_shared(
+    session_id: &str,
+    window_name: &str,
+    cmd: &[String],
+    cwd: Option<String>,
+    env: &[(String, String)],
+    size: (u16, u16),
+    attach: bool,
+) -> Result<Launch> {
+    if !available() {
+        return Err(PiError::invalid_input("the tmux backend needs tmux on the PATH").into());
+    }
+    let name = shared_name(session_id);
+    let target = format!("={name}");
+    if attach {
+        if !is_running_shared(session_id) {
+            return Err(PiError::invalid_input("tmux session is no longer running").into());
+        }
+    } else {
+        create_window(&name, window_name, cmd, cwd.as_deref(), env, size)?;
+    }
+    let argv = vec!["tmux".into(), "attach-session".into(), "-t".into(), target.clone()];
+    // Killing the window drops it from `pi` too; its own session goes with it
+    let cleanup = vec!["tmux".into(), "kill-window".into(), "-t".into(), format!("{target}:")];
+    Ok(Launch { cmd: argv, cwd, env: Vec::new(), cleanup: Some(cleanup) })
+}
+
+/// Start tmux session `name` with one window running `cmd` and link that
+/// window into the shared session, creating it if need be.
+fn create_window(
+    name: &str,
+    window_name: &str,
+    cmd: &[String],
+    cwd: Option<&str>,
+    env: &[(String, String)],
+    (cols, rows): (u16, u16),
+) -> Result<()> {
+    let (cols, rows) = (cols.to_string(), rows.to_string());
+    let mut args = vec!["new-session", "-d", "-s", name, "-n", window_name, "-x", cols.as_str()];
+    args.extend(["-y", rows.as_str()]);
+    if let Some(dir) = cwd {
+        args.extend(["-c", dir]);
+    }
+    let env: Vec<String> = env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    for pair in &env {
+        args.extend(["-e", pair.as_str()]);
+    }
+    if !cmd.is_empty() {
+        args.push("--");
+        args.extend(cmd.iter().map(String::as_str));
+    }
+    tmux(&args)?;
+    // The app's client has the terminal to itself; `pi` keeps its status bar
+    tmux(&["set-option", "-t", &format!("={name}"), "status", "off"])?;
+
+    let source = format!("={name}:");
+    let shared = format!("={SHARED_SESSION}");
+    if tmux(&["has-session", "-t", &shared]).is_ok() {
+        tmux(&["link-window", "-d", "-s", &source, "-t", &format!("{shared}:")])?;
+    } else {
+        // A new session always comes with a window; swap ours in for it
+        let placeholder =
+            tmux(&["new-session", "-d", "-s", SHARED_SESSION, "-P", "-F", "#{window_id}"])?;
+        tmux(&["link-window", "-d", "-s", &source, "-t", &format!("{shared}:")])?;
+        tmux(&["kill-window", "-t", placeholder.trim()])?;
+    }
+    Ok(())
+}
+
+/// Run a tmux command on the default server; its stdout.
+fn tmux(args: &[&str]) -> Result<String> {
+    let out = Command::new("tmux").args(args).output().context("run tmux")?;
+    if !out.status.success() {
+        let command = args.first().copied().unwrap_or_default();
+        bail!("tmux {command}: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+fn config_file() -> Result<PathBuf> {
+    let path = std::env::temp_dir().join("pi-builder-tmux.conf");
+    std::fs::write(&path, CONFIG).with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
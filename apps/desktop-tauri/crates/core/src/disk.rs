@@ -0,0 +1,153 @@
+//! Disk usage of worktrees.
+//!
+//! Parallel worktrees each carry their own `node_modules`, `target/` and
+//! virtualenvs, and together they fill disks quickly. `usage` walks a
+//! worktree and breaks the total down by top-level entry, flagging build
+//! and dependency directories that can be deleted and regenerated.
+//!
+//! Walking a large tree takes seconds, so `worktree_list` only reports the
+//! last computed total from `SizeCache`, which recomputes stale entries on
+//! a background thread. Symlinks aren't followed and hard-linked files are
+//! counted once.
+
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a computed size is reused before it is recomputed.
+const SIZE_TTL: Duration = Duration::from_secs(300);
+
+/// Top-level directories that are generated and safe to delete.
+const REGENERABLE: &[&str] = &[
+    "node_modules",
+    "target",
+    ".venv",
+    "venv",
+    "dist",
+    "build",
+    ".next",
+    ".turbo",
+    ".gradle",
+    "__pycache__",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryUsage {
+    /// Path relative to the worktree root.
+    pub path: String,
+    pub bytes: u64,
+    /// Build output or dependencies that can be regenerated.
+    pub regenerable: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskUsage {
+    pub total_bytes: u64,
+    pub files: u64,
+    /// Bytes in regenerable directories: what a cleanup would free.
+    pub reclaimable_bytes: u64,
+    /// Top-level entries, largest first.
+    pub entries: Vec<EntryUsage>,
+}
+
+/// Walk `root` and total its size.
+pub fn usage(root: &Path) -> DiskUsage {
+    let mut seen = HashSet::new();
+    let mut files = 0;
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(root).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let bytes = size_of(&entry.path(), &mut seen, &mut files);
+        let regenerable = REGENERABLE.contains(&name.as_str());
+        entries.push(EntryUsage { path: name, bytes, regenerable });
+    }
+    entries.sort_by_key(|e| Reverse(e.bytes));
+    DiskUsage {
+        total_bytes: entries.iter().map(|e| e.bytes).sum(),
+        files,
+        reclaimable_bytes: entries.iter().filter(|e| e.regenerable).map(|e| e.bytes).sum(),
+        entries,
+    }
+}
+
+/// Bytes under `path`, counting each hard-linked file once.
+fn size_of(path: &Path, seen: &mut HashSet<(u64, u64)>, files: &mut u64) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else { return 0 };
+    if meta.is_dir() {
+        return fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|e| size_of(&e.path(), seen, files))
+            .sum();
+    }
+    if !meta.is_file() {
+        return 0;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if meta.nlink() > 1 && !seen.insert((meta.dev(), meta.ino())) {
+            return 0;
+        }
+    }
+    *files += 1;
+    meta.len()
+}
+
+struct Cached {
+    bytes: Option<u64>,
+    computed: Option<Instant>,
+    running: bool,
+}
+
+/// Last known total size per worktree path, refreshed in the background.
+#[derive(Default)]
+pub struct SizeCache {
+    entries: Arc<Mutex<HashMap<PathBuf, Cached>>>,
+}
+
+impl SizeCache {
+    /// Last computed size of `path`. Starts a background recompute if there
+    /// is none yet or it is older than `SIZE_TTL`.
+    pub fn size(&self, path: &Path) -> Option<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let cached = entries
+            .entry(path.to_path_buf())
+            .or_insert(Cached { bytes: None, computed: None, running: false });
+        let stale = cached.computed.map_or(true, |at| at.elapsed() >= SIZE_TTL);
+        if stale && !cached.running {
+            cached.running = true;
+            let entries = self.entries.clone();
+            let path = path.to_path_buf();
+            thread::spawn(move || {
+                let bytes = usage(&path).total_bytes;
+                entries.lock().unwrap().insert(
+                    path,
+                    Cached { bytes: Some(bytes), computed: Some(Instant::now()), running: false },
+                );
+            });
+        }
+        cached.bytes
+    }
+
+    /// Record a size computed elsewhere (e.g. by `worktree_disk_usage`).
+    pub fn store(&self, path: &Path, bytes: u64) {
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            Cached { bytes: Some(bytes), computed: Some(Instant::now()), running: false },
+        );
+    }
+
+    /// Forget worktrees that no longer exist.
+    pub fn retain(&self, paths: &[PathBuf]) {
+        self.entries.lock().unwrap().retain(|p, c| c.running || paths.contains(p));
+    }
+}
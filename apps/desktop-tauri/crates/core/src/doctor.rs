@@ -0,0 +1,248 @@
+//! Environment diagnostics.
+//!
+//! `run` checks what sessions and worktrees depend on and reports each
+//! check with what to do about a failure, so the UI can show a setup
+//! problem before a spawn fails on it: the git CLI (fetch, clone, LFS,
+//! submodules and exports shell out to it), libgit2 and its features, the
+//! default shell, opening a PTY, terminfo for `TERM`, write access to where
+//! worktrees go, the OS keychain, and each agent profile's binary. An agent
+//! that isn't installed is a warning, not a failure; nobody needs every
+//! agent.
+
+use crate::{
+    agents::{self, AgentProfile},
+    pty::{self, ShellConfig},
+    secrets,
+    settings::Settings,
+    termenv::{self, TermEnv},
+    worktree,
+};
+use portable_pty::{native_pty_system, PtySize};
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    /// Stable id, e.g. `git` or `agent:claude`.
+    pub id: String,
+    pub status: CheckStatus,
+    /// What was found, or what went wrong.
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(id: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { id: id.into(), status: CheckStatus::Ok, detail: detail.into(), fix: None }
+    }
+
+    fn problem(
+        id: impl Into<String>,
+        status: CheckStatus,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self { id: id.into(), status, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+    /// No check failed; warnings allowed.
+    pub ok: bool,
+}
+
+/// Run every check. `repo` is the current repo, if any, for the worktree
+/// location.
+pub fn run(settings: &Settings, repo: Option<&str>) -> DoctorReport {
+    let mut checks = vec![git_cli(), libgit2(), shell(&settings.shell), pty_backend()];
+    checks.extend(terminfo(&settings.terminal_env));
+    checks.extend(worktree_root(settings.worktree_root.as_deref(), repo));
+    checks.push(keychain());
+    checks.extend(agents::all_profiles(&settings.agent_profiles).iter().filter_map(agent));
+    let ok = checks.iter().all(|c| c.status != CheckStatus::Fail);
+    DoctorReport { checks, ok }
+}
+
+fn git_cli() -> Check {
+    match Command::new("git").arg("--version").output() {
+        Ok(out) if out.status.success() => {
+            Check::ok("git", String::from_utf8_lossy(&out.stdout).trim())
+        }
+        Ok(out) => Check::problem(
+            "git",
+            CheckStatus::Warn,
+            format!("`git --version` failed: {}", String::from_utf8_lossy(&out.stderr).trim()),
+            "Reinstall git",
+        ),
+        Err(e) => Check::problem(
+            "git",
+            CheckStatus::Warn,
+            format!("git not found: {e}"),
+            "Install git and put it on PATH; fetch, clone, LFS, submodules and exports need it",
+        ),
+    }
+}
+
+fn libgit2() -> Check {
+    let version = git2::Version::get();
+    let (major, minor, patch) = version.libgit2_version();
+    let mut features = Vec::new();
+    for (name, on) in
+        [("https", version.https()), ("ssh", version.ssh()), ("threads", version.threads())]
+    {
+        if on {
+            features.push(name);
+        }
+    }
+    Check::ok("libgit2", format!("libgit2 {major}.{minor}.{patch} ({})", features.join(", ")))
+}
+
+fn shell(config: &ShellConfig) -> Check {
+    let program = pty::shell_program(config);
+    match find_program(&program) {
+        Some(path) => Check::ok("shell", path.display().to_string()),
+        None => Check::problem(
+            "shell",
+            CheckStatus::Fail,
+            format!("shell `{program}` not found"),
+            "Set an installed shell in the shell settings",
+        ),
+    }
+}
+
+fn pty_backend() -> Check {
+    match native_pty_system().openpty(PtySize::default()) {
+        Ok(_) => Check::ok("pty", "opened a terminal"),
+        Err(e) => Check::problem(
+            "pty",
+            CheckStatus::Fail,
+            format!("can't open a terminal: {e:#}"),
+            if cfg!(windows) {
+                "Update Windows; sessions need ConPTY (Windows 10 1809 or later)"
+            } else {
+                "Check that /dev/ptmx is available and not exhausted"
+            },
+        ),
+    }
+}
+
+/// A terminfo entry for the configured `TERM`; nothing to check on Windows
+/// or with the check turned off.
+fn terminfo(env: &TermEnv) -> Option<Check> {
+    let term = env.term.trim();
+    if cfg!(windows) || !env.check_terminfo || term.is_empty() {
+        return None;
+    }
+    let dirs = termenv::terminfo_dirs();
+    if dirs.is_empty() {
+        return Some(Check::problem(
+            "terminfo",
+            CheckStatus::Warn,
+            "no terminfo database found",
+            "Install ncurses' terminfo (e.g. ncurses-base or ncurses-terminfo-base)",
+        ));
+    }
+    if termenv::has_entry(&dirs, term) {
+        return Some(Check::ok("terminfo", format!("TERM={term}")));
+    }
+    Some(Check::problem(
+        "terminfo",
+        CheckStatus::Warn,
+        format!("no terminfo entry for {term}; sessions use TERM={}", termenv::usable_term(term)),
+        "Install the full terminfo database (e.g. ncurses-term) for full-screen programs",
+    ))
+}
+
+/// Write access where worktrees are created; nothing to check without a
+/// repo or a root.
+fn worktree_root(root: Option<&str>, repo: Option<&str>) -> Option<Check> {
+    let dir = match (root, repo) {
+        (_, Some(repo)) => worktree::worktree_base_dir(repo, root),
+        (Some(root), None) => PathBuf::from(root),
+        (None, None) => return None,
+    };
+    let probe = dir.join(format!(".pi-builder-probe-{}", std::process::id()));
+    let result = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&probe, b""));
+    let _ = std::fs::remove_file(&probe);
+    Some(match result {
+        Ok(()) => Check::ok("worktree_root", dir.display().to_string()),
+        Err(e) => Check::problem(
+            "worktree_root",
+            CheckStatus::Fail,
+            format!("can't write to {}: {e}", dir.display()),
+            "Pick a writable worktree root in the settings",
+        ),
+    })
+}
+
+fn keychain() -> Check {
+    match secrets::keychain_available() {
+        Ok(()) => Check::ok("keychain", "available"),
+        Err(e) => Check::problem(
+            "keychain",
+            CheckStatus::Warn,
+            format!("{e:#}"),
+            if cfg!(target_os = "linux") {
+                "Start a Secret Service provider such as gnome-keyring to use secrets"
+            } else {
+                "Unlock the OS keychain to use secrets"
+            },
+        ),
+    }
+}
+
+/// The profile's binary on PATH, or `ssh` for remote profiles; `None` for
+/// profiles that run the shell.
+fn agent(profile: &AgentProfile) -> Option<Check> {
+    let id = format!("agent:{}", profile.id);
+    let program = if profile.ssh.is_some() { "ssh" } else { profile.command.first()?.as_str() };
+    Some(match find_program(program) {
+        Some(path) => Check::ok(id, path.display().to_string()),
+        None => Check::problem(
+            id,
+            CheckStatus::Warn,
+            format!("`{program}` not found on PATH"),
+            format!("Install {program} to use the {} agent", profile.id),
+        ),
+    })
+}
+
+/// Resolve `program` as the OS would: paths as given, bare names on PATH
+/// (trying PATHEXT extensions on Windows).
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let extensions: Vec<OsString> = if cfg!(windows) {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+        std::iter::once(OsString::new()).chain(pathext.split(';').map(OsString::from)).collect()
+    } else {
+        vec![OsString::new()]
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+        extensions.iter().find_map(|ext| {
+            let mut name = OsString::from(program);
+            name.push(ext);
+            let candidate = dir.join(name);
+            candidate.is_file().then_some(candidate)
+        })
+    })
+}
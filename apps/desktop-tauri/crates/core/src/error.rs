@@ -0,0 +1,152 @@
+//! Error type returned by every Tauri command.
+//!
+//! Serialized as `{ code, message, detail }` so the frontend can branch on
+//! `code` (offer "pick a repo", "resolve conflicts", "re-authenticate", ...)
+//! instead of matching message text. Backend modules keep using `anyhow`;
+//! they return a `PiError` inside it where the code matters, and everything
+//! else is classified from the error chain (git2 / io errors).
+
+use serde::Serialize;
+use std::{error::Error as StdError, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    RepoNotConfigured,
+    SessionNotFound,
+    WorktreeNotFound,
+    TaskNotFound,
+    SnapshotNotFound,
+    NotFound,
+    /// A path resolved outside the worktree it was scoped to.
+    PathOutsideWorktree,
+    /// A path the OS or git can't use; the message says why.
+    InvalidPath,
+    InvalidInput,
+    /// A review state change the workflow doesn't allow.
+    InvalidTransition,
+    /// Too many sessions running to start another.
+    SessionLimit,
+    /// A worktree changed protected paths.
+    ProtectedPaths,
+    /// A lifecycle hook refused the operation; see `hooks.rs`.
+    HookRejected,
+    /// An agent would start in the main checkout; see `mainguard.rs`.
+    MainCheckout,
+    /// Another operation holds the worktree; `detail` names it.
+    WorktreeBusy,
+    MergeConflict,
+    GitAuth,
+    Git,
+    Io,
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Full cause chain, when it adds anything to `message`.
+    pub detail: Option<String>,
+}
+
+impl PiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), detail: None }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn repo_not_configured() -> Self {
+        Self::new(ErrorCode::RepoNotConfigured, "no repo configured")
+    }
+
+    pub fn session_not_found(id: &str) -> Self {
+        Self::new(ErrorCode::SessionNotFound, format!("session not found: {id}"))
+    }
+
+    pub fn worktree_not_found(name: &str) -> Self {
+        Self::new(ErrorCode::WorktreeNotFound, format!("worktree not found: {name}"))
+    }
+
+    /// `name` is `None` for the main checkout.
+    pub fn worktree_busy(name: Option<&str>, operation: &str) -> Self {
+        let what = match name {
+            Some(name) => format!("worktree {name}"),
+            None => "main checkout".to_string(),
+        };
+        Self::new(ErrorCode::WorktreeBusy, format!("{what} is busy: {operation} in progress"))
+            .with_detail(operation)
+    }
+
+    pub fn task_not_found(id: &str) -> Self {
+        Self::new(ErrorCode::TaskNotFound, format!("task not found: {id}"))
+    }
+
+    pub fn snapshot_not_found(id: &str) -> Self {
+        Self::new(ErrorCode::SnapshotNotFound, format!("snapshot not found: {id}"))
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    pub fn invalid_path(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidPath, message)
+    }
+}
+
+impl fmt::Display for PiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for PiError {}
+
+impl From<anyhow::Error> for PiError {
+    fn from(err: anyhow::Error) -> Self {
+        let full = format!("{err:#}");
+        let detail = (err.chain().count() > 1).then_some(full);
+        if let Some(pi) = err.chain().find_map(|e| e.downcast_ref::<PiError>()) {
+            return Self { detail: detail.or_else(|| pi.detail.clone()), ..pi.clone() };
+        }
+        let code = err.chain().find_map(classify).unwrap_or(ErrorCode::Internal);
+        Self { code, message: err.to_string(), detail }
+    }
+}
+
+impl From<git2::Error> for PiError {
+    fn from(err: git2::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<std::io::Error> for PiError {
+    fn from(err: std::io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+fn classify(err: &(dyn StdError + 'static)) -> Option<ErrorCode> {
+    if let Some(e) = err.downcast_ref::<git2::Error>() {
+        return Some(match e.code() {
+            git2::ErrorCode::Auth | git2::ErrorCode::Certificate => ErrorCode::GitAuth,
+            git2::ErrorCode::Conflict
+            | git2::ErrorCode::MergeConflict
+            | git2::ErrorCode::Unmerged => ErrorCode::MergeConflict,
+            git2::ErrorCode::NotFound => ErrorCode::NotFound,
+            _ => ErrorCode::Git,
+        });
+    }
+    if let Some(e) = err.downcast_ref::<std::io::Error>() {
+        return Some(match e.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            _ => ErrorCode::Io,
+        });
+    }
+    None
+}
@@ -0,0 +1,14 @@
+//! Event emission abstraction.
+//!
+//! Backend subsystems emit through an `EventSink` rather than holding an
+//! `AppHandle`, so they run without a Tauri app (tests, CLI, server). The
+//! desktop app implements it on top of Tauri's emitter.
+
+use serde_json::Value;
+use std::sync::Arc;
+
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: Value);
+}
+
+pub type SharedSink = Arc<dyn EventSink>;
@@ -0,0 +1,159 @@
+//! Exporting a worktree's work for people without access to the remote.
+//!
+//! - `tarball`: a `.tar.gz` of the worktree's files under a `<name>/`
+//!   directory, as they are on disk, without ignored files.
+//! - `patch`: one patch with everything changed since the branch forked off
+//!   the main checkout's branch, uncommitted and untracked files included.
+//!   Applies with `git apply`.
+//! - `patch_series`: `git format-patch` of the commits since the fork
+//!   point, one file each, into a directory. Uncommitted changes aren't in
+//!   it.
+
+use crate::{error::PiError, worktree};
+use anyhow::{bail, Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use git2::{DiffFormat, DiffOptions, Oid, Repository};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    process::Command,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Tarball,
+    Patch,
+    PatchSeries,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportResult {
+    pub format: ExportFormat,
+    /// The file written, or the directory for a patch series.
+    pub path: String,
+    /// Files in the tarball, or patch files in the series.
+    pub files: Vec<String>,
+    pub bytes: u64,
+}
+
+/// Export worktree `name` to `dest`, which must be absolute. Existing files
+/// are overwritten; a patch series directory is created if needed.
+pub fn export(
+    main: &Repository,
+    name: &str,
+    format: ExportFormat,
+    dest: &Path,
+) -> Result<ExportResult> {
+    if !dest.is_absolute() {
+        return Err(PiError::invalid_input("export path must be absolute").into());
+    }
+    let wt = worktree::open_in(main, name)?;
+    let workdir = wt.workdir().context("worktree has no workdir")?.to_path_buf();
+    let (files, bytes) = match format {
+        ExportFormat::Tarball => tarball(&workdir, name, dest)?,
+        ExportFormat::Patch => squashed_patch(main, &wt, dest)?,
+        ExportFormat::PatchSeries => patch_series(main, &wt, &workdir, dest)?,
+    };
+    Ok(ExportResult { format, path: dest.to_string_lossy().into_owned(), files, bytes })
+}
+
+/// Where the worktree's branch forked off the main checkout's HEAD.
+fn fork_point(main: &Repository, wt: &Repository) -> Result<Oid> {
+    let head = wt.head()?.peel_to_commit()?.id();
+    let main_head = main.head()?.peel_to_commit()?.id();
+    Ok(main.merge_base(main_head, head).unwrap_or(main_head))
+}
+
+fn tarball(workdir: &Path, name: &str, dest: &Path) -> Result<(Vec<String>, u64)> {
+    let file = File::create(dest).with_context(|| format!("create {}", dest.display()))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(BufWriter::new(file), Compression::default()));
+    tar.follow_symlinks(false);
+    let mut files = Vec::new();
+    let walk = ignore::WalkBuilder::new(workdir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+    for entry in walk {
+        let entry = entry.context("walk worktree")?;
+        if entry.depth() == 0 || entry.file_type().is_some_and(|t| t.is_dir()) {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(workdir).context("path outside worktree")?;
+        tar.append_path_with_name(entry.path(), Path::new(name).join(rel))
+            .with_context(|| format!("add {}", rel.display()))?;
+        files.push(rel.to_string_lossy().replace('\\', "/"));
+    }
+    let mut out = tar.into_inner()?.finish()?;
+    out.flush()?;
+    files.sort();
+    Ok((files, std::fs::metadata(dest)?.len()))
+}
+
+fn squashed_patch(main: &Repository, wt: &Repository, dest: &Path) -> Result<(Vec<String>, u64)> {
+    let base = wt.find_commit(fork_point(main, wt)?)?.tree()?;
+    let mut opts = DiffOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true)
+        .show_binary(true);
+    let diff = wt
+        .diff_tree_to_workdir_with_index(Some(&base), Some(&mut opts))
+        .context("diff worktree against fork point")?;
+    if diff.deltas().len() == 0 {
+        return Err(PiError::invalid_input("no changes since the branch forked").into());
+    }
+    let mut patch = Vec::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    std::fs::write(dest, &patch).with_context(|| format!("write {}", dest.display()))?;
+    let files = diff
+        .deltas()
+        .filter_map(|d| d.new_file().path().or(d.old_file().path()))
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .collect();
+    Ok((files, patch.len() as u64))
+}
+
+fn patch_series(
+    main: &Repository,
+    wt: &Repository,
+    workdir: &Path,
+    dest: &Path,
+) -> Result<(Vec<String>, u64)> {
+    let base = fork_point(main, wt)?;
+    if wt.head()?.peel_to_commit()?.id() == base {
+        return Err(PiError::invalid_input("no commits since the branch forked").into());
+    }
+    std::fs::create_dir_all(dest).with_context(|| format!("create {}", dest.display()))?;
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(workdir)
+        .arg("format-patch")
+        .arg("-o")
+        .arg(dest)
+        .arg(format!("{base}..HEAD"))
+        .output()
+        .context("run git format-patch")?;
+    if !out.status.success() {
+        bail!("git format-patch: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    let mut files = Vec::new();
+    let mut bytes = 0;
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        let path = Path::new(line.trim());
+        bytes += std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if let Some(file) = path.file_name() {
+            files.push(file.to_string_lossy().into_owned());
+        }
+    }
+    Ok((files, bytes))
+}
@@ -0,0 +1,217 @@
+//! File access scoped to a worktree.
+//!
+//! The review UI needs file contents without giving the webview blanket
+//! filesystem access. Every path is resolved relative to the worktree root,
+//! canonicalized (following symlinks), and rejected if it ends up outside.
+//! The few files the webview names for writing go through `resolve_new`.
+
+use crate::{
+    codec::DataEncoding,
+    error::{ErrorCode, PiError},
+};
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// Files larger than this are returned truncated.
+pub const MAX_READ_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileStat {
+    /// Path relative to the worktree root, `/`-separated.
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified_ms: Option<u64>,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileContent {
+    pub path: String,
+    pub size: u64,
+    /// `utf8` for text, `base64` when the file isn't valid UTF-8.
+    pub encoding: DataEncoding,
+    pub content: String,
+    pub truncated: bool,
+}
+
+/// Resolve `rel` inside `root`, refusing anything that escapes it.
+pub fn resolve(root: &Path, rel: &str) -> Result<PathBuf> {
+    let root = root.canonicalize().context("worktree root")?;
+    let rel = Path::new(rel);
+    if rel.is_absolute() || rel.components().any(|c| matches!(c, Component::Prefix(_))) {
+        return Err(PiError::new(
+            ErrorCode::PathOutsideWorktree,
+            "path must be relative to the worktree",
+        )
+        .into());
+    }
+    let full = root.join(rel).canonicalize().context("path not found")?;
+    if !full.starts_with(&root) {
+        return Err(escapes());
+    }
+    Ok(full)
+}
+
+/// Resolve `rel` inside `root` for a file about to be written, creating
+/// its parent directories. Refuses anything that escapes `root`, through
+/// `..` or a symlink, before creating anything; `rel` needn't exist.
+pub fn resolve_new(root: &Path, rel: &str) -> Result<PathBuf> {
+    check_relative(rel)?;
+    let root = root.canonicalize().context("worktree root")?;
+    let rel = Path::new(rel);
+    let mut dir = root.clone();
+    for component in rel.parent().into_iter().flat_map(Path::components) {
+        dir.push(component);
+        if !dir.exists() {
+            fs::create_dir(&dir)?;
+        }
+        // An existing directory may be a symlink out of the worktree
+        dir = dir.canonicalize()?;
+        if !dir.starts_with(&root) {
+            return Err(escapes());
+        }
+    }
+    let full = dir.join(rel.file_name().context("path names no file")?);
+    if fs::symlink_metadata(&full).is_ok_and(|m| m.file_type().is_symlink()) {
+        return Err(escapes());
+    }
+    Ok(full)
+}
+
+/// Fail unless `rel` names a file below wherever it is joined: no root,
+/// drive or `..`.
+pub fn check_relative(rel: &str) -> Result<()> {
+    let path = Path::new(rel);
+    let below = path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !below || path.file_name().is_none() {
+        return Err(PiError::new(
+            ErrorCode::PathOutsideWorktree,
+            "path must be relative to the worktree, without `..`",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn escapes() -> anyhow::Error {
+    PiError::new(ErrorCode::PathOutsideWorktree, "path escapes the worktree").into()
+}
+
+pub fn stat(root: &Path, rel: &str) -> Result<FileStat> {
+    let full = resolve(root, rel)?;
+    // The entry `rel` names, which may be a symlink, not where it leads;
+    // its directory must be inside the worktree as well
+    let rel_path = Path::new(rel);
+    let entry = match (rel_path.parent(), rel_path.file_name()) {
+        (Some(parent), Some(name)) => resolve(root, &parent.to_string_lossy())?.join(name),
+        _ => full,
+    };
+    let meta = fs::symlink_metadata(&entry)?;
+    Ok(FileStat {
+        path: relative(root, &entry),
+        kind: kind_of(&meta.file_type()),
+        size: meta.len(),
+        modified_ms: meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64),
+        readonly: meta.permissions().readonly(),
+    })
+}
+
+/// List a directory: directories first, then files, each alphabetical.
+pub fn list_dir(root: &Path, rel: &str) -> Result<Vec<DirEntryInfo>> {
+    let full = resolve(root, rel)?;
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&full).context("read dir")?.flatten() {
+        let Ok(meta) = entry.metadata() else { continue };
+        let kind = entry.file_type().map(|t| kind_of(&t)).unwrap_or(EntryKind::Other);
+        entries.push(DirEntryInfo {
+            name: entry.file_name().to_string_lossy().to_string(),
+            path: relative(root, &entry.path()),
+            kind,
+            size: meta.len(),
+        });
+    }
+    entries.sort_by(|a, b| {
+        (a.kind != EntryKind::Dir, &a.name).cmp(&(b.kind != EntryKind::Dir, &b.name))
+    });
+    Ok(entries)
+}
+
+pub fn read_file(root: &Path, rel: &str) -> Result<FileContent> {
+    use std::io::Read;
+
+    let full = resolve(root, rel)?;
+    let meta = fs::metadata(&full)?;
+    if !meta.is_file() {
+        return Err(PiError::invalid_input("not a file").into());
+    }
+    let mut bytes = Vec::new();
+    fs::File::open(&full)?
+        .take(MAX_READ_BYTES)
+        .read_to_end(&mut bytes)?;
+    let truncated = meta.len() > MAX_READ_BYTES;
+
+    let (encoding, content) = match String::from_utf8(bytes) {
+        Ok(text) => (DataEncoding::Utf8, text),
+        Err(e) => (
+            DataEncoding::Base64,
+            base64::engine::general_purpose::STANDARD.encode(e.into_bytes()),
+        ),
+    };
+    Ok(FileContent {
+        path: relative(root, &full),
+        size: meta.len(),
+        encoding,
+        content,
+        truncated,
+    })
+}
+
+fn kind_of(t: &fs::FileType) -> EntryKind {
+    if t.is_symlink() {
+        EntryKind::Symlink
+    } else if t.is_dir() {
+        EntryKind::Dir
+    } else if t.is_file() {
+        EntryKind::File
+    } else {
+        EntryKind::Other
+    }
+}
+
+fn relative(root: &Path, full: &Path) -> String {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    full.strip_prefix(&root)
+        .unwrap_or(full)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
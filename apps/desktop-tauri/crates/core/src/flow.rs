@@ -0,0 +1,320 @@
+//! Bounded output queue between a session's PTY reader and its emitter.
+//!
+//! The reader pushes redacted output; a separate emitter thread pops it and
+//! sends `pty://data` events. With flow control on, the frontend acks the
+//! bytes it has rendered (`pty_ack`) and the emitter keeps at most
+//! `window_bytes` unacknowledged, suspending until acks make room.
+//!
+//! The emitter sends at most `max_events_per_sec` events per session; output
+//! arriving in between is merged into the next event, so a chatty session
+//! sends fewer, larger events instead of flooding the webview. When the
+//! queue reaches `max_queue_bytes` the overflow policy decides what happens:
+//!
+//! - `pause`: the reader stops reading, the kernel PTY buffer fills, and the
+//!   child blocks on write until the frontend catches up.
+//! - `drop`: output is discarded and a marker with the dropped byte count is
+//!   emitted in its place.
+//! - `spill`: output goes to a temp file and is replayed in order.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Largest chunk read back from a spill file at once.
+const SPILL_READ_BYTES: usize = 64 * 1024;
+
+/// Most output merged into one event.
+const MAX_EVENT_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    #[default]
+    Pause,
+    Drop,
+    Spill,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlowSettings {
+    pub policy: OverflowPolicy,
+    pub max_queue_bytes: usize,
+    /// Unacknowledged bytes allowed in flight for flow-controlled sessions.
+    pub window_bytes: usize,
+    /// Per session; 0 emits every chunk as soon as it is read.
+    pub max_events_per_sec: u32,
+}
+
+impl Default for FlowSettings {
+    fn default() -> Self {
+        Self {
+            policy: OverflowPolicy::default(),
+            max_queue_bytes: 4 * 1024 * 1024,
+            window_bytes: 1024 * 1024,
+            max_events_per_sec: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub queued_bytes: usize,
+    pub queued_chunks: usize,
+    pub in_flight_bytes: usize,
+    /// Waiting in the spill file.
+    pub spilled_bytes: u64,
+    /// Dropped since the session started.
+    pub dropped_bytes: u64,
+    pub emitted_bytes: u64,
+    /// The reader is blocked on a full queue.
+    pub paused: bool,
+}
+
+/// An item for the emitter: output, or a note that output was dropped.
+pub enum Chunk {
+    Data(Vec<u8>),
+    Dropped(u64),
+}
+
+pub struct OutputQueue {
+    settings: FlowSettings,
+    flow_control: bool,
+    spill_path: PathBuf,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    chunks: VecDeque<Vec<u8>>,
+    bytes: usize,
+    in_flight: usize,
+    /// Dropped since the last marker was emitted.
+    pending_dropped: u64,
+    stats: QueueStats,
+    spill: Option<Spill>,
+    closed: bool,
+    aborted: bool,
+}
+
+struct Spill {
+    file: File,
+    read_pos: u64,
+    write_pos: u64,
+}
+
+impl OutputQueue {
+    pub fn new(session_id: &str, settings: FlowSettings, flow_control: bool) -> Self {
+        let spill_path = std::env::temp_dir().join(format!("pi-builder-spill-{session_id}"));
+        Self {
+            settings,
+            flow_control,
+            spill_path,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Queue output from the reader, applying the overflow policy.
+    pub fn push(&self, bytes: Vec<u8>) {
+        if bytes.is_empty() {
+            return;
+        }
+        let max = self.settings.max_queue_bytes.max(1);
+        let mut state = self.state.lock().unwrap();
+        if state.aborted {
+            return;
+        }
+        match self.settings.policy {
+            OverflowPolicy::Pause => {
+                while state.bytes > 0 && state.bytes + bytes.len() > max && !state.aborted {
+                    state.stats.paused = true;
+                    state = self.changed.wait(state).unwrap();
+                }
+                state.stats.paused = false;
+                if state.aborted {
+                    return;
+                }
+            }
+            OverflowPolicy::Drop => {
+                if state.bytes > 0 && state.bytes + bytes.len() > max {
+                    state.pending_dropped += bytes.len() as u64;
+                    state.stats.dropped_bytes += bytes.len() as u64;
+                    return;
+                }
+            }
+            OverflowPolicy::Spill => {
+                let spilling = state.spill.as_ref().is_some_and(|s| s.read_pos < s.write_pos);
+                if spilling || (state.bytes > 0 && state.bytes + bytes.len() > max) {
+                    match self.spill(&mut state, &bytes) {
+                        Ok(()) => {
+                            self.changed.notify_all();
+                            return;
+                        }
+                        // Can't spill: keep it in memory rather than lose it
+                        Err(e) => log::warn!("spill output: {e}"),
+                    }
+                }
+            }
+        }
+        state.bytes += bytes.len();
+        state.chunks.push_back(bytes);
+        Self::refresh(&mut state);
+        self.changed.notify_all();
+    }
+
+    /// Least time between two events, if rate limited.
+    pub fn min_interval(&self) -> Option<Duration> {
+        let rate = self.settings.max_events_per_sec;
+        (rate > 0).then(|| Duration::from_secs(1) / rate)
+    }
+
+    /// Next chunk for the emitter: all queued output that fits in one event
+    /// and, with flow control, the window. Blocks until output is available
+    /// and the window has room; `None` once the queue is closed and drained,
+    /// or aborted.
+    pub fn pop(&self) -> Option<Chunk> {
+        let window = self.settings.window_bytes.max(1);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.aborted {
+                return None;
+            }
+            let blocked = self.flow_control && state.in_flight >= window;
+            if !blocked {
+                if state.pending_dropped > 0 {
+                    return Some(Chunk::Dropped(std::mem::take(&mut state.pending_dropped)));
+                }
+                if let Some(mut bytes) = self.next_bytes(&mut state) {
+                    let room = if self.flow_control {
+                        window.saturating_sub(state.in_flight)
+                    } else {
+                        usize::MAX
+                    };
+                    let limit = room.min(MAX_EVENT_BYTES);
+                    while let Some(next) = state.chunks.front() {
+                        if bytes.len() + next.len() > limit {
+                            break;
+                        }
+                        let next = state.chunks.pop_front().expect("front checked");
+                        state.bytes -= next.len();
+                        bytes.extend_from_slice(&next);
+                    }
+                    if self.flow_control {
+                        state.in_flight += bytes.len();
+                    }
+                    state.stats.emitted_bytes += bytes.len() as u64;
+                    Self::refresh(&mut state);
+                    self.changed.notify_all();
+                    return Some(Chunk::Data(bytes));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// The frontend has consumed `bytes` of emitted output.
+    pub fn ack(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(bytes);
+        Self::refresh(&mut state);
+        self.changed.notify_all();
+    }
+
+    /// No more output is coming; the emitter drains what is left.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Discard everything and release both reader and emitter.
+    pub fn abort(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.aborted = true;
+        state.chunks.clear();
+        state.bytes = 0;
+        state.spill = None;
+        Self::refresh(&mut state);
+        let _ = std::fs::remove_file(&self.spill_path);
+        self.changed.notify_all();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    fn next_bytes(&self, state: &mut State) -> Option<Vec<u8>> {
+        if let Some(bytes) = state.chunks.pop_front() {
+            state.bytes -= bytes.len();
+            return Some(bytes);
+        }
+        let spill = state.spill.as_mut()?;
+        if spill.read_pos >= spill.write_pos {
+            return None;
+        }
+        let len = (spill.write_pos - spill.read_pos).min(SPILL_READ_BYTES as u64) as usize;
+        let mut buf = vec![0; len];
+        let read = spill
+            .file
+            .seek(SeekFrom::Start(spill.read_pos))
+            .and_then(|_| spill.file.read_exact(&mut buf));
+        if let Err(e) = read {
+            log::warn!("read spilled output: {e}");
+            state.spill = None;
+            return None;
+        }
+        spill.read_pos += len as u64;
+        if spill.read_pos == spill.write_pos {
+            // Fully replayed; start over so the file doesn't grow forever
+            let _ = spill.file.set_len(0);
+            spill.read_pos = 0;
+            spill.write_pos = 0;
+        }
+        Some(buf)
+    }
+
+    fn spill(&self, state: &mut State, bytes: &[u8]) -> std::io::Result<()> {
+        if state.spill.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .truncate(true)
+                .open(&self.spill_path)?;
+            state.spill = Some(Spill { file, read_pos: 0, write_pos: 0 });
+        }
+        let spill = state.spill.as_mut().expect("spill file");
+        spill.file.seek(SeekFrom::Start(spill.write_pos))?;
+        spill.file.write_all(bytes)?;
+        spill.write_pos += bytes.len() as u64;
+        Self::refresh(state);
+        Ok(())
+    }
+
+    fn refresh(state: &mut State) {
+        state.stats.queued_bytes = state.bytes;
+        state.stats.queued_chunks = state.chunks.len();
+        state.stats.in_flight_bytes = state.in_flight;
+        state.stats.spilled_bytes =
+            state.spill.as_ref().map(|s| s.write_pos - s.read_pos).unwrap_or(0);
+    }
+}
+
+impl Drop for OutputQueue {
+    fn drop(&mut self) {
+        if self.state.get_mut().map(|s| s.spill.is_some()).unwrap_or(false) {
+            let _ = std::fs::remove_file(&self.spill_path);
+        }
+    }
+}
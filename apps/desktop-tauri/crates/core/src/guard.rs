@@ -0,0 +1,252 @@
+//! Guarded mode: confirm dangerous commands before they are submitted.
+//!
+//! A guarded session follows the line being typed into it. Keystrokes pass
+//! through as usual; only the Enter that would submit a line matching a
+//! confirm pattern is held back, together with anything typed after it.
+//! The host emits `pty://confirm/<id>` and the held input is written once
+//! `pty_confirm` allows it, or dropped when it doesn't. Lines matching a
+//! deny pattern are never submitted.
+//!
+//! The typed line misses text recalled from shell history or completed by
+//! the application, so with `check_echo` the row under the cursor, as the
+//! terminal shows it, is checked too.
+
+use crate::{error::PiError, paste};
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Longest line followed; longer input keeps only its tail.
+const MAX_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuardSettings {
+    /// Regexes for lines that need confirmation.
+    pub confirm: Vec<String>,
+    /// Regexes for lines that are refused outright.
+    pub deny: Vec<String>,
+    /// Also check the terminal's cursor row, not just the typed text.
+    pub check_echo: bool,
+}
+
+impl Default for GuardSettings {
+    fn default() -> Self {
+        Self {
+            confirm: paste::DESTRUCTIVE.iter().map(|(p, _)| p.to_string()).collect(),
+            deny: Vec::new(),
+            check_echo: true,
+        }
+    }
+}
+
+impl GuardSettings {
+    pub fn validate(&self) -> Result<()> {
+        for p in self.confirm.iter().chain(&self.deny) {
+            if let Err(e) = Regex::new(p) {
+                bail!("invalid guard pattern {p:?}: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiled `GuardSettings`.
+#[derive(Debug, Clone, Default)]
+pub struct GuardRules {
+    confirm: Vec<Regex>,
+    deny: Vec<Regex>,
+    check_echo: bool,
+}
+
+impl GuardRules {
+    /// Invalid patterns are skipped; `GuardSettings::validate` rejects them
+    /// before they are saved.
+    pub fn compile(settings: &GuardSettings) -> Self {
+        let compile = |patterns: &[String]| -> Vec<Regex> {
+            patterns.iter().filter_map(|p| Regex::new(p).ok()).collect()
+        };
+        Self {
+            confirm: compile(&settings.confirm),
+            deny: compile(&settings.deny),
+            check_echo: settings.check_echo,
+        }
+    }
+
+    /// The matched text of each pattern that hits any of `lines`.
+    fn matches(patterns: &[Regex], lines: &[&str]) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        for re in patterns {
+            if let Some(m) = lines.iter().find_map(|l| re.find(l)) {
+                let text = m.as_str().trim().to_string();
+                if !found.contains(&text) {
+                    found.push(text);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// What became of a chunk of input.
+#[derive(Debug, Default)]
+pub struct Filtered {
+    /// Write this now.
+    pub write: String,
+    /// A line was held for confirmation.
+    pub held: Option<Held>,
+    /// A line was refused; its Enter and the rest of the chunk are dropped.
+    pub denied: Option<Denied>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Held {
+    pub token: String,
+    pub line: String,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Denied {
+    pub line: String,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    #[default]
+    Text,
+    Esc,
+    /// Inside a CSI or SS3 sequence (arrow keys, bracketed paste markers).
+    Seq,
+}
+
+/// The line being typed, rebuilt from keystrokes: editing keys and escape
+/// sequences are applied or skipped rather than kept.
+#[derive(Default)]
+pub(crate) struct LineFollower {
+    line: String,
+    scan: Scan,
+}
+
+impl LineFollower {
+    /// Track one typed character. Returns false for Enter.
+    pub(crate) fn follow(&mut self, c: char) -> bool {
+        if matches!(c, '\r' | '\n') {
+            self.scan = Scan::Text;
+            return false;
+        }
+        self.scan = match (self.scan, c) {
+            (_, '\x1b') => Scan::Esc,
+            (Scan::Esc, '[' | 'O') => Scan::Seq,
+            (Scan::Seq, '\x40'..='\x7e') => Scan::Text,
+            (Scan::Seq, _) => Scan::Seq,
+            // Backspace / DEL
+            (_, '\x7f' | '\x08') => {
+                self.line.pop();
+                Scan::Text
+            }
+            // Ctrl-C, Ctrl-U: the line is gone
+            (_, '\x03' | '\x15') => {
+                self.line.clear();
+                Scan::Text
+            }
+            (_, c) if c.is_control() => Scan::Text,
+            (_, c) => {
+                if self.line.len() >= MAX_LINE {
+                    self.line.remove(0);
+                }
+                self.line.push(c);
+                Scan::Text
+            }
+        };
+        true
+    }
+
+    /// The line typed so far, starting a new one.
+    pub(crate) fn take(&mut self) -> String {
+        std::mem::take(&mut self.line)
+    }
+}
+
+/// Per-session line follower and the input held for confirmation.
+pub struct InputGuard {
+    rules: GuardRules,
+    line: LineFollower,
+    /// Held line's token and the input from its Enter on.
+    pending: Option<(Held, String)>,
+}
+
+impl InputGuard {
+    pub fn new(rules: GuardRules) -> Self {
+        Self { rules, line: LineFollower::default(), pending: None }
+    }
+
+    /// The line waiting for confirmation, if any.
+    pub fn held(&self) -> Option<&Held> {
+        self.pending.as_ref().map(|(held, _)| held)
+    }
+
+    /// Filter input typed into the session. `echo` returns the cursor row
+    /// as displayed, for `check_echo`. While a line is held, all input is
+    /// queued behind it.
+    pub fn input(&mut self, data: &str, echo: impl Fn() -> String) -> Filtered {
+        let mut out = Filtered::default();
+        if let Some((_, queued)) = &mut self.pending {
+            queued.push_str(data);
+            return out;
+        }
+        for (i, c) in data.char_indices() {
+            if self.line.follow(c) {
+                continue;
+            }
+            // Enter: decide on the line before it goes out
+            let typed = self.line.take();
+            let shown = if self.rules.check_echo { echo() } else { String::new() };
+            let lines = [typed.as_str(), shown.trim()];
+            let line = if typed.trim().is_empty() { shown.trim() } else { typed.trim() };
+            let denied = GuardRules::matches(&self.rules.deny, &lines);
+            if !denied.is_empty() {
+                out.write.push_str(&data[..i]);
+                out.denied = Some(Denied { line: line.to_string(), reasons: denied });
+                return out;
+            }
+            let reasons = GuardRules::matches(&self.rules.confirm, &lines);
+            if !reasons.is_empty() {
+                let held = Held {
+                    token: uuid::Uuid::new_v4().to_string(),
+                    line: line.to_string(),
+                    reasons,
+                };
+                self.pending = Some((held.clone(), data[i..].to_string()));
+                out.write.push_str(&data[..i]);
+                out.held = Some(held);
+                return out;
+            }
+        }
+        out.write.push_str(data);
+        out
+    }
+
+    /// Release or drop the held line. On allow, its Enter is written and
+    /// the input queued behind it is filtered like new input, by typed text
+    /// only since the screen hasn't caught up with it. On refusal the
+    /// queued input goes too. Fails if `token` isn't the held line.
+    pub fn confirm(&mut self, token: &str, allow: bool) -> Result<Filtered> {
+        match &self.pending {
+            Some((held, _)) if held.token == token => {}
+            _ => return Err(PiError::invalid_input("no input held with that token").into()),
+        }
+        let (_, queued) = self.pending.take().expect("checked above");
+        if !allow {
+            return Ok(Filtered::default());
+        }
+        let mut chars = queued.chars();
+        let enter = chars.next().map(String::from).unwrap_or_default();
+        let mut out = self.input(chars.as_str(), String::new);
+        out.write.insert_str(0, &enter);
+        Ok(out)
+    }
+}
@@ -0,0 +1,122 @@
+//! Syntax highlighting for diffs, so the review pane doesn't ship a
+//! highlighter and grammars for every language to the webview.
+//!
+//! Files get a `language` from their name. With highlighting on, every diff
+//! line also gets `tokens`: its text split into runs with a scope such as
+//! `keyword.control` or `string.quoted`, which the frontend maps to theme
+//! colors. Old and new sides of a hunk are parsed separately, each from
+//! the top of the hunk, so a hunk starting inside a block comment or
+//! string can be highlighted wrong until the construct closes.
+
+use crate::staging::FileDiff;
+use serde::Serialize;
+use std::sync::OnceLock;
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet};
+
+/// Files with more diff lines than this get a language but no tokens.
+const MAX_LINES: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Token {
+    pub text: String,
+    /// First two atoms of the innermost meaningful scope, e.g. `string.quoted`.
+    pub scope: Option<String>,
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn syntax_for(path: &str) -> Option<&'static SyntaxReference> {
+    let set = syntaxes();
+    let name = path.rsplit('/').next()?;
+    let by_ext = name.rsplit_once('.').and_then(|(_, ext)| set.find_syntax_by_extension(ext));
+    // Extension lists also hold whole names like `Makefile`
+    by_ext.or_else(|| set.find_syntax_by_extension(name))
+}
+
+/// Language name for `path`, e.g. `Rust` or `TypeScript`.
+pub fn language(path: &str) -> Option<String> {
+    syntax_for(path).map(|s| s.name.clone())
+}
+
+/// Fill in `tokens` for every line of `files`. Binary, LFS and very large
+/// files and unknown languages are left alone.
+pub fn annotate(files: &mut [FileDiff]) {
+    for file in files.iter_mut().filter(|f| !f.binary) {
+        let Some(syntax) = syntax_for(&file.path) else { continue };
+        let lines: usize = file.hunks.iter().map(|h| h.lines.len()).sum();
+        if lines > MAX_LINES {
+            continue;
+        }
+        for hunk in &mut file.hunks {
+            let (mut old, mut new) = (Side::new(syntax), Side::new(syntax));
+            for line in &mut hunk.lines {
+                line.tokens = match line.origin {
+                    '-' => Some(old.line(&line.content)),
+                    '+' => Some(new.line(&line.content)),
+                    ' ' => {
+                        old.line(&line.content);
+                        Some(new.line(&line.content))
+                    }
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
+/// Parser state for one side of a hunk.
+struct Side {
+    state: ParseState,
+    stack: ScopeStack,
+}
+
+impl Side {
+    fn new(syntax: &SyntaxReference) -> Self {
+        Self { state: ParseState::new(syntax), stack: ScopeStack::new() }
+    }
+
+    fn line(&mut self, content: &str) -> Vec<Token> {
+        // The newline-aware grammars want the line ending
+        let mut text = content.to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let ops = self.state.parse_line(&text, syntaxes()).unwrap_or_default();
+        let text = text.trim_end_matches(['\n', '\r']);
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut pos = 0;
+        for (at, op) in ops {
+            let at = at.min(text.len());
+            if at > pos {
+                push(&mut tokens, &text[pos..at], scope_name(&self.stack));
+                pos = at;
+            }
+            let _ = self.stack.apply(&op);
+        }
+        if pos < text.len() {
+            push(&mut tokens, &text[pos..], scope_name(&self.stack));
+        }
+        tokens
+    }
+}
+
+fn push(tokens: &mut Vec<Token>, text: &str, scope: Option<String>) {
+    match tokens.last_mut() {
+        Some(last) if last.scope == scope => last.text.push_str(text),
+        _ => tokens.push(Token { text: text.to_string(), scope }),
+    }
+}
+
+/// Innermost scope that says something about the text; `source.*` and
+/// `meta.*` only describe where it is.
+fn scope_name(stack: &ScopeStack) -> Option<String> {
+    let skip = |s: &Scope| {
+        let name = s.build_string();
+        name.starts_with("source.") || name.starts_with("text.") || name.starts_with("meta.")
+    };
+    let scope = stack.as_slice().iter().rev().find(|s| !skip(s))?;
+    Some(scope.build_string().split('.').take(2).collect::<Vec<_>>().join("."))
+}
@@ -0,0 +1,210 @@
+//! Commit history of an agent worktree, for the review panel.
+//!
+//! Commits reachable from the worktree HEAD are listed newest first. Ones
+//! not on the base branch (the main checkout's HEAD) are flagged `agent`,
+//! so the UI can tell the agent's work from the history it started on.
+//!
+//! Blame gives the same view per line of a file: which commit last touched
+//! it, by whom, how long ago, and whether that was the agent.
+
+use crate::files;
+use anyhow::{Context, Result};
+use git2::{BlameOptions, Commit, DiffOptions, Oid, Repository, Sort};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Serialize)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author: String,
+    pub email: String,
+    /// Commit time, unix seconds.
+    pub timestamp: i64,
+    pub summary: String,
+    pub message: String,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Not reachable from the base branch.
+    pub agent: bool,
+}
+
+/// Up to `limit` commits on the worktree's branch, newest first, optionally
+/// only those made at or after `since` (unix seconds).
+pub fn log(
+    repo_path: &str,
+    worktree: &str,
+    limit: usize,
+    since: Option<i64>,
+) -> Result<Vec<CommitInfo>> {
+    let main = Repository::open(repo_path).context("open repo")?;
+    let wt_repo = Repository::open(crate::worktree::worktree_path(repo_path, worktree)?)
+        .context("open worktree")?;
+    let head = wt_repo.head()?.peel_to_commit().context("worktree HEAD")?.id();
+    let base = main.head()?.peel_to_commit().context("base HEAD")?.id();
+    let agent_only = agent_commits(&wt_repo, head, base)?;
+
+    let mut walk = wt_repo.revwalk()?;
+    walk.set_sorting(Sort::TIME)?;
+    walk.push(head)?;
+    let mut commits = Vec::new();
+    for oid in walk {
+        if commits.len() >= limit {
+            break;
+        }
+        let commit = wt_repo.find_commit(oid?)?;
+        if since.is_some_and(|s| commit.time().seconds() < s) {
+            // Sorted by time, so everything after is older too
+            break;
+        }
+        commits.push(commit_info(&wt_repo, &commit, agent_only.contains(&commit.id()))?);
+    }
+    Ok(commits)
+}
+
+/// Lines `start..=end`, 1-based.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BlameRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlameLine {
+    /// 1-based.
+    pub line: usize,
+    pub content: String,
+    /// `None` for lines not committed yet.
+    pub sha: Option<String>,
+    pub author: Option<String>,
+    pub email: Option<String>,
+    /// Commit time, unix seconds.
+    pub timestamp: Option<i64>,
+    /// Seconds since the commit.
+    pub age_secs: Option<i64>,
+    pub summary: Option<String>,
+    /// Committed by the agent, or uncommitted.
+    pub agent: bool,
+}
+
+/// Who last changed each line of `path` in the worktree, as it is on disk:
+/// lines changed since the last commit have no commit. `range` limits the
+/// lines returned.
+pub fn blame(
+    repo_path: &str,
+    worktree: &str,
+    path: &str,
+    range: Option<BlameRange>,
+) -> Result<Vec<BlameLine>> {
+    let main = Repository::open(repo_path).context("open repo")?;
+    let root = crate::worktree::worktree_path(repo_path, worktree)?;
+    let wt_repo = Repository::open(&root).context("open worktree")?;
+    let full = files::resolve(&root, path)?;
+    let rel = full.strip_prefix(root.canonicalize()?).context("path outside worktree")?;
+    let bytes = std::fs::read(&full).with_context(|| format!("read {path}"))?;
+    let content = String::from_utf8_lossy(&bytes);
+    let head = wt_repo.head()?.peel_to_commit().context("worktree HEAD")?.id();
+    let base = main.head()?.peel_to_commit().context("base HEAD")?.id();
+    let agent_only = agent_commits(&wt_repo, head, base)?;
+
+    // Blame HEAD's history, then the file as it is on disk on top of it
+    let file_blame = if committed_in(&wt_repo, head, rel)? {
+        let mut opts = BlameOptions::new();
+        opts.newest_commit(head);
+        Some(wt_repo.blame_file(rel, Some(&mut opts)).with_context(|| format!("blame {path}"))?)
+    } else {
+        None
+    };
+    let blame = file_blame.as_ref().map(|b| b.blame_buffer(content.as_bytes())).transpose()?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64)?;
+    let (start, end) = range.map_or((1, usize::MAX), |r| (r.start.max(1), r.end));
+    let mut commits: HashMap<Oid, Option<Commit>> = HashMap::new();
+    let mut lines = Vec::new();
+    for (i, text) in content.lines().enumerate() {
+        let line = i + 1;
+        if line < start {
+            continue;
+        }
+        if line > end {
+            break;
+        }
+        let oid = blame
+            .as_ref()
+            .and_then(|b| b.get_line(line))
+            .map(|h| h.final_commit_id())
+            .filter(|oid| !oid.is_zero());
+        let commit = oid.and_then(|oid| {
+            commits.entry(oid).or_insert_with(|| wt_repo.find_commit(oid).ok()).clone()
+        });
+        let Some(commit) = commit else {
+            lines.push(BlameLine {
+                line,
+                content: text.to_string(),
+                sha: None,
+                author: None,
+                email: None,
+                timestamp: None,
+                age_secs: None,
+                summary: None,
+                agent: true,
+            });
+            continue;
+        };
+        let author = commit.author();
+        let timestamp = commit.time().seconds();
+        lines.push(BlameLine {
+            line,
+            content: text.to_string(),
+            sha: Some(commit.id().to_string()),
+            author: author.name().map(str::to_string),
+            email: author.email().map(str::to_string),
+            timestamp: Some(timestamp),
+            age_secs: Some((now - timestamp).max(0)),
+            summary: commit.summary().map(str::to_string),
+            agent: agent_only.contains(&commit.id()),
+        });
+    }
+    Ok(lines)
+}
+
+/// Whether `path` exists in the commit `head`.
+fn committed_in(repo: &Repository, head: Oid, path: &Path) -> Result<bool> {
+    let tree = repo.find_commit(head)?.tree()?;
+    Ok(tree.get_path(path).is_ok())
+}
+
+fn agent_commits(repo: &Repository, head: Oid, base: Oid) -> Result<HashSet<Oid>> {
+    let mut walk = repo.revwalk()?;
+    walk.push(head)?;
+    walk.hide(base)?;
+    Ok(walk.flatten().collect())
+}
+
+fn commit_info(repo: &Repository, commit: &Commit, agent: bool) -> Result<CommitInfo> {
+    let tree = commit.tree()?;
+    let parent_tree = commit.parent(0).ok().map(|p| p.tree()).transpose()?;
+    let diff = repo.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        Some(&tree),
+        Some(&mut DiffOptions::new()),
+    )?;
+    let stats = diff.stats()?;
+    let author = commit.author();
+    Ok(CommitInfo {
+        sha: commit.id().to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        email: author.email().unwrap_or_default().to_string(),
+        timestamp: commit.time().seconds(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+        message: commit.message().unwrap_or_default().to_string(),
+        files_changed: stats.files_changed(),
+        insertions: stats.insertions(),
+        deletions: stats.deletions(),
+        agent,
+    })
+}
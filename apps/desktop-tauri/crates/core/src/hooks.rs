@@ -0,0 +1,264 @@
+//! Lifecycle hooks: external programs run at points in an operation.
+//!
+//! Hooks are configured in settings as a lifecycle point and a command
+//! (run directly, not through a shell). At its point each hook gets a JSON
+//! object describing the operation on stdin, with `PI_BUILDER_HOOK` set to
+//! the point's name, and has `timeout_secs` (default 10s) to finish.
+//! Hooks of a point run in the order configured.
+//!
+//! - `pre_spawn`: before a session starts. The input has `agentId`, `cmd`,
+//!   `cwd`, `env`, `repo`, `worktree` and `title`. Stdout may hold a JSON
+//!   object replacing `cmd` or `cwd` and adding `env`; later hooks see the
+//!   changes.
+//! - `post_worktree_create`: after a worktree is created, before its setup
+//!   runs, with `repo`, `worktree`, `path` and `branch`. Refusing removes
+//!   the worktree again.
+//! - `pre_merge`: before a worktree is merged into the main checkout, with
+//!   `repo` and `worktree`.
+//! - `post_exit`: after a session exits, with the `pty://exit` payload.
+//!   Runs in the background; it can't change anything.
+//!
+//! A hook refuses the operation by exiting non-zero (its stderr is the
+//! reason) or by printing `{ "veto": "<reason>" }`. A hook that can't be
+//! started, times out or prints something other than a JSON object refuses
+//! too, so a broken hook fails safe. Refusals are `hook_rejected` errors.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    events::{EventSink, SharedSink},
+    pty::SpawnOptions,
+    settings::Settings,
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time a hook gets when it doesn't set `timeout_secs`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a running hook is checked for having exited.
+const POLL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookPoint {
+    PreSpawn,
+    PostWorktreeCreate,
+    PreMerge,
+    PostExit,
+}
+
+impl HookPoint {
+    fn name(self) -> &'static str {
+        match self {
+            Self::PreSpawn => "pre_spawn",
+            Self::PostWorktreeCreate => "post_worktree_create",
+            Self::PreMerge => "pre_merge",
+            Self::PostExit => "post_exit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hook {
+    pub point: HookPoint,
+    /// Program and its arguments.
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+/// What hooks printed, merged in order.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HookResponse {
+    pub veto: Option<String>,
+    pub cmd: Option<Vec<String>>,
+    pub cwd: Option<String>,
+    pub env: HashMap<String, String>,
+}
+
+pub fn validate(hooks: &[Hook]) -> Result<()> {
+    for hook in hooks {
+        if hook.command.first().map_or(true, |p| p.trim().is_empty()) {
+            let message = format!("{} hook has no command", hook.point.name());
+            return Err(PiError::invalid_input(message).into());
+        }
+        if hook.timeout_secs == Some(0) {
+            return Err(PiError::invalid_input("hook timeout must be above 0").into());
+        }
+    }
+    Ok(())
+}
+
+/// Run the hooks for `point` with `input`, which must be a JSON object.
+/// Each later hook gets `input` with the earlier ones' `cmd`, `cwd` and
+/// `env` applied. Fails with `hook_rejected` if one refuses.
+pub fn run(hooks: &[Hook], point: HookPoint, mut input: Value) -> Result<HookResponse> {
+    let mut merged = HookResponse::default();
+    for hook in hooks.iter().filter(|h| h.point == point) {
+        let program = hook.command.first().map(String::as_str).unwrap_or_default();
+        let response = run_one(hook, point, &input).map_err(|e| {
+            let message = format!("{} hook {program} refused: {e:#}", point.name());
+            PiError::new(ErrorCode::HookRejected, message)
+        })?;
+        if let Some(cmd) = response.cmd {
+            input["cmd"] = cmd.clone().into();
+            merged.cmd = Some(cmd);
+        }
+        if let Some(cwd) = response.cwd {
+            input["cwd"] = cwd.clone().into();
+            merged.cwd = Some(cwd);
+        }
+        if !response.env.is_empty() {
+            if !input["env"].is_object() {
+                input["env"] = Value::Object(Map::new());
+            }
+            for (key, value) in response.env {
+                input["env"][&key] = value.clone().into();
+                merged.env.insert(key, value);
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Run the `pre_spawn` hooks for a session about to start with `opts` in
+/// `worktree`, and apply what they print to `opts`.
+pub fn pre_spawn(hooks: &[Hook], opts: &mut SpawnOptions, worktree: Option<&str>) -> Result<()> {
+    if !hooks.iter().any(|h| h.point == HookPoint::PreSpawn) {
+        return Ok(());
+    }
+    let env: Map<String, Value> =
+        opts.env.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect();
+    let input = serde_json::json!({
+        "agentId": opts.agent_id,
+        "cmd": opts.cmd,
+        "cwd": opts.cwd,
+        "env": env,
+        "repo": opts.repo,
+        "worktree": worktree,
+        "title": opts.meta.title,
+    });
+    let response = run(hooks, HookPoint::PreSpawn, input)?;
+    if let Some(cmd) = response.cmd {
+        opts.cmd = cmd;
+    }
+    if let Some(cwd) = response.cwd {
+        opts.cwd = Some(cwd);
+    }
+    for (key, value) in response.env {
+        opts.env.retain(|(k, _)| *k != key);
+        opts.env.push((key, value));
+    }
+    Ok(())
+}
+
+/// Run the hooks for `point` in the background, logging refusals.
+pub fn notify(hooks: &[Hook], point: HookPoint, input: Value) {
+    let hooks: Vec<Hook> = hooks.iter().filter(|h| h.point == point).cloned().collect();
+    if hooks.is_empty() {
+        return;
+    }
+    thread::spawn(move || {
+        if let Err(e) = run(&hooks, point, input) {
+            log::warn!("{e:#}");
+        }
+    });
+}
+
+fn run_one(hook: &Hook, point: HookPoint, input: &Value) -> Result<HookResponse> {
+    let (program, args) = hook.command.split_first().context("no command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .env("PI_BUILDER_HOOK", point.name())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("start")?;
+    // Written and read on threads so a hook that ignores stdin or fills a
+    // pipe can't stall us past the timeout
+    let mut stdin = child.stdin.take().context("hook stdin")?;
+    let body = input.to_string();
+    thread::spawn(move || {
+        let _ = stdin.write_all(body.as_bytes());
+    });
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+
+    let timeout = hook.timeout_secs.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("timed out after {}s", timeout.as_secs());
+        }
+        thread::sleep(POLL);
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let reason = stderr.trim();
+        match status.code() {
+            _ if !reason.is_empty() => bail!("{reason}"),
+            Some(code) => bail!("exited with {code}"),
+            None => bail!("killed by a signal"),
+        }
+    }
+    let response: HookResponse = if stdout.trim().is_empty() {
+        HookResponse::default()
+    } else {
+        serde_json::from_str(&stdout).context("stdout isn't a JSON object")?
+    };
+    if let Some(reason) = response.veto {
+        bail!("{reason}");
+    }
+    Ok(response)
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut out = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut out);
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    })
+}
+
+/// Event sink that forwards everything to `inner` and runs the
+/// `post_exit` hooks when a session exits.
+pub struct HookSink {
+    inner: SharedSink,
+    settings: Arc<Mutex<Settings>>,
+}
+
+impl HookSink {
+    pub fn new(inner: SharedSink, settings: Arc<Mutex<Settings>>) -> Self {
+        Self { inner, settings }
+    }
+}
+
+impl EventSink for HookSink {
+    fn emit(&self, event: &str, payload: Value) {
+        if event.starts_with("pty://exit/") {
+            let hooks = self.settings.lock().unwrap().hooks.clone();
+            notify(&hooks, HookPoint::PostExit, payload.clone());
+        }
+        self.inner.emit(event, payload);
+    }
+}
@@ -0,0 +1,104 @@
+//! Lines typed into a session, for shell-style recall.
+//!
+//! Input typed into a session (`pty_input`, broadcasts and lines released
+//! by the guard) is followed like the guard does, and each submitted line
+//! is kept, newest last, up to `MAX_ENTRIES` per session. Blank lines and
+//! a repeat of the previous line aren't kept.
+//!
+//! Input for a password prompt is left out. Hidden input isn't echoed, so
+//! when Enter is typed the row under the cursor still ends in the prompt
+//! (`Password:`, `Enter passphrase for key ...:`); a line submitted there
+//! isn't recorded. Histories live in memory with their session.
+
+use crate::guard::LineFollower;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Lines kept per session.
+pub const MAX_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub line: String,
+    /// Unix millis.
+    pub at: u64,
+}
+
+/// A line found by searching every session's history.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryMatch {
+    pub session_id: String,
+    pub agent_id: String,
+    pub line: String,
+    /// Unix millis.
+    pub at: u64,
+}
+
+#[derive(Default)]
+pub struct InputHistory {
+    line: LineFollower,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl InputHistory {
+    /// Follow typed `data`, recording the lines it submits. `cursor_line`
+    /// returns the row under the cursor as displayed.
+    pub fn input(&mut self, data: &str, cursor_line: impl Fn() -> String) {
+        for c in data.chars() {
+            if self.line.follow(c) {
+                continue;
+            }
+            let line = self.line.take();
+            let line = line.trim();
+            if line.is_empty() || is_secret_prompt(&cursor_line()) {
+                continue;
+            }
+            if self.entries.back().is_some_and(|e| e.line == line) {
+                continue;
+            }
+            if self.entries.len() == MAX_ENTRIES {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(HistoryEntry { line: line.to_string(), at: now_ms() });
+        }
+    }
+
+    /// Up to `limit` lines, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Lines containing `query`, ignoring case, newest first.
+    pub fn search(&self, query: &str) -> Vec<HistoryEntry> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .rev()
+            .filter(|e| e.line.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether `row` ends in a prompt for hidden input.
+fn is_secret_prompt(row: &str) -> bool {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r"(?i)(password|passphrase|passcode|\bpin)[^:]*:$").expect("secret prompt")
+    });
+    re.is_match(row.trim_end())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,263 @@
+//! GitHub issue and pull request context for agent prompts.
+//!
+//! Fetches title, body and comments (and, for PRs, the diff) over the REST
+//! API so the frontend can paste real task context into a prompt. `repo` is
+//! `owner/name`.
+//!
+//! There is no stored-credentials support yet, so the token comes from
+//! `GITHUB_TOKEN` / `GH_TOKEN`, falling back to `gh auth token`. Public
+//! repos work without one, subject to the anonymous rate limit.
+
+use crate::error::{ErrorCode, PiError};
+use anyhow::{Context, Result};
+use reqwest::{header, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const API: &str = "https://api.github.com";
+
+/// Diffs beyond this are truncated; agents can't use a 50 MB prompt anyway.
+const MAX_DIFF_BYTES: usize = 512 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct Comment {
+    pub author: String,
+    pub body: String,
+    pub created_at: String,
+    /// File the comment is attached to, for PR review comments.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssueContext {
+    pub number: u64,
+    pub title: String,
+    pub body: String,
+    pub state: String,
+    pub author: String,
+    pub url: String,
+    pub labels: Vec<String>,
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PullContext {
+    #[serde(flatten)]
+    pub issue: IssueContext,
+    pub base: String,
+    pub head: String,
+    pub diff: String,
+    pub diff_truncated: bool,
+}
+
+// Raw API shapes — only the fields we read.
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct RawIssue {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    user: User,
+    html_url: String,
+    #[serde(default)]
+    labels: Vec<Label>,
+}
+
+#[derive(Deserialize)]
+struct RawPull {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    user: User,
+    html_url: String,
+    #[serde(default)]
+    labels: Vec<Label>,
+    base: Ref,
+    head: Ref,
+}
+
+#[derive(Deserialize)]
+struct Ref {
+    label: String,
+}
+
+#[derive(Deserialize)]
+struct RawComment {
+    user: User,
+    body: Option<String>,
+    created_at: String,
+    path: Option<String>,
+}
+
+pub async fn fetch_issue(repo: &str, number: u64) -> Result<IssueContext> {
+    let client = Client::new(repo).await?;
+    let raw: RawIssue = client.get_json(&format!("issues/{number}")).await?;
+    let comments = client.comments(&format!("issues/{number}/comments")).await?;
+    Ok(IssueContext {
+        number: raw.number,
+        title: raw.title,
+        body: raw.body.unwrap_or_default(),
+        state: raw.state,
+        author: raw.user.login,
+        url: raw.html_url,
+        labels: raw.labels.into_iter().map(|l| l.name).collect(),
+        comments,
+    })
+}
+
+/// A pull request with its conversation and review comments, oldest first,
+/// and the unified diff against its base.
+pub async fn fetch_pull(repo: &str, number: u64) -> Result<PullContext> {
+    let client = Client::new(repo).await?;
+    let raw: RawPull = client.get_json(&format!("pulls/{number}")).await?;
+    let mut comments = client.comments(&format!("issues/{number}/comments")).await?;
+    comments.extend(client.comments(&format!("pulls/{number}/comments")).await?);
+    comments.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let mut diff = client
+        .request(&format!("pulls/{number}"))
+        .header(header::ACCEPT, "application/vnd.github.diff")
+        .send()
+        .await
+        .context("fetch pull request diff")
+        .and_then(check_status)?
+        .text()
+        .await
+        .context("read pull request diff")?;
+    let diff_truncated = diff.len() > MAX_DIFF_BYTES;
+    if diff_truncated {
+        let mut end = MAX_DIFF_BYTES;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        diff.truncate(end);
+    }
+
+    Ok(PullContext {
+        issue: IssueContext {
+            number: raw.number,
+            title: raw.title,
+            body: raw.body.unwrap_or_default(),
+            state: raw.state,
+            author: raw.user.login,
+            url: raw.html_url,
+            labels: raw.labels.into_iter().map(|l| l.name).collect(),
+            comments,
+        },
+        base: raw.base.label,
+        head: raw.head.label,
+        diff,
+        diff_truncated,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Client
+// ---------------------------------------------------------------------------
+
+struct Client {
+    http: reqwest::Client,
+    repo: String,
+    token: Option<String>,
+}
+
+impl Client {
+    async fn new(repo: &str) -> Result<Self> {
+        let valid = repo.split_once('/').is_some_and(|(owner, name)| {
+            !owner.is_empty() && !name.is_empty() && !name.contains('/')
+        });
+        if !valid {
+            return Err(PiError::invalid_input(format!("expected owner/name, got: {repo}"))
+                .into());
+        }
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("pi-builder/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("build http client")?;
+        Ok(Self { http, repo: repo.to_string(), token: token().await })
+    }
+
+    fn request(&self, path: &str) -> RequestBuilder {
+        let mut req = self
+            .http
+            .get(format!("{API}/repos/{}/{path}", self.repo))
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        req
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let resp = self
+            .request(path)
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .with_context(|| format!("GET {path}"))
+            .and_then(check_status)?;
+        resp.json().await.with_context(|| format!("decode {path}"))
+    }
+
+    /// All comments at `path`, following pagination.
+    async fn comments(&self, path: &str) -> Result<Vec<Comment>> {
+        let mut comments = Vec::new();
+        for page in 1.. {
+            let raw: Vec<RawComment> =
+                self.get_json(&format!("{path}?per_page=100&page={page}")).await?;
+            let done = raw.len() < 100;
+            comments.extend(raw.into_iter().map(|c| Comment {
+                author: c.user.login,
+                body: c.body.unwrap_or_default(),
+                created_at: c.created_at,
+                path: c.path,
+            }));
+            if done {
+                break;
+            }
+        }
+        Ok(comments)
+    }
+}
+
+fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let code = match status {
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorCode::GitAuth,
+        _ => ErrorCode::Internal,
+    };
+    let mut err = PiError::new(code, format!("GitHub returned {status}"));
+    if code == ErrorCode::NotFound {
+        err = err.with_detail("private repos need GITHUB_TOKEN or `gh auth login`");
+    }
+    Err(err.into())
+}
+
+/// Token from the environment, or from the GitHub CLI if it's logged in.
+async fn token() -> Option<String> {
+    for var in ["GITHUB_TOKEN", "GH_TOKEN"] {
+        if let Ok(token) = std::env::var(var) {
+            if !token.trim().is_empty() {
+                return Some(token.trim().to_string());
+            }
+        }
+    }
+    let out = tokio::process::Command::new("gh").args(["auth", "token"]).output().await.ok()?;
+    let token = String::from_utf8(out.stdout).ok()?.trim().to_string();
+    (out.status.success() && !token.is_empty()).then_some(token)
+}
@@ -0,0 +1,3 @@
+//! Clients for external services that feed context into agent sessions.
+
+pub mod github;
@@ -0,0 +1,209 @@
+//! Local socket protocol between the `pi` CLI and a running desktop app.
+//!
+//! The app listens on a Unix socket (`socket_path`) readable only by the
+//! user. Each line a client writes is a `Request`; the app answers each with
+//! one `Response` line. Methods are named after the Tauri commands they
+//! run and take the same arguments, so the CLI can do what the GUI does.
+//!
+//! The same socket speaks JSON-RPC 2.0 (`rpc.rs`); `bridge_stdio` relays
+//! it for programs that talk over stdio.
+//!
+//! Windows has no socket yet; the CLI reports that instead of connecting.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Overrides the socket location, for tests and for running several
+/// instances side by side.
+pub const SOCKET_ENV: &str = "PI_BUILDER_SOCKET";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// A serialized `PiError` (`{ code, message, detail }`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+impl Response {
+    pub fn ok(result: Value) -> Self {
+        Self { result: Some(result), error: None }
+    }
+
+    pub fn err(error: impl Serialize) -> Self {
+        let error = serde_json::to_value(error).unwrap_or_else(|e| e.to_string().into());
+        Self { result: None, error: Some(error) }
+    }
+}
+
+/// Where the desktop app listens: `$PI_BUILDER_SOCKET`, else
+/// `$XDG_RUNTIME_DIR/pi-builder.sock`, else `pi-builder.sock` in a per-user
+/// directory of the temp dir.
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join("pi-builder.sock");
+    }
+    private_temp_dir().join("pi-builder.sock")
+}
+
+fn private_temp_dir() -> PathBuf {
+    let user = std::env::var("USER").unwrap_or_else(|_| "user".into());
+    std::env::temp_dir().join(format!("pi-builder-{user}"))
+}
+
+/// Get the directory of the socket at `path` ready to bind in. The
+/// per-user directory in the shared temp dir is created `0700`, and one
+/// that already exists must be a directory closed to others: the socket
+/// is only chmodded after its bind, so in an open directory another user
+/// could connect, or put their own socket there first.
+#[cfg(unix)]
+pub fn prepare_socket_dir(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = private_temp_dir();
+    if path.parent() != Some(dir.as_path()) {
+        return Ok(());
+    }
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("create {}", dir.display())),
+    }
+    let meta =
+        std::fs::symlink_metadata(&dir).with_context(|| format!("stat {}", dir.display()))?;
+    if !meta.is_dir() || meta.permissions().mode() & 0o077 != 0 {
+        bail!("{} is not a private directory; remove it to let the app recreate it", dir.display());
+    }
+    Ok(())
+}
+
+/// Send one request to the app at `socket_path()` and wait for the answer.
+#[cfg(unix)]
+pub fn call(method: &str, params: Value) -> Result<Value> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut stream = connect()?;
+    let request = Request { method: method.into(), params };
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).context("send request")?;
+
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer).context("read response")?;
+    if answer.is_empty() {
+        bail!("app closed the connection without answering");
+    }
+    let response: Response = serde_json::from_str(&answer).context("parse response")?;
+    match response.error {
+        Some(error) => Err(anyhow!(
+            "{}",
+            error["message"].as_str().map(str::to_string).unwrap_or_else(|| error.to_string())
+        )),
+        None => Ok(response.result.unwrap_or(Value::Null)),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn call(_method: &str, _params: Value) -> Result<Value> {
+    bail!("the pi CLI can't reach the desktop app on this platform yet")
+}
+
+/// Relay JSON-RPC messages between stdin/stdout and the app (see `rpc.rs`)
+/// until stdin closes and every answer is out. Messages are one per line,
+/// or framed by `Content-Length` headers as in LSP when `lsp` is set.
+#[cfg(unix)]
+pub fn bridge_stdio(lsp: bool) -> Result<()> {
+    use std::{
+        io::{self, BufRead, BufReader, Write},
+        net::Shutdown,
+        thread,
+    };
+
+    let stream = connect()?;
+    let mut to_app = stream.try_clone().context("clone socket")?;
+    let printer = thread::spawn(move || -> Result<()> {
+        let mut out = io::stdout().lock();
+        for line in BufReader::new(stream).lines() {
+            let line = line.context("read from app")?;
+            if lsp {
+                write!(out, "Content-Length: {}\r\n\r\n{line}", line.len())?;
+            } else {
+                writeln!(out, "{line}")?;
+            }
+            out.flush()?;
+        }
+        Ok(())
+    });
+
+    let mut input = io::stdin().lock();
+    loop {
+        let message = if lsp {
+            read_framed(&mut input)?
+        } else {
+            let mut line = String::new();
+            (input.read_line(&mut line).context("read stdin")? > 0).then_some(line)
+        };
+        let Some(message) = message else { break };
+        // The app reads a line per message; raw newlines in JSON are only
+        // ever whitespace between tokens
+        let message = message.replace(['\r', '\n'], " ");
+        if message.trim().is_empty() {
+            continue;
+        }
+        to_app.write_all(format!("{}\n", message.trim()).as_bytes()).context("send to app")?;
+    }
+    // The app answers what it has read, then closes its end
+    to_app.shutdown(Shutdown::Write).context("close socket")?;
+    printer.join().map_err(|_| anyhow!("output thread panicked"))?
+}
+
+#[cfg(not(unix))]
+pub fn bridge_stdio(_lsp: bool) -> Result<()> {
+    bail!("the pi CLI can't reach the desktop app on this platform yet")
+}
+
+#[cfg(unix)]
+fn connect() -> Result<std::os::unix::net::UnixStream> {
+    let path = socket_path();
+    std::os::unix::net::UnixStream::connect(&path).with_context(|| {
+        format!("no pi-builder app listening on {} (is it running?)", path.display())
+    })
+}
+
+/// One `Content-Length` framed message, or `None` at end of input.
+#[cfg(unix)]
+fn read_framed(input: &mut impl std::io::BufRead) -> Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).context("read stdin")? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().context("bad Content-Length")?);
+            }
+        }
+    }
+    let length = length.context("message without Content-Length")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).context("read message")?;
+    String::from_utf8(body).map(Some).context("message isn't UTF-8")
+}
@@ -0,0 +1,209 @@
+//! Replay journal of backend events.
+//!
+//! A reloaded (or crashed) webview has missed every event emitted while it
+//! was gone. State events (session lifecycle, worktree status, task and
+//! review changes) are numbered with a sequence that keeps increasing
+//! across restarts and kept in a bounded journal, `events.jsonl` in the
+//! config dir. The frontend remembers the last `seq` it saw and calls
+//! `events_since` to catch up instead of rebuilding its state from scratch.
+//!
+//! Journaled events carry their number as a `seq` field in the payload.
+//! Output and progress events (`pty://data`, clone progress, ...) aren't
+//! journaled: they are large and meaningless once stale.
+
+use crate::events::{EventSink, SharedSink};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Entries kept in memory and, after compaction, on disk.
+const MAX_ENTRIES: usize = 5000;
+
+/// Event name prefixes that are journaled.
+const JOURNALED: &[&str] = &[
+    "pty://spawned/",
+    "pty://queued/",
+    "pty://started/",
+    "pty://exit/",
+    "pty://meta/",
+    "pty://state/",
+    "pty://idle/",
+    "pty://timeout/",
+    "pty://error/",
+    "worktree://status",
+    "worktree://conflict",
+    "worktree://violation",
+    "task://changed",
+    "review://changed",
+    "merge_queue://changed",
+    "repo://fetched",
+    "repo://base-updated",
+    "check://result",
+    "settings://changed",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    /// Unix millis.
+    pub at: u64,
+    pub event: String,
+    pub payload: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsSince {
+    pub events: Vec<JournalEntry>,
+    /// Highest sequence number emitted so far.
+    pub latest: u64,
+    /// False when entries after the requested `seq` were already dropped;
+    /// the caller has to reload its state in full.
+    pub complete: bool,
+}
+
+struct Inner {
+    entries: VecDeque<JournalEntry>,
+    latest: u64,
+    file: Option<File>,
+    /// Lines in the file, compacted back to `MAX_ENTRIES` at twice that.
+    lines: usize,
+}
+
+pub struct EventJournal {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl EventJournal {
+    /// Load the journal at `path`; unreadable lines are skipped.
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries = VecDeque::new();
+        let mut lines = 0;
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                lines += 1;
+                if let Ok(entry) = serde_json::from_str::<JournalEntry>(&line) {
+                    if entries.len() == MAX_ENTRIES {
+                        entries.pop_front();
+                    }
+                    entries.push_back(entry);
+                }
+            }
+        }
+        let latest = entries.back().map_or(0, |e| e.seq);
+        let file = open_append(&path).map_err(|e| log::warn!("event journal: {e:#}")).ok();
+        Self { path, inner: Mutex::new(Inner { entries, latest, file, lines }) }
+    }
+
+    /// Number and record `event`. Returns its sequence number, or `None` if
+    /// the event isn't journaled.
+    pub fn record(&self, event: &str, payload: &Value) -> Option<u64> {
+        if !JOURNALED.iter().any(|p| event.starts_with(p)) {
+            return None;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.latest += 1;
+        let entry = JournalEntry {
+            seq: inner.latest,
+            at: now_ms(),
+            event: event.into(),
+            payload: payload.clone(),
+        };
+        if let Some(file) = inner.file.as_mut() {
+            if let Ok(line) = serde_json::to_string(&entry) {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+        inner.lines += 1;
+        if inner.entries.len() == MAX_ENTRIES {
+            inner.entries.pop_front();
+        }
+        let seq = entry.seq;
+        inner.entries.push_back(entry);
+        if inner.lines >= MAX_ENTRIES * 2 {
+            if let Err(e) = self.compact(&mut inner) {
+                log::warn!("compact event journal: {e:#}");
+            }
+        }
+        Some(seq)
+    }
+
+    /// Entries after `seq`, oldest first.
+    pub fn since(&self, seq: u64) -> EventsSince {
+        let inner = self.inner.lock().unwrap();
+        let oldest = inner.entries.front().map_or(inner.latest + 1, |e| e.seq);
+        EventsSince {
+            events: inner.entries.iter().filter(|e| e.seq > seq).cloned().collect(),
+            latest: inner.latest,
+            complete: seq + 1 >= oldest,
+        }
+    }
+
+    /// Rewrite the file with only the in-memory entries.
+    fn compact(&self, inner: &mut Inner) -> Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut out = String::new();
+        for entry in &inner.entries {
+            out.push_str(&serde_json::to_string(entry)?);
+            out.push('\n');
+        }
+        fs::write(&tmp, out).context("write event journal")?;
+        inner.file = None;
+        fs::rename(&tmp, &self.path).context("replace event journal")?;
+        inner.file = Some(open_append(&self.path)?);
+        inner.lines = inner.entries.len();
+        Ok(())
+    }
+}
+
+/// Sink that journals events before passing them on, adding `seq` to
+/// journaled object payloads.
+pub struct JournalSink {
+    inner: SharedSink,
+    journal: Arc<EventJournal>,
+}
+
+impl JournalSink {
+    pub fn new(inner: SharedSink, journal: Arc<EventJournal>) -> Self {
+        Self { inner, journal }
+    }
+}
+
+impl EventSink for JournalSink {
+    fn emit(&self, event: &str, mut payload: Value) {
+        if let Some(seq) = self.journal.record(event, &payload) {
+            if let Value::Object(map) = &mut payload {
+                map.insert("seq".into(), seq.into());
+            }
+        }
+        self.inner.emit(event, payload);
+    }
+}
+
+/// Location of the journal inside the app config dir.
+pub fn journal_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("events.jsonl")
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("create config dir")?;
+    }
+    OpenOptions::new().create(true).append(true).open(path).context("open event journal")
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,64 @@
+//! Git LFS in agent worktrees.
+//!
+//! libgit2 doesn't run the LFS smudge filter, so a fresh worktree holds
+//! pointer files instead of assets. When the repo's `.gitattributes` routes
+//! anything through `filter=lfs`, we run `git lfs pull` in the new worktree
+//! to fetch and check out the real content. Diffs mark LFS-tracked files
+//! rather than showing pointer text.
+
+use anyhow::{bail, Context, Result};
+use git2::{AttrCheckFlags, Repository};
+use serde::Serialize;
+use std::{path::Path, process::Command};
+
+#[derive(Debug, Serialize)]
+pub struct LfsReport {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+/// Whether the checkout at `wt_path` has LFS-tracked paths.
+pub fn uses_lfs(wt_path: &Path) -> bool {
+    std::fs::read_to_string(wt_path.join(".gitattributes"))
+        .map(|attrs| {
+            attrs
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.starts_with('#'))
+                .any(|l| l.split_whitespace().any(|a| a == "filter=lfs"))
+        })
+        .unwrap_or(false)
+}
+
+/// Fetch and check out LFS objects in a worktree.
+pub fn pull(wt_path: &Path) -> LfsReport {
+    match run_pull(wt_path) {
+        Ok(()) => LfsReport { ok: true, error: None },
+        Err(e) => LfsReport { ok: false, error: Some(format!("{e:#}")) },
+    }
+}
+
+/// Whether `path` (relative to the workdir) goes through the LFS filter.
+pub fn is_tracked(repo: &Repository, path: &str) -> bool {
+    repo.get_attr(Path::new(path), "filter", AttrCheckFlags::default())
+        .ok()
+        .flatten()
+        == Some("lfs")
+}
+
+fn run_pull(wt_path: &Path) -> Result<()> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(wt_path)
+        .args(["lfs", "pull"])
+        .output()
+        .context("run git lfs pull")?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        if stderr.contains("is not a git command") {
+            bail!("git-lfs is not installed; LFS files are left as pointers");
+        }
+        bail!("git lfs pull: {}", stderr.trim());
+    }
+    Ok(())
+}
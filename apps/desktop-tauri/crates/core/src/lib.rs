@@ -0,0 +1,94 @@
+//! pi-builder core: PTY sessions, worktrees, agents and tasks.
+//!
+//! Nothing here depends on Tauri. Events go out through `EventSink` and
+//! notifications through `Notifier`, so the desktop app, a CLI or a server
+//! can host the same backend.
+
+pub mod actions;
+pub mod activity;
+pub mod agents;
+pub mod annotate;
+pub mod audit;
+pub mod basesync;
+pub mod branches;
+pub mod checks;
+pub mod bulk;
+pub mod codec;
+pub mod comments;
+pub mod compare;
+pub mod container;
+pub mod depcache;
+pub mod detach;
+pub mod disk;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod files;
+pub mod flow;
+pub mod guard;
+pub mod highlight;
+pub mod history;
+pub mod hooks;
+pub mod inputhistory;
+pub mod integrations;
+pub mod ipc;
+pub mod journal;
+pub mod lfs;
+pub mod locks;
+pub mod logs;
+pub mod macros;
+pub mod mainguard;
+pub mod mergemsg;
+pub mod mergequeue;
+pub mod notify;
+pub mod paste;
+pub mod patch;
+pub mod paths;
+pub mod pipe;
+pub mod playback;
+pub mod proctree;
+pub mod protect;
+pub mod pty;
+pub mod rebase;
+pub mod recording;
+pub mod redact;
+pub mod remote;
+pub mod repo_cache;
+pub mod repo_config;
+pub mod report;
+pub mod repro;
+pub mod review;
+pub mod rpc;
+pub mod scale;
+pub mod scrollback;
+pub mod screen;
+pub mod search;
+pub mod secrets;
+pub mod sendfile;
+pub mod settings;
+pub mod setup;
+pub mod signing;
+pub mod snapshot;
+pub mod sparse;
+pub mod spawnqueue;
+pub mod ssh;
+pub mod staging;
+pub mod stash;
+pub mod submodules;
+pub mod summary;
+pub mod system;
+pub mod target;
+pub mod tasks;
+pub mod termenv;
+pub mod terminal;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timeline;
+pub mod tree;
+pub mod usage;
+pub mod webhooks;
+#[cfg(windows)]
+pub mod winjob;
+pub mod workspace;
+pub mod worktree;
@@ -0,0 +1,59 @@
+//! Locks against concurrent destructive operations on a worktree.
+//!
+//! Merges, rebases, removals and snapshots each take the worktree they
+//! change for as long as they run; stash operations and merges take the
+//! main checkout too. Two of them on the same checkout would otherwise
+//! write its index at the same time. Locks aren't waited for: a second
+//! operation fails at once with `WorktreeBusy`, naming the one in
+//! progress, and the caller can retry. A lock is released when its guard
+//! is dropped.
+
+use crate::error::PiError;
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Repo path and worktree name; `None` is the main checkout.
+type Key = (String, Option<String>);
+
+/// Held locks with the operation holding each.
+#[derive(Clone, Default)]
+pub struct WorktreeLocks {
+    held: Arc<Mutex<HashMap<Key, &'static str>>>,
+}
+
+/// Releases its lock when dropped.
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct LockGuard {
+    held: Arc<Mutex<HashMap<Key, &'static str>>>,
+    key: Key,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.held.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl WorktreeLocks {
+    /// Take worktree `name` of `repo` for `operation`.
+    pub fn worktree(&self, repo: &str, name: &str, operation: &'static str) -> Result<LockGuard> {
+        self.take((repo.to_string(), Some(name.to_string())), operation)
+    }
+
+    /// Take the main checkout of `repo` for `operation`.
+    pub fn main_checkout(&self, repo: &str, operation: &'static str) -> Result<LockGuard> {
+        self.take((repo.to_string(), None), operation)
+    }
+
+    fn take(&self, key: Key, operation: &'static str) -> Result<LockGuard> {
+        let mut held = self.held.lock().unwrap();
+        if let Some(holder) = held.get(&key) {
+            return Err(PiError::worktree_busy(key.1.as_deref(), holder).into());
+        }
+        held.insert(key.clone(), operation);
+        Ok(LockGuard { held: self.held.clone(), key })
+    }
+}
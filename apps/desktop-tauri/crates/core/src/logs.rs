@@ -0,0 +1,144 @@
+//! Per-session output logs.
+//!
+//! When enabled, each session's output is mirrored to `<log dir>/<id>.log`
+//! after redaction, escape sequences and all. A log that grows past
+//! `max_file_bytes` is rotated to `<id>.log.1`, `.2`, ... keeping at most
+//! `max_files` old parts. Logs older than `retention_days` are deleted at
+//! startup. Writes go straight to the file, so nothing is lost if the app
+//! exits mid-session.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub enabled: bool,
+    pub max_file_bytes: u64,
+    /// Rotated parts kept per session, besides the live file.
+    pub max_files: usize,
+    /// Delete logs not modified for this many days; 0 keeps them forever.
+    pub retention_days: u64,
+    /// Also record output with its timing, for playback; see
+    /// `recording.rs`.
+    pub record: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+            retention_days: 14,
+            record: false,
+        }
+    }
+}
+
+/// Live log file for one session.
+pub struct SessionLog {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_file_bytes: u64,
+    max_files: usize,
+}
+
+impl SessionLog {
+    pub fn open(dir: &Path, session_id: &str, settings: &LogSettings) -> Result<Self> {
+        fs::create_dir_all(dir).context("create log dir")?;
+        let path = log_path(dir, session_id);
+        let file = open_append(&path)?;
+        Ok(Self {
+            path,
+            file,
+            written: 0,
+            max_file_bytes: settings.max_file_bytes.max(1),
+            max_files: settings.max_files,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append output, rotating first if it would overflow the current file.
+    /// Errors are logged and swallowed so logging never breaks a session.
+    pub fn write(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if self.written > 0 && self.written + bytes.len() as u64 > self.max_file_bytes {
+            if let Err(e) = self.rotate() {
+                log::warn!("rotate {}: {e:#}", self.path.display());
+            }
+        }
+        match self.file.write_all(bytes) {
+            Ok(()) => self.written += bytes.len() as u64,
+            Err(e) => log::warn!("write {}: {e}", self.path.display()),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path).context("truncate log")?;
+            self.written = 0;
+            return Ok(());
+        }
+        let _ = fs::remove_file(part_path(&self.path, self.max_files));
+        for n in (1..self.max_files).rev() {
+            let _ = fs::rename(part_path(&self.path, n), part_path(&self.path, n + 1));
+        }
+        fs::rename(&self.path, part_path(&self.path, 1)).context("rotate log")?;
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Live log file of a session.
+pub fn log_path(dir: &Path, session_id: &str) -> PathBuf {
+    dir.join(format!("{session_id}.log"))
+}
+
+/// Delete log files (live and rotated) older than the retention period.
+pub fn prune(dir: &Path, settings: &LogSettings) {
+    if settings.retention_days == 0 {
+        return;
+    }
+    let max_age = Duration::from_secs(settings.retention_days * 24 * 60 * 60);
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_log = name.contains(".log") || name.ends_with(".cast");
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok());
+        if is_log && age.is_some_and(|a| a > max_age) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn part_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log {}", path.display()))
+}
@@ -0,0 +1,231 @@
+//! Input macros: record what is typed into a session and type it again.
+//!
+//! Recording is opt-in per session. While it is on, input typed or pasted
+//! into the session is kept with the time between writes, up to
+//! `MAX_RECORDED` bytes. A recording saved under a name becomes a macro in
+//! `macros.json` in the config dir; playing it types its input into a
+//! session at the recorded pace, scaled by a speed factor, so shells and
+//! agents that read ahead see it as they would a person typing. Pauses are
+//! shortened to `MAX_GAP`, so time spent thinking isn't replayed. Played
+//! input goes through the session's guard like typed input, and
+//! `macro://played/<id>` is emitted when playback ends.
+//!
+//! Macros are stored as typed, passwords included; stop recording before
+//! typing a secret.
+
+use crate::error::{ErrorCode, PiError};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Input kept per recording; what is typed past it isn't recorded.
+const MAX_RECORDED: usize = 256 * 1024;
+
+/// Longest pause kept between two writes.
+const MAX_GAP: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MacroStep {
+    /// Wait before writing `data`, at speed 1.
+    pub delay_ms: u64,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+    /// Unix millis.
+    pub created_at: u64,
+}
+
+#[derive(Default)]
+struct Recording {
+    on: bool,
+    last: Option<Instant>,
+    steps: Vec<MacroStep>,
+    bytes: usize,
+}
+
+/// Input recordings of sessions that have opted in.
+#[derive(Default)]
+pub struct Recorder {
+    sessions: Mutex<HashMap<String, Recording>>,
+}
+
+impl Recorder {
+    /// Start a new recording, dropping the session's last one, or stop
+    /// recording and keep what was recorded.
+    pub fn set(&self, session_id: &str, on: bool) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if on {
+            sessions.insert(session_id.to_string(), Recording { on, ..Default::default() });
+        } else if let Some(recording) = sessions.get_mut(session_id) {
+            recording.on = false;
+        }
+    }
+
+    /// Note input written to a session, if it is recording.
+    pub fn record(&self, session_id: &str, data: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(recording) = sessions.get_mut(session_id).filter(|r| r.on) else { return };
+        if data.is_empty() || recording.bytes + data.len() > MAX_RECORDED {
+            return;
+        }
+        let now = Instant::now();
+        let gap = recording.last.map_or(Duration::ZERO, |last| (now - last).min(MAX_GAP));
+        recording.last = Some(now);
+        recording.bytes += data.len();
+        recording.steps.push(MacroStep { delay_ms: gap.as_millis() as u64, data: data.into() });
+    }
+
+    /// What the session's recording holds so far.
+    pub fn steps(&self, session_id: &str) -> Vec<MacroStep> {
+        self.sessions.lock().unwrap().get(session_id).map(|r| r.steps.clone()).unwrap_or_default()
+    }
+
+    pub fn forget(&self, session_id: &str) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+}
+
+/// Saved macros, by name.
+pub struct MacroStore {
+    path: PathBuf,
+    macros: Mutex<Vec<Macro>>,
+}
+
+impl MacroStore {
+    pub fn load(path: PathBuf) -> Self {
+        let macros = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, macros: Mutex::new(macros) }
+    }
+
+    pub fn list(&self) -> Vec<Macro> {
+        let mut macros = self.macros.lock().unwrap().clone();
+        macros.sort_by(|a, b| a.name.cmp(&b.name));
+        macros
+    }
+
+    pub fn get(&self, name: &str) -> Result<Macro> {
+        let macros = self.macros.lock().unwrap();
+        let found = macros.iter().find(|m| m.name == name).cloned();
+        let not_found = || PiError::new(ErrorCode::NotFound, format!("no macro named {name}"));
+        Ok(found.ok_or_else(not_found)?)
+    }
+
+    /// Save `steps` as `name`, replacing a macro of that name.
+    pub fn save(&self, name: &str, steps: Vec<MacroStep>) -> Result<Macro> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(PiError::invalid_input("macro name is empty").into());
+        }
+        if steps.is_empty() {
+            return Err(PiError::invalid_input("nothing was recorded").into());
+        }
+        let saved = Macro { name: name.to_string(), steps, created_at: now_ms() };
+        {
+            let mut macros = self.macros.lock().unwrap();
+            macros.retain(|m| m.name != name);
+            macros.push(saved.clone());
+        }
+        self.save_file()?;
+        Ok(saved)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.macros.lock().unwrap().retain(|m| m.name != name);
+        self.save_file()
+    }
+
+    fn save_file(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.macros.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write macros")?;
+        Ok(())
+    }
+}
+
+pub fn macros_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("macros.json")
+}
+
+/// Macros being played, by session; one at a time per session.
+#[derive(Clone, Default)]
+pub struct Playbacks {
+    running: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+impl Playbacks {
+    /// Play `steps` on a thread, passing each to `write` after its delay
+    /// divided by `speed`, then call `done` with whether it ran to the end.
+    /// Playback stops at the first failed write.
+    pub fn play(
+        &self,
+        session_id: &str,
+        steps: Vec<MacroStep>,
+        speed: f64,
+        mut write: impl FnMut(&str) -> Result<()> + Send + 'static,
+        done: impl FnOnce(Result<bool>) + Send + 'static,
+    ) -> Result<()> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(PiError::invalid_input("speed must be above 0").into());
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(session_id) {
+                return Err(PiError::invalid_input("a macro is already playing").into());
+            }
+            running.insert(session_id.to_string(), stop.clone());
+        }
+        let running = self.running.clone();
+        let session_id = session_id.to_string();
+        thread::spawn(move || {
+            let result = (|| {
+                for step in &steps {
+                    thread::sleep(Duration::from_millis(step.delay_ms).div_f64(speed));
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(false);
+                    }
+                    write(&step.data)?;
+                }
+                Ok(true)
+            })();
+            running.lock().unwrap().remove(&session_id);
+            done(result);
+        });
+        Ok(())
+    }
+
+    /// Stop the session's playback before its next write. Returns whether
+    /// one was playing.
+    pub fn stop(&self, session_id: &str) -> bool {
+        let running = self.running.lock().unwrap();
+        let stop = running.get(session_id);
+        if let Some(stop) = stop {
+            stop.store(true, Ordering::SeqCst);
+        }
+        stop.is_some()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
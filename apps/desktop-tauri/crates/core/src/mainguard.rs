@@ -0,0 +1,78 @@
+//! Keeping agents out of the main checkout.
+//!
+//! An agent started without a worktree runs in the repo's main checkout,
+//! next to the user's own uncommitted work, and its changes can't be
+//! reviewed or thrown away as a unit. `main_checkout_guard` in settings
+//! makes `pty_spawn` refuse such a session, or refuse it unless the spawn
+//! confirms with `allow_main_checkout`. Either way the error is
+//! `main_checkout`, telling the user to give a worktree instead.
+//!
+//! A session is in the main checkout when its cwd is in the repo's
+//! directory but not in one of its worktrees. The built-in `shell` agent is
+//! left alone: a plain terminal in the repo is how the user works there.
+
+use crate::error::{ErrorCode, PiError};
+use anyhow::Result;
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MainCheckoutGuard {
+    #[default]
+    Off,
+    /// Refuse unless the spawn sets `allow_main_checkout`.
+    Confirm,
+    /// Always refuse.
+    Refuse,
+}
+
+/// Fail with `main_checkout` if `guard` doesn't allow `agent_id` to start
+/// in `cwd`, given the main checkout at `repo`.
+pub fn check(
+    guard: MainCheckoutGuard,
+    agent_id: &str,
+    cwd: Option<&str>,
+    repo: Option<&str>,
+    confirmed: bool,
+) -> Result<()> {
+    let allowed = match guard {
+        MainCheckoutGuard::Off => true,
+        MainCheckoutGuard::Confirm => confirmed,
+        MainCheckoutGuard::Refuse => false,
+    };
+    if allowed || agent_id == "shell" {
+        return Ok(());
+    }
+    let (Some(cwd), Some(repo)) = (cwd, repo) else { return Ok(()) };
+    if !in_main_checkout(Path::new(cwd), repo) {
+        return Ok(());
+    }
+    let message = if guard == MainCheckoutGuard::Confirm {
+        "agent would run in the main checkout; give it a worktree or confirm starting it there"
+    } else {
+        "agents can't run in the main checkout; give this one a worktree"
+    };
+    Err(PiError::new(ErrorCode::MainCheckout, message).into())
+}
+
+/// Whether `cwd` is inside the main checkout at `repo` and not in one of
+/// its worktrees, which may be nested in it.
+pub fn in_main_checkout(cwd: &Path, repo: &str) -> bool {
+    let Ok(cwd) = cwd.canonicalize() else { return false };
+    if !cwd.starts_with(canonical(Path::new(repo))) {
+        return false;
+    }
+    let Ok(repo) = Repository::open(repo) else { return true };
+    let Ok(names) = repo.worktrees() else { return true };
+    !names
+        .iter()
+        .flatten()
+        .filter_map(|name| repo.find_worktree(name).ok())
+        .any(|wt| cwd.starts_with(canonical(wt.path())))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,195 @@
+//! Merge commit messages for agent branches.
+//!
+//! The message of a merge commit is rendered from a template, so a team's
+//! history says which agent produced a change and why. The template is the
+//! one set for the repo in the app, else `merge_message` in
+//! `.pi-builder.toml`, else `DEFAULT_TEMPLATE`. Placeholders:
+//!
+//! - `{branch}`, `{worktree}`: the merged branch and its worktree
+//! - `{agent}`: agent profile of the latest session in the worktree, or
+//!   the one the worktree was created for
+//! - `{task}`: task prompt or session title the worktree was created with
+//! - `{session}`: id of the latest session in the worktree
+//! - `{duration}`: from the first session's start to the last one's end
+//! - `{files}`: one line per changed file, e.g. `M src/lib.rs (+12 -3)`
+//! - `{stat}`: e.g. `3 files changed, 40 insertions(+), 2 deletions(-)`
+//!
+//! A line with a placeholder that has no value, such as `{task}` for a
+//! worktree created by hand, is left out, so trailers like
+//! `Agent-Session: {session}` only appear when they mean something.
+//! Sessions are taken from the usage log; setup and check sessions don't
+//! count. Fast-forwards make no merge commit and use no template.
+
+use crate::{
+    checks,
+    compare::{self, BranchChange, ChangeKind},
+    error::PiError,
+    mergequeue::CHECK_AGENT_ID,
+    repo_config::RepoConfig,
+    settings::Settings,
+    setup::SETUP_AGENT_ID,
+    usage::UsageRecord,
+    worktree,
+};
+use anyhow::Result;
+use git2::{Repository, Tree};
+use regex::{Captures, Regex};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const DEFAULT_TEMPLATE: &str = "Merge branch '{branch}'";
+
+const PLACEHOLDERS: &[&str] =
+    &["branch", "worktree", "agent", "task", "session", "duration", "files", "stat"];
+
+/// Files listed by `{files}` before the rest are summed up.
+const MAX_FILES: usize = 50;
+
+fn placeholder() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\{([a-z_]+)\}").unwrap())
+}
+
+/// Template for merge commits in `repo`.
+pub fn template(repo: &str, settings: &Settings) -> String {
+    settings
+        .merge_messages
+        .get(repo)
+        .cloned()
+        .or_else(|| RepoConfig::load_or_default(Path::new(repo)).merge_message)
+        .filter(|t| !t.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_TEMPLATE.into())
+}
+
+/// Fails on placeholders that don't exist.
+pub fn validate(template: &str) -> Result<()> {
+    for caps in placeholder().captures_iter(template) {
+        if !PLACEHOLDERS.contains(&&caps[1]) {
+            let message = format!("unknown placeholder {} in merge message", &caps[0]);
+            return Err(PiError::invalid_input(message).into());
+        }
+    }
+    Ok(())
+}
+
+/// Render `template` for merging `branch` of `worktree`, whose changes are
+/// those from `base` (the merge base) to `tree`. `sessions` is every
+/// session's usage record.
+pub fn render(
+    main: &Repository,
+    template: &str,
+    worktree: &str,
+    branch: &str,
+    base: &Tree,
+    tree: &Tree,
+    sessions: &[UsageRecord],
+) -> Result<String> {
+    let mut sessions: Vec<&UsageRecord> = sessions
+        .iter()
+        .filter(|r| {
+            ![SETUP_AGENT_ID, CHECK_AGENT_ID, checks::CHECK_AGENT_ID].contains(&&*r.agent_id)
+        })
+        .filter(|r| {
+            // Tasks name their worktree after the task
+            r.worktree.as_deref() == Some(worktree) || r.task_id.as_deref() == Some(worktree)
+        })
+        .collect();
+    sessions.sort_by_key(|r| r.started_at);
+    let (created_agent, description) = worktree::created_for(main, worktree);
+    let latest = sessions.last();
+    let duration = sessions.first().map(|first| {
+        let end = sessions.iter().map(|r| r.ended_at.unwrap_or_else(now_ms)).max();
+        end.unwrap_or(first.started_at).saturating_sub(first.started_at)
+    });
+    let changes = compare::changes(main, base, tree)?;
+
+    let value = |name: &str| -> Option<String> {
+        match name {
+            "branch" => Some(branch.to_string()),
+            "worktree" => Some(worktree.to_string()),
+            "agent" => latest.map(|r| r.agent_id.clone()).or(created_agent.clone()),
+            "task" => description.clone(),
+            "session" => latest.map(|r| r.session_id.clone()),
+            "duration" => duration.map(format_duration),
+            "files" => Some(file_lines(&changes)),
+            "stat" => Some(stat(&changes)),
+            _ => None,
+        }
+    };
+    let mut lines = Vec::new();
+    for line in template.lines() {
+        let missing = placeholder()
+            .captures_iter(line)
+            .any(|caps| PLACEHOLDERS.contains(&&caps[1]) && value(&caps[1]).is_none());
+        if missing {
+            continue;
+        }
+        let line = placeholder().replace_all(line, |caps: &Captures| {
+            value(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+        });
+        lines.push(line.trim_end().to_string());
+    }
+    let message = lines.join("\n").trim().to_string();
+    if message.is_empty() {
+        return Ok(DEFAULT_TEMPLATE.replace("{branch}", branch));
+    }
+    Ok(message)
+}
+
+fn file_lines(changes: &BTreeMap<String, BranchChange>) -> String {
+    let mut lines: Vec<String> = changes
+        .iter()
+        .take(MAX_FILES)
+        .map(|(path, change)| {
+            let kind = match change.kind {
+                ChangeKind::Added => 'A',
+                ChangeKind::Modified => 'M',
+                ChangeKind::Deleted => 'D',
+            };
+            format!("{kind} {path} (+{} -{})", change.additions, change.deletions)
+        })
+        .collect();
+    if changes.len() > MAX_FILES {
+        lines.push(format!("... and {} more", changes.len() - MAX_FILES));
+    }
+    lines.join("\n")
+}
+
+pub(crate) fn stat(changes: &BTreeMap<String, BranchChange>) -> String {
+    let additions: usize = changes.values().map(|c| c.additions).sum();
+    let deletions: usize = changes.values().map(|c| c.deletions).sum();
+    let files = changes.len();
+    format!(
+        "{files} file{} changed, {additions} insertion{}(+), {deletions} deletion{}(-)",
+        plural(files),
+        plural(additions),
+        plural(deletions)
+    )
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// `1h 5m`, `12m 30s` or `45s`.
+pub(crate) fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (h, m) {
+        (0, 0) => format!("{s}s"),
+        (0, _) => format!("{m}m {s}s"),
+        _ => format!("{h}h {m}m"),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
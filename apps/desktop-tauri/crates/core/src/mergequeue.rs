@@ -0,0 +1,385 @@
+//! Merge queue: land approved agent branches one after another.
+//!
+//! Approved worktrees are queued and landed in order, one at a time. Each
+//! is rebased onto the main checkout's current HEAD, checked with the
+//! repo's check command, if it has one, in a PTY session in the worktree,
+//! and merged when the check exits 0. A branch that only moved by the
+//! rebase keeps its approval. A conflict, failed check, protected path
+//! change or failed merge fails the entry and halts the queue until it is
+//! resumed. Every change is emitted as `merge_queue://changed` with the
+//! whole queue.
+//!
+//! The check command comes from the repo's `merge_checks` setting, then
+//! `merge_check` in its `.pi-builder.toml`.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    events::SharedSink,
+    hooks::{self, HookPoint},
+    locks::WorktreeLocks,
+    mergemsg, protect,
+    pty::{PtyManager, SpawnOptions},
+    rebase::{self, RebaseStatus},
+    repo_cache::RepoCache,
+    repo_config::RepoConfig,
+    review::{self, ReviewState, ReviewStore},
+    settings::Settings,
+    worktree,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// Agent id of check sessions, so the UI can label them.
+pub const CHECK_AGENT_ID: &str = "merge-check";
+
+/// Landed, failed and cancelled entries kept for display.
+const MAX_FINISHED: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStatus {
+    Queued,
+    Rebasing,
+    Checking,
+    Merging,
+    Merged,
+    Failed,
+    Cancelled,
+}
+
+impl MergeStatus {
+    fn is_final(self) -> bool {
+        matches!(self, Self::Merged | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeEntry {
+    pub id: String,
+    pub repo: String,
+    pub worktree: String,
+    pub status: MergeStatus,
+    /// Session running the check command.
+    pub session_id: Option<String>,
+    pub error: Option<String>,
+    /// Main checkout HEAD after the merge.
+    pub commit: Option<String>,
+    /// Unix millis.
+    pub queued_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeQueueState {
+    pub entries: Vec<MergeEntry>,
+    /// An entry failed; nothing more lands until the queue is resumed.
+    pub halted: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    queue: MergeQueueState,
+    /// An entry is being landed.
+    busy: bool,
+}
+
+#[derive(Clone)]
+pub struct MergeQueue {
+    inner: Arc<Mutex<Inner>>,
+    pty: Arc<Mutex<PtyManager>>,
+    settings: Arc<Mutex<Settings>>,
+    repo_cache: Arc<RepoCache>,
+    reviews: Arc<ReviewStore>,
+    locks: WorktreeLocks,
+    events: SharedSink,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MergeQueue {
+    pub fn new(
+        pty: Arc<Mutex<PtyManager>>,
+        settings: Arc<Mutex<Settings>>,
+        repo_cache: Arc<RepoCache>,
+        reviews: Arc<ReviewStore>,
+        locks: WorktreeLocks,
+        events: SharedSink,
+    ) -> Self {
+        Self {
+            inner: Arc::default(),
+            pty,
+            settings,
+            repo_cache,
+            reviews,
+            locks,
+            events,
+            stopped: Arc::default(),
+        }
+    }
+
+    /// Stop starting entries (used at shutdown).
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Queue an approved worktree. Fails if it isn't approved or is queued
+    /// already.
+    pub fn enqueue(&self, repo: &str, worktree: &str) -> Result<MergeEntry> {
+        if self.reviews.get(repo, worktree).state != ReviewState::Approved {
+            return Err(
+                PiError::new(ErrorCode::InvalidTransition, "worktree isn't approved").into()
+            );
+        }
+        let entry = {
+            let mut inner = self.inner.lock().unwrap();
+            let queued = inner
+                .queue
+                .entries
+                .iter()
+                .any(|e| e.repo == repo && e.worktree == worktree && !e.status.is_final());
+            if queued {
+                return Err(PiError::invalid_input("worktree is already queued").into());
+            }
+            let entry = MergeEntry {
+                id: Uuid::new_v4().to_string(),
+                repo: repo.to_string(),
+                worktree: worktree.to_string(),
+                status: MergeStatus::Queued,
+                session_id: None,
+                error: None,
+                commit: None,
+                queued_at: now_ms(),
+                finished_at: None,
+            };
+            inner.queue.entries.push(entry.clone());
+            entry
+        };
+        self.changed();
+        self.pump();
+        Ok(entry)
+    }
+
+    pub fn list(&self) -> MergeQueueState {
+        self.inner.lock().unwrap().queue.clone()
+    }
+
+    /// Take a waiting entry out of the queue. An entry being landed can't
+    /// be cancelled, though killing its check session fails it.
+    pub fn cancel(&self, id: &str) -> Result<()> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner
+                .queue
+                .entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| PiError::invalid_input("no such merge queue entry"))?;
+            match entry.status {
+                MergeStatus::Queued => {}
+                s if s.is_final() => return Ok(()),
+                _ => return Err(PiError::invalid_input("entry is being landed").into()),
+            }
+            entry.status = MergeStatus::Cancelled;
+            entry.finished_at = Some(now_ms());
+        }
+        self.changed();
+        Ok(())
+    }
+
+    /// Continue after a failure.
+    pub fn resume(&self) {
+        self.inner.lock().unwrap().queue.halted = false;
+        self.changed();
+        self.pump();
+    }
+
+    /// Start landing the next entry unless one is in progress or the queue
+    /// is halted.
+    fn pump(&self) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let entry = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.busy || inner.queue.halted {
+                return;
+            }
+            let Some(entry) =
+                inner.queue.entries.iter().find(|e| e.status == MergeStatus::Queued).cloned()
+            else {
+                return;
+            };
+            inner.busy = true;
+            entry
+        };
+        let queue = self.clone();
+        thread::spawn(move || {
+            let result = queue.land(&entry);
+            let failed = result.is_err();
+            queue.update(&entry.id, |e| {
+                match result {
+                    Ok(commit) => {
+                        e.status = MergeStatus::Merged;
+                        e.commit = Some(commit);
+                    }
+                    Err(err) => {
+                        e.status = MergeStatus::Failed;
+                        e.error = Some(format!("{err:#}"));
+                    }
+                }
+                e.finished_at = Some(now_ms());
+            });
+            {
+                let mut inner = queue.inner.lock().unwrap();
+                inner.busy = false;
+                inner.queue.halted |= failed;
+                prune(&mut inner.queue.entries);
+            }
+            queue.changed();
+            queue.pump();
+        });
+    }
+
+    /// Rebase, check and merge one entry. Returns the merge commit.
+    fn land(&self, entry: &MergeEntry) -> Result<String> {
+        let (repo, name) = (entry.repo.as_str(), entry.worktree.as_str());
+        let review = self.reviews.get(repo, name);
+        if review.state != ReviewState::Approved {
+            return Err(anyhow!("worktree is no longer approved"));
+        }
+        let _lock = self.locks.worktree(repo, name, "merge queue")?;
+
+        self.set_status(&entry.id, MergeStatus::Rebasing);
+        let head = self.repo_cache.with_repo(repo, |r| review::worktree_head(r, name))?;
+        if Some(&head) != review.reviewed_head.as_ref() {
+            return Err(anyhow!("branch has new commits since it was approved"));
+        }
+        let sign = self.settings.lock().unwrap().sign_commits;
+        let outcome = self.repo_cache.with_repo(repo, |r| rebase::rebase_in(r, name, sign));
+        self.repo_cache.mark_stale(repo);
+        let outcome = outcome?;
+        if outcome.status == RebaseStatus::Conflict {
+            if let Err(e) = rebase::abort(repo, name) {
+                log::warn!("abort rebase of {name}: {e:#}");
+            }
+            return Err(anyhow!("rebase conflicts in {}", outcome.conflicts.join(", ")));
+        }
+
+        let check = check_command(repo, &self.settings.lock().unwrap());
+        if let Some(check) = check {
+            self.set_status(&entry.id, MergeStatus::Checking);
+            let code = self.run_check(entry, &check)?;
+            if code != 0 {
+                return Err(anyhow!("check `{check}` exited with {code}"));
+            }
+        }
+
+        self.set_status(&entry.id, MergeStatus::Merging);
+        let patterns = protect::patterns(repo, &self.settings.lock().unwrap());
+        let protected = protect::Protected::new(&patterns)?;
+        let violations =
+            self.repo_cache.with_repo(repo, |r| protect::violations(r, name, &protected))?;
+        if !violations.is_empty() {
+            return Err(anyhow!("changes protected paths: {}", violations.join(", ")));
+        }
+        let hooks = self.settings.lock().unwrap().hooks.clone();
+        let input = serde_json::json!({ "repo": repo, "worktree": name });
+        hooks::run(&hooks, HookPoint::PreMerge, input)?;
+        let _main = self.locks.main_checkout(repo, "merge queue")?;
+        let template = mergemsg::template(repo, &self.settings.lock().unwrap());
+        let sessions = self.pty.lock().unwrap().usage_records();
+        let merged = self
+            .repo_cache
+            .with_repo(repo, |r| review::merge(r, name, &outcome.head, &template, &sessions, sign));
+        self.repo_cache.mark_stale(repo);
+        let merged = merged?;
+        let review = self.reviews.transition(
+            repo,
+            name,
+            ReviewState::Merged,
+            None,
+            Some(merged.commit.clone()),
+        )?;
+        self.events.emit("review://changed", serde_json::json!({ "repo": repo, "entry": review }));
+        Ok(merged.commit)
+    }
+
+    /// Run `check` in the worktree and wait for its exit code.
+    fn run_check(&self, entry: &MergeEntry, check: &str) -> Result<u32> {
+        let path = worktree::worktree_path(&entry.repo, &entry.worktree)?;
+        let argv = if cfg!(windows) {
+            vec!["cmd.exe".into(), "/C".into(), check.to_string()]
+        } else {
+            vec!["sh".into(), "-c".into(), check.to_string()]
+        };
+        let size = self.settings.lock().unwrap().terminal;
+        let mut opts = SpawnOptions::new(CHECK_AGENT_ID, argv);
+        opts.cwd = Some(path.to_string_lossy().into_owned());
+        opts.cols = size.cols;
+        opts.rows = size.rows;
+        opts.repo = Some(entry.repo.clone());
+        opts.meta.title = Some(format!("check {}", entry.worktree));
+        let (tx, rx) = mpsc::channel();
+        opts.on_exit = Some(Box::new(move |code| {
+            let _ = tx.send(code);
+        }));
+        let session_id = self.pty.lock().unwrap().spawn(opts, self.events.clone())?;
+        self.update(&entry.id, |e| e.session_id = Some(session_id));
+        rx.recv().map_err(|_| anyhow!("check session ended without an exit code"))
+    }
+
+    fn set_status(&self, id: &str, status: MergeStatus) {
+        self.update(id, |e| e.status = status);
+        self.changed();
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut MergeEntry)) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.queue.entries.iter_mut().find(|e| e.id == id) {
+            f(entry);
+        }
+    }
+
+    fn changed(&self) {
+        let queue = self.list();
+        self.events.emit("merge_queue://changed", serde_json::to_value(queue).unwrap_or_default());
+    }
+}
+
+/// The repo's check command: its setting, then its config file.
+pub fn check_command(repo: &str, settings: &Settings) -> Option<String> {
+    settings
+        .merge_checks
+        .get(repo)
+        .cloned()
+        .or_else(|| RepoConfig::load_or_default(Path::new(repo)).merge_check)
+        .filter(|c| !c.trim().is_empty())
+}
+
+/// Drop the oldest finished entries beyond `MAX_FINISHED`.
+fn prune(entries: &mut Vec<MergeEntry>) {
+    let finished = entries.iter().filter(|e| e.status.is_final()).count();
+    let mut excess = finished.saturating_sub(MAX_FINISHED);
+    entries.retain(|e| {
+        if excess > 0 && e.status.is_final() {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,111 @@
+//! Desktop notifications for agent activity.
+//!
+//! `NotifyingSink` wraps the app's event sink and turns selected events
+//! into OS notifications: a session exiting (`pty://exit`), an agent going
+//! quiet (`pty://idle`), and a merge conflict (`worktree://conflict`). Each
+//! kind can be switched off in settings.
+
+use crate::{
+    events::{EventSink, SharedSink},
+    settings::Settings,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationPrefs {
+    pub session_exit: bool,
+    pub agent_idle: bool,
+    pub merge_conflict: bool,
+    /// Play the platform's default notification sound.
+    pub sound: bool,
+    /// Seconds without output before a session counts as idle; 0 disables
+    /// idle detection.
+    pub idle_secs: u64,
+}
+
+impl Default for NotificationPrefs {
+    fn default() -> Self {
+        Self {
+            session_exit: true,
+            agent_idle: true,
+            merge_conflict: true,
+            sound: true,
+            idle_secs: 60,
+        }
+    }
+}
+
+/// Shows an OS notification. Abstracted so the sink works without Tauri.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, title: &str, body: &str, sound: bool);
+}
+
+pub type SharedNotifier = Arc<dyn Notifier>;
+
+/// Event sink that forwards everything to `inner` and raises notifications
+/// for the events enabled in settings.
+pub struct NotifyingSink {
+    inner: SharedSink,
+    notifier: SharedNotifier,
+    settings: Arc<Mutex<Settings>>,
+    muted: AtomicBool,
+}
+
+impl NotifyingSink {
+    pub fn new(
+        inner: SharedSink,
+        notifier: SharedNotifier,
+        settings: Arc<Mutex<Settings>>,
+    ) -> Self {
+        Self { inner, notifier, settings, muted: AtomicBool::new(false) }
+    }
+
+    /// Stop raising notifications; events are still forwarded.
+    pub fn mute(&self) {
+        self.muted.store(true, Ordering::SeqCst);
+    }
+}
+
+impl EventSink for NotifyingSink {
+    fn emit(&self, event: &str, payload: Value) {
+        if self.muted.load(Ordering::SeqCst) {
+            self.inner.emit(event, payload);
+            return;
+        }
+        let prefs = self.settings.lock().unwrap().notifications.clone();
+        if let Some((title, body)) = notification_for(&prefs, event, &payload) {
+            self.notifier.notify(&title, &body, prefs.sound);
+        }
+        self.inner.emit(event, payload);
+    }
+}
+
+/// Title and body for an event, or `None` if it isn't one we notify on (or
+/// its kind is disabled).
+pub fn notification_for(
+    prefs: &NotificationPrefs,
+    event: &str,
+    payload: &Value,
+) -> Option<(String, String)> {
+    let agent = payload["agentId"].as_str().unwrap_or("agent");
+    if event.starts_with("pty://exit/") && prefs.session_exit {
+        let code = payload["exitCode"].as_u64().unwrap_or(0);
+        let title = if code == 0 { "Agent finished" } else { "Agent failed" };
+        return Some((title.into(), format!("{agent} exited with code {code}")));
+    }
+    if event.starts_with("pty://idle/") && prefs.agent_idle {
+        let secs = payload["idleSecs"].as_u64().unwrap_or(prefs.idle_secs);
+        return Some(("Agent idle".into(), format!("{agent} has been quiet for {secs}s")));
+    }
+    if event.starts_with("worktree://conflict") && prefs.merge_conflict {
+        let worktree = payload["worktree"].as_str().unwrap_or("worktree");
+        return Some(("Merge conflict".into(), format!("Conflicts in {worktree} need attention")));
+    }
+    None
+}
@@ -0,0 +1,189 @@
+//! Pasting into a session: bracketed paste and the paste guard.
+//!
+//! Applications that can tell typed input from pasted text (shells with
+//! readline, most agent TUIs) turn on bracketed paste with `ESC[?2004h`.
+//! The reader watches for it, and `pty_paste` wraps the text in
+//! `ESC[200~ ... ESC[201~` while it is on, so a pasted newline doesn't
+//! submit a half-pasted prompt.
+//!
+//! Multi-line pastes that look dangerous (`rm -rf`, force pushes, a whole
+//! file) can be held back until the user confirms: `pty_paste` emits
+//! `pty://paste-confirm/<id>` and waits for `pty_paste_confirm`.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::oneshot;
+
+pub(crate) const PASTE_START: &str = "\x1b[200~";
+pub(crate) const PASTE_END: &str = "\x1b[201~";
+
+/// DECSET/DECRST private mode for bracketed paste.
+const BRACKETED_PASTE_MODE: &str = "2004";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasteSettings {
+    /// Ask before sending multi-line pastes the guard flags.
+    pub confirm: bool,
+    /// Multi-line pastes longer than this are flagged.
+    pub max_lines: usize,
+    /// How long a paste waits for confirmation before it is dropped.
+    pub confirm_timeout_secs: u64,
+}
+
+impl Default for PasteSettings {
+    fn default() -> Self {
+        Self { confirm: true, max_lines: 50, confirm_timeout_secs: 60 }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasteResult {
+    /// False when the user declined or didn't answer in time.
+    pub pasted: bool,
+    pub bracketed: bool,
+    /// Why the guard asked for confirmation.
+    pub flagged: Vec<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Bracketed paste mode
+// ---------------------------------------------------------------------------
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    #[default]
+    Text,
+    Esc,
+    Csi,
+    /// Inside `ESC [ ?`, collecting parameters.
+    Private,
+}
+
+/// Follows `ESC[?2004h` / `ESC[?2004l` in a session's output, including
+/// sequences split across reads and combined ones like `ESC[?1049;2004h`.
+#[derive(Default)]
+pub struct PasteModeTracker {
+    scan: Scan,
+    params: String,
+    enabled: bool,
+}
+
+impl PasteModeTracker {
+    /// Returns the new mode if `bytes` changed it.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<bool> {
+        let before = self.enabled;
+        for &b in bytes {
+            self.scan = match (self.scan, b) {
+                (_, 0x1b) => Scan::Esc,
+                (Scan::Esc, b'[') => Scan::Csi,
+                (Scan::Csi, b'?') => {
+                    self.params.clear();
+                    Scan::Private
+                }
+                (Scan::Private, b'0'..=b'9' | b';') if self.params.len() < 64 => {
+                    self.params.push(b as char);
+                    Scan::Private
+                }
+                (Scan::Private, b'h' | b'l') => {
+                    if self.params.split(';').any(|p| p == BRACKETED_PASTE_MODE) {
+                        self.enabled = b == b'h';
+                    }
+                    Scan::Text
+                }
+                _ => Scan::Text,
+            };
+        }
+        (self.enabled != before).then_some(self.enabled)
+    }
+}
+
+/// Text to write for a paste: newlines as the Enter key sends them, and
+/// bracketed when the application asked for it. An embedded end marker is
+/// removed so pasted text can't break out of the bracket.
+pub fn encode(data: &str, bracketed: bool) -> String {
+    let text = data.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        format!("{PASTE_START}{}{PASTE_END}", text.replace(PASTE_END, ""))
+    } else {
+        text
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Guard
+// ---------------------------------------------------------------------------
+
+/// Why a paste needs confirmation; empty if it doesn't. Single-line pastes
+/// are never flagged: nothing runs until the user presses Enter.
+pub fn check(data: &str, settings: &PasteSettings) -> Vec<String> {
+    let lines = data.trim_end().lines().count();
+    if lines < 2 {
+        return Vec::new();
+    }
+    let mut reasons: Vec<String> = destructive_patterns()
+        .iter()
+        .filter(|(re, _)| re.is_match(data))
+        .map(|(_, what)| what.to_string())
+        .collect();
+    if lines > settings.max_lines {
+        reasons.push(format!("{lines} lines"));
+    }
+    reasons
+}
+
+/// Commands worth a second look, and what to call them. Also the default
+/// confirm list of guarded sessions (`guard.rs`).
+pub(crate) const DESTRUCTIVE: [(&str, &str); 10] = [
+    (r"\brm\s+(-\w*[rf]\w*\s+)+", "recursive or forced rm"),
+    (r"\bgit\s+push\b.*(\s-f\b|--force)", "force push"),
+    (r"\bgit\s+reset\s+--hard\b", "git reset --hard"),
+    (r"\bgit\s+clean\s+-\w*f", "git clean"),
+    (r"(?i)\bdrop\s+(table|database|schema)\b", "SQL DROP"),
+    (r"(?i)\btruncate\s+table\b", "SQL TRUNCATE"),
+    (r"\b(mkfs(\.\w+)?|dd\s+if=)", "disk write"),
+    (r"\bchmod\s+-R\s+0?777\b", "chmod -R 777"),
+    (r"\b(curl|wget)\b[^|\n]*\|\s*(sudo\s+)?(ba|z)?sh\b", "pipe to shell"),
+    (r":\(\)\s*\{\s*:\|:&\s*\};:", "fork bomb"),
+];
+
+fn destructive_patterns() -> &'static [(Regex, &'static str)] {
+    static PATTERNS: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        DESTRUCTIVE
+            .into_iter()
+            .map(|(p, what)| (Regex::new(p).expect("paste guard pattern"), what))
+            .collect()
+    })
+}
+
+/// Pastes waiting for the user's answer, by paste id.
+#[derive(Default)]
+pub struct PendingPastes {
+    waiting: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+}
+
+impl PendingPastes {
+    pub fn wait(&self, paste_id: &str) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(paste_id.to_string(), tx);
+        rx
+    }
+
+    /// Answer a pending paste. Returns false if it already timed out.
+    pub fn answer(&self, paste_id: &str, accept: bool) -> bool {
+        match self.waiting.lock().unwrap().remove(paste_id) {
+            Some(tx) => tx.send(accept).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn forget(&self, paste_id: &str) {
+        self.waiting.lock().unwrap().remove(paste_id);
+    }
+}
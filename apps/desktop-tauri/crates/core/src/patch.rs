@@ -0,0 +1,167 @@
+//! Applying external patches to a worktree.
+//!
+//! Patches come from agents running elsewhere or from PR suggestions. Each
+//! hunk is first checked on its own against the worktree, so the report
+//! says exactly which hunks don't fit. The patch is then applied whole or
+//! not at all, like `git apply`. With `three_way`, a patch that doesn't
+//! apply cleanly goes through `git apply --3way` instead, which falls back
+//! on the blobs recorded in the patch and leaves conflict markers where it
+//! must (libgit2 has no three-way apply).
+
+use crate::{error::PiError, worktree};
+use anyhow::{bail, Context, Result};
+use git2::{ApplyLocation, ApplyOptions, Diff, Patch, Repository};
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, Serialize)]
+pub struct HunkResult {
+    pub header: String,
+    pub old_start: u32,
+    pub new_start: u32,
+    /// Applies cleanly on its own.
+    pub ok: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PatchFile {
+    pub path: String,
+    pub hunks: Vec<HunkResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PatchReport {
+    /// Whether anything was written to the worktree.
+    pub applied: bool,
+    /// Applied with `git apply --3way`.
+    pub three_way: bool,
+    pub files: Vec<PatchFile>,
+    /// Files left with conflict markers by a three-way apply.
+    pub conflicts: Vec<String>,
+    /// Why the patch wasn't applied, if it wasn't.
+    pub error: Option<String>,
+}
+
+/// Apply `unified_diff` to the working tree of `worktree`.
+pub fn apply(
+    main: &Repository,
+    worktree: &str,
+    unified_diff: &str,
+    three_way: bool,
+) -> Result<PatchReport> {
+    let repo = worktree::open_in(main, worktree)?;
+    let diff = Diff::from_buffer(unified_diff.as_bytes())
+        .map_err(|e| PiError::invalid_input(format!("not a unified diff: {}", e.message())))?;
+    let files = check_hunks(&repo, &diff)?;
+    let clean = files.iter().all(|f| f.hunks.iter().all(|h| h.ok));
+
+    let mut report =
+        PatchReport { applied: false, three_way: false, files, conflicts: Vec::new(), error: None };
+    if clean {
+        match repo.apply(&diff, ApplyLocation::WorkDir, None) {
+            Ok(()) => report.applied = true,
+            Err(e) => report.error = Some(e.message().to_string()),
+        }
+        if report.applied || !three_way {
+            return Ok(report);
+        }
+    } else if !three_way {
+        report.error = Some("some hunks don't apply".into());
+        return Ok(report);
+    }
+
+    report.three_way = true;
+    let workdir = repo.workdir().context("bare worktree")?;
+    match apply_three_way(workdir, unified_diff) {
+        Ok(()) => {
+            report.applied = true;
+            report.error = None;
+        }
+        Err(e) => report.error = Some(format!("{e:#}")),
+    }
+    report.conflicts = conflicted(&repo);
+    // --3way writes conflicts and still exits non-zero
+    report.applied |= !report.conflicts.is_empty();
+    Ok(report)
+}
+
+/// Try every hunk alone against the working tree, without writing.
+fn check_hunks(repo: &Repository, diff: &Diff) -> Result<Vec<PatchFile>> {
+    let mut files = Vec::new();
+    for (idx, delta) in diff.deltas().enumerate() {
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let mut hunks = Vec::new();
+        let Some(patch) = Patch::from_diff(diff, idx)? else {
+            files.push(PatchFile { path, hunks });
+            continue;
+        };
+        for h in 0..patch.num_hunks() {
+            let (hunk, _) = patch.hunk(h)?;
+            let coords = (hunk.old_start(), hunk.old_lines(), hunk.new_start(), hunk.new_lines());
+            let mut opts = ApplyOptions::new();
+            opts.check(true);
+            opts.delta_callback(|d| {
+                d.and_then(|d| d.new_file().path().or_else(|| d.old_file().path()))
+                    .is_some_and(|p| p.to_string_lossy().replace('\\', "/") == path)
+            });
+            opts.hunk_callback(|hunk| {
+                hunk.is_some_and(|h| {
+                    (h.old_start(), h.old_lines(), h.new_start(), h.new_lines()) == coords
+                })
+            });
+            let ok = repo.apply(diff, ApplyLocation::WorkDir, Some(&mut opts)).is_ok();
+            hunks.push(HunkResult {
+                header: String::from_utf8_lossy(hunk.header()).trim_end().to_string(),
+                old_start: hunk.old_start(),
+                new_start: hunk.new_start(),
+                ok,
+            });
+        }
+        files.push(PatchFile { path, hunks });
+    }
+    Ok(files)
+}
+
+fn apply_three_way(workdir: &Path, unified_diff: &str) -> Result<()> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(workdir)
+        .args(["apply", "--3way", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("run git apply")?;
+    child
+        .stdin
+        .take()
+        .context("git apply stdin")?
+        .write_all(unified_diff.as_bytes())
+        .context("write patch")?;
+    let out = child.wait_with_output().context("run git apply")?;
+    if !out.status.success() {
+        bail!("git apply --3way: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+fn conflicted(repo: &Repository) -> Vec<String> {
+    let Ok(index) = repo.index() else { return Vec::new() };
+    let Ok(conflicts) = index.conflicts() else { return Vec::new() };
+    let mut paths: Vec<String> = conflicts
+        .flatten()
+        .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+        .map(|e| String::from_utf8_lossy(&e.path).to_string())
+        .collect();
+    paths.dedup();
+    paths
+}
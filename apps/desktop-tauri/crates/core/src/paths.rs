@@ -0,0 +1,186 @@
+//! Paths the OS and git can use, long ones on Windows included.
+//!
+//! `check` rejects a path up front, saying why, when it isn't Unicode (git
+//! works in UTF-8), has a name too long for the filesystem, or on Windows
+//! has a reserved name (`CON`, `NUL`, `COM1`, ...), a character Windows
+//! doesn't allow, or a name ending in a dot or space. Otherwise such a
+//! path fails deep inside git2 with a message that doesn't say which path
+//! or what is wrong with it.
+//!
+//! Windows limits paths to 260 chars (`MAX_PATH`) unless they carry the
+//! `\\?\` extended-length prefix. std's file functions add it themselves
+//! and libgit2 does once `core.longpaths` is set, which `enable_long_paths`
+//! does for a repo before worktrees are added to it. A process can't
+//! start in a directory that long, prefix or not, so `cwd` gives the
+//! directory's 8.3 short name instead, looked up by its prefixed path.
+
+use crate::error::PiError;
+use anyhow::Result;
+use git2::Repository;
+use std::path::{Component, Path, PathBuf};
+
+/// Longest path Windows takes without the extended-length prefix.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Longest file name most filesystems allow.
+const MAX_NAME: usize = 255;
+
+/// Longest extended-length path on Windows, in UTF-16 units.
+const MAX_EXTENDED: usize = 32_767;
+
+/// Device names Windows reserves, with or without an extension.
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows doesn't allow in file names.
+const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Fail, saying why, unless `path` is one git and the OS can use. `what`
+/// names it in the message ("repo", "worktree", ...).
+pub fn check(path: &Path, what: &str) -> Result<()> {
+    let Some(text) = path.to_str() else {
+        return Err(unusable(path, what, "isn't valid Unicode, which git needs"));
+    };
+    for component in path.components() {
+        let Component::Normal(name) = component else { continue };
+        let name = name.to_string_lossy();
+        let len = if cfg!(windows) { name.encode_utf16().count() } else { name.len() };
+        if len > MAX_NAME {
+            return Err(unusable(path, what, &format!("has a name over {MAX_NAME} characters")));
+        }
+        if cfg!(windows) {
+            if let Some(reason) = windows_name_problem(&name) {
+                return Err(unusable(path, what, &reason));
+            }
+        }
+    }
+    if cfg!(windows) && text.encode_utf16().count() > MAX_EXTENDED {
+        return Err(unusable(path, what, "is longer than Windows allows"));
+    }
+    Ok(())
+}
+
+/// Why Windows can't have a file named `name`, if it can't.
+fn windows_name_problem(name: &str) -> Option<String> {
+    if let Some(c) = name.chars().find(|c| INVALID_CHARS.contains(c) || c.is_control()) {
+        return Some(format!("has {c:?} in {name:?}, which Windows doesn't allow"));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Some(format!("has {name:?}, which Windows can't keep a trailing dot or space on"));
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Some(format!("has {name:?}, a name Windows reserves for a device"));
+    }
+    None
+}
+
+fn unusable(path: &Path, what: &str, reason: &str) -> anyhow::Error {
+    PiError::invalid_path(format!("{what} path {} {reason}", path.display())).into()
+}
+
+/// Set `core.longpaths` in `repo`'s config on Windows unless it is set
+/// either way, so libgit2 and Git for Windows check out files past
+/// `MAX_PATH`. Does nothing elsewhere.
+pub fn enable_long_paths(repo: &Repository) -> Result<()> {
+    if !cfg!(windows) {
+        return Ok(());
+    }
+    let mut config = repo.config()?;
+    if config.get_bool("core.longpaths").is_err() {
+        config.set_bool("core.longpaths", true)?;
+    }
+    Ok(())
+}
+
+/// The directory to start a process in for `dir`: `dir`, or on Windows
+/// its 8.3 short name when `dir` is too long to start in. Fails, saying
+/// why, when it isn't a directory or has no short name short enough.
+pub fn cwd(dir: &str) -> Result<PathBuf> {
+    let path = Path::new(dir);
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => return Err(unusable(path, "working directory", "isn't a directory")),
+        Err(e) => return Err(unusable(path, "working directory", &format!("can't be used: {e}"))),
+    }
+    #[cfg(windows)]
+    if path.as_os_str().len() >= MAX_PATH {
+        let short = windows::short_name(path).filter(|s| s.as_os_str().len() < MAX_PATH);
+        let reason = "is too long for Windows to start a process in; move the repo or \
+                      worktree root somewhere shorter";
+        return short.ok_or_else(|| unusable(path, "working directory", reason));
+    }
+    Ok(path.to_path_buf())
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::{OsStr, OsString},
+        os::windows::ffi::{OsStrExt, OsStringExt},
+        path::{Component, Path, PathBuf, Prefix},
+    };
+    use windows_sys::Win32::Storage::FileSystem::GetShortPathNameW;
+
+    /// `path` as `\\?\C:\...` or `\\?\UNC\server\share\...`, with `.` and
+    /// `..` resolved as the prefix stops Windows doing it. `None` unless it
+    /// starts with a drive or a UNC share.
+    pub fn extended(path: &Path) -> Option<PathBuf> {
+        let mut components = path.components();
+        let Some(Component::Prefix(prefix)) = components.next() else { return None };
+        let mut out = match prefix.kind() {
+            Prefix::Disk(letter) => OsString::from(format!(r"\\?\{}:", letter as char)),
+            Prefix::UNC(server, share) => {
+                let mut out = OsString::from(r"\\?\UNC\");
+                out.push(server);
+                out.push(r"\");
+                out.push(share);
+                out
+            }
+            _ => return None,
+        };
+        let mut names: Vec<&OsStr> = Vec::new();
+        for component in components {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::ParentDir => {
+                    names.pop();
+                }
+                _ => {}
+            }
+        }
+        for name in names {
+            out.push(r"\");
+            out.push(name);
+        }
+        Some(PathBuf::from(out))
+    }
+
+    /// The 8.3 short name of existing `path`, without the prefix. `None`
+    /// where the volume keeps no short names.
+    pub fn short_name(path: &Path) -> Option<PathBuf> {
+        let long = extended(path).unwrap_or_else(|| path.to_path_buf());
+        let wide: Vec<u16> = long.as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `wide` is NUL-terminated; a zero-length call only sizes
+        let len = unsafe { GetShortPathNameW(wide.as_ptr(), std::ptr::null_mut(), 0) };
+        if len == 0 {
+            return None;
+        }
+        let mut buf = vec![0u16; len as usize];
+        // SAFETY: `buf` holds `len` units, as asked for
+        let written = unsafe { GetShortPathNameW(wide.as_ptr(), buf.as_mut_ptr(), len) };
+        if written == 0 || written >= len {
+            return None;
+        }
+        buf.truncate(written as usize);
+        let short = OsString::from_wide(&buf).to_string_lossy().into_owned();
+        let short = match short.strip_prefix(r"\\?\UNC\") {
+            Some(rest) => format!(r"\\{rest}"),
+            None => short.strip_prefix(r"\\?\").unwrap_or(&short).to_string(),
+        };
+        Some(PathBuf::from(short))
+    }
+}
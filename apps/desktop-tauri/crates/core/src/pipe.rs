@@ -0,0 +1,239 @@
+//! Pipes: one session's output typed into another.
+//!
+//! A pipe takes each complete line of its source session's output, ANSI
+//! stripped and redacted, and keeps it if it matches the pipe's filter
+//! regex (every line without one). Kept lines are gathered until the
+//! source has been quiet for `FLUSH_AFTER`, or `MAX_BATCH` bytes are
+//! waiting, and then pasted into the target session followed by Enter, so
+//! an agent gets a burst of test output as one message rather than a line
+//! at a time. Pipes that would form a cycle are refused: two sessions
+//! echoing each other's output would never stop.
+//!
+//! A pipe closes when it is removed, when its source exits, or when typing
+//! into its target fails; `pty://pipe-closed/<id>` is emitted then.
+
+use crate::{error::PiError, events::SharedSink, scrollback::LineTracker};
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Quiet time on the source before gathered lines are sent.
+const FLUSH_AFTER: Duration = Duration::from_millis(500);
+
+/// Gathered text sent without waiting for the source to go quiet.
+const MAX_BATCH: usize = 16 * 1024;
+
+/// Longest line forwarded; longer lines are cut.
+const MAX_LINE: usize = 4096;
+
+/// Output lines of a session, split off for its pipes.
+#[derive(Clone, Default)]
+pub struct OutputTaps {
+    taps: Arc<Mutex<Vec<Tap>>>,
+}
+
+struct Tap {
+    pipe_id: String,
+    lines: LineTracker,
+    filter: Option<Regex>,
+    tx: mpsc::Sender<String>,
+}
+
+impl OutputTaps {
+    /// Split `bytes` into lines for every tap; taps whose pipe is gone are
+    /// dropped.
+    pub(crate) fn feed(&self, bytes: &[u8]) {
+        let mut taps = self.taps.lock().unwrap();
+        taps.retain_mut(|tap| {
+            let mut open = true;
+            let Tap { lines, filter, tx, .. } = tap;
+            lines.feed(bytes, |line| {
+                if open && filter.as_ref().map_or(true, |f| f.is_match(&line)) {
+                    open = tx.send(line).is_ok();
+                }
+            });
+            open
+        });
+    }
+
+    /// Drop every tap, which closes their pipes once the lines already
+    /// gathered are sent. Called when the session's output ends.
+    pub(crate) fn close(&self) {
+        self.taps.lock().unwrap().clear();
+    }
+
+    fn remove(&self, pipe_id: &str) {
+        self.taps.lock().unwrap().retain(|t| t.pipe_id != pipe_id);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipeInfo {
+    pub id: String,
+    pub from_session: String,
+    pub to_session: String,
+    pub filter: Option<String>,
+}
+
+struct Pipe {
+    info: PipeInfo,
+    taps: OutputTaps,
+}
+
+/// Open pipes.
+#[derive(Clone, Default)]
+pub struct Pipes {
+    pipes: Arc<Mutex<HashMap<String, Pipe>>>,
+}
+
+impl Pipes {
+    /// Forward lines of the session whose output `taps` carries to
+    /// `to_session` through `write`, on a thread of its own.
+    pub fn open(
+        &self,
+        from_session: &str,
+        to_session: &str,
+        filter: Option<String>,
+        taps: OutputTaps,
+        mut write: impl FnMut(&str) -> Result<()> + Send + 'static,
+        events: SharedSink,
+    ) -> Result<PipeInfo> {
+        if from_session == to_session {
+            return Err(PiError::invalid_input("can't pipe a session into itself").into());
+        }
+        let regex = match filter.as_deref() {
+            Some(f) => Some(
+                Regex::new(f).map_err(|e| PiError::invalid_input(format!("bad filter: {e}")))?,
+            ),
+            None => None,
+        };
+        let info = PipeInfo {
+            id: Uuid::new_v4().to_string(),
+            from_session: from_session.to_string(),
+            to_session: to_session.to_string(),
+            filter,
+        };
+        let (tx, rx) = mpsc::channel::<String>();
+        {
+            let mut pipes = self.pipes.lock().unwrap();
+            if reaches(&pipes, to_session, from_session) {
+                return Err(PiError::invalid_input("pipe would form a cycle").into());
+            }
+            let tap = Tap {
+                pipe_id: info.id.clone(),
+                lines: LineTracker::new(MAX_LINE),
+                filter: regex,
+                tx,
+            };
+            taps.taps.lock().unwrap().push(tap);
+            pipes.insert(info.id.clone(), Pipe { info: info.clone(), taps });
+        }
+
+        let pipes = self.clone();
+        let id = info.id.clone();
+        thread::spawn(move || {
+            let mut batch = String::new();
+            let mut first: Option<Instant> = None;
+            let error = loop {
+                let (line, open) = match rx.recv_timeout(FLUSH_AFTER) {
+                    Ok(line) => (Some(line), true),
+                    Err(RecvTimeoutError::Timeout) => (None, true),
+                    Err(RecvTimeoutError::Disconnected) => (None, false),
+                };
+                let quiet = line.is_none();
+                if let Some(line) = line {
+                    batch.push_str(&line);
+                    batch.push('\n');
+                    first.get_or_insert_with(Instant::now);
+                }
+                // A source that never goes quiet still gets its lines out
+                let due = quiet
+                    || batch.len() >= MAX_BATCH
+                    || first.is_some_and(|t| t.elapsed() >= FLUSH_AFTER * 4);
+                if due && !batch.is_empty() {
+                    if let Err(e) = write(batch.trim_end_matches('\n')) {
+                        break Some(format!("{e:#}"));
+                    }
+                    batch.clear();
+                    first = None;
+                }
+                if !open {
+                    break None;
+                }
+            };
+            pipes.remove(&id);
+            events.emit(
+                &format!("pty://pipe-closed/{id}"),
+                serde_json::json!({ "pipeId": id, "error": error }),
+            );
+        });
+        Ok(info)
+    }
+
+    pub fn list(&self) -> Vec<PipeInfo> {
+        let pipes = self.pipes.lock().unwrap();
+        let mut list: Vec<PipeInfo> = pipes.values().map(|p| p.info.clone()).collect();
+        list.sort_by(|a, b| a.from_session.cmp(&b.from_session).then(a.id.cmp(&b.id)));
+        list
+    }
+
+    /// Close a pipe; lines already gathered are still sent.
+    pub fn close(&self, pipe_id: &str) -> Result<()> {
+        let pipe = self.pipes.lock().unwrap().remove(pipe_id);
+        let pipe = pipe.ok_or_else(|| PiError::invalid_input("no such pipe"))?;
+        pipe.taps.remove(pipe_id);
+        Ok(())
+    }
+
+    /// Close the pipes into or out of a session.
+    pub fn close_session(&self, session_id: &str) {
+        let ids: Vec<String> = self
+            .list()
+            .into_iter()
+            .filter(|p| p.from_session == session_id || p.to_session == session_id)
+            .map(|p| p.id)
+            .collect();
+        for id in ids {
+            let _ = self.close(&id);
+        }
+    }
+
+    fn remove(&self, pipe_id: &str) {
+        if let Some(pipe) = self.pipes.lock().unwrap().remove(pipe_id) {
+            pipe.taps.remove(pipe_id);
+        }
+    }
+}
+
+/// Whether output of `from` already flows into `to` through open pipes.
+fn reaches(pipes: &HashMap<String, Pipe>, from: &str, to: &str) -> bool {
+    let mut stack = vec![from];
+    let mut seen = Vec::new();
+    while let Some(session) = stack.pop() {
+        if session == to {
+            return true;
+        }
+        if seen.contains(&session) {
+            continue;
+        }
+        seen.push(session);
+        stack.extend(
+            pipes
+                .values()
+                .filter(|p| p.info.from_session == session)
+                .map(|p| p.info.to_session.as_str()),
+        );
+    }
+    false
+}
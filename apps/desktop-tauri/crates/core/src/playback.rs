@@ -0,0 +1,256 @@
+//! Playback of session recordings.
+//!
+//! Opening a recording (see `recording.rs`) gives a virtual session that
+//! can be played at any speed, paused and seeked. While it plays its
+//! output is emitted as `playback://data/<id>` with the recording time,
+//! resizes as `playback://resize/<id>`, and `playback://ended/<id>` once
+//! the end is reached. Gaps with no output play as at most `MAX_IDLE`, so
+//! a night's session doesn't take a night to watch.
+//!
+//! Seeking renders the screen at that time on the backend: the terminal
+//! state is kept every `KEYFRAME_BYTES` of output while opening, and a
+//! seek starts from the last keyframe before it rather than replaying
+//! from zero. The result is a `ScreenSnapshot` whose `ansi` the frontend
+//! writes into a cleared xterm before playing on.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    events::SharedSink,
+    recording::{self, CastEvent},
+    screen::{Screen, ScreenSnapshot},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Output replayed at most between keyframes.
+const KEYFRAME_BYTES: usize = 256 * 1024;
+
+/// Longest pause played, before dividing by the speed.
+const MAX_IDLE: Duration = Duration::from_secs(2);
+
+/// How often a sleeping playback checks whether it was paused.
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackInfo {
+    pub id: String,
+    pub recording_id: String,
+    /// Size at the start of the recording.
+    pub cols: u16,
+    pub rows: u16,
+    pub duration_ms: u64,
+}
+
+/// Terminal state just before event `index`.
+struct Keyframe {
+    index: usize,
+    cols: u16,
+    rows: u16,
+    state: Vec<u8>,
+}
+
+struct Playback {
+    info: PlaybackInfo,
+    events: Vec<(u64, CastEvent)>,
+    keyframes: Vec<Keyframe>,
+    /// Recording time playback is at, in ms.
+    position: Mutex<u64>,
+    speed: Mutex<f64>,
+    /// Set while playing; cleared to pause.
+    playing: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl Playback {
+    /// Index of the first event after `t`.
+    fn next_index(&self, t: u64) -> usize {
+        self.events.partition_point(|(at, _)| *at <= t)
+    }
+
+    /// Stop playing. Returns whether it was.
+    fn pause(&self) -> bool {
+        let running = self.playing.lock().unwrap().take();
+        if let Some(running) = &running {
+            running.store(false, Ordering::SeqCst);
+        }
+        running.is_some()
+    }
+}
+
+/// Open playbacks.
+#[derive(Clone, Default)]
+pub struct Playbacks {
+    open: Arc<Mutex<HashMap<String, Arc<Playback>>>>,
+}
+
+impl Playbacks {
+    /// Open the recording `recording_id` in `dir`, paused at its start.
+    pub fn open(&self, dir: &Path, recording_id: &str) -> Result<PlaybackInfo> {
+        let cast = recording::load(dir, recording_id)?;
+        let mut screen = Screen::new(cast.rows, cast.cols);
+        let (mut cols, mut rows) = (cast.cols, cast.rows);
+        let mut keyframes = Vec::new();
+        let mut since_keyframe = KEYFRAME_BYTES;
+        for (index, (_, event)) in cast.events.iter().enumerate() {
+            if since_keyframe >= KEYFRAME_BYTES {
+                keyframes.push(Keyframe { index, cols, rows, state: screen.state() });
+                since_keyframe = 0;
+            }
+            match event {
+                CastEvent::Output(data) => {
+                    screen.feed(data.as_bytes());
+                    since_keyframe += data.len();
+                }
+                CastEvent::Resize { cols: c, rows: r } => {
+                    (cols, rows) = (*c, *r);
+                    screen.resize(rows, cols);
+                }
+            }
+        }
+        let info = PlaybackInfo {
+            id: Uuid::new_v4().to_string(),
+            recording_id: recording_id.to_string(),
+            cols: cast.cols,
+            rows: cast.rows,
+            duration_ms: cast.events.last().map_or(0, |(t, _)| *t),
+        };
+        let playback = Playback {
+            info: info.clone(),
+            events: cast.events,
+            keyframes,
+            position: Mutex::new(0),
+            speed: Mutex::new(1.0),
+            playing: Mutex::new(None),
+        };
+        self.open.lock().unwrap().insert(info.id.clone(), Arc::new(playback));
+        Ok(info)
+    }
+
+    /// Move to `t` ms into the recording and return the screen then. A
+    /// playing playback carries on from there.
+    pub fn seek(&self, playback_id: &str, t: u64, events: SharedSink) -> Result<ScreenSnapshot> {
+        let playback = self.get(playback_id)?;
+        let t = t.min(playback.info.duration_ms);
+        let next = playback.next_index(t);
+        let (mut screen, from) = match playback.keyframes.iter().rev().find(|k| k.index <= next) {
+            Some(k) => {
+                let mut screen = Screen::new(k.rows, k.cols);
+                screen.feed(&k.state);
+                (screen, k.index)
+            }
+            None => (Screen::new(playback.info.rows, playback.info.cols), 0),
+        };
+        for (_, event) in &playback.events[from..next] {
+            match event {
+                CastEvent::Output(data) => screen.feed(data.as_bytes()),
+                CastEvent::Resize { cols, rows } => screen.resize(*rows, *cols),
+            }
+        }
+        let resume = playback.pause();
+        *playback.position.lock().unwrap() = t;
+        if resume {
+            let speed = *playback.speed.lock().unwrap();
+            self.play(playback_id, Some(speed), events)?;
+        }
+        Ok(screen.snapshot())
+    }
+
+    /// Play from the current position at `speed` times real time, or the
+    /// speed it last played at.
+    pub fn play(&self, playback_id: &str, speed: Option<f64>, events: SharedSink) -> Result<()> {
+        let playback = self.get(playback_id)?;
+        let speed = speed.unwrap_or(*playback.speed.lock().unwrap());
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(PiError::invalid_input("speed must be above 0").into());
+        }
+        playback.pause();
+        *playback.speed.lock().unwrap() = speed;
+        let running = Arc::new(AtomicBool::new(true));
+        *playback.playing.lock().unwrap() = Some(running.clone());
+        let id = playback_id.to_string();
+        thread::spawn(move || {
+            let mut at = *playback.position.lock().unwrap();
+            for (t, event) in &playback.events[playback.next_index(at)..] {
+                let wait = Duration::from_millis(t.saturating_sub(at)).min(MAX_IDLE);
+                if !sleep_while(&running, wait.div_f64(speed)) {
+                    return;
+                }
+                // Checked under the lock so a seek's position isn't overwritten
+                let mut position = playback.position.lock().unwrap();
+                if !running.load(Ordering::SeqCst) {
+                    return;
+                }
+                match event {
+                    CastEvent::Output(data) => events.emit(
+                        &format!("playback://data/{id}"),
+                        serde_json::json!({ "playbackId": id, "data": data, "t": t }),
+                    ),
+                    CastEvent::Resize { cols, rows } => events.emit(
+                        &format!("playback://resize/{id}"),
+                        serde_json::json!({ "playbackId": id, "cols": cols, "rows": rows }),
+                    ),
+                }
+                at = *t;
+                *position = at;
+            }
+            let mut playing = playback.playing.lock().unwrap();
+            if playing.as_ref().is_some_and(|r| Arc::ptr_eq(r, &running)) {
+                playing.take();
+                events.emit(
+                    &format!("playback://ended/{id}"),
+                    serde_json::json!({ "playbackId": id, "position": at }),
+                );
+            }
+        });
+        Ok(())
+    }
+
+    /// Pause; returns the position in ms.
+    pub fn pause(&self, playback_id: &str) -> Result<u64> {
+        let playback = self.get(playback_id)?;
+        playback.pause();
+        let position = *playback.position.lock().unwrap();
+        Ok(position)
+    }
+
+    pub fn close(&self, playback_id: &str) -> Result<()> {
+        let playback = self.open.lock().unwrap().remove(playback_id);
+        playback.ok_or_else(|| not_found(playback_id))?.pause();
+        Ok(())
+    }
+
+    fn get(&self, playback_id: &str) -> Result<Arc<Playback>> {
+        let open = self.open.lock().unwrap();
+        open.get(playback_id).cloned().ok_or_else(|| not_found(playback_id).into())
+    }
+}
+
+fn not_found(playback_id: &str) -> PiError {
+    PiError::new(ErrorCode::NotFound, format!("no playback {playback_id}"))
+}
+
+/// Sleep for `duration` unless `running` is cleared first. Returns whether
+/// it is still set.
+fn sleep_while(running: &AtomicBool, duration: Duration) -> bool {
+    let mut left = duration;
+    while !left.is_zero() {
+        if !running.load(Ordering::SeqCst) {
+            return false;
+        }
+        let step = left.min(TICK);
+        thread::sleep(step);
+        left -= step;
+    }
+    running.load(Ordering::SeqCst)
+}
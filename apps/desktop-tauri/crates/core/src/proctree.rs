@@ -0,0 +1,115 @@
+//! Processes running under a PTY session.
+//!
+//! An agent rarely works alone: it starts a shell, which runs `npm`, which
+//! starts `node`, which starts `webpack`. `tree` snapshots the session's
+//! child and all of its descendants so the UI can show what is running and
+//! kill one runaway grandchild without ending the session.
+
+use crate::error::PiError;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, Signal, System, UpdateKind};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub name: String,
+    pub cmdline: Vec<String>,
+    /// `running`, `sleeping`, `zombie`, ... as the OS reports it.
+    pub state: String,
+    pub memory_bytes: u64,
+    /// Unix seconds.
+    pub start_time: u64,
+    pub children: Vec<ProcessNode>,
+}
+
+/// The process `root_pid` and its descendants, or `None` if it has exited.
+pub fn tree(root_pid: u32) -> Option<ProcessNode> {
+    let system = snapshot();
+    let children = children_by_parent(&system);
+    let root = system.process(Pid::from_u32(root_pid))?;
+    Some(node(root, &system, &children))
+}
+
+/// Signal process `pid`, which must be a descendant of `root_pid` (the
+/// session's own child is ended with `pty_kill`). Sends SIGTERM, or SIGKILL
+/// when `force`; platforms without signals kill outright.
+pub fn kill_descendant(root_pid: u32, pid: u32, force: bool) -> Result<()> {
+    if pid == root_pid {
+        return Err(PiError::invalid_input("use pty_kill to end the session itself").into());
+    }
+    let system = snapshot();
+    let children = children_by_parent(&system);
+    if !is_descendant(&children, Pid::from_u32(root_pid), Pid::from_u32(pid)) {
+        return Err(PiError::invalid_input(format!(
+            "process {pid} does not belong to this session"
+        ))
+        .into());
+    }
+    let Some(process) = system.process(Pid::from_u32(pid)) else { return Ok(()) };
+    let signal = if force { Signal::Kill } else { Signal::Term };
+    if process.kill_with(signal).is_none() {
+        process.kill();
+    }
+    Ok(())
+}
+
+fn snapshot() -> System {
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::new().with_cmd(UpdateKind::Always).with_memory(),
+    );
+    system
+}
+
+fn children_by_parent(system: &System) -> HashMap<Pid, Vec<Pid>> {
+    let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+    for (pid, process) in system.processes() {
+        // Threads show up as processes on Linux; skip them
+        if process.thread_kind().is_some_and(|k| k == sysinfo::ThreadKind::Userland) {
+            continue;
+        }
+        if let Some(parent) = process.parent() {
+            children.entry(parent).or_default().push(*pid);
+        }
+    }
+    for pids in children.values_mut() {
+        pids.sort();
+    }
+    children
+}
+
+fn is_descendant(children: &HashMap<Pid, Vec<Pid>>, root: Pid, pid: Pid) -> bool {
+    let mut stack = vec![root];
+    while let Some(p) = stack.pop() {
+        for &child in children.get(&p).into_iter().flatten() {
+            if child == pid {
+                return true;
+            }
+            stack.push(child);
+        }
+    }
+    false
+}
+
+fn node(process: &Process, system: &System, children: &HashMap<Pid, Vec<Pid>>) -> ProcessNode {
+    ProcessNode {
+        pid: process.pid().as_u32(),
+        name: process.name().to_string_lossy().to_string(),
+        cmdline: process.cmd().iter().map(|a| a.to_string_lossy().to_string()).collect(),
+        state: process.status().to_string().to_lowercase(),
+        memory_bytes: process.memory(),
+        start_time: process.start_time(),
+        children: children
+            .get(&process.pid())
+            .into_iter()
+            .flatten()
+            .filter_map(|pid| system.process(*pid))
+            .map(|child| node(child, system, children))
+            .collect(),
+    }
+}
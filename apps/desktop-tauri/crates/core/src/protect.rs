@@ -0,0 +1,143 @@
+//! Protected paths: files agents shouldn't change in their worktrees.
+//!
+//! Patterns use gitignore syntax (`migrations/**`, `*.lock`, `deploy/`)
+//! and come from the repo's `protected_paths` setting plus its
+//! `.pi-builder.toml`. A worktree violates them when a matching file
+//! differs from where its branch forked off the main checkout's branch,
+//! committed or not. A background monitor emits `worktree://violation`
+//! when a worktree's violations change, and `review_merge` refuses a
+//! worktree that has any unless told to override.
+
+use crate::{
+    events::SharedSink,
+    repo_cache::RepoCache,
+    repo_config::RepoConfig,
+    settings::Settings,
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{DiffOptions, Repository};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How often worktrees are checked, when the repo has protected paths.
+const PROTECT_POLL: Duration = Duration::from_secs(10);
+
+pub struct Protected {
+    matcher: Gitignore,
+}
+
+impl Protected {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("invalid protected path {pattern:?}"))?;
+        }
+        Ok(Self { matcher: builder.build()? })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matcher.is_empty()
+    }
+
+    /// Whether `path` (relative, `/`-separated) or a parent is protected.
+    pub fn matches(&self, path: &str) -> bool {
+        self.matcher.matched_path_or_any_parents(path, false).is_ignore()
+    }
+}
+
+/// Protected path patterns for `repo`: its setting, then its config file.
+pub fn patterns(repo: &str, settings: &Settings) -> Vec<String> {
+    let mut patterns = settings.protected_paths.get(repo).cloned().unwrap_or_default();
+    for pattern in RepoConfig::load_or_default(Path::new(repo)).protected_paths {
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
+
+/// Protected files changed in `worktree` since its branch forked off the
+/// main checkout's HEAD, including uncommitted and untracked files.
+pub fn violations(main: &Repository, name: &str, protected: &Protected) -> Result<Vec<String>> {
+    if protected.is_empty() {
+        return Ok(Vec::new());
+    }
+    let wt = worktree::open_in(main, name)?;
+    let head = wt.head()?.peel_to_commit()?.id();
+    let main_head = main.head()?.peel_to_commit()?.id();
+    let base = main.merge_base(main_head, head).unwrap_or(main_head);
+    let base_tree = wt.find_commit(base)?.tree()?;
+    let mut opts = DiffOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+    let diff = wt
+        .diff_tree_to_workdir_with_index(Some(&base_tree), Some(&mut opts))
+        .context("diff worktree against fork point")?;
+    let mut paths = Vec::new();
+    for delta in diff.deltas() {
+        for file in [delta.new_file(), delta.old_file()] {
+            let Some(path) = file.path() else { continue };
+            let path = path.to_string_lossy().replace('\\', "/");
+            if protected.matches(&path) && !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Check the current repo's worktrees every few seconds and emit
+/// `worktree://violation` with a worktree's protected changes whenever
+/// they differ from the last check.
+pub fn spawn_monitor(
+    cache: Arc<RepoCache>,
+    repo_path: Arc<Mutex<Option<String>>>,
+    settings: Arc<Mutex<Settings>>,
+    events: SharedSink,
+) {
+    thread::spawn(move || {
+        let mut reported: HashMap<(String, String), Vec<String>> = HashMap::new();
+        loop {
+            thread::sleep(PROTECT_POLL);
+            let Some(repo) = repo_path.lock().unwrap().clone() else { continue };
+            let patterns = patterns(&repo, &settings.lock().unwrap());
+            let protected = match Protected::new(&patterns) {
+                Ok(p) if !p.is_empty() => p,
+                Ok(_) => continue,
+                Err(e) => {
+                    log::warn!("{e:#}");
+                    continue;
+                }
+            };
+            let Ok(list) = cache.list(&repo) else { continue };
+            for wt in list {
+                let found = cache.with_repo(&repo, |main| violations(main, &wt.name, &protected));
+                let paths = match found {
+                    Ok(paths) => paths,
+                    Err(e) => {
+                        log::debug!("protected paths in {}: {e:#}", wt.name);
+                        continue;
+                    }
+                };
+                let key = (repo.clone(), wt.name.clone());
+                if reported.get(&key).map_or(paths.is_empty(), |last| *last == paths) {
+                    continue;
+                }
+                events.emit(
+                    "worktree://violation",
+                    serde_json::json!({ "repo": repo, "worktree": wt.name, "paths": paths }),
+                );
+                reported.insert(key, paths);
+            }
+        }
+    });
+}
@@ -0,0 +1,1489 @@
+//! PTY session management via portable-pty.
+//!
+//! Each PtySession wraps a portable-pty child process. stdout is forwarded
+//! to the Tauri event system as high-frequency "pty://data/<id>" events.
+//! stdin is written via Tauri commands. No WebSocket layer — Tauri IPC handles
+//! the frontend ↔ backend channel.
+//!
+//! A session that produced output and then stays quiet for the configured
+//! idle period gets a single "pty://idle/<id>" event until it prints again.
+//! Its `state` (running / awaiting_input / idle, see `activity.rs`) is in
+//! `pty_list` and changes are emitted as "pty://state/<id>".
+//!
+//! Output lines are also fed to the session's pipes, which type them into
+//! other sessions; see `pipe.rs`.
+//!
+//! Lines typed into a session are kept for recall; see `inputhistory.rs`.
+//!
+//! Sessions carry a user-assigned title, tags and group for the sidebar;
+//! changes are announced as "pty://meta/<id>" with the session's listing,
+//! which is also the payload of "pty://spawned/<id>" when a session starts.
+//!
+//! Output passes through a bounded per-session queue (see `flow.rs`) between
+//! the reader thread and a separate emitter thread, so a slow frontend
+//! applies backpressure instead of growing memory without limit.
+//!
+//! A panic in the reader thread (a parser choking on a chunk, say) is
+//! caught: the chunk is dropped, "pty://error/<id>" reports it, and reading
+//! resumes on a fresh clone of the PTY reader. If that fails, or after
+//! `MAX_READER_RESTARTS`, the session stops streaming and `pty_list` shows
+//! it as `degraded` with the reason; its exit is still reported.
+
+use crate::{
+    activity::{Activity, SessionState},
+    annotate::{Annotation, AnnotatorSet, OutputAnnotator},
+    codec::{DataEncoding, Encoder, PROTOCOL_VERSION},
+    container::ContainerSpec,
+    detach::{self, SessionBackend},
+    error::PiError,
+    events::SharedSink,
+    flow::{Chunk, FlowSettings, OutputQueue, QueueStats},
+    guard::{Filtered, GuardRules, InputGuard},
+    inputhistory::{HistoryEntry, HistoryMatch, InputHistory},
+    logs::{LogSettings, SessionLog},
+    paste::PasteModeTracker,
+    paths,
+    pipe::OutputTaps,
+    recording::CastWriter,
+    redact::{self, Redactor},
+    repro::{self, LaunchSnapshot},
+    screen::{Screen, ScreenSnapshot},
+    scrollback::{ExportedText, LineRange, Scrollback, SearchResult, SpillSettings},
+    secrets,
+    ssh::SshTarget,
+    summary,
+    target::{Launch, SpawnTarget},
+    termenv::TermEnv,
+    usage::{self, UsageRecord, UsageTracker},
+};
+#[cfg(windows)]
+use crate::winjob;
+use regex::bytes::Regex;
+use anyhow::{Context, Result};
+use portable_pty::{native_pty_system, Child, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::HashMap,
+    io::{Read, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+pub struct PtySession {
+    pub id: String,
+    pub agent_id: String,
+    pub cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub pid: Option<u32>,
+    /// `None` once closed; on Windows that happens when the child exits.
+    master: Arc<Mutex<Option<Box<dyn MasterPty + Send>>>>,
+    /// The PTY's input; portable-pty hands its writer out only once.
+    writer: Mutex<Box<dyn Write + Send>>,
+    killer: Mutex<Box<dyn ChildKiller + Send + Sync>>,
+    /// Job holding the child and everything it starts.
+    #[cfg(windows)]
+    job: Option<winjob::Job>,
+    /// Current `(cols, rows)`.
+    size: Mutex<(u16, u16)>,
+    pub alive: Arc<Mutex<bool>>,
+    /// Set once the reader thread has emitted `pty://exit`.
+    finished: Arc<Mutex<bool>>,
+    /// Why output stopped streaming, if the reader thread failed for good.
+    degraded: Arc<Mutex<Option<String>>>,
+    /// Run after killing the child (e.g. remove its container).
+    cleanup: Option<Vec<String>>,
+    /// Output log file, when logging is enabled.
+    pub log_path: Option<PathBuf>,
+    /// Timed recording, when recording is enabled; see `recording.rs`.
+    cast: Arc<Mutex<Option<CastWriter>>>,
+    pub meta: Mutex<SessionMeta>,
+    activity: Arc<Mutex<Activity>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    /// Current screen grid, see `screen.rs`.
+    screen: Arc<Mutex<Screen>>,
+    /// Set in guarded mode.
+    guard: Mutex<Option<InputGuard>>,
+    /// Lines typed into the session.
+    history: Mutex<InputHistory>,
+    usage: Arc<Mutex<UsageTracker>>,
+    /// The application turned on bracketed paste (`ESC[?2004h`).
+    bracketed_paste: Arc<AtomicBool>,
+    /// Output lines going to pipes, see `pipe.rs`.
+    taps: OutputTaps,
+    /// Task this session runs, if any.
+    pub task_id: Option<String>,
+    pub repo: Option<String>,
+    /// Runs under tmux and survives the app; see `detach.rs`.
+    pub detached: bool,
+    /// `tmux` for a window of the shared tmux session, else its own server.
+    pub backend: SessionBackend,
+    /// Unix millis.
+    pub started_at: u64,
+    /// What the session was launched with; see `repro.rs`.
+    pub launch: LaunchSnapshot,
+    output: Arc<OutputQueue>,
+}
+
+impl PtySession {
+    pub fn write(&self, data: &str) -> Result<()> {
+        let master = self.master.lock().unwrap();
+        master.as_ref().context("session has exited")?;
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(data.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Add the lines typed `data` submits to the input history.
+    fn record_input(&self, data: &str) {
+        let cursor_line = || self.screen.lock().unwrap().cursor_line();
+        self.history.lock().unwrap().input(data, cursor_line);
+    }
+
+    /// Resize the terminal. Zero sizes, which a hidden xterm reports while
+    /// the layout settles, are rejected, and unchanged sizes skipped:
+    /// ConPTY redraws the whole screen on every resize.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        if cols == 0 || rows == 0 {
+            return Err(PiError::invalid_input(format!("invalid size {cols}x{rows}")).into());
+        }
+        let mut size = self.size.lock().unwrap();
+        if *size == (cols, rows) {
+            return Ok(());
+        }
+        let master = self.master.lock().unwrap();
+        let master = master.as_ref().context("session has exited")?;
+        master.resize(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })?;
+        *size = (cols, rows);
+        self.screen.lock().unwrap().resize(rows, cols);
+        if let Some(cast) = self.cast.lock().unwrap().as_mut() {
+            cast.resize(cols, rows);
+        }
+        Ok(())
+    }
+
+    /// Listing entry as returned by `pty_list`.
+    pub fn info(&self) -> serde_json::Value {
+        let meta = self.meta.lock().unwrap();
+        let (cols, rows) = *self.size.lock().unwrap();
+        serde_json::json!({
+            "sessionId": self.id,
+            "agentId": self.agent_id,
+            "taskId": self.task_id,
+            "alive": *self.alive.lock().unwrap(),
+            "cols": cols,
+            "rows": rows,
+            "state": self.activity.lock().unwrap().state,
+            "title": meta.title,
+            "tags": meta.tags,
+            "group": meta.group,
+            "guarded": self.guard.lock().unwrap().is_some(),
+            "detached": self.detached,
+            "backend": self.backend,
+            "degraded": self.degraded.lock().unwrap().clone(),
+        })
+    }
+
+    pub fn kill(&self) {
+        self.stop(true);
+    }
+
+    /// Stop the child; without `cleanup`, a detached session's tmux server
+    /// and a container are left running.
+    fn stop(&self, cleanup: bool) {
+        *self.alive.lock().unwrap() = false;
+        // Killing the child alone would leave its children running
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            job.terminate(1);
+        }
+        let _ = self.killer.lock().unwrap().kill();
+        self.output.abort();
+        if !cleanup {
+            return;
+        }
+        if let Some([program, args @ ..]) = self.cleanup.as_deref() {
+            let mut command = std::process::Command::new(program);
+            command
+                .args(args)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null());
+            thread::spawn(move || command.status());
+        }
+    }
+}
+
+/// User-assigned labels for telling sessions apart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionMeta {
+    pub title: Option<String>,
+    /// Trimmed, non-empty and unique.
+    pub tags: Vec<String>,
+    pub group: Option<String>,
+}
+
+impl SessionMeta {
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags.clear();
+        for tag in tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !self.tags.iter().any(|t| t == tag) {
+                self.tags.push(tag.to_string());
+            }
+        }
+    }
+}
+
+/// Upper bounds enforced by the session watchdog; unset means no limit.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionLimits {
+    /// Wall-clock time since spawn.
+    pub max_runtime_secs: Option<u64>,
+    /// Time without output (since spawn, before the first output).
+    pub max_idle_secs: Option<u64>,
+}
+
+impl SessionLimits {
+    fn is_empty(&self) -> bool {
+        self.max_runtime_secs.is_none() && self.max_idle_secs.is_none()
+    }
+}
+
+/// Narrows `pty_list`; unset fields match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SessionFilter {
+    pub agent_id: Option<String>,
+    /// Sessions carrying this tag.
+    pub tag: Option<String>,
+    pub group: Option<String>,
+    pub alive: Option<bool>,
+    pub state: Option<SessionState>,
+}
+
+impl SessionFilter {
+    fn matches(&self, session: &PtySession) -> bool {
+        let meta = session.meta.lock().unwrap();
+        self.agent_id.as_ref().map_or(true, |a| *a == session.agent_id)
+            && self.tag.as_ref().map_or(true, |t| meta.tags.contains(t))
+            && self.group.as_ref().map_or(true, |g| meta.group.as_ref() == Some(g))
+            && self.alive.map_or(true, |a| a == *session.alive.lock().unwrap())
+            && self.state.map_or(true, |s| s == session.activity.lock().unwrap().state)
+    }
+}
+
+/// Shell used when a session is spawned with an empty `cmd`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Shell binary; `None` uses `$SHELL` (or `cmd.exe` on Windows).
+    pub program: Option<String>,
+    /// Launch as a login shell (`-l`) so `~/.profile` and friends are
+    /// sourced. Ignored on Windows.
+    pub login: bool,
+    /// Extra arguments appended after the login flag.
+    pub args: Vec<String>,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self { program: None, login: true, args: Vec::new() }
+    }
+}
+
+/// What happens to live sessions when the app exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitBehavior {
+    /// Kill every child and wait briefly for it to go.
+    #[default]
+    Kill,
+    /// Leave children alone. The PTY master still closes with the app, so
+    /// only processes that ignore SIGHUP (e.g. under `nohup`) survive;
+    /// spawn with `detached` to keep a session for sure.
+    Detach,
+}
+
+/// Persisted description of a session, written at shutdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub session_id: String,
+    pub agent_id: String,
+    pub cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub pid: Option<u32>,
+    pub alive: bool,
+    #[serde(default)]
+    pub meta: SessionMeta,
+    #[serde(default)]
+    pub repo: Option<String>,
+    /// Re-attached on the next launch if still running.
+    #[serde(default)]
+    pub detached: bool,
+    #[serde(default)]
+    pub backend: SessionBackend,
+    #[serde(default)]
+    pub launch: Option<LaunchSnapshot>,
+}
+
+/// Called from the reader thread with the child's exit code once it exits.
+pub type ExitHook = Box<dyn FnOnce(u32) + Send>;
+
+/// Everything needed to launch a session. An empty `cmd` runs the default
+/// shell.
+pub struct SpawnOptions {
+    pub agent_id: String,
+    pub cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub cols: u16,
+    pub rows: u16,
+    pub encoding: DataEncoding,
+    pub target: SpawnTarget,
+    /// Run inside a container instead; exclusive with a non-native target.
+    pub container: Option<ContainerSpec>,
+    /// Run on a remote host over SSH; exclusive with the other backends.
+    pub ssh: Option<SshTarget>,
+    /// Hold output until the frontend acks it with `pty_ack`.
+    pub flow_control: bool,
+    /// Confirm dangerous input lines before submitting them; see `guard.rs`.
+    pub guarded: bool,
+    pub meta: SessionMeta,
+    /// Regexes for token/cost lines; see `usage.rs`.
+    pub usage_patterns: Vec<String>,
+    pub task_id: Option<String>,
+    /// Repo the session works on, for per-repo session limits.
+    pub repo: Option<String>,
+    pub limits: SessionLimits,
+    pub on_exit: Option<ExitHook>,
+    /// Run under tmux so the session survives the app; see `detach.rs`.
+    pub detached: bool,
+    /// Run as a window of the shared tmux session; detached regardless of
+    /// `detached`. See `detach.rs`.
+    pub backend: SessionBackend,
+    /// Use this id instead of a new one. With `detached`, attach to the
+    /// surviving session with this id instead of starting `cmd`.
+    pub session_id: Option<String>,
+    /// Session whose worktree this one takes over, recorded in the launch
+    /// snapshot and usage log.
+    pub handoff_from: Option<String>,
+    /// Scrollback lines kept in memory; `None` keeps `scrollback::MAX_LINES`.
+    pub scrollback_lines: Option<usize>,
+    /// Record the session regardless of the log settings' `record`.
+    pub record: Option<bool>,
+}
+
+impl SpawnOptions {
+    pub fn new(agent_id: impl Into<String>, cmd: Vec<String>) -> Self {
+        Self {
+            agent_id: agent_id.into(),
+            cmd,
+            cwd: None,
+            env: Vec::new(),
+            cols: 220,
+            rows: 50,
+            encoding: DataEncoding::default(),
+            target: SpawnTarget::default(),
+            container: None,
+            ssh: None,
+            flow_control: false,
+            guarded: false,
+            meta: SessionMeta::default(),
+            usage_patterns: Vec::new(),
+            task_id: None,
+            repo: None,
+            limits: SessionLimits::default(),
+            on_exit: None,
+            detached: false,
+            backend: SessionBackend::default(),
+            session_id: None,
+            handoff_from: None,
+            scrollback_lines: None,
+            record: None,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PtyManager
+// ---------------------------------------------------------------------------
+
+/// How often the activity watcher ticks a session's state.
+const ACTIVITY_POLL: Duration = Duration::from_millis(250);
+
+/// How often the watchdog checks session limits.
+const WATCHDOG_POLL: Duration = Duration::from_secs(1);
+
+/// Time a timed-out session gets to exit after Ctrl-C before it is killed.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Times a session's reader thread recovers from a panic before giving up.
+pub const MAX_READER_RESTARTS: u32 = 3;
+
+pub struct PtyManager {
+    sessions: HashMap<String, Arc<PtySession>>,
+    redact_patterns: Vec<Regex>,
+    redact_env: bool,
+    idle_after: Option<Duration>,
+    shell: ShellConfig,
+    /// Log directory and settings; `None` until configured.
+    logging: Option<(PathBuf, LogSettings)>,
+    flow: FlowSettings,
+    scrollback_spill: SpillSettings,
+    term_env: TermEnv,
+    /// Where finished sessions' usage is appended.
+    usage_log: Option<PathBuf>,
+    guard_rules: GuardRules,
+    /// Annotators for new sessions' output; `None` turns annotation off.
+    annotators: Option<AnnotatorSet>,
+}
+
+impl Default for PtyManager {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            redact_patterns: Vec::new(),
+            redact_env: true,
+            idle_after: None,
+            shell: ShellConfig::default(),
+            logging: None,
+            flow: FlowSettings::default(),
+            scrollback_spill: SpillSettings::default(),
+            term_env: TermEnv::default(),
+            usage_log: None,
+            guard_rules: GuardRules::default(),
+            annotators: None,
+        }
+    }
+}
+
+impl PtyManager {
+    /// Set the redaction applied to sessions spawned from now on.
+    pub fn configure_redaction(&mut self, patterns: Vec<Regex>, redact_env: bool) {
+        self.redact_patterns = patterns;
+        self.redact_env = redact_env;
+    }
+
+    /// Emit `pty://idle` after this long without output, for sessions
+    /// spawned from now on. `None` disables idle detection.
+    pub fn configure_idle(&mut self, idle_after: Option<Duration>) {
+        self.idle_after = idle_after;
+    }
+
+    /// Set the shell used for sessions spawned without a command.
+    pub fn configure_shell(&mut self, shell: ShellConfig) {
+        self.shell = shell;
+    }
+
+    /// Mirror output of sessions spawned from now on into `dir`, if
+    /// `settings.enabled`.
+    pub fn configure_logging(&mut self, dir: PathBuf, settings: LogSettings) {
+        self.logging = Some((dir, settings));
+    }
+
+    /// Append each finished session's usage to `path`.
+    pub fn configure_usage_log(&mut self, path: PathBuf) {
+        self.usage_log = Some(path);
+    }
+
+    /// Set the output queue bound and overflow policy for sessions spawned
+    /// from now on.
+    pub fn configure_flow(&mut self, flow: FlowSettings) {
+        self.flow = flow;
+    }
+
+    /// Spill scrollback of sessions spawned from now on to disk as it
+    /// falls out of memory; see `scrollback.rs`.
+    pub fn configure_scrollback_spill(&mut self, spill: SpillSettings) {
+        self.scrollback_spill = spill;
+    }
+
+    /// Set `TERM` and friends for sessions spawned from now on; see
+    /// `termenv.rs`.
+    pub fn configure_term_env(&mut self, term_env: TermEnv) {
+        self.term_env = term_env;
+    }
+
+    /// Set the patterns guarded sessions check, for sessions guarded from
+    /// now on.
+    pub fn configure_guard(&mut self, rules: GuardRules) {
+        self.guard_rules = rules;
+    }
+
+    /// Scan output of sessions spawned from now on with these annotators
+    /// and emit `pty://annotation`; see `annotate.rs`.
+    pub fn configure_annotators(&mut self, annotators: Option<AnnotatorSet>) {
+        self.annotators = annotators;
+    }
+
+    pub fn spawn(&mut self, opts: SpawnOptions, events: SharedSink) -> Result<String> {
+        let SpawnOptions {
+            agent_id,
+            cmd,
+            cwd,
+            env,
+            cols,
+            rows,
+            encoding,
+            target,
+            container,
+            ssh,
+            flow_control,
+            guarded,
+            meta,
+            usage_patterns,
+            task_id,
+            repo,
+            limits,
+            on_exit,
+            detached,
+            backend,
+            session_id,
+            handoff_from,
+            scrollback_lines,
+            record,
+        } = opts;
+        if session_id.as_ref().is_some_and(|id| self.sessions.contains_key(id)) {
+            return Err(PiError::invalid_input("a session with that id exists").into());
+        }
+        let attach = session_id.is_some();
+        let id = session_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let requested_env = repro::mask_env(&env);
+        let (env, secret_values) = secrets::resolve_env(env)?;
+        let native = target == SpawnTarget::Native && container.is_none() && ssh.is_none();
+        let launch = if backend == SessionBackend::Tmux {
+            if !native {
+                return Err(PiError::invalid_input("tmux sessions run natively only").into());
+            }
+            // The window gets the session's env; tmux sets its own TERM there
+            let mut window_env: Vec<(String, String)> =
+                self.term_env.vars(true).into_iter().filter(|(k, _)| k != "TERM").collect();
+            window_env.extend(env.iter().cloned());
+            let size = (cols, rows);
+            detach::launch_shared(&id, &agent_id, &cmd, cwd.clone(), &window_env, size, attach)?
+        } else if detached {
+            if !native {
+                return Err(PiError::invalid_input("detached sessions run natively only").into());
+            }
+            detach::launch(&id, &cmd, cwd.clone(), attach)?
+        } else {
+            resolve_launch(&target, container.as_ref(), ssh.as_ref(), &cmd, &cwd, &env)?
+        };
+        let pty_system = native_pty_system();
+        let pair = pty_system
+            .openpty(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
+            .context("openpty")?;
+
+        // Build command
+        let mut builder = if launch.cmd.is_empty() {
+            default_shell(&self.shell)
+        } else {
+            let mut b = CommandBuilder::new(&launch.cmd[0]);
+            for arg in launch.cmd.iter().skip(1) {
+                b.arg(arg);
+            }
+            b
+        };
+
+        if let Some(dir) = &launch.cwd {
+            // Fail with the reason rather than start somewhere else; a
+            // re-attaching tmux client doesn't care where it starts
+            if native && !attach {
+                builder.cwd(paths::cwd(dir)?);
+            } else {
+                builder.cwd(dir);
+            }
+        }
+        let launch_snapshot = {
+            let mut secrets = secret_values.clone();
+            secrets.extend(
+                env.iter().filter(|(k, _)| redact::is_secret_name(k)).map(|(_, v)| v.clone()),
+            );
+            let argv: Vec<String> =
+                builder.get_argv().iter().map(|a| a.to_string_lossy().into_owned()).collect();
+            let mut snapshot_env = requested_env;
+            snapshot_env.extend(repro::mask_env(&launch.env));
+            LaunchSnapshot {
+                agent_id: agent_id.clone(),
+                cmd: cmd.clone(),
+                resolved_cmd: repro::mask_args(&argv, &secrets),
+                cwd: cwd.clone(),
+                env: snapshot_env,
+                cols,
+                rows,
+                target: target.clone(),
+                container: container.clone(),
+                ssh: ssh.clone(),
+                repo: repo.clone(),
+                git: cwd.as_deref().and_then(|dir| repro::git_state(Path::new(dir))),
+                handoff_from,
+                reproduce: String::new(),
+                taken_at: now_ms(),
+            }
+            .with_reproduce()
+        };
+        let worktree = launch_snapshot.git.as_ref().and_then(|g| g.worktree.clone());
+        let handoff_from = launch_snapshot.handoff_from.clone();
+        // Where to look for what the session changed when it exits
+        let baseline = cwd.clone().zip(launch_snapshot.git.as_ref().map(|g| g.commit.clone()));
+        let mut redactor =
+            Redactor::for_spawn(&env, self.redact_patterns.clone(), self.redact_env);
+        redactor.add_literals(secret_values);
+        for (key, value) in self.term_env.vars(native) {
+            builder.env(key, value);
+        }
+        for (key, value) in env.into_iter().chain(launch.env) {
+            builder.env(key, value);
+        }
+
+        // Spawn into the slave PTY
+        let mut child: Box<dyn Child + Send + Sync> = pair.slave.spawn_command(builder)?;
+        let killer = Mutex::new(child.clone_killer());
+        let pid = child.process_id();
+        #[cfg(windows)]
+        let job = pid.and_then(|pid| {
+            winjob::Job::for_process(pid).map_err(|e| log::warn!("job object: {e:#}")).ok()
+        });
+        let reader = pair.master.try_clone_reader().context("clone reader")?;
+        let writer = Mutex::new(pair.master.take_writer().context("take writer")?);
+
+        let mut log = match &self.logging {
+            Some((dir, settings)) if settings.enabled => SessionLog::open(dir, &id, settings)
+                .map_err(|e| log::warn!("session log: {e:#}"))
+                .ok(),
+            _ => None,
+        };
+        let cast = match &self.logging {
+            Some((dir, settings)) if record.unwrap_or(settings.record) => {
+                CastWriter::create(dir, &id, cols, rows, settings)
+                    .map_err(|e| log::warn!("session recording: {e:#}"))
+                    .ok()
+            }
+            _ => None,
+        };
+        let cast = Arc::new(Mutex::new(cast));
+        let alive = Arc::new(Mutex::new(true));
+        let finished: Arc<Mutex<bool>> = Arc::default();
+        let degraded: Arc<Mutex<Option<String>>> = Arc::default();
+        let master = Arc::new(Mutex::new(Some(pair.master)));
+        let output = Arc::new(OutputQueue::new(&id, self.flow.clone(), flow_control));
+        let activity: Arc<Mutex<Activity>> = Arc::default();
+        let scrollback = Arc::new(Mutex::new(
+            scrollback_lines
+                .map_or_else(Scrollback::default, Scrollback::with_max_lines)
+                .with_spill(&id, &self.scrollback_spill),
+        ));
+        let screen = Arc::new(Mutex::new(Screen::new(rows, cols)));
+        let usage_tracker = Arc::new(Mutex::new(UsageTracker::new(&usage_patterns)));
+        let bracketed_paste: Arc<AtomicBool> = Arc::default();
+        let taps = OutputTaps::default();
+        let mut annotator = self.annotators.as_ref().map(|make| OutputAnnotator::new(make()));
+        let started_at = now_ms();
+
+        let session = Arc::new(PtySession {
+            id: id.clone(),
+            agent_id: agent_id.clone(),
+            cmd,
+            cwd,
+            pid,
+            master: master.clone(),
+            writer,
+            killer,
+            #[cfg(windows)]
+            job,
+            size: Mutex::new((cols, rows)),
+            alive: alive.clone(),
+            finished: finished.clone(),
+            degraded: degraded.clone(),
+            cleanup: launch.cleanup,
+            log_path: log.as_ref().map(|l| l.path().to_path_buf()),
+            cast: cast.clone(),
+            meta: Mutex::new(meta),
+            activity: activity.clone(),
+            scrollback: scrollback.clone(),
+            screen: screen.clone(),
+            guard: Mutex::new(guarded.then(|| InputGuard::new(self.guard_rules.clone()))),
+            history: Mutex::default(),
+            usage: usage_tracker.clone(),
+            bracketed_paste: bracketed_paste.clone(),
+            taps: taps.clone(),
+            task_id: task_id.clone(),
+            repo,
+            detached: detached || backend == SessionBackend::Tmux,
+            backend,
+            started_at,
+            launch: launch_snapshot,
+            output: output.clone(),
+        });
+
+        spawn_activity_watcher(
+            id.clone(),
+            agent_id.clone(),
+            self.idle_after,
+            alive.clone(),
+            activity.clone(),
+            events.clone(),
+        );
+
+        if !limits.is_empty() {
+            spawn_watchdog(Arc::downgrade(&session), limits, activity.clone(), events.clone());
+        }
+
+        // Emitter thread — drains the output queue into Tauri events
+        let emitter = spawn_emitter(
+            id.clone(),
+            agent_id.clone(),
+            encoding,
+            output.clone(),
+            events.clone(),
+        );
+
+        // ConPTY keeps the output pipe open after the child exits, until the
+        // pseudoconsole is closed, so the reader would never see EOF. Wait for
+        // the child here instead and close the console once it is gone.
+        #[cfg(windows)]
+        let exit_status = {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let master = master.clone();
+            thread::spawn(move || {
+                let _ = tx.send(child.wait().map(|s| s.exit_code()).unwrap_or(1));
+                let closed = master.lock().unwrap().take();
+                drop(closed);
+            });
+            rx
+        };
+
+        let spawned_events = events.clone();
+
+        // Reader thread — queues PTY stdout for the emitter
+        let usage_log = self.usage_log.clone();
+        let session_id = id.clone();
+        let agent_id_clone = agent_id.clone();
+        let alive_clone = alive.clone();
+        let reader_master = master.clone();
+        thread::spawn(move || {
+            let mut chunks = read_chunks(reader);
+            let mut paste_mode = PasteModeTracker::default();
+            let mut restarts = 0;
+            let mut output_bytes = 0u64;
+            // The locks the reader shares; a caught panic may have poisoned them
+            let clear_poison = || {
+                activity.clear_poison();
+                scrollback.clear_poison();
+                screen.clear_poison();
+                usage_tracker.clear_poison();
+                cast.clear_poison();
+            };
+            loop {
+                let read = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let received = if redactor.has_pending() {
+                        chunks.recv_timeout(redact::IDLE_FLUSH)
+                    } else {
+                        chunks.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                    };
+                    let bytes = match received {
+                        Ok(chunk) => {
+                            output_bytes += chunk.len() as u64;
+                            if let Some(on) = paste_mode.feed(&chunk) {
+                                bracketed_paste.store(on, Ordering::Relaxed);
+                            }
+                            redactor.filter(&chunk)
+                        }
+                        // Quiet, with what may be a secret's start held back
+                        Err(RecvTimeoutError::Timeout) => redactor.flush_idle(),
+                        Err(RecvTimeoutError::Disconnected) => return false,
+                    };
+                    // Redacted, since the prompt line goes out in events
+                    if let Some(state) = activity.lock().unwrap().output(&bytes) {
+                        emit_state(&events, &session_id, &agent_id_clone, state, "");
+                    }
+                    scrollback.lock().unwrap().feed(&bytes);
+                    screen.lock().unwrap().feed(&bytes);
+                    usage_tracker.lock().unwrap().feed(&bytes);
+                    if let Some(annotator) = annotator.as_mut() {
+                        emit_annotations(&events, &session_id, annotator.feed(&bytes));
+                    }
+                    if let Some(log) = log.as_mut() {
+                        log.write(&bytes);
+                    }
+                    if let Some(cast) = cast.lock().unwrap().as_mut() {
+                        cast.output(&bytes);
+                    }
+                    taps.feed(&bytes);
+                    output.push(bytes);
+                    true
+                }));
+                let error = match read {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(panic) => panic_message(&*panic),
+                };
+                clear_poison();
+                restarts += 1;
+                let recloned = if restarts > MAX_READER_RESTARTS {
+                    None
+                } else {
+                    let master = reader_master.lock().unwrap_or_else(PoisonError::into_inner);
+                    master.as_ref().and_then(|m| m.try_clone_reader().ok())
+                };
+                events.emit(
+                    &format!("pty://error/{session_id}"),
+                    serde_json::json!({
+                        "sessionId": session_id,
+                        "agentId": agent_id_clone,
+                        "error": error,
+                        "recovered": recloned.is_some(),
+                        "restarts": restarts,
+                    }),
+                );
+                match recloned {
+                    Some(recloned) => chunks = read_chunks(recloned),
+                    None => {
+                        log::warn!("session {session_id} stopped streaming: {error}");
+                        *degraded.lock().unwrap() = Some(error);
+                        break;
+                    }
+                }
+            }
+            let flushed = panic::catch_unwind(AssertUnwindSafe(|| {
+                let rest = redactor.flush();
+                scrollback.lock().unwrap().feed(&rest);
+                screen.lock().unwrap().feed(&rest);
+                usage_tracker.lock().unwrap().feed(&rest);
+                if let Some(annotator) = annotator.as_mut() {
+                    emit_annotations(&events, &session_id, annotator.feed(&rest));
+                }
+                if let Some(log) = log.as_mut() {
+                    log.write(&rest);
+                }
+                if let Some(cast) = cast.lock().unwrap().as_mut() {
+                    cast.output(&rest);
+                }
+                taps.feed(&rest);
+                output.push(rest);
+            }));
+            if let Err(panic) = flushed {
+                log::warn!("session {session_id} flush: {}", panic_message(&*panic));
+                clear_poison();
+            }
+            taps.close();
+            output.close();
+            // Deliver all output before announcing the exit
+            let _ = emitter.join();
+            *alive_clone.lock().unwrap() = false;
+            #[cfg(windows)]
+            let exit_code = exit_status.recv().unwrap_or(1);
+            #[cfg(not(windows))]
+            let exit_code = child.wait().map(|s| s.exit_code()).unwrap_or(1);
+            let ended_at = now_ms();
+            let summary = summary::summarize(
+                exit_code,
+                ended_at.saturating_sub(started_at),
+                output_bytes,
+                baseline.as_ref().map(|(cwd, base)| (Path::new(cwd), base.as_str())),
+            );
+            let record = UsageRecord {
+                session_id: session_id.clone(),
+                agent_id: agent_id_clone.clone(),
+                task_id,
+                worktree,
+                handoff_from,
+                started_at,
+                ended_at: Some(ended_at),
+                usage: usage_tracker.lock().unwrap().usage(),
+                summary: Some(summary),
+            };
+            if let Some(path) = &usage_log {
+                if let Err(e) = usage::append(path, &record) {
+                    log::warn!("usage log: {e:#}");
+                }
+            }
+            events.emit(
+                &format!("pty://exit/{}", session_id),
+                serde_json::json!({
+                    "sessionId": session_id,
+                    "agentId": agent_id_clone,
+                    "exitCode": exit_code,
+                    "usage": record.usage,
+                    "summary": record.summary,
+                }),
+            );
+            if let Some(hook) = on_exit {
+                hook(exit_code);
+            }
+            *finished.lock().unwrap() = true;
+        });
+
+        spawned_events.emit(&format!("pty://spawned/{id}"), session.info());
+        self.sessions.insert(id.clone(), session);
+        Ok(id)
+    }
+
+    pub fn write(&self, session_id: &str, data: &str) -> Result<()> {
+        self.get(session_id)?.write(data)
+    }
+
+    /// Where the session's output lines are split off for pipes.
+    pub fn output_taps(&self, session_id: &str) -> Result<OutputTaps> {
+        Ok(self.get(session_id)?.taps.clone())
+    }
+
+    /// The session itself, to write to it without holding the manager's
+    /// lock through a long write.
+    pub fn session(&self, session_id: &str) -> Result<Arc<PtySession>> {
+        Ok(self.get(session_id)?.clone())
+    }
+
+    /// Write typed input, through the session's guard in guarded mode.
+    pub fn input(&self, session_id: &str, data: &str) -> Result<Filtered> {
+        let session = self.get(session_id)?;
+        let filtered = match session.guard.lock().unwrap().as_mut() {
+            Some(guard) => guard.input(data, || session.screen.lock().unwrap().cursor_line()),
+            None => Filtered { write: data.to_string(), ..Default::default() },
+        };
+        if !filtered.write.is_empty() {
+            session.record_input(&filtered.write);
+            session.write(&filtered.write)?;
+        }
+        Ok(filtered)
+    }
+
+    /// Answer a line held by the guard; see `InputGuard::confirm`.
+    pub fn confirm_input(&self, session_id: &str, token: &str, allow: bool) -> Result<Filtered> {
+        let session = self.get(session_id)?;
+        let filtered = match session.guard.lock().unwrap().as_mut() {
+            Some(guard) => guard.confirm(token, allow)?,
+            None => return Err(PiError::invalid_input("session is not guarded").into()),
+        };
+        if !filtered.write.is_empty() {
+            session.record_input(&filtered.write);
+            session.write(&filtered.write)?;
+        }
+        Ok(filtered)
+    }
+
+    /// Turn guarded mode on or off. Turning it off drops any held input.
+    pub fn set_guarded(&self, session_id: &str, guarded: bool) -> Result<()> {
+        let session = self.get(session_id)?;
+        let mut guard = session.guard.lock().unwrap();
+        if guarded != guard.is_some() {
+            *guard = guarded.then(|| InputGuard::new(self.guard_rules.clone()));
+        }
+        Ok(())
+    }
+
+    pub fn resize(&self, session_id: &str, cols: u16, rows: u16) -> Result<()> {
+        self.get(session_id)?.resize(cols, rows)
+    }
+
+    pub fn kill(&self, session_id: &str) {
+        if let Some(s) = self.sessions.get(session_id) {
+            s.kill();
+        }
+    }
+
+    /// Close the app's client of a detached session, leaving its tmux
+    /// server and agent running. It is attached again on the next launch.
+    pub fn detach(&self, session_id: &str) -> Result<()> {
+        let session = self.get(session_id)?;
+        if !session.detached {
+            let message = "session isn't detached; it can only be killed";
+            return Err(PiError::invalid_input(message).into());
+        }
+        session.stop(false);
+        Ok(())
+    }
+
+    pub fn list(&self, filter: &SessionFilter) -> Vec<serde_json::Value> {
+        self.sessions
+            .values()
+            .filter(|s| filter.matches(s))
+            .map(|s| s.info())
+            .collect()
+    }
+
+    /// Change a session's title/tags/group and emit `pty://meta/<id>` with
+    /// its updated listing.
+    pub fn update_meta(
+        &self,
+        session_id: &str,
+        change: impl FnOnce(&mut SessionMeta),
+        events: &SharedSink,
+    ) -> Result<serde_json::Value> {
+        let session = self.get(session_id)?;
+        change(&mut session.meta.lock().unwrap());
+        let info = session.info();
+        events.emit(&format!("pty://meta/{session_id}"), info.clone());
+        Ok(info)
+    }
+
+    pub fn live_session_ids(&self) -> Vec<String> {
+        self.sessions
+            .values()
+            .filter(|s| *s.alive.lock().unwrap())
+            .map(|s| s.id.clone())
+            .collect()
+    }
+
+    /// Whether the session's application has bracketed paste on.
+    pub fn bracketed_paste(&self, session_id: &str) -> Result<bool> {
+        Ok(self.get(session_id)?.bracketed_paste.load(Ordering::Relaxed))
+    }
+
+    /// Process id of the session's child.
+    pub fn pid(&self, session_id: &str) -> Result<u32> {
+        self.get(session_id)?
+            .pid
+            .ok_or_else(|| PiError::invalid_input("session has no process id").into())
+    }
+
+    pub fn log_path(&self, session_id: &str) -> Result<Option<PathBuf>> {
+        Ok(self.get(session_id)?.log_path.clone())
+    }
+
+    /// Release `bytes` of a flow-controlled session's window.
+    pub fn ack(&self, session_id: &str, bytes: usize) -> Result<()> {
+        self.get(session_id)?.output.ack(bytes);
+        Ok(())
+    }
+
+    /// Search a session's scrollback; see `Scrollback::search`.
+    pub fn search(
+        &self,
+        session_id: &str,
+        query: &str,
+        regex: bool,
+        case_sensitive: bool,
+        max_results: usize,
+    ) -> Result<SearchResult> {
+        let scrollback = self.get(session_id)?.scrollback.lock().unwrap();
+        scrollback.search(query, regex, case_sensitive, max_results)
+    }
+
+    /// A session's scrollback as plain text; see `Scrollback::export`.
+    pub fn export_text(&self, session_id: &str, range: LineRange) -> Result<ExportedText> {
+        self.get(session_id)?.scrollback.lock().unwrap().export(range)
+    }
+
+    /// A session's scrollback, to search or export without holding the
+    /// manager while spilled output is read.
+    pub fn scrollback(&self, session_id: &str) -> Result<Arc<Mutex<Scrollback>>> {
+        Ok(self.get(session_id)?.scrollback.clone())
+    }
+
+    /// What a session's terminal shows right now.
+    pub fn screen(&self, session_id: &str) -> Result<ScreenSnapshot> {
+        Ok(self.get(session_id)?.screen.lock().unwrap().snapshot())
+    }
+
+    /// Running sessions, overall and working on `repo`.
+    pub fn running(&self, repo: Option<&str>) -> (usize, usize) {
+        let alive: Vec<_> =
+            self.sessions.values().filter(|s| *s.alive.lock().unwrap()).collect();
+        let in_repo = alive.iter().filter(|s| repo.is_some() && s.repo.as_deref() == repo).count();
+        (alive.len(), in_repo)
+    }
+
+    /// Every session's usage: those in the usage log, then running ones.
+    pub fn usage_records(&self) -> Vec<UsageRecord> {
+        let mut records = self.usage_log.as_deref().map(usage::load).unwrap_or_default();
+        records.extend(self.live_usage());
+        records
+    }
+
+    /// Usage so far of sessions whose record isn't in the usage log yet.
+    pub fn live_usage(&self) -> Vec<UsageRecord> {
+        self.sessions
+            .values()
+            .filter(|s| !*s.finished.lock().unwrap())
+            .map(|s| UsageRecord {
+                session_id: s.id.clone(),
+                agent_id: s.agent_id.clone(),
+                task_id: s.task_id.clone(),
+                worktree: s.launch.git.as_ref().and_then(|g| g.worktree.clone()),
+                handoff_from: s.launch.handoff_from.clone(),
+                started_at: s.started_at,
+                ended_at: None,
+                usage: s.usage.lock().unwrap().usage(),
+                summary: None,
+            })
+            .collect()
+    }
+
+    pub fn stats(&self, session_id: &str) -> Result<QueueStats> {
+        Ok(self.get(session_id)?.output.stats())
+    }
+
+    pub fn records(&self) -> Vec<SessionRecord> {
+        self.sessions
+            .values()
+            .map(|s| SessionRecord {
+                session_id: s.id.clone(),
+                agent_id: s.agent_id.clone(),
+                cmd: s.cmd.clone(),
+                cwd: s.cwd.clone(),
+                pid: s.pid,
+                alive: *s.alive.lock().unwrap(),
+                meta: s.meta.lock().unwrap().clone(),
+                repo: s.repo.clone(),
+                detached: s.detached,
+                backend: s.backend,
+                launch: Some(s.launch.clone()),
+            })
+            .collect()
+    }
+
+    pub fn launch_snapshot(&self, id: &str) -> Result<LaunchSnapshot> {
+        Ok(self.get(id)?.launch.clone())
+    }
+
+    /// Up to `limit` lines typed into the session, newest first.
+    pub fn input_history(&self, session_id: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+        Ok(self.get(session_id)?.history.lock().unwrap().recent(limit))
+    }
+
+    /// Up to `limit` lines typed into any session that contain `query`,
+    /// ignoring case, newest first.
+    pub fn search_input_history(&self, query: &str, limit: usize) -> Vec<HistoryMatch> {
+        let mut found: Vec<HistoryMatch> = self
+            .sessions
+            .values()
+            .flat_map(|s| {
+                let entries = s.history.lock().unwrap().search(query);
+                entries.into_iter().map(|e| HistoryMatch {
+                    session_id: s.id.clone(),
+                    agent_id: s.agent_id.clone(),
+                    line: e.line,
+                    at: e.at,
+                })
+            })
+            .collect();
+        found.sort_by_key(|m| Reverse(m.at));
+        found.truncate(limit);
+        found
+    }
+
+    /// Kill every session that isn't detached and wait up to `timeout` for
+    /// their reader threads to finish. Returns the number of those still
+    /// running afterwards.
+    pub fn kill_all(&self, timeout: Duration) -> usize {
+        let doomed = || self.sessions.values().filter(|s| !s.detached);
+        for s in doomed() {
+            s.kill();
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending = doomed().filter(|s| !*s.finished.lock().unwrap()).count();
+            if pending == 0 || Instant::now() >= deadline {
+                return pending;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<&Arc<PtySession>> {
+        Ok(self.sessions.get(id).ok_or_else(|| PiError::session_not_found(id))?)
+    }
+}
+
+/// Pick the backend for a spawn. At most one of a non-native target, a
+/// container, or SSH may be given.
+fn resolve_launch(
+    target: &SpawnTarget,
+    container: Option<&ContainerSpec>,
+    ssh: Option<&SshTarget>,
+    cmd: &[String],
+    cwd: &Option<String>,
+    env: &[(String, String)],
+) -> Result<Launch> {
+    let backends = [*target != SpawnTarget::Native, container.is_some(), ssh.is_some()];
+    if backends.iter().filter(|b| **b).count() > 1 {
+        return Err(PiError::invalid_input("WSL, container and SSH backends can't be combined")
+            .into());
+    }
+    if let Some(spec) = container {
+        return Ok(spec.launch(cmd.to_vec(), cwd.clone(), env));
+    }
+    if let Some(remote) = ssh {
+        return Ok(remote.launch(cmd.to_vec(), env));
+    }
+    target.launch(cmd.to_vec(), cwd.clone(), env)
+}
+
+/// Pop output from `queue` and emit it as `pty://data` events until the
+/// queue is closed and drained. Each event carries the raw byte count, which
+/// is what flow-controlled frontends ack.
+fn spawn_emitter(
+    session_id: String,
+    agent_id: String,
+    encoding: DataEncoding,
+    queue: Arc<OutputQueue>,
+    events: SharedSink,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut encoder = Encoder::new(encoding);
+        let emit_data = |data: String, bytes: usize| {
+            if data.is_empty() {
+                return;
+            }
+            events.emit(
+                &format!("pty://data/{}", session_id),
+                serde_json::json!({
+                    "v": PROTOCOL_VERSION,
+                    "sessionId": session_id,
+                    "agentId": agent_id,
+                    "encoding": encoding,
+                    "data": data,
+                    "bytes": bytes,
+                }),
+            );
+        };
+        let interval = queue.min_interval();
+        let mut last_emit: Option<Instant> = None;
+        loop {
+            // Let output pile up between events so it goes out merged
+            if let (Some(interval), Some(last)) = (interval, last_emit) {
+                thread::sleep(interval.saturating_sub(last.elapsed()));
+            }
+            let Some(chunk) = queue.pop() else { break };
+            last_emit = Some(Instant::now());
+            match chunk {
+                Chunk::Data(bytes) => emit_data(encoder.encode(&bytes), bytes.len()),
+                Chunk::Dropped(n) => {
+                    let marker = format!("\r\n[pi-builder: dropped {n} bytes of output]\r\n");
+                    emit_data(encoder.encode(marker.as_bytes()), 0);
+                }
+            }
+        }
+        emit_data(encoder.flush(), 0);
+    })
+}
+
+/// Tick a session's activity and emit `pty://state` on changes and one
+/// `pty://idle` each time it goes quiet for `idle_after`. Exits with the
+/// session.
+fn spawn_activity_watcher(
+    session_id: String,
+    agent_id: String,
+    idle_after: Option<Duration>,
+    alive: Arc<Mutex<bool>>,
+    activity: Arc<Mutex<Activity>>,
+    events: SharedSink,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(ACTIVITY_POLL);
+        if !*alive.lock().unwrap() {
+            break;
+        }
+        let (tick, line) = {
+            let mut activity = activity.lock().unwrap();
+            (activity.tick(idle_after), activity.current_line())
+        };
+        if let Some(state) = tick.state {
+            emit_state(&events, &session_id, &agent_id, state, &line);
+        }
+        if let (true, Some(idle_after)) = (tick.went_idle, idle_after) {
+            events.emit(
+                &format!("pty://idle/{}", session_id),
+                serde_json::json!({
+                    "sessionId": session_id,
+                    "agentId": agent_id,
+                    "idleSecs": idle_after.as_secs(),
+                }),
+            );
+        }
+    });
+}
+
+/// Enforce `limits`: when one is exceeded, emit `pty://timeout/<id>` with
+/// the reason, interrupt the child (Ctrl-C), and kill it if it is still
+/// running after `TIMEOUT_GRACE`.
+fn spawn_watchdog(
+    session: Weak<PtySession>,
+    limits: SessionLimits,
+    activity: Arc<Mutex<Activity>>,
+    events: SharedSink,
+) {
+    let started = Instant::now();
+    thread::spawn(move || loop {
+        thread::sleep(WATCHDOG_POLL);
+        let Some(session) = session.upgrade() else { break };
+        if !*session.alive.lock().unwrap() {
+            break;
+        }
+        let quiet = activity.lock().unwrap().quiet_for().unwrap_or_else(|| started.elapsed());
+        let exceeded = [
+            ("max_runtime", limits.max_runtime_secs, started.elapsed()),
+            ("max_idle", limits.max_idle_secs, quiet),
+        ]
+        .into_iter()
+        .find_map(|(reason, limit, elapsed)| {
+            limit.filter(|&secs| elapsed >= Duration::from_secs(secs)).map(|secs| (reason, secs))
+        });
+        let Some((reason, limit_secs)) = exceeded else { continue };
+
+        events.emit(
+            &format!("pty://timeout/{}", session.id),
+            serde_json::json!({
+                "sessionId": session.id,
+                "agentId": session.agent_id,
+                "reason": reason,
+                "limitSecs": limit_secs,
+            }),
+        );
+        log::info!("session {} exceeded {reason} ({limit_secs}s), stopping", session.id);
+        let _ = session.write("\x03");
+        let deadline = Instant::now() + TIMEOUT_GRACE;
+        while Instant::now() < deadline && *session.alive.lock().unwrap() {
+            thread::sleep(WATCHDOG_POLL);
+        }
+        if *session.alive.lock().unwrap() {
+            session.kill();
+        }
+        break;
+    });
+}
+
+/// `line` is the prompt text when the session is awaiting input.
+fn emit_state(
+    events: &SharedSink,
+    session_id: &str,
+    agent_id: &str,
+    state: SessionState,
+    line: &str,
+) {
+    let prompt = (state == SessionState::AwaitingInput).then_some(line);
+    events.emit(
+        &format!("pty://state/{session_id}"),
+        serde_json::json!({
+            "sessionId": session_id,
+            "agentId": agent_id,
+            "state": state,
+            "prompt": prompt,
+        }),
+    );
+}
+
+fn emit_annotations(events: &SharedSink, session_id: &str, annotations: Vec<Annotation>) {
+    if annotations.is_empty() {
+        return;
+    }
+    events.emit(
+        &format!("pty://annotation/{session_id}"),
+        serde_json::json!({ "sessionId": session_id, "annotations": annotations }),
+    );
+}
+
+/// Read `reader` on a thread of its own, so its chunks can be waited for
+/// with a timeout. The channel holds one chunk, keeping the backpressure
+/// of a blocking read.
+fn read_chunks(mut reader: Box<dyn Read + Send>) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => panic.downcast_ref::<String>().cloned().unwrap_or_else(|| "panicked".into()),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The shell binary `config` runs.
+pub fn shell_program(config: &ShellConfig) -> String {
+    config.program.clone().unwrap_or_else(|| {
+        if cfg!(windows) {
+            "cmd.exe".into()
+        } else {
+            std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".into())
+        }
+    })
+}
+
+fn default_shell(config: &ShellConfig) -> CommandBuilder {
+    let mut builder = CommandBuilder::new(shell_program(config));
+    if config.login && !cfg!(windows) {
+        builder.arg("-l");
+    }
+    for arg in &config.args {
+        builder.arg(arg);
+    }
+    builder
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::{PtyManager, SpawnOptions};
+    use crate::testing::{RecordingSink, Script};
+    use regex::Regex;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    /// The number `mode con` prints after `label`, with ConPTY's cursor
+    /// moves (which stand in for runs of spaces) taken out.
+    fn console_value(output: &str, label: &str) -> Option<u32> {
+        let plain = Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap().replace_all(output, " ");
+        let value = Regex::new(&format!(r"{label}\s*(\d+)")).unwrap().captures(&plain)?;
+        value[1].parse().ok()
+    }
+
+    #[test]
+    fn exit_code_is_reported() {
+        let sink = RecordingSink::new();
+        let mut pty = PtyManager::default();
+        let opts = SpawnOptions::new("test", Script::new().print("done").exit(3).cmd());
+        let id = pty.spawn(opts, sink.clone()).unwrap();
+        let exit = sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+        assert_eq!(exit["exitCode"], 3);
+        assert!(sink.output(&id).contains("done"));
+    }
+
+    #[test]
+    fn resize_reaches_the_child() {
+        let sink = RecordingSink::new();
+        let mut pty = PtyManager::default();
+        // The ping gives the resize time to land before the size is read
+        let argv =
+            vec!["cmd.exe".into(), "/C".into(), "ping -n 3 127.0.0.1 >NUL & mode con".into()];
+        let id = pty.spawn(SpawnOptions::new("test", argv), sink.clone()).unwrap();
+        pty.resize(&id, 100, 40).unwrap();
+        sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+        let output = sink.output(&id);
+        assert_eq!(console_value(&output, "Columns:"), Some(100), "{output:?}");
+        assert_eq!(console_value(&output, "Lines:"), Some(40), "{output:?}");
+    }
+}
@@ -0,0 +1,177 @@
+//! Rebase an agent branch onto the current base branch tip.
+//!
+//! The rebase runs on disk in the agent's worktree, so the checkout follows
+//! along. If a commit conflicts, the rebase stops with the conflicts left in
+//! the worktree: resolve and stage them, then continue, or abort to restore
+//! the branch as it was.
+//!
+//! With `sign` the replayed commits are signed once the rebase finishes;
+//! see `signing.rs`.
+
+use crate::{error::PiError, signing, worktree};
+use anyhow::{Context, Result};
+use git2::{Oid, Rebase, Repository, RepositoryState};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebaseStatus {
+    /// Already based on the base tip; nothing to do.
+    UpToDate,
+    Rebased,
+    /// Stopped on a conflicting commit; see `conflicts`.
+    Conflict,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebaseOutcome {
+    pub status: RebaseStatus,
+    /// Commits replayed by this call.
+    pub applied: usize,
+    /// Conflicted paths, when stopped.
+    pub conflicts: Vec<String>,
+    /// Worktree HEAD afterwards.
+    pub head: String,
+}
+
+/// Start rebasing the worktree's branch onto the main checkout's HEAD.
+/// Refuses to run with uncommitted changes or another operation in progress.
+pub fn rebase(repo_path: &str, worktree: &str, sign: bool) -> Result<RebaseOutcome> {
+    let main = Repository::open(repo_path).context("open repo")?;
+    rebase_in(&main, worktree, sign)
+}
+
+/// `rebase` against an already open main repo.
+pub fn rebase_in(main: &Repository, worktree: &str, sign: bool) -> Result<RebaseOutcome> {
+    let base = main.head()?.peel_to_commit().context("base HEAD")?.id();
+    rebase_onto(main, worktree, base, sign)
+}
+
+/// `rebase_in` onto commit `base` instead of the main checkout's HEAD.
+pub fn rebase_onto(
+    main: &Repository,
+    worktree: &str,
+    base: Oid,
+    sign: bool,
+) -> Result<RebaseOutcome> {
+    let repo = worktree::open_in(main, worktree)?;
+    if repo.state() != RepositoryState::Clean {
+        return Err(PiError::invalid_input("another git operation is in progress").into());
+    }
+    if worktree::is_dirty(&repo) {
+        return Err(PiError::invalid_input("worktree has uncommitted changes").into());
+    }
+
+    let head_ref = repo.head()?;
+    let head = head_ref.peel_to_commit()?.id();
+    if head == base || repo.graph_descendant_of(head, base)? {
+        return Ok(RebaseOutcome {
+            status: RebaseStatus::UpToDate,
+            applied: 0,
+            conflicts: Vec::new(),
+            head: head.to_string(),
+        });
+    }
+
+    let branch = repo.reference_to_annotated_commit(&head_ref)?;
+    let upstream = repo.find_annotated_commit(base)?;
+    let mut rebase = repo.rebase(Some(&branch), Some(&upstream), None, None)?;
+    run(&repo, &mut rebase, base, 0, sign)
+}
+
+/// Continue a rebase stopped on conflicts, once they are resolved and
+/// staged.
+pub fn continue_rebase(repo_path: &str, worktree: &str, sign: bool) -> Result<RebaseOutcome> {
+    let repo = open_worktree(repo_path, worktree)?;
+    let mut rebase = repo.open_rebase(None).context("no rebase in progress")?;
+    if let Some(outcome) = conflict_outcome(&repo, 0)? {
+        return Ok(outcome);
+    }
+    let onto = stored_onto(&repo)?;
+    let mut applied = 0;
+    if rebase.operation_current().is_some() {
+        applied += commit_current(&repo, &mut rebase)?;
+    }
+    run(&repo, &mut rebase, onto, applied, sign)
+}
+
+/// Abandon an in-progress rebase, restoring the branch and checkout.
+pub fn abort(repo_path: &str, worktree: &str) -> Result<()> {
+    let repo = open_worktree(repo_path, worktree)?;
+    repo.open_rebase(None).context("no rebase in progress")?.abort()?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+/// Apply the rest of `rebase`, which replays onto commit `onto`.
+fn run(
+    repo: &Repository,
+    rebase: &mut Rebase,
+    onto: Oid,
+    mut applied: usize,
+    sign: bool,
+) -> Result<RebaseOutcome> {
+    while let Some(op) = rebase.next() {
+        op?;
+        if let Some(outcome) = conflict_outcome(repo, applied)? {
+            return Ok(outcome);
+        }
+        applied += commit_current(repo, rebase)?;
+    }
+    rebase.finish(Some(&worktree::signature(repo)))?;
+    if sign && applied > 0 {
+        signing::resign(repo, onto).context("sign rebased commits")?;
+    }
+    Ok(RebaseOutcome {
+        status: RebaseStatus::Rebased,
+        applied,
+        conflicts: Vec::new(),
+        head: repo.head()?.peel_to_commit()?.id().to_string(),
+    })
+}
+
+/// The commit the rebase in progress replays onto, from the state libgit2
+/// keeps for it; git2 has no accessor for it.
+fn stored_onto(repo: &Repository) -> Result<Oid> {
+    let onto = std::fs::read_to_string(repo.path().join("rebase-merge").join("onto"))
+        .context("read rebase state")?;
+    Ok(Oid::from_str(onto.trim())?)
+}
+
+/// Commit the current operation; returns 0 if it became empty (already
+/// upstream) and was skipped.
+fn commit_current(repo: &Repository, rebase: &mut Rebase) -> Result<usize> {
+    match rebase.commit(None, &worktree::signature(repo), None) {
+        Ok(_) => Ok(1),
+        Err(e) if e.code() == git2::ErrorCode::Applied => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn conflict_outcome(repo: &Repository, applied: usize) -> Result<Option<RebaseOutcome>> {
+    let index = repo.index()?;
+    if !index.has_conflicts() {
+        return Ok(None);
+    }
+    let mut conflicts: Vec<String> = index
+        .conflicts()?
+        .flatten()
+        .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        .collect();
+    conflicts.dedup();
+    Ok(Some(RebaseOutcome {
+        status: RebaseStatus::Conflict,
+        applied,
+        conflicts,
+        head: repo.head()?.peel_to_commit()?.id().to_string(),
+    }))
+}
+
+fn open_worktree(repo_path: &str, worktree: &str) -> Result<Repository> {
+    let path = worktree::worktree_path(repo_path, worktree)?;
+    Repository::open(path).context("open worktree")
+}
@@ -0,0 +1,185 @@
+//! Timed session recordings.
+//!
+//! With `record` on in the log settings, each session's output (after
+//! redaction) and its resizes are also written to `<log dir>/<id>.cast`
+//! with the time they happened, as asciicast v2, so `asciinema play` can
+//! show them too. The recording id is the session id. Unlike logs,
+//! recordings aren't rotated: one that reaches `max_file_bytes` times
+//! `max_files + 1`, what the session's log parts may use, stops growing.
+//! They are deleted with the logs after `retention_days`.
+//!
+//! `load` reads a recording back for playback (see `playback.rs`); a line
+//! cut short by a crash is skipped.
+
+use crate::{error::PiError, logs::LogSettings};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    width: u16,
+    height: u16,
+    /// Unix seconds.
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+/// Writes one session's recording.
+pub struct CastWriter {
+    file: File,
+    start: Instant,
+    /// Output ending inside a UTF-8 character, held for the next chunk.
+    pending: Vec<u8>,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl CastWriter {
+    pub fn create(
+        dir: &Path,
+        session_id: &str,
+        cols: u16,
+        rows: u16,
+        settings: &LogSettings,
+    ) -> Result<Self> {
+        fs::create_dir_all(dir).context("create log dir")?;
+        let path = cast_path(dir, session_id);
+        let mut file = File::create(&path).with_context(|| format!("create {}", path.display()))?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+        let header = Header { version: 2, width: cols, height: rows, timestamp };
+        let line = format!("{}\n", serde_json::to_string(&header)?);
+        file.write_all(line.as_bytes())?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            pending: Vec::new(),
+            written: line.len() as u64,
+            max_bytes: settings.max_file_bytes.max(1).saturating_mul(settings.max_files as u64 + 1),
+        })
+    }
+
+    pub fn output(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            // Not UTF-8 at all; record it as the terminal would show it
+            Err(_) => self.pending.len(),
+        };
+        let chunk: Vec<u8> = self.pending.drain(..valid).collect();
+        if !chunk.is_empty() {
+            self.event("o", &String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.event("r", &format!("{cols}x{rows}"));
+    }
+
+    fn event(&mut self, kind: &str, data: &str) {
+        if self.written >= self.max_bytes {
+            return;
+        }
+        let t = self.start.elapsed().as_millis() as f64 / 1000.0;
+        let Ok(line) = serde_json::to_string(&(t, kind, data)) else { return };
+        self.written += line.len() as u64 + 1;
+        if self.written >= self.max_bytes {
+            log::warn!("recording reached its size limit; later output isn't recorded");
+        }
+        if let Err(e) = self.file.write_all(format!("{line}\n").as_bytes()) {
+            log::warn!("recording: {e}");
+            self.written = self.max_bytes;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CastEvent {
+    Output(String),
+    Resize { cols: u16, rows: u16 },
+}
+
+/// A recording read back.
+pub struct Cast {
+    pub cols: u16,
+    pub rows: u16,
+    /// Milliseconds from the start, with what happened then, in order.
+    pub events: Vec<(u64, CastEvent)>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingInfo {
+    /// Session id.
+    pub id: String,
+    pub bytes: u64,
+    /// Unix millis of the last write.
+    pub modified_at: u64,
+}
+
+pub fn cast_path(dir: &Path, session_id: &str) -> PathBuf {
+    dir.join(format!("{session_id}.cast"))
+}
+
+/// Recordings in `dir`, most recently written first.
+pub fn list(dir: &Path) -> Vec<RecordingInfo> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut list: Vec<RecordingInfo> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let id = name.strip_suffix(".cast")?.to_string();
+            let meta = entry.metadata().ok()?;
+            let modified_at = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_millis() as u64);
+            Some(RecordingInfo { id, bytes: meta.len(), modified_at })
+        })
+        .collect();
+    list.sort_by_key(|r| Reverse(r.modified_at));
+    list
+}
+
+/// Read the recording of session `id` from `dir`.
+pub fn load(dir: &Path, id: &str) -> Result<Cast> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(PiError::invalid_input(format!("invalid recording id {id:?}")).into());
+    }
+    let path = cast_path(dir, id);
+    let file = File::open(&path).with_context(|| format!("open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().context("empty recording")??;
+    let header: Header = serde_json::from_str(&header).context("parse recording header")?;
+    if header.version != 2 {
+        let message = format!("unsupported recording version {}", header.version);
+        return Err(PiError::invalid_input(message).into());
+    }
+    let mut events = Vec::new();
+    for line in lines {
+        let Ok((t, kind, data)) = serde_json::from_str::<(f64, String, String)>(&line?) else {
+            continue;
+        };
+        let t = (t.max(0.0) * 1000.0) as u64;
+        let event = match kind.as_str() {
+            "o" => CastEvent::Output(data),
+            "r" => {
+                let Some((cols, rows)) = data.split_once('x') else { continue };
+                let (Ok(cols), Ok(rows)) = (cols.parse(), rows.parse()) else { continue };
+                CastEvent::Resize { cols, rows }
+            }
+            _ => continue,
+        };
+        events.push((t, event));
+    }
+    Ok(Cast { cols: header.width, rows: header.height, events })
+}
@@ -0,0 +1,202 @@
+//! Secret redaction for PTY output.
+//!
+//! Agents love to echo their environment, and users screen-share the app.
+//! Every output chunk passes through a `Redactor` before it is emitted:
+//! values of secret-looking env vars are masked, as are matches of the
+//! regexes configured in settings.
+//!
+//! Works on raw bytes so it applies to every data encoding. Literal secrets
+//! split across two reads are caught by holding back a chunk's tail while it
+//! could still be the start of a secret. Shorter tails than `MIN_PARTIAL`
+//! aren't held, and a held tail is let out, masked, once output has been
+//! quiet for `IDLE_FLUSH`, so a prompt is never stuck behind redaction. In
+//! both cases the rest of the secret is masked when the next read brings
+//! it. Regexes are applied per emitted chunk only.
+
+use regex::bytes::Regex;
+use std::time::Duration;
+
+pub const MASK: &str = "•••";
+
+/// Shorter values are too likely to collide with ordinary output.
+const MIN_SECRET_LEN: usize = 8;
+
+/// A chunk's tail is held back only once this much of a secret's start is
+/// there.
+const MIN_PARTIAL: usize = 4;
+
+/// Quiet time after which a held-back tail goes out; see `flush_idle`.
+pub const IDLE_FLUSH: Duration = Duration::from_millis(100);
+
+const SECRET_MARKERS: &[&str] = &["KEY", "TOKEN", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL"];
+const NON_SECRET_SUFFIXES: &[&str] = &["_PATH", "_FILE", "_DIR", "_SOCK", "_URL_BASE"];
+
+pub fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|m| upper.contains(m))
+        && !NON_SECRET_SUFFIXES.iter().any(|s| upper.ends_with(s))
+}
+
+/// Compile configured patterns, skipping (and logging) invalid ones.
+pub fn compile_patterns(patterns: &[String]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .filter_map(|p| match Regex::new(p) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                log::warn!("ignoring invalid redaction pattern {p:?}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+pub struct Redactor {
+    literals: Vec<Vec<u8>>,
+    patterns: Vec<Regex>,
+    pending: Vec<u8>,
+    /// The start of a secret that went out at the end of the last chunk,
+    /// and whether it went out masked.
+    started: Option<(Vec<u8>, bool)>,
+}
+
+impl Redactor {
+    pub fn new(mut literals: Vec<String>, patterns: Vec<Regex>) -> Self {
+        literals.retain(|l| l.len() >= MIN_SECRET_LEN);
+        // Longest first so a secret containing another is masked whole
+        literals.sort_by_key(|l| std::cmp::Reverse(l.len()));
+        literals.dedup();
+        let literals = literals.into_iter().map(String::into_bytes).collect();
+        Self { literals, patterns, pending: Vec::new(), started: None }
+    }
+
+    /// Redactor for a session: secret-looking vars from the spawn env and,
+    /// if `include_process_env`, from the app's own environment (which the
+    /// child inherits).
+    pub fn for_spawn(
+        spawn_env: &[(String, String)],
+        patterns: Vec<Regex>,
+        include_process_env: bool,
+    ) -> Self {
+        let mut literals: Vec<String> = spawn_env
+            .iter()
+            .filter(|(k, _)| is_secret_name(k))
+            .map(|(_, v)| v.clone())
+            .collect();
+        if include_process_env {
+            literals.extend(std::env::vars().filter(|(k, _)| is_secret_name(k)).map(|(_, v)| v));
+        }
+        Self::new(literals, patterns)
+    }
+
+    /// Also mask `literals`, e.g. resolved secrets whose env var names
+    /// don't look secret.
+    pub fn add_literals(&mut self, literals: Vec<String>) {
+        let mut all: Vec<String> =
+            self.literals.drain(..).map(|l| String::from_utf8_lossy(&l).into_owned()).collect();
+        all.extend(literals);
+        *self = Self::new(all, std::mem::take(&mut self.patterns));
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.literals.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Redact a chunk. May return less than was passed in; the held-back
+    /// tail is prepended to the next chunk or returned by `flush_idle` or
+    /// `flush`.
+    pub fn filter(&mut self, chunk: &[u8]) -> Vec<u8> {
+        if self.is_noop() {
+            return chunk.to_vec();
+        }
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(chunk);
+        let data = self.mask_rest(data);
+        let mut data = self.mask_literals(data);
+
+        let hold = self.partial_suffix_len(&data);
+        if hold < MIN_PARTIAL {
+            let start = &data[data.len() - hold..];
+            self.started = (hold > 0).then(|| (start.to_vec(), false));
+        } else {
+            self.pending = data.split_off(data.len() - hold);
+        }
+        self.mask_patterns(data)
+    }
+
+    /// Whether a chunk's tail is held back.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Let out the held-back tail once output has gone quiet. It is at least
+    /// `MIN_PARTIAL` bytes of a secret's start, so it goes out masked.
+    pub fn flush_idle(&mut self) -> Vec<u8> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        self.started = Some((std::mem::take(&mut self.pending), true));
+        MASK.as_bytes().to_vec()
+    }
+
+    /// Emit whatever is still held back (end of stream).
+    pub fn flush(&mut self) -> Vec<u8> {
+        let data = std::mem::take(&mut self.pending);
+        let data = self.mask_literals(data);
+        self.mask_patterns(data)
+    }
+
+    /// Mask the rest of a secret whose start went out before `data`.
+    fn mask_rest(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        let Some((start, masked)) = self.started.take() else { return data };
+        let rest = self.literals.iter().find_map(|lit| {
+            let rest = lit.strip_prefix(start.as_slice()).filter(|r| !r.is_empty())?;
+            data.starts_with(rest).then_some(rest.len())
+        });
+        if let Some(len) = rest {
+            let mask = if masked { &[][..] } else { MASK.as_bytes() };
+            data.splice(..len, mask.iter().copied());
+        }
+        data
+    }
+
+    fn mask_literals(&self, mut data: Vec<u8>) -> Vec<u8> {
+        for lit in &self.literals {
+            data = replace_all(&data, lit, MASK.as_bytes());
+        }
+        data
+    }
+
+    fn mask_patterns(&self, mut data: Vec<u8>) -> Vec<u8> {
+        for re in &self.patterns {
+            data = re.replace_all(&data, MASK.as_bytes()).into_owned();
+        }
+        data
+    }
+
+    /// Length of the longest suffix of `data` that is a proper prefix of a
+    /// secret.
+    fn partial_suffix_len(&self, data: &[u8]) -> usize {
+        self.literals
+            .iter()
+            .flat_map(|lit| (1..lit.len()).rev().find(|&k| data.ends_with(&lit[..k])))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn replace_all(haystack: &[u8], needle: &[u8], with: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(needle) {
+            out.extend_from_slice(with);
+            i += needle.len();
+        } else {
+            out.push(haystack[i]);
+            i += 1;
+        }
+    }
+    out
+}
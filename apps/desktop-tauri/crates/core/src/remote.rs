@@ -0,0 +1,329 @@
+//! Fetching from the remote, by hand or on an interval, and cloning.
+//!
+//! Divergence between worktrees only says how agents relate to the local
+//! base branch; when `main` moves upstream nobody notices until a push
+//! fails. `fetch` updates the remote tracking branches so `WorktreeInfo`
+//! can report ahead/behind against the base branch's upstream.
+//!
+//! Credentials come from the SSH agent or the user's git credential helper.
+//! If libgit2 can't authenticate (or can't speak the transport), the `git`
+//! CLI is tried with the user's full git setup.
+//!
+//! `clone` streams transfer progress so new users can start from a URL
+//! instead of cloning in another terminal first.
+
+use crate::{
+    basesync, error::PiError, events::SharedSink, locks::WorktreeLocks, repo_cache::RepoCache,
+    settings::Settings,
+};
+use anyhow::{bail, Context, Result};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    Config, Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository,
+};
+use serde::Serialize;
+use std::{
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the auto-fetch thread checks whether a fetch is due.
+const AUTO_FETCH_POLL: Duration = Duration::from_secs(15);
+
+/// Shortest allowed auto-fetch interval.
+pub const MIN_FETCH_INTERVAL_SECS: u64 = 60;
+
+/// Credential callbacks allowed before giving up; libgit2 keeps asking
+/// while the server rejects what it's given.
+const MAX_AUTH_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchReport {
+    pub remote: String,
+    /// Whether the `git` CLI did the fetch after libgit2 failed.
+    pub via_cli: bool,
+    /// Upstream of the main checkout's branch, e.g. `origin/main`.
+    pub upstream: Option<String>,
+    /// Main checkout's commits not on its upstream.
+    pub ahead: usize,
+    /// Upstream commits the main checkout doesn't have.
+    pub behind: usize,
+}
+
+/// Fetch `remote`, or the main branch's upstream remote (`origin` if it
+/// has none).
+pub fn fetch(repo_path: &str, remote: Option<&str>) -> Result<FetchReport> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let remote_name = match remote {
+        Some(name) => name.to_string(),
+        None => upstream_remote(&repo).unwrap_or_else(|| "origin".into()),
+    };
+    let via_cli = match fetch_git2(&repo, &remote_name) {
+        Ok(()) => false,
+        Err(e) => {
+            log::info!("libgit2 fetch of {remote_name} failed ({e:#}), trying git");
+            fetch_cli(repo_path, &remote_name)?;
+            true
+        }
+    };
+    let (upstream, ahead, behind) = match crate::worktree::upstream_of(&repo) {
+        Some((name, oid)) => {
+            let head = repo.head()?.peel_to_commit()?.id();
+            let (ahead, behind) = repo.graph_ahead_behind(head, oid)?;
+            (Some(name), ahead, behind)
+        }
+        None => (None, 0, 0),
+    };
+    Ok(FetchReport { remote: remote_name, via_cli, upstream, ahead, behind })
+}
+
+fn fetch_git2(repo: &Repository, remote_name: &str) -> Result<()> {
+    let mut remote = repo.find_remote(remote_name).context("find remote")?;
+    let config = repo.config().context("repo config")?;
+    let mut callbacks = RemoteCallbacks::new();
+    add_credentials(&mut callbacks, &config);
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(callbacks);
+    remote.fetch::<&str>(&[], Some(&mut opts), None).context("fetch")?;
+    Ok(())
+}
+
+/// Answer credential requests from the SSH agent or `config`'s credential
+/// helper, giving up after `MAX_AUTH_ATTEMPTS`.
+fn add_credentials<'a>(callbacks: &mut RemoteCallbacks<'a>, config: &'a Config) {
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > MAX_AUTH_ATTEMPTS {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(config, url, username)
+        } else {
+            Cred::default()
+        }
+    });
+}
+
+fn fetch_cli(repo_path: &str, remote_name: &str) -> Result<()> {
+    let out = Command::new("git")
+        .args(["-C", repo_path, "fetch", "--quiet", remote_name])
+        // Never block on a password prompt nobody can see
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .context("run git fetch")?;
+    if !out.status.success() {
+        bail!("git fetch {remote_name}: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Clone
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClonePhase {
+    Receiving,
+    Resolving,
+    Checkout,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloneProgress {
+    pub phase: ClonePhase,
+    pub done: usize,
+    pub total: usize,
+    pub received_bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloneReport {
+    pub path: String,
+    /// Whether the `git` CLI did the clone after libgit2 failed.
+    pub via_cli: bool,
+}
+
+/// Clone `url` into `dest`, which must not exist or be empty. `depth`
+/// makes a shallow clone. Progress is reported in whole percent steps.
+pub fn clone(
+    url: &str,
+    dest: &Path,
+    depth: Option<u32>,
+    mut on_progress: impl FnMut(CloneProgress),
+) -> Result<CloneReport> {
+    if dest.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(PiError::invalid_input(format!(
+            "{} already exists and is not empty",
+            dest.display()
+        ))
+        .into());
+    }
+    let existed = dest.exists();
+    let path = dest.to_string_lossy().to_string();
+    let git2_result = {
+        let config = Config::open_default().context("git config")?;
+        let mut last = None;
+        let mut report = |p: CloneProgress| {
+            let step = (p.phase as u8, (p.done * 100).checked_div(p.total));
+            if last != Some(step) {
+                last = Some(step);
+                on_progress(p);
+            }
+        };
+        clone_git2(url, dest, depth, &config, &mut report)
+    };
+    match git2_result {
+        Ok(()) => Ok(CloneReport { path, via_cli: false }),
+        Err(e) => {
+            log::info!("libgit2 clone of {url} failed ({e:#}), trying git");
+            // Start the CLI from a clean slate
+            if existed {
+                let _ = std::fs::remove_dir_all(dest).and_then(|()| std::fs::create_dir(dest));
+            } else {
+                let _ = std::fs::remove_dir_all(dest);
+            }
+            clone_cli(url, dest, depth)?;
+            Ok(CloneReport { path, via_cli: true })
+        }
+    }
+}
+
+fn clone_git2(
+    url: &str,
+    dest: &Path,
+    depth: Option<u32>,
+    config: &Config,
+    report: &mut dyn FnMut(CloneProgress),
+) -> Result<()> {
+    let report = std::cell::RefCell::new(report);
+    let mut callbacks = RemoteCallbacks::new();
+    add_credentials(&mut callbacks, config);
+    callbacks.transfer_progress(|p| {
+        let receiving = p.received_objects() < p.total_objects();
+        (*report.borrow_mut())(CloneProgress {
+            phase: if receiving { ClonePhase::Receiving } else { ClonePhase::Resolving },
+            done: if receiving { p.received_objects() } else { p.indexed_deltas() },
+            total: if receiving { p.total_objects() } else { p.total_deltas() },
+            received_bytes: p.received_bytes(),
+        });
+        true
+    });
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(callbacks);
+    if let Some(depth) = depth {
+        fetch.depth(depth.min(i32::MAX as u32) as i32);
+    }
+    let mut checkout = CheckoutBuilder::new();
+    checkout.progress(|_, done, total| {
+        (*report.borrow_mut())(CloneProgress {
+            phase: ClonePhase::Checkout,
+            done,
+            total,
+            received_bytes: 0,
+        });
+    });
+    RepoBuilder::new()
+        .fetch_options(fetch)
+        .with_checkout(checkout)
+        .clone(url, dest)
+        .context("clone")?;
+    Ok(())
+}
+
+fn clone_cli(url: &str, dest: &Path, depth: Option<u32>) -> Result<()> {
+    let mut command = Command::new("git");
+    command.args(["clone", "--quiet"]);
+    if let Some(depth) = depth {
+        command.arg(format!("--depth={depth}"));
+    }
+    let out = command
+        .arg("--")
+        .arg(url)
+        .arg(dest)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .context("run git clone")?;
+    if !out.status.success() {
+        bail!("git clone: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Remote of the current branch's upstream.
+fn upstream_remote(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    let refname = head.name()?;
+    let buf = repo.branch_upstream_remote(refname).ok()?;
+    buf.as_str().map(str::to_string)
+}
+
+/// Fetch the current repo every `fetch_interval_secs` (when set), then mark
+/// its worktree status stale so the status monitor pushes fresh upstream
+/// counts. Each fetch also emits `repo://fetched` with the report, and
+/// `repo://base-updated` if the base branch moved; see `basesync.rs`.
+pub fn spawn_auto_fetch(
+    repo_path: Arc<Mutex<Option<String>>>,
+    settings: Arc<Mutex<Settings>>,
+    cache: Arc<RepoCache>,
+    locks: WorktreeLocks,
+    events: SharedSink,
+) {
+    thread::spawn(move || {
+        let mut last: Option<(String, Instant)> = None;
+        loop {
+            thread::sleep(AUTO_FETCH_POLL);
+            let Some(secs) = settings.lock().unwrap().fetch_interval_secs else { continue };
+            let Some(repo) = repo_path.lock().unwrap().clone() else { continue };
+            let interval = Duration::from_secs(secs.max(MIN_FETCH_INTERVAL_SECS));
+            let due = match &last {
+                Some((path, at)) => *path != repo || at.elapsed() >= interval,
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+            last = Some((repo.clone(), Instant::now()));
+            let before = basesync::tip(&repo);
+            match fetch(&repo, None) {
+                Ok(report) => {
+                    events.emit(
+                        "repo://fetched",
+                        serde_json::json!({ "repo": repo, "report": report }),
+                    );
+                    let moved = match (before, basesync::tip(&repo)) {
+                        (Some((_, from)), Some((upstream, to))) if from != to => {
+                            Some((upstream, from, to))
+                        }
+                        _ => None,
+                    };
+                    if let Some((upstream, from, to)) = moved {
+                        let (auto_rebase, sign) = {
+                            let settings = settings.lock().unwrap();
+                            (settings.auto_rebase_worktrees, settings.sign_commits)
+                        };
+                        let tips = (from, to);
+                        match basesync::update(&repo, upstream, tips, auto_rebase, sign, &locks) {
+                            Ok(update) => events.emit(
+                                "repo://base-updated",
+                                serde_json::to_value(&update).unwrap_or_default(),
+                            ),
+                            Err(e) => log::warn!("base update {repo}: {e:#}"),
+                        }
+                    }
+                    cache.mark_stale(&repo);
+                }
+                Err(e) => log::debug!("auto fetch {repo}: {e:#}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,336 @@
+//! Open repository handles and worktree status, kept between commands.
+//!
+//! Opening a repository and every worktree on each `worktree_list` poll
+//! costs seconds on large monorepos. Handles are cached per repo path, and
+//! a background thread rescans worktree status and pushes it to the
+//! frontend as `worktree://status` when it changes, so `worktree_list`
+//! answers from the last scan.
+//!
+//! Commands that add, remove or rewrite worktrees call `mark_stale`; a
+//! handle whose repository disappeared from disk is dropped on next use.
+//! Worktree sizes come from `disk::SizeCache`, so a size computed in the
+//! background shows up in the next scan. Per-file status for the file tree
+//! is cached per worktree for one poll interval.
+//!
+//! Whether a worktree is dirty is the costly part of a scan, so it is kept
+//! too, with a fingerprint of what it depends on: HEAD, the index file and
+//! the modified times of the directories near the worktree's root, which
+//! change when files are created, deleted or saved by rename. While the
+//! fingerprint holds, a scan reuses the last answer; an edit in place
+//! touches none of these, so an answer older than `DIRTY_MAX_AGE` is
+//! rechecked regardless, and `status` with `refresh` rechecks at once.
+//! A worktree marked degraded (see `scale.rs`) is checked without its
+//! untracked files, and its answer kept for `DEGRADED_MAX_AGE`.
+
+use crate::{
+    disk::SizeCache,
+    error::PiError,
+    events::SharedSink,
+    tree::StatusSnapshot,
+    worktree::{self, WorktreeInfo},
+};
+use anyhow::{Context, Result};
+use git2::{Oid, Repository};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the background thread rescans worktree status.
+const STATUS_POLL: Duration = Duration::from_secs(5);
+
+/// Longest a worktree's dirty state is reused while its fingerprint holds.
+const DIRTY_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// `DIRTY_MAX_AGE` for degraded worktrees.
+const DEGRADED_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Directory levels below a worktree's root in its fingerprint.
+const FINGERPRINT_DEPTH: usize = 2;
+
+struct Entry {
+    main: Repository,
+    /// Open worktree repos by worktree name.
+    worktrees: HashMap<String, Repository>,
+}
+
+/// What a worktree's dirty state was worked out from.
+#[derive(PartialEq)]
+struct Fingerprint {
+    head: Option<Oid>,
+    /// Modified time and length of the index file.
+    index: Option<(SystemTime, u64)>,
+    /// Modified times of the directories near the root, sorted by path.
+    dirs: Vec<(PathBuf, SystemTime)>,
+}
+
+impl Fingerprint {
+    fn read(repo: &Repository) -> Self {
+        let index = fs::metadata(repo.path().join("index"))
+            .ok()
+            .and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+        let mut dirs = Vec::new();
+        if let Some(root) = repo.workdir() {
+            dir_times(repo, root, Path::new(""), FINGERPRINT_DEPTH, &mut dirs);
+        }
+        dirs.sort();
+        Self { head: repo.head().ok().and_then(|h| h.target()), index, dirs }
+    }
+}
+
+/// Modified times of `root/rel` and the directories below it, `depth`
+/// levels down. Ignored directories are skipped: nothing in them makes a
+/// worktree dirty, and `node_modules` alone can hold thousands.
+fn dir_times(
+    repo: &Repository,
+    root: &Path,
+    rel: &Path,
+    depth: usize,
+    out: &mut Vec<(PathBuf, SystemTime)>,
+) {
+    let dir = root.join(rel);
+    let Ok(modified) = fs::metadata(&dir).and_then(|meta| meta.modified()) else { return };
+    out.push((rel.to_path_buf(), modified));
+    if depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(&dir) else { return };
+    for entry in entries.flatten() {
+        let rel = rel.join(entry.file_name());
+        if !entry.file_type().is_ok_and(|t| t.is_dir())
+            || entry.file_name() == ".git"
+            || repo.is_path_ignored(&rel).unwrap_or(false)
+        {
+            continue;
+        }
+        dir_times(repo, root, &rel, depth - 1, out);
+    }
+}
+
+struct DirtyState {
+    dirty: bool,
+    fingerprint: Fingerprint,
+    checked_at: Instant,
+}
+
+/// (repo path, worktree name).
+type WorktreeKey = (String, String);
+/// A file status snapshot and when it was read.
+type CachedStatus = (Instant, Arc<StatusSnapshot>);
+
+#[derive(Default)]
+pub struct RepoCache {
+    entries: Mutex<HashMap<String, Arc<Mutex<Entry>>>>,
+    /// Last scan per repo path; absent when stale.
+    statuses: Mutex<HashMap<String, Vec<WorktreeInfo>>>,
+    /// File status per (repo path, worktree name), with when it was read.
+    file_statuses: Mutex<HashMap<WorktreeKey, CachedStatus>>,
+    /// Dirty state per (repo path, worktree name).
+    dirty: Mutex<HashMap<(String, String), DirtyState>>,
+    /// Worktrees over the scale limits, as (repo path, worktree name).
+    degraded: Mutex<HashSet<(String, String)>>,
+    pub sizes: SizeCache,
+}
+
+impl RepoCache {
+    /// Run `f` with the cached handle for `repo_path`, opening it if needed.
+    pub fn with_repo<T>(
+        &self,
+        repo_path: &str,
+        f: impl FnOnce(&Repository) -> Result<T>,
+    ) -> Result<T> {
+        let entry = self.entry(repo_path)?;
+        let entry = entry.lock().unwrap();
+        f(&entry.main)
+    }
+
+    /// Worktree status from the last scan, scanning now if there is none.
+    pub fn list(&self, repo_path: &str) -> Result<Vec<WorktreeInfo>> {
+        if let Some(list) = self.statuses.lock().unwrap().get(repo_path) {
+            return Ok(list.clone());
+        }
+        self.scan(repo_path).map(|(list, _)| list)
+    }
+
+    /// Recompute worktree status with cached handles. Returns the list and
+    /// whether it differs from the previous scan.
+    pub fn scan(&self, repo_path: &str) -> Result<(Vec<WorktreeInfo>, bool)> {
+        let entry = self.entry(repo_path)?;
+        let list = {
+            let mut entry = entry.lock().unwrap();
+            let Entry { main, worktrees } = &mut *entry;
+            let names: Vec<String> =
+                main.worktrees()?.iter().flatten().map(str::to_string).collect();
+            worktrees.retain(|name, _| names.contains(name));
+
+            let mut list = Vec::new();
+            for name in &names {
+                let Ok(wt) = main.find_worktree(name) else { continue };
+                if !wt.path().exists() {
+                    worktrees.remove(name);
+                    continue;
+                }
+                if !worktrees.contains_key(name) {
+                    let Ok(repo) = Repository::open(wt.path()) else { continue };
+                    worktrees.insert(name.clone(), repo);
+                }
+                let wt_repo = &worktrees[name];
+                let dirty = self.dirty(repo_path, name, wt_repo, false);
+                let mut info = worktree::info_for(main, wt_repo, name, wt.path(), dirty);
+                info.size_bytes = self.sizes.size(wt.path());
+                list.push(info);
+            }
+            list
+        };
+        let paths: Vec<PathBuf> = list.iter().map(|w| PathBuf::from(&w.path)).collect();
+        self.sizes.retain(&paths);
+        self.dirty
+            .lock()
+            .unwrap()
+            .retain(|(repo, name), _| repo != repo_path || list.iter().any(|w| &w.name == name));
+        self.degraded
+            .lock()
+            .unwrap()
+            .retain(|(repo, name)| repo != repo_path || list.iter().any(|w| &w.name == name));
+        let previous = self.statuses.lock().unwrap().insert(repo_path.to_string(), list.clone());
+        let changed = previous.as_ref() != Some(&list);
+        Ok((list, changed))
+    }
+
+    /// Status of one worktree, from the last scan if there is one. With
+    /// `refresh` its dirty state is checked now whatever the fingerprint
+    /// says; the next scan passes the result on to `worktree://status`.
+    pub fn status(&self, repo_path: &str, name: &str, refresh: bool) -> Result<WorktreeInfo> {
+        if !refresh {
+            let statuses = self.statuses.lock().unwrap();
+            let cached = statuses.get(repo_path).and_then(|l| l.iter().find(|w| w.name == name));
+            if let Some(info) = cached {
+                return Ok(info.clone());
+            }
+        }
+        let entry = self.entry(repo_path)?;
+        let mut entry = entry.lock().unwrap();
+        let Entry { main, worktrees } = &mut *entry;
+        let wt = main.find_worktree(name).map_err(|_| PiError::worktree_not_found(name))?;
+        if !worktrees.contains_key(name) {
+            let repo = Repository::open(wt.path()).context("open worktree")?;
+            worktrees.insert(name.to_string(), repo);
+        }
+        let wt_repo = &worktrees[name];
+        let dirty = self.dirty(repo_path, name, wt_repo, refresh);
+        let mut info = worktree::info_for(main, wt_repo, name, wt.path(), dirty);
+        info.size_bytes = self.sizes.size(wt.path());
+        Ok(info)
+    }
+
+    /// Mark worktree `name` as over the scale limits, or not any more.
+    pub fn set_degraded(&self, repo_path: &str, name: &str, degraded: bool) {
+        let key = (repo_path.to_string(), name.to_string());
+        let mut set = self.degraded.lock().unwrap();
+        if degraded {
+            set.insert(key);
+        } else {
+            set.remove(&key);
+        }
+    }
+
+    /// Whether a worktree is dirty, reusing the last answer while its
+    /// fingerprint holds and it is younger than `DIRTY_MAX_AGE`.
+    fn dirty(&self, repo_path: &str, name: &str, wt_repo: &Repository, refresh: bool) -> bool {
+        let key = (repo_path.to_string(), name.to_string());
+        let degraded = self.degraded.lock().unwrap().contains(&key);
+        let max_age = if degraded { DEGRADED_MAX_AGE } else { DIRTY_MAX_AGE };
+        // Read first, so a change during the check shows up next time
+        let fingerprint = Fingerprint::read(wt_repo);
+        if !refresh {
+            if let Some(last) = self.dirty.lock().unwrap().get(&key) {
+                if last.fingerprint == fingerprint && last.checked_at.elapsed() < max_age {
+                    return last.dirty;
+                }
+            }
+        }
+        let dirty = if degraded {
+            worktree::has_tracked_changes(wt_repo)
+        } else {
+            worktree::is_dirty(wt_repo)
+        };
+        let state = DirtyState { dirty, fingerprint, checked_at: Instant::now() };
+        self.dirty.lock().unwrap().insert(key, state);
+        dirty
+    }
+
+    /// Per-file git status of a worktree, reread once it is older than the
+    /// status poll interval.
+    pub fn file_status(&self, repo_path: &str, name: &str) -> Result<Arc<StatusSnapshot>> {
+        let key = (repo_path.to_string(), name.to_string());
+        if let Some((at, snapshot)) = self.file_statuses.lock().unwrap().get(&key) {
+            if at.elapsed() < STATUS_POLL {
+                return Ok(snapshot.clone());
+            }
+        }
+        let entry = self.entry(repo_path)?;
+        let snapshot = {
+            let mut entry = entry.lock().unwrap();
+            let Entry { main, worktrees } = &mut *entry;
+            if !worktrees.contains_key(name) {
+                let repo = worktree::open_in(main, name)?;
+                worktrees.insert(name.to_string(), repo);
+            }
+            Arc::new(StatusSnapshot::read(&worktrees[name])?)
+        };
+        self.file_statuses.lock().unwrap().insert(key, (Instant::now(), snapshot.clone()));
+        Ok(snapshot)
+    }
+
+    /// Forget the last scan so the next `list` rescans.
+    pub fn mark_stale(&self, repo_path: &str) {
+        self.statuses.lock().unwrap().remove(repo_path);
+        self.file_statuses.lock().unwrap().retain(|(repo, _), _| repo != repo_path);
+        self.dirty.lock().unwrap().retain(|(repo, _), _| repo != repo_path);
+    }
+
+    /// Drop the handles and status for `repo_path`.
+    pub fn invalidate(&self, repo_path: &str) {
+        self.entries.lock().unwrap().remove(repo_path);
+        self.mark_stale(repo_path);
+    }
+
+    fn entry(&self, repo_path: &str) -> Result<Arc<Mutex<Entry>>> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(repo_path) {
+            if entry.lock().unwrap().main.path().exists() {
+                return Ok(entry.clone());
+            }
+            entries.remove(repo_path);
+        }
+        let main = Repository::open(Path::new(repo_path)).context("open repo")?;
+        let entry = Arc::new(Mutex::new(Entry { main, worktrees: HashMap::new() }));
+        entries.insert(repo_path.to_string(), entry.clone());
+        Ok(entry)
+    }
+}
+
+/// Rescan the current repo every few seconds and emit `worktree://status`
+/// with the full list when anything changed.
+pub fn spawn_status_monitor(
+    cache: Arc<RepoCache>,
+    repo_path: Arc<Mutex<Option<String>>>,
+    events: SharedSink,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(STATUS_POLL);
+        let Some(repo) = repo_path.lock().unwrap().clone() else { continue };
+        match cache.scan(&repo) {
+            Ok((list, true)) => events.emit(
+                "worktree://status",
+                serde_json::json!({ "repo": repo, "worktrees": list }),
+            ),
+            Ok(_) => {}
+            Err(e) => log::debug!("worktree status scan: {e:#}"),
+        }
+    });
+}
@@ -0,0 +1,93 @@
+//! `.pi-builder.toml`: per-repo defaults checked into the repo.
+//!
+//! Lets a team share setup commands, branch naming, cache-link rules, a
+//! default agent and protected paths without everyone configuring their
+//! own settings. The file is read from the main checkout when needed, so
+//! edits apply to the next worktree or spawn.
+//!
+//! ```toml
+//! default_agent = "claude"
+//! branch_template = "agent/{agent}/{slug}"
+//! setup = ["npm ci", "cp \"$PI_BUILDER_REPO/.env\" ."]
+//! protected_paths = ["migrations/**", "*.lock"]
+//! merge_check = "cargo test"
+//! merge_message = "Merge {branch} ({agent})\n\nTask: {task}\n\n{files}"
+//! sparse = ["services/foo", "libs/shared"]
+//!
+//! [checks]
+//! test = "cargo test"
+//! lint = "cargo clippy -- -D warnings"
+//!
+//! [[cache]]
+//! path = "node_modules"
+//! strategy = "symlink"
+//! ```
+//!
+//! Settings made for the repo in the app win over the file; the file wins
+//! over global settings such as the branch template.
+
+use crate::{
+    checks,
+    depcache::{self, CacheRule},
+    mergemsg, sparse,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+pub const CONFIG_FILE: &str = ".pi-builder.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// Setup commands for new worktrees, joined with `&&`.
+    pub setup: Vec<String>,
+    /// Agent profile for spawns and tasks that don't name one.
+    pub default_agent: Option<String>,
+    pub branch_template: Option<String>,
+    /// Globs, relative to the worktree root, agents shouldn't modify.
+    pub protected_paths: Vec<String>,
+    /// Run in a worktree before the merge queue lands it; must exit 0.
+    pub merge_check: Option<String>,
+    /// Template for merge commit messages; see `mergemsg.rs`.
+    pub merge_message: Option<String>,
+    /// Directories new worktrees share with the main checkout.
+    pub cache: Vec<CacheRule>,
+    /// Only check out these paths in new worktrees; see `sparse.rs`.
+    pub sparse: Vec<String>,
+    /// Commands runnable in a worktree by name; see `checks.rs`.
+    pub checks: BTreeMap<String, String>,
+}
+
+impl RepoConfig {
+    /// The repo's config file, or `None` if it has none.
+    pub fn load(repo: &Path) -> Result<Option<Self>> {
+        let path = repo.join(CONFIG_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+        };
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("parse {}", path.display()))?;
+        depcache::validate(&config.cache).with_context(|| format!("{CONFIG_FILE}: cache"))?;
+        sparse::validate(&config.sparse).with_context(|| format!("{CONFIG_FILE}: sparse"))?;
+        checks::validate(&config.checks).with_context(|| format!("{CONFIG_FILE}: checks"))?;
+        if let Some(template) = &config.merge_message {
+            mergemsg::validate(template)
+                .with_context(|| format!("{CONFIG_FILE}: merge_message"))?;
+        }
+        Ok(Some(config))
+    }
+
+    /// Like `load`, but a broken file is logged and treated as absent so
+    /// it doesn't block creating worktrees.
+    pub fn load_or_default(repo: &Path) -> Self {
+        Self::load(repo)
+            .unwrap_or_else(|e| {
+                log::warn!("{e:#}");
+                None
+            })
+            .unwrap_or_default()
+    }
+}
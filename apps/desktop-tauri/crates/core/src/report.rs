@@ -0,0 +1,229 @@
+//! Task reports: one artifact per agent run, for reviewing it afterwards.
+//!
+//! A report gathers what is known about a task: its prompt and agent, when
+//! it ran and how it ended, token usage, the files its branch changed
+//! since it forked off the main checkout's branch, the merge queue's
+//! checks of its worktree, and where its log and recording are. It comes
+//! as Markdown or JSON, returned inline or written to a file.
+//!
+//! The changes are read from the worktree, so a report made after the
+//! worktree was removed has none. Uncommitted changes aren't counted.
+
+use crate::{
+    compare::{self, BranchChange, ChangeKind},
+    error::PiError,
+    mergemsg,
+    mergequeue::{MergeEntry, MergeStatus},
+    tasks::Task,
+    usage::{Usage, UsageRecord},
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskReport {
+    pub task: Task,
+    pub duration_ms: Option<u64>,
+    /// Summed over the task's sessions.
+    pub usage: Usage,
+    /// Files changed on the task's branch, when its worktree is there.
+    pub changes: Option<BTreeMap<String, BranchChange>>,
+    /// Merge queue runs for the task's worktree, oldest first.
+    pub checks: Vec<MergeEntry>,
+    pub log_path: Option<String>,
+    /// Recording of the task's session; open with `pty_playback_open`.
+    pub recording_id: Option<String>,
+    pub recording_path: Option<String>,
+    /// Unix millis.
+    pub generated_at: u64,
+}
+
+impl TaskReport {
+    /// `recording` is the path of the task session's recording and its id.
+    pub fn new(
+        task: Task,
+        usage: Usage,
+        changes: Option<BTreeMap<String, BranchChange>>,
+        checks: Vec<MergeEntry>,
+        log_path: Option<PathBuf>,
+        recording: Option<(PathBuf, String)>,
+    ) -> Self {
+        let now = now_ms();
+        let (recording_path, recording_id) = recording.unzip();
+        Self {
+            duration_ms: task.started_at.map(|s| task.finished_at.unwrap_or(now).saturating_sub(s)),
+            task,
+            usage,
+            changes,
+            checks,
+            log_path: log_path.map(|p| p.to_string_lossy().into_owned()),
+            recording_id,
+            recording_path: recording_path.map(|p| p.to_string_lossy().into_owned()),
+            generated_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportResult {
+    pub format: ReportFormat,
+    /// The file written, when given one.
+    pub path: Option<String>,
+    /// The report, when not written to a file.
+    pub content: Option<String>,
+}
+
+/// Files the worktree's branch changed since it forked off the main
+/// checkout's branch.
+pub fn branch_changes(main: &Repository, name: &str) -> Result<BTreeMap<String, BranchChange>> {
+    let wt = worktree::open_in(main, name)?;
+    let head = wt.head()?.peel_to_commit()?;
+    let main_head = main.head()?.peel_to_commit()?.id();
+    let base = main.merge_base(main_head, head.id()).unwrap_or(main_head);
+    let base_tree = main.find_commit(base)?.tree()?;
+    let head_tree = head.tree()?;
+    compare::changes(main, &base_tree, &head_tree)
+}
+
+/// Usage of the task's sessions, added up.
+pub fn task_usage(task_id: &str, records: Vec<UsageRecord>) -> Usage {
+    let records = records.into_iter().filter(|r| r.task_id.as_deref() == Some(task_id));
+    crate::usage::report(records, Default::default()).total
+}
+
+pub fn render(report: &TaskReport, format: ReportFormat) -> Result<String> {
+    match format {
+        ReportFormat::Json => Ok(serde_json::to_string_pretty(report)?),
+        ReportFormat::Markdown => Ok(markdown(report)),
+    }
+}
+
+/// Render `report` and write it to `dest`, which must be absolute, or
+/// return it inline.
+pub fn output(
+    report: &TaskReport,
+    format: ReportFormat,
+    dest: Option<&Path>,
+) -> Result<ReportResult> {
+    let content = render(report, format)?;
+    let Some(dest) = dest else {
+        return Ok(ReportResult { format, path: None, content: Some(content) });
+    };
+    if !dest.is_absolute() {
+        return Err(PiError::invalid_input("report path must be absolute").into());
+    }
+    std::fs::write(dest, content).with_context(|| format!("write {}", dest.display()))?;
+    Ok(ReportResult { format, path: Some(dest.to_string_lossy().into_owned()), content: None })
+}
+
+fn markdown(report: &TaskReport) -> String {
+    let task = &report.task;
+    let mut out = String::new();
+    let _ = writeln!(out, "# Task {}\n", task.id);
+    let _ = writeln!(out, "- Agent: `{}`", task.agent);
+    let _ = writeln!(out, "- Status: {:?}", task.status);
+    if let Some(code) = task.exit_code {
+        let _ = writeln!(out, "- Exit code: {code}");
+    }
+    if let Some(error) = &task.error {
+        let _ = writeln!(out, "- Error: {error}");
+    }
+    if let Some(duration) = report.duration_ms {
+        let _ = writeln!(out, "- Duration: {}", mergemsg::format_duration(duration));
+    }
+    if let Some(worktree) = &task.worktree {
+        let _ = writeln!(out, "- Worktree: `{worktree}`");
+    }
+    if let Some(session) = &task.session_id {
+        let _ = writeln!(out, "- Session: `{session}`");
+    }
+    let usage = &report.usage;
+    if *usage != Usage::default() {
+        let _ = writeln!(
+            out,
+            "- Usage: {} input / {} output tokens, ${:.2}",
+            usage.input_tokens, usage.output_tokens, usage.cost_usd
+        );
+    }
+
+    let _ = writeln!(out, "\n## Prompt\n");
+    match &task.prompt {
+        Some(prompt) => {
+            let fence = if prompt.contains("```") { "~~~~" } else { "```" };
+            let _ = writeln!(out, "{fence}\n{}\n{fence}", prompt.trim_end());
+        }
+        None => {
+            let _ = writeln!(out, "None; the agent ran `{}`.", task.cmd.join(" "));
+        }
+    }
+
+    let _ = writeln!(out, "\n## Changes\n");
+    match &report.changes {
+        Some(changes) if !changes.is_empty() => {
+            let _ = writeln!(out, "{}\n", mergemsg::stat(changes));
+            let _ = writeln!(out, "| File | Change | + | - |\n| --- | --- | ---: | ---: |");
+            for (path, change) in changes {
+                let kind = match change.kind {
+                    ChangeKind::Added => "added",
+                    ChangeKind::Modified => "modified",
+                    ChangeKind::Deleted => "deleted",
+                };
+                let _ = writeln!(
+                    out,
+                    "| `{path}` | {kind} | {} | {} |",
+                    change.additions, change.deletions
+                );
+            }
+        }
+        Some(_) => {
+            let _ = writeln!(out, "No committed changes.");
+        }
+        None => {
+            let _ = writeln!(out, "No worktree to read them from.");
+        }
+    }
+
+    let _ = writeln!(out, "\n## Checks\n");
+    if report.checks.is_empty() {
+        let _ = writeln!(out, "Not run.");
+    }
+    for check in &report.checks {
+        let result = match (check.status, &check.error) {
+            (MergeStatus::Merged, _) => "passed, merged".to_string(),
+            (_, Some(error)) => format!("{:?}: {error}", check.status),
+            (status, None) => format!("{status:?}"),
+        };
+        let _ = writeln!(out, "- {result}");
+    }
+
+    if report.log_path.is_some() || report.recording_path.is_some() {
+        let _ = writeln!(out, "\n## Output\n");
+        if let Some(log) = &report.log_path {
+            let _ = writeln!(out, "- Log: `{log}`");
+        }
+        if let Some(recording) = &report.recording_path {
+            let _ = writeln!(out, "- Recording: `{recording}`");
+        }
+    }
+    out
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
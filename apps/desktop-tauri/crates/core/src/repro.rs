@@ -0,0 +1,130 @@
+//! Launch snapshots: what a session was started with, to start it again.
+//!
+//! Every session records at spawn the command as given and as run (after
+//! the shell default and any WSL, container or SSH wrapping), the cwd, the
+//! env, the terminal size and, when the cwd is in a git repo, the commit
+//! checked out there. Env values of secret-looking names are masked and
+//! secret references (`${secret:NAME}`) kept as written; secret values in
+//! the command as run are masked too. `reproduce` is a shell command line
+//! that repeats the launch by hand, without the secret env.
+//!
+//! `pty_respawn` starts the same agent with the same command, backend and
+//! size in a new session; one that ran in a worktree gets a new worktree
+//! at the commit it started from. Uncommitted changes it had at spawn
+//! aren't carried over, and env is rebuilt from the agent profile, so
+//! secrets resolve to their current values. Snapshots are kept in
+//! `sessions.json`, so sessions of the previous run can be respawned too.
+
+use crate::{
+    container::ContainerSpec,
+    redact::{self, MASK},
+    ssh::{shell_quote, SshTarget},
+    target::SpawnTarget,
+};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitState {
+    pub commit: String,
+    /// `None` on a detached HEAD.
+    pub branch: Option<String>,
+    /// Uncommitted changes at spawn, which a respawn doesn't have.
+    pub dirty: bool,
+    /// Name of the linked worktree the cwd is in, if it is in one.
+    pub worktree: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchSnapshot {
+    pub agent_id: String,
+    /// Command as given; empty for the default shell.
+    pub cmd: Vec<String>,
+    /// Command as run.
+    pub resolved_cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub cols: u16,
+    pub rows: u16,
+    pub target: SpawnTarget,
+    pub container: Option<ContainerSpec>,
+    pub ssh: Option<SshTarget>,
+    pub repo: Option<String>,
+    pub git: Option<GitState>,
+    /// Session that handed its worktree over to this one; see
+    /// `session_handoff`.
+    #[serde(default)]
+    pub handoff_from: Option<String>,
+    /// Shell command line repeating the launch, without secret env.
+    pub reproduce: String,
+    /// Unix millis.
+    pub taken_at: u64,
+}
+
+impl LaunchSnapshot {
+    /// Fill in `reproduce` from the other fields.
+    pub fn with_reproduce(mut self) -> Self {
+        let mut parts = Vec::new();
+        if let Some(cwd) = &self.cwd {
+            parts.push(format!("cd {}", shell_quote(cwd)));
+        }
+        if let Some(git) = &self.git {
+            parts.push(format!("git checkout --detach {}", git.commit));
+        }
+        let mut line: Vec<String> = self
+            .env
+            .iter()
+            .filter(|(_, value)| value != MASK && !value.contains("${secret:"))
+            .map(|(name, value)| format!("{name}={}", shell_quote(value)))
+            .collect();
+        line.extend(self.resolved_cmd.iter().map(|a| shell_quote(a)));
+        parts.push(line.join(" "));
+        self.reproduce = parts.join(" && ");
+        self
+    }
+}
+
+/// Commit, branch and status of the repo `cwd` is in.
+pub fn git_state(cwd: &Path) -> Option<GitState> {
+    let repo = Repository::discover(cwd).ok()?;
+    let head = repo.head().ok()?;
+    let commit = head.peel_to_commit().ok()?.id().to_string();
+    let branch = head.is_branch().then(|| head.shorthand().map(String::from)).flatten();
+    let dirty = repo.statuses(None).map(|s| !s.is_empty()).unwrap_or(false);
+    // A linked worktree's git dir is `<main>/.git/worktrees/<name>`
+    let worktree = repo
+        .is_worktree()
+        .then(|| repo.path().file_name().map(|n| n.to_string_lossy().into_owned()))
+        .flatten();
+    Some(GitState { commit, branch, dirty, worktree })
+}
+
+/// `env` with the values of secret-looking names masked.
+pub fn mask_env(env: &[(String, String)]) -> Vec<(String, String)> {
+    env.iter()
+        .map(|(name, value)| {
+            let reference = value.starts_with("${secret:") && value.ends_with('}');
+            let value = if redact::is_secret_name(name) && !reference {
+                MASK.into()
+            } else {
+                value.clone()
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// `argv` with every occurrence of `secrets` masked.
+pub fn mask_args(argv: &[String], secrets: &[String]) -> Vec<String> {
+    argv.iter()
+        .map(|arg| {
+            secrets
+                .iter()
+                .filter(|s| !s.is_empty())
+                .fold(arg.clone(), |arg, secret| arg.replace(secret.as_str(), MASK))
+        })
+        .collect()
+}
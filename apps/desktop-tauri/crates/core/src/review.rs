@@ -0,0 +1,348 @@
+//! Review workflow for agent branches.
+//!
+//! Each agent worktree moves through
+//!
+//! ```text
+//! in_progress → ready_for_review → approved → merged
+//!      ↑               │               │
+//!      └───────────────┴───────────────┘   (changes requested / reopened)
+//! ```
+//!
+//! and can be discarded from any state before it is merged. A worktree
+//! nobody has touched yet is `in_progress`. Marking a branch ready or
+//! approved records its HEAD; `merge` refuses a branch that isn't approved
+//! or that gained commits since, so nothing half-reviewed lands by
+//! accident. State lives in `reviews.json` in the config dir and every
+//! change is emitted as `review://changed`.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    mergemsg, signing,
+    usage::UsageRecord,
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{build::CheckoutBuilder, BranchType, Repository, RepositoryState};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    #[default]
+    InProgress,
+    ReadyForReview,
+    Approved,
+    Merged,
+    Discarded,
+}
+
+impl ReviewState {
+    fn is_final(self) -> bool {
+        matches!(self, ReviewState::Merged | ReviewState::Discarded)
+    }
+
+    /// Whether `self → to` is a legal step.
+    fn can_move_to(self, to: ReviewState) -> bool {
+        use ReviewState::*;
+        match (self, to) {
+            (InProgress, ReadyForReview) => true,
+            (ReadyForReview, InProgress | Approved) => true,
+            (Approved, InProgress | Merged) => true,
+            (from, Discarded) => !from.is_final(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition {
+    pub from: ReviewState,
+    pub to: ReviewState,
+    /// Unix millis.
+    pub at: u64,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewEntry {
+    pub worktree: String,
+    pub state: ReviewState,
+    /// Branch HEAD when it was last marked ready or approved.
+    pub reviewed_head: Option<String>,
+    /// Merge commit (or fast-forward target) once merged.
+    pub merge_commit: Option<String>,
+    pub history: Vec<Transition>,
+}
+
+impl ReviewEntry {
+    fn new(worktree: &str) -> Self {
+        Self {
+            worktree: worktree.to_string(),
+            state: ReviewState::default(),
+            reviewed_head: None,
+            merge_commit: None,
+            history: Vec::new(),
+        }
+    }
+}
+
+/// Review entries per repo path, then per worktree name.
+type Entries = HashMap<String, HashMap<String, ReviewEntry>>;
+
+pub struct ReviewStore {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl ReviewStore {
+    /// Load `path`; a missing or unreadable file starts empty.
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, entries: Mutex::new(entries) }
+    }
+
+    /// Entries for `repo`, one per worktree, untracked ones as `in_progress`.
+    pub fn list(&self, repo: &str, worktrees: &[String]) -> Vec<ReviewEntry> {
+        let entries = self.entries.lock().unwrap();
+        let tracked = entries.get(repo);
+        let mut list: Vec<ReviewEntry> = worktrees
+            .iter()
+            .map(|name| {
+                tracked
+                    .and_then(|t| t.get(name).cloned())
+                    .unwrap_or_else(|| ReviewEntry::new(name))
+            })
+            .collect();
+        // Merged and discarded worktrees are usually gone; keep their record
+        if let Some(tracked) = tracked {
+            list.extend(
+                tracked
+                    .values()
+                    .filter(|e| e.state.is_final() && !worktrees.contains(&e.worktree))
+                    .cloned(),
+            );
+        }
+        list.sort_by(|a, b| a.worktree.cmp(&b.worktree));
+        list
+    }
+
+    pub fn get(&self, repo: &str, worktree: &str) -> ReviewEntry {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(repo)
+            .and_then(|t| t.get(worktree).cloned())
+            .unwrap_or_else(|| ReviewEntry::new(worktree))
+    }
+
+    /// Move `worktree` to `to`, recording `head` for ready/approved. Fails
+    /// with `invalid_transition` if the state machine doesn't allow it.
+    pub fn transition(
+        &self,
+        repo: &str,
+        worktree: &str,
+        to: ReviewState,
+        note: Option<String>,
+        head: Option<String>,
+    ) -> Result<ReviewEntry> {
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries
+                .entry(repo.to_string())
+                .or_default()
+                .entry(worktree.to_string())
+                .or_insert_with(|| ReviewEntry::new(worktree));
+            check_transition(entry.state, to)?;
+            entry.history.push(Transition { from: entry.state, to, at: now_ms(), note });
+            entry.state = to;
+            match to {
+                ReviewState::ReadyForReview | ReviewState::Approved => entry.reviewed_head = head,
+                ReviewState::InProgress => entry.reviewed_head = None,
+                ReviewState::Merged => entry.merge_commit = head,
+                ReviewState::Discarded => {}
+            }
+            entry.clone()
+        };
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Drop the record of a worktree that was removed without review.
+    pub fn forget(&self, repo: &str, worktree: &str) {
+        let removed = {
+            let mut entries = self.entries.lock().unwrap();
+            let removed = entries.get_mut(repo).and_then(|t| t.remove(worktree));
+            removed.is_some_and(|e| !e.state.is_final())
+        };
+        if removed {
+            if let Err(e) = self.save() {
+                log::warn!("save reviews: {e:#}");
+            }
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.entries.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write reviews")?;
+        Ok(())
+    }
+}
+
+/// Location of the review state inside the app config dir.
+pub fn reviews_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("reviews.json")
+}
+
+pub fn check_transition(from: ReviewState, to: ReviewState) -> Result<()> {
+    if from.can_move_to(to) {
+        return Ok(());
+    }
+    Err(PiError::new(
+        ErrorCode::InvalidTransition,
+        format!("cannot move from {from:?} to {to:?}"),
+    )
+    .into())
+}
+
+// ---------------------------------------------------------------------------
+// Git side
+// ---------------------------------------------------------------------------
+
+/// HEAD commit of a worktree's branch, for recording what was reviewed.
+pub fn worktree_head(main: &Repository, worktree: &str) -> Result<String> {
+    let repo = worktree::open_in(main, worktree)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    Ok(head.to_string())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeOutcome {
+    pub branch: String,
+    /// New HEAD of the main checkout.
+    pub commit: String,
+    pub fast_forward: bool,
+}
+
+/// Merge an approved worktree's branch into the main checkout's branch.
+/// The branch must still be at `reviewed_head`, and both checkouts must be
+/// clean. Conflicts fail the merge without touching anything. A merge
+/// commit's message is rendered from `template` (see `mergemsg.rs`), with
+/// `sessions` for the agent metadata, and signed with `sign` (see
+/// `signing.rs`).
+pub fn merge(
+    main: &Repository,
+    worktree: &str,
+    reviewed_head: &str,
+    template: &str,
+    sessions: &[UsageRecord],
+    sign: bool,
+) -> Result<MergeOutcome> {
+    let wt_repo = worktree::open_in(main, worktree)?;
+    if worktree::is_dirty(&wt_repo) {
+        return Err(PiError::invalid_input("worktree has uncommitted changes").into());
+    }
+    let branch = branch_of(&wt_repo)?;
+    let theirs = main.find_branch(&branch, BranchType::Local)?.into_reference();
+    let theirs = main.reference_to_annotated_commit(&theirs)?;
+    if theirs.id().to_string() != reviewed_head {
+        return Err(PiError::new(
+            ErrorCode::InvalidTransition,
+            "branch has new commits since it was approved",
+        )
+        .into());
+    }
+    if main.state() != RepositoryState::Clean {
+        return Err(PiError::invalid_input("main checkout is in the middle of an operation").into());
+    }
+    if worktree::is_dirty(main) {
+        let message = "main checkout has uncommitted changes; stash them first";
+        return Err(PiError::invalid_input(message).into());
+    }
+
+    let (analysis, _) = main.merge_analysis(&[&theirs])?;
+    let mut head = main.head()?;
+    if analysis.is_up_to_date() {
+        let commit = head.peel_to_commit()?.id().to_string();
+        return Ok(MergeOutcome { branch, commit, fast_forward: false });
+    }
+    if analysis.is_fast_forward() {
+        let target = main.find_object(theirs.id(), None)?;
+        main.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))?;
+        head.set_target(theirs.id(), &format!("pi-builder: fast-forward to {branch}"))?;
+        let commit = theirs.id().to_string();
+        return Ok(MergeOutcome { branch, commit, fast_forward: true });
+    }
+
+    let ours = head.peel_to_commit()?;
+    let theirs = main.find_commit(theirs.id())?;
+    let mut index = main.merge_commits(&ours, &theirs, None)?;
+    if index.has_conflicts() {
+        let files: Vec<String> = index
+            .conflicts()?
+            .flatten()
+            .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+            .map(|e| String::from_utf8_lossy(&e.path).to_string())
+            .collect();
+        return Err(PiError::new(ErrorCode::MergeConflict, format!("{branch} conflicts"))
+            .with_detail(files.join("\n"))
+            .into());
+    }
+    let tree = main.find_tree(index.write_tree_to(main)?)?;
+    let base = main.find_commit(main.merge_base(ours.id(), theirs.id())?)?.tree()?;
+    let message =
+        mergemsg::render(main, template, worktree, &branch, &base, &theirs.tree()?, sessions)?;
+    let sig = worktree::signature(main);
+    let parents = [&ours, &theirs];
+    let commit = signing::commit(main, Some("HEAD"), &sig, &sig, &message, &tree, &parents, sign)?;
+    // The checkout was clean, so forcing only brings in the merged changes
+    main.checkout_head(Some(CheckoutBuilder::new().force()))?;
+    Ok(MergeOutcome { branch, commit: commit.to_string(), fast_forward: false })
+}
+
+/// The message merging a worktree's branch into the main checkout's branch
+/// now would get, were it not a fast-forward.
+pub fn merge_message(
+    main: &Repository,
+    worktree: &str,
+    template: &str,
+    sessions: &[UsageRecord],
+) -> Result<String> {
+    let wt_repo = worktree::open_in(main, worktree)?;
+    let branch = branch_of(&wt_repo)?;
+    let ours = main.head()?.peel_to_commit()?;
+    let theirs = wt_repo.head()?.peel_to_commit()?;
+    let base = main.find_commit(main.merge_base(ours.id(), theirs.id())?)?.tree()?;
+    let theirs_tree = theirs.tree()?;
+    mergemsg::render(main, template, worktree, &branch, &base, &theirs_tree, sessions)
+}
+
+fn branch_of(wt_repo: &Repository) -> Result<String> {
+    let head = wt_repo.head()?;
+    Ok(head
+        .shorthand()
+        .filter(|_| head.is_branch())
+        .context("worktree HEAD is detached")?
+        .to_string())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,466 @@
+//! JSON-RPC 2.0 for driving the app from other programs.
+//!
+//! The app's local socket (see `ipc.rs`) also speaks JSON-RPC 2.0: a line
+//! holding a request object with `"jsonrpc": "2.0"`, or a batch array of
+//! them, is answered with one response line; notifications (no `id`) get
+//! none. Methods are those in `METHODS`, named after the Tauri commands
+//! they run and taking the same arguments by name. `rpc.discover` returns
+//! `METHODS` and `TYPES`, the schemas of the methods and their structured
+//! parameters. `pi rpc` bridges stdio to the socket for editors and CI,
+//! with LSP-style `Content-Length` framing under `--lsp`.
+//!
+//! A failed call's error has the `PiError` (`{ code, message, detail }`)
+//! as `data`; its code is -32602 for invalid input, -32603 for internal
+//! errors and -32000 otherwise.
+
+use crate::error::{ErrorCode, PiError};
+use serde::Serialize;
+use serde_json::Value;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const SERVER_ERROR: i64 = -32000;
+
+/// Returns the schemas; answered by the socket itself.
+pub const DISCOVER: &str = "rpc.discover";
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn ok(id: Value, result: Value) -> Self {
+        Self { jsonrpc: "2.0", id, result: Some(result), error: None }
+    }
+
+    pub fn err(id: Value, code: i64, message: impl Into<String>) -> Self {
+        let error = RpcError { code, message: message.into(), data: None };
+        Self { jsonrpc: "2.0", id, result: None, error: Some(error) }
+    }
+
+    pub fn from_result(id: Value, result: Result<Value, PiError>) -> Self {
+        match result {
+            Ok(value) => Self::ok(id, value),
+            Err(e) => {
+                let code = match e.code {
+                    ErrorCode::InvalidInput => INVALID_PARAMS,
+                    ErrorCode::Internal => INTERNAL_ERROR,
+                    _ => SERVER_ERROR,
+                };
+                let mut response = Self::err(id, code, e.message.clone());
+                if let Some(error) = response.error.as_mut() {
+                    error.data = serde_json::to_value(&e).ok();
+                }
+                response
+            }
+        }
+    }
+}
+
+/// A request taken apart.
+#[derive(Debug)]
+pub struct Call {
+    /// `None` for a notification.
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: Value,
+}
+
+/// Whether a parsed line is JSON-RPC rather than the plain `ipc` protocol.
+pub fn is_rpc(message: &Value) -> bool {
+    message.is_array() || message.get("jsonrpc").is_some()
+}
+
+/// Check one request object of a message, or the error response to send
+/// back. Positional params aren't supported: every method takes named
+/// arguments.
+pub fn parse_call(request: &Value) -> Result<Call, Box<RpcResponse>> {
+    let id = request.get("id").cloned();
+    let invalid = |message: &str| {
+        Box::new(RpcResponse::err(id.clone().unwrap_or(Value::Null), INVALID_REQUEST, message))
+    };
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid("jsonrpc must be \"2.0\""));
+    }
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Err(invalid("method must be a string"));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    if params.is_array() {
+        let id = id.unwrap_or(Value::Null);
+        return Err(Box::new(RpcResponse::err(id, INVALID_PARAMS, "params must be an object")));
+    }
+    if !params.is_null() && !params.is_object() {
+        return Err(invalid("params must be an object"));
+    }
+    Ok(Call { id, method: method.to_string(), params })
+}
+
+// ---------------------------------------------------------------------------
+// Schemas
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+pub struct Param {
+    pub name: &'static str,
+    /// TypeScript notation; capitalised names are in `TYPES`, or results
+    /// described by the command docs.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Method {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub params: &'static [Param],
+    pub result: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypeSchema {
+    pub name: &'static str,
+    /// Fields of an object, all optional unless marked required.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub fields: &'static [Param],
+    /// Values of a string enum.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub values: &'static [&'static str],
+}
+
+const fn req(name: &'static str, ty: &'static str) -> Param {
+    Param { name, ty, required: true }
+}
+
+const fn opt(name: &'static str, ty: &'static str) -> Param {
+    Param { name, ty, required: false }
+}
+
+const fn method(
+    name: &'static str,
+    summary: &'static str,
+    params: &'static [Param],
+    result: &'static str,
+) -> Method {
+    Method { name, summary, params, result }
+}
+
+pub const METHODS: &[Method] = &[
+    method(DISCOVER, "Schemas of the methods and types", &[], "{ methods, types }"),
+    method("get_repo_path", "The current repo", &[], "string | null"),
+    method("set_repo_path", "Make a repo current", &[req("path", "string")], "null"),
+    method("agent_profiles", "Built-in and custom agent profiles", &[], "AgentProfile[]"),
+    method("pty_spawn", "Start a session", &[req("args", "SpawnArgs")], "SpawnResult"),
+    method(
+        "pty_respawn",
+        "Launch a session's agent again as it was started",
+        &[req("session_id", "string"), opt("queue", "boolean")],
+        "SpawnResult",
+    ),
+    method(
+        "pty_launch_snapshot",
+        "What a session was launched with",
+        &[req("session_id", "string")],
+        "LaunchSnapshot",
+    ),
+    method(
+        "pty_input",
+        "Type into a session",
+        &[req("session_id", "string"), req("data", "string")],
+        "null",
+    ),
+    method(
+        "pty_input_history",
+        "Lines typed into a session, newest first",
+        &[req("session_id", "string"), opt("limit", "number")],
+        "HistoryEntry[]",
+    ),
+    method(
+        "pty_resize",
+        "Resize a session's terminal",
+        &[req("session_id", "string"), req("cols", "number"), req("rows", "number")],
+        "null",
+    ),
+    method("pty_kill", "Kill a session", &[req("session_id", "string")], "null"),
+    method(
+        "pty_list",
+        "Sessions, optionally filtered",
+        &[opt("filter", "SessionFilter")],
+        "object[]",
+    ),
+    method(
+        "pty_screen",
+        "A session's visible screen",
+        &[req("session_id", "string")],
+        "ScreenSnapshot",
+    ),
+    method(
+        "pty_export_text",
+        "A session's output as plain text, or written to `path`",
+        &[req("session_id", "string"), opt("range", "LineRange"), opt("path", "string")],
+        "ExportedText",
+    ),
+    method(
+        "pty_rename",
+        "Set or clear a session's title",
+        &[req("session_id", "string"), opt("title", "string")],
+        "object",
+    ),
+    method("worktree_list", "Worktrees of the current repo", &[], "WorktreeInfo[]"),
+    method(
+        "worktree_create",
+        "Create a worktree and run the repo's setup hook",
+        &[
+            req("session_id", "string"),
+            opt("skip_submodules", "boolean"),
+            opt("agent_id", "string"),
+            opt("slug", "string"),
+            opt("sparse", "string[]"),
+        ],
+        "WorktreeCreated",
+    ),
+    method(
+        "worktree_create_from",
+        "Create a worktree branched off another worktree's HEAD",
+        &[
+            req("name", "string"),
+            req("source_worktree", "string"),
+            opt("skip_submodules", "boolean"),
+            opt("agent_id", "string"),
+            opt("slug", "string"),
+            opt("sparse", "string[]"),
+        ],
+        "WorktreeCreated",
+    ),
+    method("worktree_remove", "Remove a worktree", &[req("name", "string")], "null"),
+    method(
+        "worktree_diff",
+        "Changes in a worktree since it forked",
+        &[req("name", "string"), opt("highlight", "boolean")],
+        "FileDiff[]",
+    ),
+    method(
+        "worktree_log",
+        "Commits on a worktree's branch",
+        &[req("name", "string"), opt("limit", "number"), opt("since", "number")],
+        "CommitInfo[]",
+    ),
+    method(
+        "worktree_rebase",
+        "Rebase a worktree onto the main checkout",
+        &[req("name", "string")],
+        "RebaseOutcome",
+    ),
+    method("worktree_checks", "Checks the current repo can run, by name", &[], "object"),
+    method(
+        "worktree_run_check",
+        "Run a named check in a worktree; returns the session id",
+        &[req("name", "string"), req("check_name", "string")],
+        "string",
+    ),
+    method(
+        "worktree_export",
+        "Export a worktree's work to an absolute path",
+        &[req("name", "string"), req("format", "ExportFormat"), req("path", "string")],
+        "ExportResult",
+    ),
+    method("review_list", "Review state of the current repo's worktrees", &[], "ReviewEntry[]"),
+    method(
+        "review_transition",
+        "Move a worktree to another review state",
+        &[req("name", "string"), req("to", "ReviewState"), opt("note", "string")],
+        "ReviewEntry",
+    ),
+    method(
+        "review_merge",
+        "Merge an approved worktree",
+        &[
+            req("name", "string"),
+            opt("remove_worktree", "boolean"),
+            opt("allow_protected", "boolean"),
+            opt("comments_path", "string"),
+        ],
+        "MergeOutcome",
+    ),
+    method(
+        "review_discard",
+        "Discard a worktree's work",
+        &[req("name", "string"), opt("note", "string")],
+        "ReviewEntry",
+    ),
+    method(
+        "review_comment_add",
+        "Comment on a line of a worktree's diff",
+        &[
+            req("worktree", "string"),
+            req("file", "string"),
+            req("line", "number"),
+            req("body", "string"),
+        ],
+        "ReviewComment",
+    ),
+    method(
+        "review_comment_list",
+        "Review comments on a worktree",
+        &[req("worktree", "string")],
+        "ReviewComment[]",
+    ),
+    method(
+        "merge_queue_enqueue",
+        "Queue an approved worktree",
+        &[req("name", "string")],
+        "MergeEntry",
+    ),
+    method("merge_queue_list", "The merge queue", &[], "MergeQueueState"),
+    method("merge_queue_cancel", "Take an entry out of the queue", &[req("id", "string")], "null"),
+    method("task_enqueue", "Queue a task", &[req("spec", "TaskSpec")], "Task"),
+    method("task_list", "Queued, running and finished tasks", &[], "Task[]"),
+    method(
+        "task_timeline",
+        "What happened in a task, oldest first",
+        &[req("task_id", "string")],
+        "TimelineEntry[]",
+    ),
+    method("task_cancel", "Cancel or kill a task", &[req("task_id", "string")], "null"),
+    method(
+        "events_since",
+        "Journaled events after a sequence number",
+        &[req("seq", "number")],
+        "EventsSince",
+    ),
+    method("usage_report", "Token and cost usage", &[opt("range", "TimeRange")], "UsageReport"),
+    method(
+        "session_history",
+        "Finished sessions with their exit summaries, newest first",
+        &[opt("limit", "number"), opt("filter", "HistoryFilter")],
+        "UsageRecord[]",
+    ),
+    method(
+        "audit_query",
+        "Audit log entries",
+        &[opt("range", "TimeRange"), opt("filter", "AuditFilter")],
+        "AuditEntry[]",
+    ),
+];
+
+pub const TYPES: &[TypeSchema] = &[
+    TypeSchema {
+        name: "SpawnArgs",
+        fields: &[
+            opt("agent_id", "string"),
+            req("cmd", "string[]"),
+            opt("cwd", "string"),
+            opt("worktree", "string"),
+            opt("base", "string"),
+            opt("cols", "number"),
+            opt("rows", "number"),
+            opt("encoding", "\"utf8\" | \"base64\""),
+            opt("target", "string"),
+            opt("container", "object"),
+            opt("ssh", "object"),
+            opt("flow_control", "boolean"),
+            opt("guarded", "boolean"),
+            opt("queue", "boolean"),
+            opt("detached", "boolean"),
+            opt("backend", "\"pty\" | \"tmux\""),
+            opt("title", "string"),
+            opt("tags", "string[]"),
+            opt("group", "string"),
+            opt("skip_submodules", "boolean"),
+            opt("sparse", "string[]"),
+            opt("max_runtime_secs", "number"),
+            opt("max_idle_secs", "number"),
+            opt("profile", "string"),
+            opt("allow_main_checkout", "boolean"),
+        ],
+        values: &[],
+    },
+    TypeSchema {
+        name: "TaskSpec",
+        fields: &[
+            opt("agent", "string"),
+            opt("prompt", "string"),
+            opt("cmd", "string[]"),
+            opt("repo", "string"),
+            opt("max_runtime_secs", "number"),
+            opt("max_idle_secs", "number"),
+            opt("sparse", "string[]"),
+        ],
+        values: &[],
+    },
+    TypeSchema {
+        name: "SessionFilter",
+        fields: &[
+            opt("agent_id", "string"),
+            opt("tag", "string"),
+            opt("group", "string"),
+            opt("alive", "boolean"),
+            opt("state", "string"),
+        ],
+        values: &[],
+    },
+    TypeSchema {
+        name: "LineRange",
+        fields: &[opt("start", "number"), opt("end", "number")],
+        values: &[],
+    },
+    TypeSchema {
+        name: "TimeRange",
+        fields: &[opt("since", "number"), opt("until", "number")],
+        values: &[],
+    },
+    TypeSchema {
+        name: "HistoryFilter",
+        fields: &[
+            opt("agentId", "string"),
+            opt("taskId", "string"),
+            opt("worktree", "string"),
+            opt("succeeded", "boolean"),
+            opt("since", "number"),
+        ],
+        values: &[],
+    },
+    TypeSchema {
+        name: "AuditFilter",
+        fields: &[
+            opt("command", "string"),
+            opt("sessionId", "string"),
+            opt("source", "\"webview\" | \"cli\" | \"event\""),
+            opt("limit", "number"),
+        ],
+        values: &[],
+    },
+    TypeSchema {
+        name: "ReviewState",
+        fields: &[],
+        values: &["in_progress", "ready_for_review", "approved", "merged", "discarded"],
+    },
+    TypeSchema { name: "ExportFormat", fields: &[], values: &["tarball", "patch", "patch_series"] },
+];
+
+pub fn find(name: &str) -> Option<&'static Method> {
+    METHODS.iter().find(|m| m.name == name)
+}
+
+/// The `rpc.discover` result.
+pub fn discover() -> Value {
+    serde_json::json!({ "methods": METHODS, "types": TYPES })
+}
@@ -0,0 +1,177 @@
+//! Sizing up a worktree before status scans lean on it.
+//!
+//! Status scans stat every tracked file and walk every untracked directory
+//! that isn't ignored. A repo with hundreds of thousands of tracked files,
+//! or a checkout with dependencies or build output nobody ignored (an
+//! in-tree `node_modules`, `target`), makes every scan crawl. `check`
+//! counts tracked files from the index and walks the worktree for
+//! untracked ones, honoring `.gitignore`, and warns past `scale_limits`.
+//! Well-known artifact directories that aren't ignored come with the
+//! `.gitignore` rule that would fix them.
+//!
+//! A worktree over either limit is degraded: the repo cache checks it for
+//! changes without looking at untracked files, and less often. See
+//! `RepoCache::set_degraded`.
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+};
+
+/// Directory names that hold dependencies or build output.
+const ARTIFACT_DIRS: &[&str] = &[
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    ".venv",
+    "venv",
+    "__pycache__",
+    ".next",
+    ".gradle",
+    ".tox",
+    ".turbo",
+];
+
+/// Untracked directories listed in a report.
+const MAX_DIRS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScaleLimits {
+    pub max_tracked_files: usize,
+    /// Untracked files that aren't ignored; the count stops past this.
+    pub max_untracked_files: usize,
+}
+
+impl Default for ScaleLimits {
+    fn default() -> Self {
+        Self { max_tracked_files: 200_000, max_untracked_files: 20_000 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScaleReport {
+    pub tracked_files: usize,
+    /// Untracked files that aren't ignored, up to just past the limit.
+    pub untracked_files: usize,
+    /// Counting stopped at the limit; there are more.
+    pub untracked_capped: bool,
+    /// Untracked directories with the most files, largest first.
+    pub untracked_dirs: Vec<UntrackedDir>,
+    pub warnings: Vec<ScaleWarning>,
+    /// Status scans skip untracked files in this worktree.
+    pub degraded: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UntrackedDir {
+    /// Relative to the worktree root.
+    pub path: String,
+    pub files: usize,
+    /// `.gitignore` rule that would exclude it, for artifact directories.
+    pub ignore_rule: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScaleWarning {
+    /// More tracked files than `max_tracked_files`.
+    LargeRepo { tracked_files: usize, limit: usize },
+    /// More untracked files than `max_untracked_files`.
+    ManyUntracked { untracked_files: usize, limit: usize },
+    /// Dependencies or build output that isn't ignored.
+    UnignoredArtifacts { path: String, files: usize, ignore_rule: String },
+}
+
+/// Count the files of the worktree at `path` and warn past `limits`.
+pub fn check(path: &Path, limits: &ScaleLimits) -> Result<ScaleReport> {
+    let repo = Repository::open(path).context("open worktree")?;
+    let index = repo.index().context("read index")?;
+    let tracked_files = index.len();
+
+    let mut untracked_files = 0;
+    let mut untracked_capped = false;
+    let mut dirs: HashMap<String, (usize, Option<String>)> = HashMap::new();
+    let walk = ignore::WalkBuilder::new(path)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+    for entry in walk {
+        let Ok(entry) = entry else { continue };
+        if entry.depth() == 0 || entry.file_type().is_some_and(|t| t.is_dir()) {
+            continue;
+        }
+        let Ok(rel) = entry.path().strip_prefix(path) else { continue };
+        if index.get_path(rel, 0).is_some() {
+            continue;
+        }
+        if untracked_files == limits.max_untracked_files {
+            untracked_capped = true;
+            break;
+        }
+        untracked_files += 1;
+        if let Some((dir, rule)) = group(rel) {
+            dirs.entry(dir).or_insert((0, rule)).0 += 1;
+        }
+    }
+
+    let mut untracked_dirs: Vec<UntrackedDir> = dirs
+        .into_iter()
+        .map(|(path, (files, ignore_rule))| UntrackedDir { path, files, ignore_rule })
+        .collect();
+    untracked_dirs.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.path.cmp(&b.path)));
+    untracked_dirs.truncate(MAX_DIRS);
+
+    let mut warnings = Vec::new();
+    if tracked_files > limits.max_tracked_files {
+        let limit = limits.max_tracked_files;
+        warnings.push(ScaleWarning::LargeRepo { tracked_files, limit });
+    }
+    if untracked_capped {
+        let limit = limits.max_untracked_files;
+        warnings.push(ScaleWarning::ManyUntracked { untracked_files, limit });
+    }
+    for dir in &untracked_dirs {
+        if let Some(rule) = &dir.ignore_rule {
+            warnings.push(ScaleWarning::UnignoredArtifacts {
+                path: dir.path.clone(),
+                files: dir.files,
+                ignore_rule: rule.clone(),
+            });
+        }
+    }
+    let degraded = tracked_files > limits.max_tracked_files || untracked_capped;
+    Ok(ScaleReport {
+        tracked_files,
+        untracked_files,
+        untracked_capped,
+        untracked_dirs,
+        warnings,
+        degraded,
+    })
+}
+
+/// The directory an untracked file is counted under: the artifact
+/// directory it is in, with its ignore rule, or else its top-level
+/// directory. `None` for a file at the root.
+fn group(rel: &Path) -> Option<(String, Option<String>)> {
+    let parts: Vec<&str> = rel
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect();
+    let dirs = &parts[..parts.len().saturating_sub(1)];
+    if let Some(i) = dirs.iter().position(|part| ARTIFACT_DIRS.contains(part)) {
+        return Some((dirs[..=i].join("/"), Some(format!("{}/", dirs[i]))));
+    }
+    dirs.first().map(|dir| (dir.to_string(), None))
+}
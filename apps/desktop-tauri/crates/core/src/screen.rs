@@ -0,0 +1,150 @@
+//! Server-side screen state of a session.
+//!
+//! Every session's output also goes through a `vt100` parser, so the backend
+//! always knows what the terminal currently shows. Switching tabs can then
+//! restore a terminal from `pty_screen` instead of replaying megabytes of
+//! escape sequences into xterm.js.
+//!
+//! A snapshot has the grid as runs of cells with the same attributes,
+//! plus `ansi`, the same screen as escape sequences that redraw it, for
+//! writing straight into a fresh xterm.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+pub enum CellColor {
+    Default,
+    /// Palette index (0-255).
+    Indexed(u8),
+    Rgb([u8; 3]),
+}
+
+impl From<vt100::Color> for CellColor {
+    fn from(color: vt100::Color) -> Self {
+        match color {
+            vt100::Color::Default => CellColor::Default,
+            vt100::Color::Idx(i) => CellColor::Indexed(i),
+            vt100::Color::Rgb(r, g, b) => CellColor::Rgb([r, g, b]),
+        }
+    }
+}
+
+/// Consecutive cells of a row with the same attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Span {
+    pub text: String,
+    /// Columns covered; wide characters take two.
+    pub width: u16,
+    pub fg: CellColor,
+    pub bg: CellColor,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenSnapshot {
+    pub rows: u16,
+    pub cols: u16,
+    pub cursor_row: u16,
+    pub cursor_col: u16,
+    pub cursor_visible: bool,
+    /// Full-screen apps (vim, less, most agent TUIs) draw here.
+    pub alternate_screen: bool,
+    /// One entry per row, top to bottom.
+    pub lines: Vec<Vec<Span>>,
+    /// Escape sequences that redraw this screen on a cleared terminal.
+    pub ansi: String,
+}
+
+pub struct Screen {
+    parser: vt100::Parser,
+}
+
+impl Screen {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        // Scrollback lives in `scrollback.rs`; only the visible grid here
+        Self { parser: vt100::Parser::new(rows, cols, 0) }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parser.process(bytes);
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.parser.set_size(rows, cols);
+    }
+
+    /// Escape sequences that recreate this terminal's state, modes
+    /// included, in a fresh parser.
+    pub(crate) fn state(&self) -> Vec<u8> {
+        self.parser.screen().state_formatted()
+    }
+
+    /// Text of the row the cursor is on.
+    pub fn cursor_line(&self) -> String {
+        let screen = self.parser.screen();
+        let (row, _) = screen.cursor_position();
+        let (_, cols) = screen.size();
+        screen.rows(0, cols).nth(row as usize).unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> ScreenSnapshot {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        let (cursor_row, cursor_col) = screen.cursor_position();
+        let lines = (0..rows).map(|row| row_spans(screen, row, cols)).collect();
+        ScreenSnapshot {
+            rows,
+            cols,
+            cursor_row,
+            cursor_col,
+            cursor_visible: !screen.hide_cursor(),
+            alternate_screen: screen.alternate_screen(),
+            lines,
+            ansi: String::from_utf8_lossy(&screen.contents_formatted()).into_owned(),
+        }
+    }
+}
+
+fn row_spans(screen: &vt100::Screen, row: u16, cols: u16) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    for col in 0..cols {
+        let Some(cell) = screen.cell(row, col) else { continue };
+        if cell.is_wide_continuation() {
+            continue;
+        }
+        let mut text = cell.contents();
+        if text.is_empty() {
+            text.push(' ');
+        }
+        let width = if cell.is_wide() { 2 } else { 1 };
+        let span = Span {
+            text,
+            width,
+            fg: cell.fgcolor().into(),
+            bg: cell.bgcolor().into(),
+            bold: cell.bold(),
+            italic: cell.italic(),
+            underline: cell.underline(),
+            inverse: cell.inverse(),
+        };
+        match spans.last_mut() {
+            Some(last) if same_style(last, &span) => {
+                last.text.push_str(&span.text);
+                last.width += span.width;
+            }
+            _ => spans.push(span),
+        }
+    }
+    spans
+}
+
+fn same_style(a: &Span, b: &Span) -> bool {
+    (a.fg, a.bg, a.bold, a.italic, a.underline, a.inverse)
+        == (b.fg, b.bg, b.bold, b.italic, b.underline, b.inverse)
+}
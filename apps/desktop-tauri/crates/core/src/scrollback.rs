@@ -0,0 +1,508 @@
+//! Plain-text scrollback per session, for searching.
+//!
+//! Output is reduced to the text a terminal would show: escape sequences
+//! are dropped, `\r` returns to the start of the line so redraws overwrite,
+//! backspace and erase-line are applied. Completed lines are kept in a ring
+//! of `MAX_LINES`, or what the session's terminal profile asks for (see
+//! `terminal.rs`); line numbers count from the start of the session, so a
+//! match keeps its number as older lines fall off.
+//!
+//! Lines are logical lines, split on `\n` only: output the terminal wrapped
+//! at its width comes back as one line, so exported transcripts reflow to
+//! wherever they are pasted.
+//!
+//! With `scrollback_spill` enabled, lines that fall out of the ring aren't
+//! dropped but appended to temp files, with the offset of each line kept
+//! in memory, so a session printing hundreds of MB of build log holds only
+//! the ring in RAM. Search and export read spilled lines through a memory
+//! map as if they were still in the ring. Spill files are split into
+//! segments; past `max_bytes` the oldest segment is deleted and its lines
+//! are gone. Files are removed with their session.
+
+use crate::error::PiError;
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// Lines kept per session by default.
+pub const MAX_LINES: usize = 10_000;
+
+/// Longest line kept, in chars; the tail beyond this is dropped.
+const MAX_LINE_CHARS: usize = 4096;
+
+/// Smallest spill segment; segments are a quarter of `max_bytes` otherwise.
+const MIN_SEGMENT_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpillSettings {
+    /// Spill lines that fall out of the in-memory ring to disk.
+    pub enabled: bool,
+    /// Disk used per session before the oldest spilled lines are deleted.
+    pub max_bytes: u64,
+}
+
+impl Default for SpillSettings {
+    fn default() -> Self {
+        Self { enabled: true, max_bytes: 1024 * 1024 * 1024 }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMatch {
+    /// Line number since the session started (0-based).
+    pub line: u64,
+    /// Char offsets of the match within `text`.
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub matches: Vec<SearchMatch>,
+    /// Oldest line still held, in memory or spilled.
+    pub first_line: u64,
+    /// Lines seen so far, including the unfinished last one.
+    pub total_lines: u64,
+    /// More matches exist beyond `max_results`.
+    pub truncated: bool,
+}
+
+/// Session line numbers `[start, end)`; open-ended when unset.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct LineRange {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedText {
+    pub text: String,
+    /// Line numbers actually exported, `[first_line, end_line)`.
+    pub first_line: u64,
+    pub end_line: u64,
+    /// Part of the range had already fallen out of the scrollback.
+    pub truncated: bool,
+    /// File the text was written to instead of being returned.
+    pub path: Option<String>,
+}
+
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    max_lines: usize,
+    /// Number of the line at `lines[0]`.
+    first_line: u64,
+    current: LineTracker,
+    /// Lines older than `lines`, when spilling is on.
+    spill: Option<Spill>,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            max_lines: MAX_LINES,
+            first_line: 0,
+            current: LineTracker::new(MAX_LINE_CHARS),
+            spill: None,
+        }
+    }
+}
+
+impl Scrollback {
+    /// Keep `max_lines` lines instead of `MAX_LINES`.
+    pub fn with_max_lines(max_lines: usize) -> Self {
+        Self { max_lines: max_lines.max(1), ..Self::default() }
+    }
+
+    /// Spill lines that fall out of the ring to temp files named after
+    /// `session_id`, if `settings.enabled`.
+    pub fn with_spill(mut self, session_id: &str, settings: &SpillSettings) -> Self {
+        if settings.enabled {
+            self.spill = Some(Spill::new(session_id, settings.max_bytes));
+        }
+        self
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let Self { lines, max_lines, first_line, current, spill } = self;
+        let mut failed = None;
+        current.feed(bytes, |line| {
+            lines.push_back(line);
+            if lines.len() > *max_lines {
+                let old = lines.pop_front().expect("over max_lines");
+                if let Some(s) = spill.as_mut().filter(|_| failed.is_none()) {
+                    failed = s.push(*first_line, &old).err();
+                }
+                *first_line += 1;
+            }
+        });
+        if let Some(s) = spill.as_mut() {
+            if let Some(e) = failed.or_else(|| s.flush().err()) {
+                // Drop what was spilled rather than leave a gap in it
+                log::warn!("spilling scrollback: {e}; older lines are dropped from now on");
+                *spill = None;
+            }
+        }
+    }
+
+    /// Plain text of the lines in `range`, including the unfinished last
+    /// line if it has any text.
+    pub fn export(&self, range: LineRange) -> Result<ExportedText> {
+        let current = self.current.text_untrimmed();
+        let total = self.first_line + self.lines.len() as u64 + u64::from(!current.is_empty());
+        let start = range.start.unwrap_or(0);
+        let first_held = self.first_held();
+        let first_line = start.max(first_held);
+        let end_line = range.end.unwrap_or(total).min(total).max(first_line);
+        let mut text = String::new();
+        self.each_line(first_line, end_line, &current, |_, line| {
+            text.push_str(line);
+            text.push('\n');
+        })?;
+        let truncated = start < first_held;
+        Ok(ExportedText { text, first_line, end_line, truncated, path: None })
+    }
+
+    /// Find `query` (a regex if `regex`, else literal text), newest matches
+    /// last. Returns at most `max_results`, keeping the most recent.
+    pub fn search(
+        &self,
+        query: &str,
+        regex: bool,
+        case_sensitive: bool,
+        max_results: usize,
+    ) -> Result<SearchResult> {
+        let pattern = if regex { query.to_string() } else { regex::escape(query) };
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(|e| PiError::invalid_input(format!("invalid regex: {e}")))?;
+
+        let current = self.current.text_untrimmed();
+        let mut matches = VecDeque::new();
+        let mut truncated = false;
+        self.each_line(0, u64::MAX, &current, |line, text| {
+            for m in re.find_iter(text) {
+                if m.start() == m.end() {
+                    continue;
+                }
+                matches.push_back(SearchMatch {
+                    line,
+                    start: text[..m.start()].chars().count(),
+                    end: text[..m.end()].chars().count(),
+                    text: text.to_string(),
+                });
+                if matches.len() > max_results {
+                    matches.pop_front();
+                    truncated = true;
+                }
+            }
+        })?;
+        Ok(SearchResult {
+            matches: matches.into(),
+            first_line: self.first_held(),
+            total_lines: self.first_line + self.lines.len() as u64 + 1,
+            truncated,
+        })
+    }
+
+    /// Number of the oldest line held, spilled or in memory.
+    fn first_held(&self) -> u64 {
+        self.spill.as_ref().and_then(Spill::first_line).unwrap_or(self.first_line)
+    }
+
+    /// Call `f` with each held line numbered in `[start, end)`, oldest
+    /// first, ending with `current`, the unfinished line.
+    fn each_line(
+        &self,
+        start: u64,
+        end: u64,
+        current: &str,
+        mut f: impl FnMut(u64, &str),
+    ) -> Result<()> {
+        if let Some(spill) = &self.spill {
+            spill
+                .each_line(start, end.min(self.first_line), &mut f)
+                .context("read spilled scrollback")?;
+        }
+        let from = start.max(self.first_line);
+        let held = self.lines.iter().map(String::as_str).chain(std::iter::once(current));
+        for (line, text) in (self.first_line..).zip(held).skip((from - self.first_line) as usize) {
+            if line >= end {
+                break;
+            }
+            f(line, text);
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Spilling
+// ---------------------------------------------------------------------------
+
+/// Lines that fell out of a session's ring, in temp file segments.
+struct Spill {
+    /// Segment paths are this plus the segment's sequence number.
+    prefix: PathBuf,
+    next_segment: u64,
+    segment_bytes: u64,
+    max_bytes: u64,
+    segments: VecDeque<Segment>,
+    /// Lines pushed since the last `flush`, for the newest segment.
+    pending: Vec<u8>,
+}
+
+/// One spill file. Each line is stored followed by `\n`.
+struct Segment {
+    path: PathBuf,
+    file: File,
+    /// Where each line starts in the file.
+    offsets: Vec<u32>,
+    /// Bytes written.
+    len: u64,
+    /// Number of the first line.
+    first_line: u64,
+}
+
+impl Spill {
+    fn new(session_id: &str, max_bytes: u64) -> Self {
+        Self {
+            prefix: std::env::temp_dir().join(format!("pi-builder-scrollback-{session_id}-")),
+            next_segment: 0,
+            segment_bytes: (max_bytes / 4).clamp(MIN_SEGMENT_BYTES, u64::from(u32::MAX)),
+            max_bytes,
+            segments: VecDeque::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Queue line number `line` for the next `flush`.
+    fn push(&mut self, line: u64, text: &str) -> io::Result<()> {
+        let full = self
+            .segments
+            .back()
+            .map_or(true, |s| s.len + self.pending.len() as u64 >= self.segment_bytes);
+        if full {
+            self.flush()?;
+            let path = PathBuf::from(format!("{}{}", self.prefix.display(), self.next_segment));
+            self.next_segment += 1;
+            self.segments.push_back(Segment::create(path, line)?);
+            // Make room for the new segment to fill up
+            while self.segments.len() > 1
+                && self.bytes() + self.segment_bytes > self.max_bytes.max(self.segment_bytes)
+            {
+                self.segments.pop_front();
+            }
+        }
+        let segment = self.segments.back_mut().expect("segment was just ensured");
+        segment.offsets.push((segment.len + self.pending.len() as u64) as u32);
+        self.pending.extend_from_slice(text.as_bytes());
+        self.pending.push(b'\n');
+        Ok(())
+    }
+
+    /// Write pending lines to the newest segment.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let segment = self.segments.back_mut().expect("pending lines have a segment");
+        segment.file.write_all(&self.pending)?;
+        segment.len += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.len).sum()
+    }
+
+    fn first_line(&self) -> Option<u64> {
+        self.segments.iter().find(|s| !s.offsets.is_empty()).map(|s| s.first_line)
+    }
+
+    fn each_line(&self, start: u64, end: u64, f: &mut impl FnMut(u64, &str)) -> io::Result<()> {
+        for segment in &self.segments {
+            let segment_end = segment.first_line + segment.offsets.len() as u64;
+            if segment_end <= start || segment.first_line >= end || segment.len == 0 {
+                continue;
+            }
+            // SAFETY: the file is private to this session and only appended
+            // to by `feed`, which can't run while the scrollback is borrowed
+            // here, so the mapped bytes don't change while they're read.
+            let map = unsafe { Mmap::map(&segment.file)? };
+            let from = start.saturating_sub(segment.first_line) as usize;
+            let to = (end.min(segment_end) - segment.first_line) as usize;
+            for i in from..to {
+                f(segment.first_line + i as u64, segment.line(&map, i));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Segment {
+    fn create(path: PathBuf, first_line: u64) -> io::Result<Self> {
+        let file =
+            OpenOptions::new().create(true).read(true).write(true).truncate(true).open(&path)?;
+        Ok(Self { path, file, offsets: Vec::new(), len: 0, first_line })
+    }
+
+    /// Line `i` of the segment, from its mapping.
+    fn line<'a>(&self, map: &'a [u8], i: usize) -> &'a str {
+        let start = self.offsets[i] as usize;
+        let end = self.offsets.get(i + 1).map_or(map.len(), |&next| next as usize);
+        std::str::from_utf8(&map[start..end.saturating_sub(1).max(start)]).unwrap_or_default()
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Line tracking
+// ---------------------------------------------------------------------------
+
+/// Tab stops every 8 columns.
+const TAB_WIDTH: usize = 8;
+
+/// Rebuilds the text of the line under the cursor from raw PTY output.
+///
+/// Bytes go through a VT parser (`vte`), so escape sequences of any kind,
+/// including ones split across reads, are consumed whole and UTF-8 is
+/// decoded before cursor movement counts columns.
+pub(crate) struct LineTracker {
+    parser: vte::Parser,
+    line: LineState,
+}
+
+#[derive(Default)]
+struct LineState {
+    chars: Vec<char>,
+    cursor: usize,
+    max: usize,
+    /// Lines finished by `\n` during the current `feed`.
+    done: Vec<String>,
+}
+
+impl LineTracker {
+    pub(crate) fn new(max: usize) -> Self {
+        Self { parser: vte::Parser::new(), line: LineState { max, ..LineState::default() } }
+    }
+
+    /// Apply `bytes`, calling `on_line` with each line completed by `\n`.
+    pub(crate) fn feed(&mut self, bytes: &[u8], mut on_line: impl FnMut(String)) {
+        for &b in bytes {
+            self.parser.advance(&mut self.line, b);
+        }
+        for line in self.line.done.drain(..) {
+            on_line(line);
+        }
+    }
+
+    /// The current line, trimmed.
+    pub(crate) fn text(&self) -> String {
+        self.text_untrimmed().trim().to_string()
+    }
+
+    fn text_untrimmed(&self) -> String {
+        self.line.text()
+    }
+}
+
+impl LineState {
+    fn text(&self) -> String {
+        self.chars.iter().collect::<String>().trim_end().to_string()
+    }
+
+    fn put(&mut self, c: char) {
+        // Cursor moved past the end: fill the gap the way the screen shows it
+        while self.chars.len() < self.cursor && self.chars.len() < self.max {
+            self.chars.push(' ');
+        }
+        if self.cursor < self.chars.len() {
+            self.chars[self.cursor] = c;
+        } else if self.chars.len() < self.max {
+            self.chars.push(c);
+        } else {
+            return;
+        }
+        self.cursor += 1;
+    }
+}
+
+impl vte::Perform for LineState {
+    fn print(&mut self, c: char) {
+        self.put(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                let line = self.text();
+                self.done.push(line);
+                self.chars.clear();
+                self.cursor = 0;
+            }
+            b'\r' => self.cursor = 0,
+            0x08 => self.cursor = self.cursor.saturating_sub(1),
+            b'\t' => self.cursor = ((self.cursor / TAB_WIDTH + 1) * TAB_WIDTH).min(self.max),
+            _ => {}
+        }
+    }
+
+    /// Handle the cursor and erase sequences that change the line's text.
+    fn csi_dispatch(&mut self, params: &vte::Params, intermediates: &[u8], _: bool, action: char) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        let n = params.iter().next().and_then(|p| p.first().copied()).map(usize::from);
+        // A missing or zero count means 1 for cursor movement
+        let count = n.filter(|&n| n > 0).unwrap_or(1);
+        match action {
+            // Erase in line: 0 = to end, 1 = to start, 2 = all
+            'K' => match n.unwrap_or(0) {
+                0 => self.chars.truncate(self.cursor),
+                1 => {
+                    let end = (self.cursor + 1).min(self.chars.len());
+                    self.chars[..end].fill(' ');
+                }
+                _ => self.chars.clear(),
+            },
+            // Delete / erase characters at the cursor
+            'P' if self.cursor < self.chars.len() => {
+                let end = (self.cursor + count).min(self.chars.len());
+                self.chars.drain(self.cursor..end);
+            }
+            'X' => {
+                let end = (self.cursor + count).min(self.chars.len());
+                if self.cursor < end {
+                    self.chars[self.cursor..end].fill(' ');
+                }
+            }
+            'C' => self.cursor += count,
+            'D' => self.cursor = self.cursor.saturating_sub(count),
+            // Column, 1-based
+            'G' => self.cursor = count - 1,
+            _ => {}
+        }
+        self.cursor = self.cursor.min(self.max);
+    }
+}
@@ -0,0 +1,194 @@
+//! Project-wide code search, ripgrep style.
+//!
+//! Walks a worktree (or the main checkout) with the `ignore` crate, so
+//! `.gitignore`, `.ignore` and hidden files are skipped the way `rg` skips
+//! them, and searches each file with `grep-searcher`. Binary files are
+//! skipped. Searches run in parallel and stop early on `max_results` or when
+//! cancelled through `Cancellations`.
+
+use crate::error::PiError;
+use anyhow::{Context, Result};
+use grep_matcher::Matcher;
+use grep_regex::{RegexMatcher, RegexMatcherBuilder};
+use grep_searcher::{sinks::UTF8, BinaryDetection, SearcherBuilder};
+use ignore::{overrides::OverrideBuilder, WalkBuilder, WalkState};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Lines longer than this (minified bundles) are cut down to the match and
+/// some context either side.
+const MAX_SNIPPET_BYTES: usize = 400;
+const SNIPPET_CONTEXT: usize = 120;
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Treat the query as a regex rather than literal text.
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// `rg --glob` style filters; `!` excludes.
+    pub globs: Vec<String>,
+    pub max_results: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeMatch {
+    /// Path relative to the search root, `/`-separated.
+    pub path: String,
+    /// 1-based.
+    pub line: u64,
+    /// Char offsets of the first match within `snippet`.
+    pub start: usize,
+    pub end: usize,
+    pub snippet: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoSearchResult {
+    /// Sorted by path, then line.
+    pub matches: Vec<CodeMatch>,
+    pub files_searched: usize,
+    /// Stopped at `max_results`.
+    pub truncated: bool,
+    pub cancelled: bool,
+}
+
+/// Search every file under `root` for `query`. Stops as soon as `cancel`
+/// is set, returning what was found so far.
+pub fn search(
+    root: &Path,
+    query: &str,
+    opts: &SearchOptions,
+    cancel: &AtomicBool,
+) -> Result<RepoSearchResult> {
+    let matcher = RegexMatcherBuilder::new()
+        .case_insensitive(!opts.case_sensitive)
+        .fixed_strings(!opts.regex)
+        .build(query)
+        .map_err(|e| PiError::invalid_input(format!("invalid regex: {e}")))?;
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &opts.globs {
+        overrides
+            .add(glob)
+            .map_err(|e| PiError::invalid_input(format!("invalid glob {glob}: {e}")))?;
+    }
+    let walker = WalkBuilder::new(root)
+        .overrides(overrides.build().context("build globs")?)
+        .build_parallel();
+
+    let matches = Mutex::new(Vec::new());
+    let files_searched = AtomicUsize::new(0);
+    let truncated = AtomicBool::new(false);
+    walker.run(|| {
+        let matcher = matcher.clone();
+        let mut searcher = SearcherBuilder::new()
+            .binary_detection(BinaryDetection::quit(0))
+            .line_number(true)
+            .build();
+        let (matches, files_searched, truncated) = (&matches, &files_searched, &truncated);
+        Box::new(move |entry| {
+            if cancel.load(Ordering::Relaxed) || truncated.load(Ordering::Relaxed) {
+                return WalkState::Quit;
+            }
+            let Ok(entry) = entry else { return WalkState::Continue };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                return WalkState::Continue;
+            }
+            files_searched.fetch_add(1, Ordering::Relaxed);
+            let path = relative(root, entry.path());
+            let sink = UTF8(|line_number, line| {
+                if let Some(m) = matched(&matcher, &path, line_number, line) {
+                    let mut matches = matches.lock().unwrap();
+                    if matches.len() >= opts.max_results {
+                        truncated.store(true, Ordering::Relaxed);
+                        return Ok(false);
+                    }
+                    matches.push(m);
+                }
+                Ok(!cancel.load(Ordering::Relaxed))
+            });
+            if let Err(e) = searcher.search_path(&matcher, entry.path(), sink) {
+                log::debug!("search {path}: {e}");
+            }
+            WalkState::Continue
+        })
+    });
+
+    let mut matches = matches.into_inner().unwrap();
+    matches.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    Ok(RepoSearchResult {
+        matches,
+        files_searched: files_searched.into_inner(),
+        truncated: truncated.into_inner(),
+        cancelled: cancel.load(Ordering::Relaxed),
+    })
+}
+
+fn matched(matcher: &RegexMatcher, path: &str, line_number: u64, line: &str) -> Option<CodeMatch> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let m = matcher.find(line.as_bytes()).ok().flatten()?;
+    let (mut from, mut to) = (0, line.len());
+    if line.len() > MAX_SNIPPET_BYTES {
+        from = m.start().saturating_sub(SNIPPET_CONTEXT);
+        to = (m.end() + SNIPPET_CONTEXT).min(line.len());
+        while !line.is_char_boundary(from) {
+            from -= 1;
+        }
+        while !line.is_char_boundary(to) {
+            to += 1;
+        }
+    }
+    let snippet = &line[from..to];
+    Some(CodeMatch {
+        path: path.to_string(),
+        line: line_number,
+        start: line[from..m.start()].chars().count(),
+        end: line[from..m.end()].chars().count(),
+        snippet: snippet.to_string(),
+    })
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+// ---------------------------------------------------------------------------
+// Cancellation
+// ---------------------------------------------------------------------------
+
+/// Cancel flags of running searches, by caller-chosen search id.
+#[derive(Default)]
+pub struct Cancellations {
+    running: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl Cancellations {
+    pub fn start(&self, search_id: &str) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.running.lock().unwrap().insert(search_id.to_string(), flag.clone());
+        flag
+    }
+
+    pub fn finish(&self, search_id: &str) {
+        self.running.lock().unwrap().remove(search_id);
+    }
+
+    /// Returns false if no search with that id is running.
+    pub fn cancel(&self, search_id: &str) -> bool {
+        match self.running.lock().unwrap().get(search_id) {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! API tokens in the OS keychain.
+//!
+//! Secrets are stored with the keyring crate (Keychain, Credential Manager,
+//! Secret Service) under the `pi-builder` service. The keychain can't list
+//! entries, so `secrets.json` in the config dir keeps the names, never the
+//! values.
+//!
+//! Agent profile env and other spawn env values reference a secret as
+//! `${secret:OPENAI_KEY}`. References are resolved in `PtyManager::spawn`,
+//! right before the child starts, so values never go to the webview; the
+//! resolved values are always redacted from the session's output.
+
+use crate::error::{ErrorCode, PiError};
+use anyhow::{Context, Result};
+use keyring::Entry;
+use regex::Regex;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+const SERVICE: &str = "pi-builder";
+
+pub struct SecretStore {
+    path: PathBuf,
+    names: Mutex<BTreeSet<String>>,
+}
+
+impl SecretStore {
+    /// Load the name index from `path`; a missing or unreadable file starts
+    /// empty.
+    pub fn load(path: PathBuf) -> Self {
+        let names = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, names: Mutex::new(names) }
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        self.names.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        check_name(name)?;
+        if value.is_empty() {
+            return Err(PiError::invalid_input("secret value is empty").into());
+        }
+        entry(name)?.set_password(value).context("store secret in keychain")?;
+        self.names.lock().unwrap().insert(name.to_string());
+        self.save()
+    }
+
+    /// Remove `name` from the keychain. Deleting a secret that isn't there
+    /// is not an error.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        match entry(name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(e).context("delete secret from keychain"),
+        }
+        self.names.lock().unwrap().remove(name);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.names.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write secret index")?;
+        Ok(())
+    }
+}
+
+/// Location of the secret name index inside the app config dir.
+pub fn secrets_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("secrets.json")
+}
+
+/// An env with its secret references resolved, and the secret values it
+/// now holds.
+pub type ResolvedEnv = (Vec<(String, String)>, Vec<String>);
+
+/// Replace `${secret:NAME}` references in `env` values with the stored
+/// secrets. Returns the resolved env and the secret values it now holds,
+/// for redaction. A reference to a missing secret fails the spawn.
+pub fn resolve_env(env: Vec<(String, String)>) -> Result<ResolvedEnv> {
+    let mut values = Vec::new();
+    let mut resolved = Vec::with_capacity(env.len());
+    for (key, value) in env {
+        if !value.contains("${secret:") {
+            resolved.push((key, value));
+            continue;
+        }
+        let mut out = String::new();
+        let mut last = 0;
+        for caps in reference().captures_iter(&value) {
+            let whole = caps.get(0).unwrap();
+            let secret = lookup(&caps[1])?;
+            out.push_str(&value[last..whole.start()]);
+            out.push_str(&secret);
+            last = whole.end();
+            values.push(secret);
+        }
+        out.push_str(&value[last..]);
+        resolved.push((key, out));
+    }
+    Ok((resolved, values))
+}
+
+/// The stored value of secret `name`.
+pub(crate) fn lookup(name: &str) -> Result<String> {
+    match entry(name)?.get_password() {
+        Ok(value) => Ok(value),
+        Err(keyring::Error::NoEntry) => {
+            Err(PiError::new(ErrorCode::NotFound, format!("secret not found: {name}")).into())
+        }
+        Err(e) => Err(e).with_context(|| format!("read secret {name} from keychain")),
+    }
+}
+
+/// Whether the OS keychain can be read, by looking up a name that is never
+/// stored.
+pub fn keychain_available() -> Result<()> {
+    match entry("pi-builder-probe")?.get_password() {
+        Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e).context("read from keychain"),
+    }
+}
+
+fn entry(name: &str) -> Result<Entry> {
+    Entry::new(SERVICE, name).context("open keychain entry")
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        return Err(PiError::invalid_input(format!(
+            "secret names are letters, digits, '_', '-' and '.': {name:?}"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn reference() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\$\{secret:([A-Za-z0-9_.-]+)\}").expect("secret reference"))
+}
@@ -0,0 +1,187 @@
+//! Files dropped onto a session.
+//!
+//! A file can be fed to a session three ways:
+//!
+//! - `path` types the path, quoted for the session's shell and followed by
+//!   a space, without pressing Enter. WSL sessions get the path as WSL sees
+//!   it; SSH and container sessions can't see local paths at all.
+//! - `content` pastes the file's text, bracketed when the application asked
+//!   for it, so an agent takes it as one paste rather than typed lines.
+//! - `base64` types a `base64 -d` heredoc that recreates the file as
+//!   `/tmp/<name>` on the session's side, for binary files and for sessions
+//!   on other hosts or in containers. It needs a POSIX shell at the prompt.
+//!
+//! Files are read and written a chunk at a time rather than whole; files
+//! over `MAX_SEND` are refused for `content` and `base64`.
+
+use crate::{
+    error::PiError,
+    paste::{PASTE_END, PASTE_START},
+    repro::LaunchSnapshot,
+    ssh::shell_quote,
+    target::{self, SpawnTarget},
+};
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// Largest file sent as content or base64.
+pub const MAX_SEND: u64 = 32 * 1024 * 1024;
+
+/// Bytes read at a time; a whole number of 76-column base64 lines.
+const CHUNK: u64 = 57 * 1024;
+
+/// Ends the base64 heredoc; can't occur in base64 output.
+const HEREDOC_END: &str = "PI_BUILDER_EOF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SendMode {
+    Path,
+    Content,
+    Base64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SentFile {
+    pub mode: SendMode,
+    /// Bytes of the file sent; 0 for `path`.
+    pub bytes: u64,
+    /// Where `base64` recreates the file in the session.
+    pub remote_path: Option<String>,
+}
+
+/// Feed the file at `path` to a session through `write`. `launch` tells
+/// how the session runs; `bracketed` whether its application enabled
+/// bracketed paste.
+pub fn send_file(
+    path: &Path,
+    mode: SendMode,
+    launch: &LaunchSnapshot,
+    bracketed: bool,
+    mut write: impl FnMut(&str) -> Result<()>,
+) -> Result<SentFile> {
+    let meta = std::fs::metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    if !meta.is_file() {
+        return Err(PiError::invalid_input(format!("{} is not a file", path.display())).into());
+    }
+    if mode != SendMode::Path && meta.len() > MAX_SEND {
+        let message = format!("{} is over {} MiB", path.display(), MAX_SEND / (1024 * 1024));
+        return Err(PiError::invalid_input(message).into());
+    }
+    let mut sent = SentFile { mode, bytes: 0, remote_path: None };
+    match mode {
+        SendMode::Path => write(&format!("{} ", quoted_path(path, launch)?))?,
+        SendMode::Content => sent.bytes = send_content(path, bracketed, &mut write)?,
+        SendMode::Base64 => {
+            let name = path.file_name().context("path has no file name")?.to_string_lossy();
+            let remote = format!("/tmp/{name}");
+            write(&format!("base64 -d > {} <<'{HEREDOC_END}'\r", shell_quote(&remote)))?;
+            sent.bytes = send_base64(path, &mut write)?;
+            write(&format!("{HEREDOC_END}\r"))?;
+            sent.remote_path = Some(remote);
+        }
+    }
+    Ok(sent)
+}
+
+/// `path` as the session's shell would take it.
+fn quoted_path(path: &Path, launch: &LaunchSnapshot) -> Result<String> {
+    if launch.ssh.is_some() || launch.container.is_some() {
+        let message = "the session can't see local paths; send the file as content or base64";
+        return Err(PiError::invalid_input(message).into());
+    }
+    let path = path.to_string_lossy();
+    Ok(match &launch.target {
+        SpawnTarget::Wsl { .. } => shell_quote(&target::to_wsl_path(&path)),
+        SpawnTarget::Native if cfg!(windows) => format!("\"{path}\""),
+        SpawnTarget::Native => shell_quote(&path),
+    })
+}
+
+/// Paste the file's text a chunk at a time. Returns the bytes read.
+fn send_content(
+    path: &Path,
+    bracketed: bool,
+    write: &mut impl FnMut(&str) -> Result<()>,
+) -> Result<u64> {
+    let mut reader = open(path)?;
+    let mut total = 0;
+    let mut pending = Vec::new();
+    if bracketed {
+        write(PASTE_START)?;
+    }
+    loop {
+        let n = reader.by_ref().take(CHUNK).read_to_end(&mut pending).context("read file")?;
+        total += n as u64;
+        if pending.is_empty() {
+            break;
+        }
+        // A chunk can end inside a character, a `\r\n` or an end marker;
+        // hold those back for the next one
+        let mut valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() && n > 0 => e.valid_up_to(),
+            Err(_) => {
+                let message = "not a UTF-8 text file; send it as base64";
+                return Err(PiError::invalid_input(message).into());
+            }
+        };
+        let text = std::str::from_utf8(&pending[..valid]).unwrap_or_default();
+        if n > 0 {
+            if let Some(esc) = text.rfind('\x1b').filter(|i| text.len() - i < PASTE_END.len()) {
+                valid = esc;
+            } else if text.ends_with('\r') {
+                valid -= 1;
+            }
+        }
+        let text = &text[..valid];
+        let text = text.replace("\r\n", "\r").replace('\n', "\r");
+        let text = if bracketed { text.replace(PASTE_END, "") } else { text };
+        if !text.is_empty() {
+            write(&text)?;
+        }
+        pending.drain(..valid);
+        if n == 0 {
+            break;
+        }
+    }
+    if bracketed {
+        write(PASTE_END)?;
+    }
+    Ok(total)
+}
+
+/// Type the file as base64 lines. Returns the bytes read.
+fn send_base64(path: &Path, write: &mut impl FnMut(&str) -> Result<()>) -> Result<u64> {
+    let mut reader = open(path)?;
+    let mut total = 0;
+    let mut chunk = Vec::new();
+    loop {
+        chunk.clear();
+        let n = reader.by_ref().take(CHUNK).read_to_end(&mut chunk).context("read file")?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&chunk);
+        let mut lines = String::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+        for line in encoded.as_bytes().chunks(76) {
+            lines.push_str(std::str::from_utf8(line).unwrap_or_default());
+            lines.push('\r');
+        }
+        write(&lines)?;
+    }
+    Ok(total)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
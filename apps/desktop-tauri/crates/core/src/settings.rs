@@ -0,0 +1,186 @@
+//! Persisted application settings.
+//!
+//! Stored as JSON in the app config dir. Every field has a default so a
+//! missing or partially written file never blocks startup. Changes made
+//! through commands are saved immediately and broadcast as
+//! `settings://changed` with the full settings as payload.
+
+use crate::{
+    agents::AgentProfile,
+    depcache::CacheRule,
+    flow::FlowSettings,
+    guard::GuardSettings,
+    hooks::Hook,
+    logs::LogSettings,
+    mainguard::MainCheckoutGuard,
+    notify::NotificationPrefs,
+    paste::PasteSettings,
+    pty::{ExitBehavior, ShellConfig},
+    scale::ScaleLimits,
+    scrollback::SpillSettings,
+    spawnqueue::ConcurrencySettings,
+    system::SystemPolicy,
+    termenv::TermEnv,
+    terminal::TerminalProfile,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Size for new sessions when the caller doesn't give one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TerminalSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for TerminalSize {
+    fn default() -> Self {
+        Self { cols: 220, rows: 50 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// Directory that holds agent worktrees, one subdirectory per repo.
+    /// `None` places them in a sibling `<repo>-agents/` directory.
+    pub worktree_root: Option<String>,
+    /// File counts past which worktree status scans degrade; see `scale.rs`.
+    pub scale_limits: ScaleLimits,
+    /// Branch name template for new worktrees, e.g. `pi/{agent}/{date}-{slug}`.
+    /// `None` uses `agent/{id}`.
+    pub branch_template: Option<String>,
+    /// Fetch the remote in the background this often; `None` only fetches
+    /// on request.
+    pub fetch_interval_secs: Option<u64>,
+    /// When a background fetch moves the base branch, rebase worktrees
+    /// without uncommitted changes onto it; see `basesync.rs`.
+    pub auto_rebase_worktrees: bool,
+    /// Post-create setup commands keyed by repo path. Take precedence over
+    /// a checked-in `.pi-builder/setup.sh`.
+    pub setup_commands: HashMap<String, Vec<String>>,
+    /// Cache directories new worktrees share with the main checkout, keyed
+    /// by repo path.
+    pub dependency_cache: HashMap<String, Vec<CacheRule>>,
+    /// Gitignore-style patterns agents shouldn't change, keyed by repo
+    /// path; see `protect.rs`.
+    pub protected_paths: HashMap<String, Vec<String>>,
+    /// Command the merge queue runs in a worktree before landing it, keyed
+    /// by repo path; see `mergequeue.rs`.
+    pub merge_checks: HashMap<String, String>,
+    /// Merge commit message templates keyed by repo path; see
+    /// `mergemsg.rs`.
+    pub merge_messages: HashMap<String, String>,
+    /// Custom agent profiles; replace built-ins with the same id.
+    pub agent_profiles: Vec<AgentProfile>,
+    /// Tasks allowed to run at once.
+    pub max_concurrent_tasks: usize,
+    /// Regexes whose matches are masked in PTY output.
+    pub redact_patterns: Vec<String>,
+    /// Mask values of secret-looking env vars (`*_KEY`, `*_TOKEN`, ...).
+    pub redact_env_secrets: bool,
+    /// Which agent events raise desktop notifications.
+    pub notifications: NotificationPrefs,
+    /// Shell for sessions spawned without a command.
+    pub shell: ShellConfig,
+    /// Kill or leave running PTY children when the app exits.
+    pub exit_behavior: ExitBehavior,
+    /// Per-session output logging.
+    pub logs: LogSettings,
+    /// Default size of new sessions.
+    pub terminal: TerminalSize,
+    /// Named session defaults `pty_spawn` can pick; see `terminal.rs`.
+    pub terminal_profiles: Vec<TerminalProfile>,
+    /// `TERM`, `COLORTERM` and `TERM_PROGRAM` for new sessions.
+    pub terminal_env: TermEnv,
+    /// Output queue bound and what to do when a session overflows it.
+    pub output: FlowSettings,
+    /// Moving old scrollback to disk for sessions with a lot of output.
+    pub scrollback_spill: SpillSettings,
+    /// Confirmation for risky multi-line pastes.
+    pub paste: PasteSettings,
+    /// Patterns guarded sessions confirm or refuse.
+    pub guard: GuardSettings,
+    /// Limits on running sessions.
+    pub concurrency: ConcurrencySettings,
+    /// Emit `pty://annotation` for file references, compiler errors, test
+    /// failures and URLs in session output.
+    pub annotate_output: bool,
+    /// When the task queue holds off for the host's sake.
+    pub system: SystemPolicy,
+    /// Programs run at lifecycle points; see `hooks.rs`.
+    pub hooks: Vec<Hook>,
+    /// Whether agents may start in the main checkout rather than a
+    /// worktree; see `mainguard.rs`.
+    pub main_checkout_guard: MainCheckoutGuard,
+    /// Sign merge and rebased commits with the git-configured key; see
+    /// `signing.rs`.
+    pub sign_commits: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            worktree_root: None,
+            scale_limits: ScaleLimits::default(),
+            branch_template: None,
+            fetch_interval_secs: None,
+            auto_rebase_worktrees: false,
+            setup_commands: HashMap::new(),
+            dependency_cache: HashMap::new(),
+            protected_paths: HashMap::new(),
+            merge_checks: HashMap::new(),
+            merge_messages: HashMap::new(),
+            agent_profiles: Vec::new(),
+            max_concurrent_tasks: 2,
+            redact_patterns: Vec::new(),
+            redact_env_secrets: true,
+            notifications: NotificationPrefs::default(),
+            shell: ShellConfig::default(),
+            exit_behavior: ExitBehavior::default(),
+            logs: LogSettings::default(),
+            terminal: TerminalSize::default(),
+            terminal_profiles: Vec::new(),
+            terminal_env: TermEnv::default(),
+            output: FlowSettings::default(),
+            scrollback_spill: SpillSettings::default(),
+            paste: PasteSettings::default(),
+            guard: GuardSettings::default(),
+            concurrency: ConcurrencySettings::default(),
+            annotate_output: true,
+            system: SystemPolicy::default(),
+            hooks: Vec::new(),
+            main_checkout_guard: MainCheckoutGuard::default(),
+            sign_commits: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Load settings from `path`, falling back to defaults if the file is
+    /// missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("create settings dir")?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).context("write settings")?;
+        Ok(())
+    }
+}
+
+/// Location of the settings file inside the app config dir.
+pub fn settings_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("settings.json")
+}
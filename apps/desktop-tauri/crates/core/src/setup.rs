@@ -0,0 +1,42 @@
+//! Post-create setup for fresh worktrees.
+//!
+//! A new worktree has no `node_modules`, no `.env`, no build caches. After
+//! `worktree_create` we run the repo's setup in a PTY so its output streams
+//! like any other session. Sources, first match wins:
+//!
+//! 1. commands configured for the repo in settings
+//! 2. `setup` in the repo's `.pi-builder.toml`
+//! 3. `.pi-builder/setup.sh` checked into the repo
+
+use std::path::Path;
+
+pub const SETUP_SCRIPT: &str = ".pi-builder/setup.sh";
+
+/// Agent id used for setup sessions so the UI can label them.
+pub const SETUP_AGENT_ID: &str = "setup";
+
+/// Resolve the setup argv for a worktree, if the repo defines one.
+pub fn setup_command(wt_path: &Path, configured: &[String]) -> Option<Vec<String>> {
+    if !configured.is_empty() {
+        let script = configured.join(" && ");
+        return Some(if cfg!(windows) {
+            vec!["cmd.exe".into(), "/C".into(), script]
+        } else {
+            vec!["sh".into(), "-c".into(), script]
+        });
+    }
+    if wt_path.join(SETUP_SCRIPT).is_file() {
+        return Some(vec!["sh".into(), SETUP_SCRIPT.into()]);
+    }
+    None
+}
+
+/// Environment handed to setup so scripts can copy files from the main
+/// checkout (`cp "$PI_BUILDER_REPO/.env" .`).
+pub fn setup_env(repo_path: &str, wt_path: &str, worktree_name: &str) -> Vec<(String, String)> {
+    vec![
+        ("PI_BUILDER_REPO".into(), repo_path.into()),
+        ("PI_BUILDER_WORKTREE".into(), wt_path.into()),
+        ("PI_BUILDER_WORKTREE_NAME".into(), worktree_name.into()),
+    ]
+}
@@ -0,0 +1,198 @@
+//! Signing the commits pi-builder makes.
+//!
+//! With `sign_commits` set, merge commits and the commits rebases replay
+//! are signed the way `git commit -S` would sign them, from the repo's git
+//! config:
+//!
+//! - `gpg.format`: `openpgp` (default) signs with `gpg.openpgp.program` or
+//!   `gpg.program` (`gpg`), `x509` with `gpg.x509.program` (`gpgsm`), and
+//!   `ssh` with `gpg.ssh.program` (`ssh-keygen`).
+//! - `user.signingkey`: the key. For OpenPGP and X.509 it defaults to the
+//!   committer's identity; SSH needs it, as a key file or a literal public
+//!   key (`ssh-ed25519 ...` or `key::...`) whose private half is in the
+//!   SSH agent.
+//!
+//! Passphrases come from gpg-agent or the SSH agent; there is nobody to
+//! type one. A commit that can't be signed fails the operation rather than
+//! landing unsigned.
+//!
+//! libgit2 can't sign while it rebases, so a rebase replays its commits
+//! unsigned, then `resign` rewrites them, signed, with the same trees,
+//! authors and messages.
+
+use anyhow::{bail, Context, Result};
+use git2::{Commit, Config, Oid, Repository, Signature, Sort, Tree};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+use uuid::Uuid;
+
+/// `Repository::commit`, signing the commit when `sign` is set.
+/// `update_ref` may name a symbolic ref such as `HEAD`; the branch it
+/// points to is moved.
+#[allow(clippy::too_many_arguments)]
+pub fn commit(
+    repo: &Repository,
+    update_ref: Option<&str>,
+    author: &Signature,
+    committer: &Signature,
+    message: &str,
+    tree: &Tree,
+    parents: &[&Commit],
+    sign: bool,
+) -> Result<Oid> {
+    if !sign {
+        return Ok(repo.commit(update_ref, author, committer, message, tree, parents)?);
+    }
+    let oid = signed(repo, author, committer, message, tree, parents)?;
+    if let Some(name) = update_ref {
+        let summary = message.lines().next().unwrap_or_default();
+        repo.find_reference(name)?.resolve()?.set_target(oid, &format!("commit: {summary}"))?;
+    }
+    Ok(oid)
+}
+
+/// Rewrite the commits on HEAD's branch since `base` as signed ones and
+/// move the branch to the result. The trees don't change, so neither does
+/// the checkout. Returns the new HEAD.
+pub fn resign(repo: &Repository, base: Oid) -> Result<Oid> {
+    let mut head = repo.head()?;
+    if !head.is_branch() {
+        bail!("HEAD is detached");
+    }
+    let mut walk = repo.revwalk()?;
+    walk.push(head.peel_to_commit()?.id())?;
+    walk.hide(base)?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
+    let mut rewritten: HashMap<Oid, Oid> = HashMap::new();
+    let mut last = None;
+    for oid in walk {
+        let old = repo.find_commit(oid?)?;
+        let parents = old
+            .parent_ids()
+            .map(|id| repo.find_commit(rewritten.get(&id).copied().unwrap_or(id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parents: Vec<&Commit> = parents.iter().collect();
+        let message = old.message_raw().unwrap_or_default();
+        let new = signed(repo, &old.author(), &old.committer(), message, &old.tree()?, &parents)?;
+        rewritten.insert(old.id(), new);
+        last = Some(new);
+    }
+    match last {
+        Some(new) => {
+            head.set_target(new, "pi-builder: sign rebased commits")?;
+            Ok(new)
+        }
+        None => Ok(head.peel_to_commit()?.id()),
+    }
+}
+
+fn signed(
+    repo: &Repository,
+    author: &Signature,
+    committer: &Signature,
+    message: &str,
+    tree: &Tree,
+    parents: &[&Commit],
+) -> Result<Oid> {
+    let buffer = repo.commit_create_buffer(author, committer, message, tree, parents)?;
+    let buffer = buffer.as_str().context("commit isn't UTF-8")?;
+    let signature = sign(&repo.config()?.snapshot()?, committer, buffer)?;
+    Ok(repo.commit_signed(buffer, &signature, None)?)
+}
+
+/// Detached signature of `payload` as `config` says to make it.
+fn sign(config: &Config, committer: &Signature, payload: &str) -> Result<String> {
+    let format = config.get_string("gpg.format").unwrap_or_else(|_| "openpgp".into());
+    let key = config.get_string("user.signingkey").ok().filter(|k| !k.trim().is_empty());
+    match format.as_str() {
+        "openpgp" => {
+            let program = config
+                .get_string("gpg.openpgp.program")
+                .or_else(|_| config.get_string("gpg.program"))
+                .unwrap_or_else(|_| "gpg".into());
+            sign_gpg(&program, key.unwrap_or_else(|| committer.to_string()), payload)
+        }
+        "x509" => {
+            let program = config.get_string("gpg.x509.program").unwrap_or_else(|_| "gpgsm".into());
+            sign_gpg(&program, key.unwrap_or_else(|| committer.to_string()), payload)
+        }
+        "ssh" => {
+            let program =
+                config.get_string("gpg.ssh.program").unwrap_or_else(|_| "ssh-keygen".into());
+            let key = key.context("gpg.format is ssh but user.signingkey isn't set")?;
+            sign_ssh(&program, &key, payload)
+        }
+        other => bail!("unsupported gpg.format {other}"),
+    }
+}
+
+fn sign_gpg(program: &str, key: String, payload: &str) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(["--status-fd=2", "-bsau", &key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {program}"))?;
+    child.stdin.take().context("signer stdin")?.write_all(payload.as_bytes())?;
+    let out = child.wait_with_output()?;
+    let status = String::from_utf8_lossy(&out.stderr);
+    // As git does, trust only the status line, not the exit code alone
+    if !out.status.success() || !status.contains("[GNUPG:] SIG_CREATED ") {
+        bail!("{program} failed to sign: {}", status.trim());
+    }
+    String::from_utf8(out.stdout).context("signature isn't UTF-8")
+}
+
+fn sign_ssh(program: &str, key: &str, payload: &str) -> Result<String> {
+    let dir = std::env::temp_dir();
+    let id = Uuid::new_v4().simple().to_string();
+    let payload_path = dir.join(format!("pi-builder-sign-{id}"));
+    let sig_path = dir.join(format!("pi-builder-sign-{id}.sig"));
+    let literal = key.strip_prefix("key::").or_else(|| key.starts_with("ssh-").then_some(key));
+    let key_path = match literal {
+        // Public key only: ssh-keygen finds the private half in the agent
+        Some(public) => {
+            let path = dir.join(format!("pi-builder-sign-{id}.pub"));
+            std::fs::write(&path, public).context("write signing key")?;
+            path
+        }
+        None => expand_home(key),
+    };
+    let result = (|| {
+        std::fs::write(&payload_path, payload).context("write commit to sign")?;
+        let mut command = Command::new(program);
+        command.args(["-Y", "sign", "-n", "git", "-f"]).arg(&key_path);
+        if literal.is_some() {
+            command.arg("-U");
+        }
+        let out = command
+            .arg(&payload_path)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("run {program}"))?;
+        if !out.status.success() {
+            bail!("{program} failed to sign: {}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        std::fs::read_to_string(&sig_path).context("read signature")
+    })();
+    let _ = std::fs::remove_file(&payload_path);
+    let _ = std::fs::remove_file(&sig_path);
+    if literal.is_some() {
+        let _ = std::fs::remove_file(&key_path);
+    }
+    result
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match (path.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,139 @@
+//! Worktree snapshots and rollback.
+//!
+//! A snapshot is a commit of the full working tree (tracked and untracked,
+//! ignored files excluded) whose parent is the worktree HEAD at the time.
+//! It never touches the branch or index; it is kept alive by a ref under
+//! `refs/pi-builder/snapshots/<worktree>/<id>`.
+//!
+//! Rollback resets the branch to the snapshot's parent and restores the
+//! snapshot's files as uncommitted changes — "undo to before I asked".
+
+use crate::error::PiError;
+use anyhow::{Context, Result};
+use crate::worktree::signature;
+use git2::{build::CheckoutBuilder, IndexAddOption, Repository, ResetType};
+use serde::Serialize;
+use uuid::Uuid;
+
+const REF_PREFIX: &str = "refs/pi-builder/snapshots";
+const MESSAGE_PREFIX: &str = "pi-builder snapshot: ";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub worktree: String,
+    pub label: String,
+    /// Commit holding the snapshot tree.
+    pub commit: String,
+    /// Worktree HEAD when the snapshot was taken.
+    pub head: String,
+    pub created_at: i64,
+}
+
+pub fn snapshot(repo_path: &str, worktree: &str, label: &str) -> Result<Snapshot> {
+    let wt_repo = open_worktree(repo_path, worktree)?;
+    let head = wt_repo.head()?.peel_to_commit().context("worktree HEAD")?;
+
+    // Stage everything into the in-memory index only; reload afterwards so
+    // the on-disk index is untouched.
+    let mut index = wt_repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    let tree = wt_repo.find_tree(index.write_tree()?)?;
+    index.read(true)?;
+
+    let sig = signature(&wt_repo);
+    let message = format!("{MESSAGE_PREFIX}{label}");
+    let commit = wt_repo.commit(None, &sig, &sig, &message, &tree, &[&head])?;
+
+    let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+    wt_repo.reference(&ref_name(worktree, &id), commit, false, &message)?;
+
+    Ok(Snapshot {
+        id,
+        worktree: worktree.to_string(),
+        label: label.to_string(),
+        commit: commit.to_string(),
+        head: head.id().to_string(),
+        created_at: sig.when().seconds(),
+    })
+}
+
+/// Snapshots for a worktree, newest first.
+pub fn list(repo_path: &str, worktree: &str) -> Result<Vec<Snapshot>> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let mut snapshots = Vec::new();
+    let glob = format!("{REF_PREFIX}/{worktree}/*");
+    for reference in repo.references_glob(&glob)?.flatten() {
+        let Some(name) = reference.name() else { continue };
+        let id = name.rsplit('/').next().unwrap_or_default().to_string();
+        let Ok(commit) = reference.peel_to_commit() else { continue };
+        let Ok(parent) = commit.parent(0) else { continue };
+        let label = commit
+            .message()
+            .unwrap_or_default()
+            .strip_prefix(MESSAGE_PREFIX)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string();
+        snapshots.push(Snapshot {
+            id,
+            worktree: worktree.to_string(),
+            label,
+            commit: commit.id().to_string(),
+            head: parent.id().to_string(),
+            created_at: commit.time().seconds(),
+        });
+    }
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+    Ok(snapshots)
+}
+
+/// Restore a snapshot. The current state is snapshotted first (labelled
+/// "before rollback") and returned so the rollback itself can be undone.
+pub fn rollback(repo_path: &str, worktree: &str, snapshot_id: &str) -> Result<Snapshot> {
+    let wt_repo = open_worktree(repo_path, worktree)?;
+    let target = wt_repo
+        .find_reference(&ref_name(worktree, snapshot_id))
+        .map_err(|_| PiError::snapshot_not_found(snapshot_id))?
+        .peel_to_commit()?;
+    let parent = target.parent(0).context("snapshot parent")?;
+
+    let safety = snapshot(repo_path, worktree, &format!("before rollback to {snapshot_id}"))?;
+
+    wt_repo.reset(parent.as_object(), ResetType::Hard, None)?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force().remove_untracked(true);
+    wt_repo.checkout_tree(target.as_object(), Some(&mut checkout))?;
+    // Leave the restored files as uncommitted changes on top of the parent
+    wt_repo.reset(parent.as_object(), ResetType::Mixed, None)?;
+
+    Ok(safety)
+}
+
+pub fn delete(repo_path: &str, worktree: &str, snapshot_id: &str) -> Result<()> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    repo.find_reference(&ref_name(worktree, snapshot_id))
+        .map_err(|_| PiError::snapshot_not_found(snapshot_id))?
+        .delete()?;
+    Ok(())
+}
+
+/// Drop every snapshot ref of a worktree (used when it is removed).
+pub fn delete_all(repo: &Repository, worktree: &str) {
+    let glob = format!("{REF_PREFIX}/{worktree}/*");
+    if let Ok(refs) = repo.references_glob(&glob) {
+        for mut reference in refs.flatten() {
+            let _ = reference.delete();
+        }
+    }
+}
+
+fn ref_name(worktree: &str, id: &str) -> String {
+    format!("{REF_PREFIX}/{worktree}/{id}")
+}
+
+fn open_worktree(repo_path: &str, worktree: &str) -> Result<Repository> {
+    let path = crate::worktree::worktree_path(repo_path, worktree)?;
+    Repository::open(path).context("open worktree")
+}
@@ -0,0 +1,76 @@
+//! Sparse worktrees, for agents that only need part of a monorepo.
+//!
+//! A worktree normally checks out the whole tree, which on a large
+//! monorepo means gigabytes per session. Given sparse paths (from
+//! `sparse` in `.pi-builder.toml`, or per spawn or task) a new worktree
+//! only materializes those; the rest stays in the index, flagged
+//! skip-worktree, so commits still carry the whole tree.
+//!
+//! libgit2 can't write sparse-checkout config, so these worktrees are
+//! made with the `git` CLI: `worktree add --no-checkout`, `sparse-checkout
+//! set`, then `read-tree` to check out what the patterns select. The
+//! sparse config is per worktree; the main checkout stays full.
+//!
+//! Paths without glob characters are directories and use cone mode, the
+//! fast one. Any glob switches to gitignore-style patterns.
+
+use crate::error::PiError;
+use anyhow::{bail, Context, Result};
+use std::{path::Path, process::Command};
+
+/// Fail on paths that can't select anything inside the worktree.
+pub fn validate(paths: &[String]) -> Result<()> {
+    for path in paths {
+        let trimmed = path.trim();
+        if trimmed.is_empty() {
+            return Err(PiError::invalid_input("sparse path is empty").into());
+        }
+        let rel = trimmed.trim_start_matches('!');
+        if Path::new(rel).is_absolute() || rel.split('/').any(|part| part == "..") {
+            let message = format!("sparse path {trimmed} must be inside the repo");
+            return Err(PiError::invalid_input(message).into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `paths` are plain directories, so cone mode can be used.
+pub fn is_cone(paths: &[String]) -> bool {
+    paths.iter().all(|p| !p.contains(['*', '?', '[', '!']))
+}
+
+/// Add worktree `wt_path` of the repo at `repo_path` on `branch`, checking
+/// out only `paths`. The worktree is removed again if a step fails.
+pub fn add_worktree(repo_path: &str, wt_path: &Path, branch: &str, paths: &[String]) -> Result<()> {
+    validate(paths)?;
+    let mut add = Command::new("git");
+    add.arg("-C").arg(repo_path).args(["worktree", "add", "--no-checkout"]).arg(wt_path);
+    run(add.arg(branch), "git worktree add")?;
+
+    let result = checkout(wt_path, paths);
+    if result.is_err() {
+        let mut remove = Command::new("git");
+        remove.arg("-C").arg(repo_path).args(["worktree", "remove", "--force"]).arg(wt_path);
+        if let Err(e) = run(&mut remove, "git worktree remove") {
+            log::warn!("removing half-made sparse worktree: {e:#}");
+        }
+    }
+    result
+}
+
+fn checkout(wt_path: &Path, paths: &[String]) -> Result<()> {
+    let mode = if is_cone(paths) { "--cone" } else { "--no-cone" };
+    let mut set = Command::new("git");
+    set.arg("-C").arg(wt_path).args(["sparse-checkout", "set", mode, "--"]);
+    run(set.args(paths.iter().map(|p| p.trim())), "git sparse-checkout set")?;
+    let mut read = Command::new("git");
+    run(read.arg("-C").arg(wt_path).args(["read-tree", "-mu", "HEAD"]), "git read-tree")
+}
+
+fn run(cmd: &mut Command, what: &str) -> Result<()> {
+    let out = cmd.output().with_context(|| format!("run {what}"))?;
+    if !out.status.success() {
+        bail!("{what}: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Concurrent session limits, and spawns waiting for a free slot.
+//!
+//! `max_sessions` caps running sessions overall and
+//! `max_sessions_per_repo` per repo; 0 or no entry means no limit. A spawn
+//! over a limit fails with `session_limit`, or, when the caller asked to
+//! queue, waits here. The host starts queued spawns oldest first as
+//! sessions exit; one for a repo at its limit doesn't hold up the others.
+//!
+//! A spawn let through holds a `Slot` until its session runs, so spawns
+//! checked at the same time can't all take the last free slot.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencySettings {
+    pub max_sessions: usize,
+    /// By repo path.
+    pub max_sessions_per_repo: HashMap<String, usize>,
+}
+
+impl ConcurrencySettings {
+    /// Why another session can't start now, given the sessions running
+    /// overall and in `repo`; `None` if it can.
+    pub fn refusal(&self, running: usize, in_repo: usize, repo: Option<&str>) -> Option<String> {
+        if self.max_sessions > 0 && running >= self.max_sessions {
+            return Some(format!("{running} of {} sessions running", self.max_sessions));
+        }
+        let max = repo.and_then(|r| self.max_sessions_per_repo.get(r)).copied().unwrap_or(0);
+        if max > 0 && in_repo >= max {
+            return Some(format!("{in_repo} of {max} sessions running in this repo"));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedSpawn {
+    pub queue_id: String,
+    pub repo: Option<String>,
+    pub agent_id: String,
+    /// Unix millis.
+    pub queued_at: u64,
+}
+
+struct Entry<T> {
+    info: QueuedSpawn,
+    request: T,
+}
+
+/// Spawn requests waiting for a slot, oldest first.
+pub struct SpawnQueue<T> {
+    entries: Mutex<VecDeque<Entry<T>>>,
+    /// Repos of the spawns holding a slot.
+    starting: Mutex<Vec<Option<String>>>,
+}
+
+impl<T> Default for SpawnQueue<T> {
+    fn default() -> Self {
+        Self { entries: Mutex::new(VecDeque::new()), starting: Mutex::default() }
+    }
+}
+
+/// A session slot taken by a spawn whose session isn't running yet;
+/// freed when dropped.
+pub struct Slot<'a> {
+    starting: &'a Mutex<Vec<Option<String>>>,
+    repo: Option<String>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut starting = self.starting.lock().unwrap();
+        if let Some(at) = starting.iter().position(|r| *r == self.repo) {
+            starting.remove(at);
+        }
+    }
+}
+
+impl<T> SpawnQueue<T> {
+    /// Queue `request`; returns its entry.
+    pub fn push(&self, repo: Option<String>, agent_id: &str, request: T) -> QueuedSpawn {
+        let info = QueuedSpawn {
+            queue_id: Uuid::new_v4().to_string(),
+            repo,
+            agent_id: agent_id.to_string(),
+            queued_at: now_ms(),
+        };
+        self.entries.lock().unwrap().push_back(Entry { info: info.clone(), request });
+        info
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    /// Take a slot for a spawn in `repo`, unless `refusal` gives a reason
+    /// not to. It is passed the spawns holding a slot, overall and in
+    /// `repo`, to count along with the running sessions.
+    pub fn reserve(
+        &self,
+        repo: Option<&str>,
+        refusal: impl FnOnce(usize, usize) -> Option<String>,
+    ) -> Result<Slot<'_>, String> {
+        let mut starting = self.starting.lock().unwrap();
+        let in_repo = starting.iter().filter(|r| repo.is_some() && r.as_deref() == repo).count();
+        if let Some(reason) = refusal(starting.len(), in_repo) {
+            return Err(reason);
+        }
+        starting.push(repo.map(str::to_string));
+        Ok(Slot { starting: &self.starting, repo: repo.map(str::to_string) })
+    }
+
+    /// Take the oldest request whose repo `can_start` admits.
+    pub fn pop_ready(
+        &self,
+        mut can_start: impl FnMut(Option<&str>) -> bool,
+    ) -> Option<(QueuedSpawn, T)> {
+        let mut entries = self.entries.lock().unwrap();
+        let at = entries.iter().position(|e| can_start(e.info.repo.as_deref()))?;
+        entries.remove(at).map(|e| (e.info, e.request))
+    }
+
+    /// Drop a queued request. Returns false if it isn't queued (any more).
+    pub fn cancel(&self, queue_id: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.info.queue_id != queue_id);
+        entries.len() != before
+    }
+
+    pub fn list(&self) -> Vec<QueuedSpawn> {
+        self.entries.lock().unwrap().iter().map(|e| e.info.clone()).collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,92 @@
+//! SSH remote backend.
+//!
+//! The session's PTY runs `ssh -tt <host> '<remote command>'`, so the agent
+//! gets a TTY on the remote machine and output streams back like any local
+//! session. Authentication is whatever `ssh` does: keys from `identity_file`
+//! or the agent, or a password prompt typed into the terminal.
+//!
+//! The local cwd means nothing remotely; sessions start in `remote_cwd`
+//! (or the remote login directory). Session env is exported in the remote
+//! command, since `SendEnv` needs server-side opt-in.
+
+use crate::target::Launch;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshTarget {
+    pub host: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub identity_file: Option<String>,
+    #[serde(default)]
+    pub remote_cwd: Option<String>,
+    /// Extra `-o` options, e.g. `StrictHostKeyChecking=accept-new`.
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+impl SshTarget {
+    /// Build the `ssh` command line. An empty `cmd` starts a login shell on
+    /// the remote host.
+    pub fn launch(&self, cmd: Vec<String>, env: &[(String, String)]) -> Launch {
+        let mut argv: Vec<String> = vec!["ssh".into(), "-tt".into()];
+        if let Some(port) = self.port {
+            argv.push("-p".into());
+            argv.push(port.to_string());
+        }
+        if let Some(identity) = &self.identity_file {
+            argv.push("-i".into());
+            argv.push(identity.clone());
+        }
+        for option in &self.options {
+            argv.push("-o".into());
+            argv.push(option.clone());
+        }
+        argv.push(match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        });
+        if let Some(remote) = self.remote_command(&cmd, env) {
+            argv.push(remote);
+        }
+        Launch { cmd: argv, cwd: None, env: Vec::new(), cleanup: None }
+    }
+
+    fn remote_command(&self, cmd: &[String], env: &[(String, String)]) -> Option<String> {
+        if cmd.is_empty() && env.is_empty() && self.remote_cwd.is_none() {
+            return None;
+        }
+        let mut script = String::new();
+        if let Some(dir) = &self.remote_cwd {
+            script.push_str(&format!("cd {} && ", shell_quote(dir)));
+        }
+        script.push_str("exec ");
+        if !env.is_empty() {
+            script.push_str("env ");
+            for (key, value) in env {
+                script.push_str(&format!("{key}={} ", shell_quote(value)));
+            }
+        }
+        if cmd.is_empty() {
+            script.push_str("\"$SHELL\" -l");
+        } else {
+            let quoted: Vec<String> = cmd.iter().map(|a| shell_quote(a)).collect();
+            script.push_str(&quoted.join(" "));
+        }
+        Some(script)
+    }
+}
+
+/// Quote `s` for a POSIX shell.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let safe = !s.is_empty()
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    if safe {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
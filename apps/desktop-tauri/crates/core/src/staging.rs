@@ -0,0 +1,276 @@
+//! Hunk-level staging for reviewing a worktree.
+//!
+//! Diffs are returned per file with an id for every hunk. The id hashes the
+//! path, hunk header and lines, so a stale id (the file changed since the
+//! diff was fetched) is rejected instead of staging the wrong lines.
+//!
+//! Staging applies the selected hunk of the index→workdir diff to the index;
+//! unstaging applies the matching hunk of the reversed HEAD→index diff.
+//!
+//! LFS-tracked files are flagged `lfs` and come without hunks: their blobs
+//! are pointer files, and a pointer diff means nothing to a reviewer.
+//!
+//! A `DiffView` can hide whitespace and line-ending changes, which agents
+//! on Windows make to whole files, and pair deleted and added files up as
+//! renames or copies. A file left with no changes is dropped. Hunks of such
+//! a diff aren't the file's real changes, so staging uses the plain diff:
+//! their ids aren't accepted by `stage_hunk`.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    highlight::{self, Token},
+    lfs,
+};
+use anyhow::{Context, Result};
+use git2::{
+    ApplyLocation, ApplyOptions, Delta, Diff, DiffFindOptions, DiffOptions, ObjectType, Oid, Patch,
+    Repository,
+};
+use serde::{Deserialize, Serialize};
+
+/// How a diff is computed for display.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct DiffView {
+    /// Ignore all whitespace (`git diff -w`).
+    pub ignore_whitespace: bool,
+    /// Ignore changes in the amount of whitespace (`git diff -b`).
+    pub ignore_whitespace_change: bool,
+    /// Ignore CR/LF changes. This ignores any whitespace at line ends, CR
+    /// included; libgit2 has no CR-only option.
+    pub ignore_line_endings: bool,
+    /// Detect renames of files at least this similar, in percent.
+    pub renames: Option<u16>,
+    /// Also detect copies of modified files, at the same similarity.
+    pub copies: bool,
+}
+
+impl DiffView {
+    fn apply(&self, opts: &mut DiffOptions) {
+        opts.ignore_whitespace(self.ignore_whitespace)
+            .ignore_whitespace_change(self.ignore_whitespace_change)
+            .ignore_whitespace_eol(self.ignore_line_endings);
+    }
+
+    fn find_similar(&self, diff: &mut Diff, untracked: bool) -> Result<()> {
+        let Some(threshold) = self.renames else { return Ok(()) };
+        if threshold > 100 {
+            return Err(PiError::invalid_input("similarity is a percentage, 0 to 100").into());
+        }
+        let mut find = DiffFindOptions::new();
+        find.renames(true)
+            .rename_threshold(threshold)
+            .copies(self.copies)
+            .copy_threshold(threshold)
+            .for_untracked(untracked);
+        diff.find_similar(Some(&mut find))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    /// Where a renamed or copied file came from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// Detected from the file name, e.g. `Rust`.
+    pub language: Option<String>,
+    pub binary: bool,
+    /// Tracked by Git LFS; `hunks` is empty.
+    pub lfs: bool,
+    pub hunks: Vec<HunkInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HunkInfo {
+    pub id: String,
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<LineInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LineInfo {
+    /// `+`, `-` or ` `.
+    pub origin: char,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    pub content: String,
+    /// Highlighted runs of `content`, when asked for; see `highlight.rs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<Token>>,
+}
+
+/// Hunk position as `(old_start, old_lines, new_start, new_lines)`.
+type Coords = (u32, u32, u32, u32);
+
+/// Changes in the worktree not yet staged.
+pub fn unstaged_diff(repo_path: &str, worktree: &str, view: &DiffView) -> Result<Vec<FileDiff>> {
+    let repo = open_worktree(repo_path, worktree)?;
+    let mut opts = workdir_options(None);
+    view.apply(&mut opts);
+    let mut diff = repo.diff_index_to_workdir(None, Some(&mut opts))?;
+    view.find_similar(&mut diff, true)?;
+    file_diffs(&repo, &diff)
+}
+
+/// Changes staged in the worktree index, relative to HEAD.
+pub fn staged_diff(repo_path: &str, worktree: &str, view: &DiffView) -> Result<Vec<FileDiff>> {
+    let repo = open_worktree(repo_path, worktree)?;
+    let mut opts = diff_options(None);
+    view.apply(&mut opts);
+    let head = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+    let mut diff = repo.diff_tree_to_index(head.as_ref(), None, Some(&mut opts))?;
+    view.find_similar(&mut diff, false)?;
+    file_diffs(&repo, &diff)
+}
+
+pub fn stage_hunk(repo_path: &str, worktree: &str, file: &str, hunk_id: &str) -> Result<()> {
+    let repo = open_worktree(repo_path, worktree)?;
+    let diff = workdir_diff(&repo, Some(file))?;
+    let coords = find_hunk(&repo, &diff, hunk_id)?;
+    apply_hunk(&repo, &diff, coords)
+}
+
+pub fn unstage_hunk(repo_path: &str, worktree: &str, file: &str, hunk_id: &str) -> Result<()> {
+    let repo = open_worktree(repo_path, worktree)?;
+    let (old_start, old_lines, new_start, new_lines) =
+        find_hunk(&repo, &index_diff(&repo, Some(file), false)?, hunk_id)?;
+    // The reversed diff swaps the old and new sides of every hunk
+    let reversed = index_diff(&repo, Some(file), true)?;
+    apply_hunk(&repo, &reversed, (new_start, new_lines, old_start, old_lines))
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+fn open_worktree(repo_path: &str, worktree: &str) -> Result<Repository> {
+    let path = crate::worktree::worktree_path(repo_path, worktree)?;
+    Repository::open(path).context("open worktree")
+}
+
+fn diff_options(file: Option<&str>) -> DiffOptions {
+    let mut opts = DiffOptions::new();
+    if let Some(file) = file {
+        opts.pathspec(file).disable_pathspec_match(true);
+    }
+    opts
+}
+
+fn workdir_options(file: Option<&str>) -> DiffOptions {
+    let mut opts = diff_options(file);
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    opts
+}
+
+fn workdir_diff<'r>(repo: &'r Repository, file: Option<&str>) -> Result<Diff<'r>> {
+    Ok(repo.diff_index_to_workdir(None, Some(&mut workdir_options(file)))?)
+}
+
+fn index_diff<'r>(repo: &'r Repository, file: Option<&str>, reverse: bool) -> Result<Diff<'r>> {
+    let mut opts = diff_options(file);
+    opts.reverse(reverse);
+    let head = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+    Ok(repo.diff_tree_to_index(head.as_ref(), None, Some(&mut opts))?)
+}
+
+pub(crate) fn file_diffs(repo: &Repository, diff: &Diff) -> Result<Vec<FileDiff>> {
+    let mut files = Vec::new();
+    for idx in 0..diff.deltas().len() {
+        let Some(patch) = Patch::from_diff(diff, idx)? else { continue };
+        let delta = patch.delta();
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let old_path = matches!(delta.status(), Delta::Renamed | Delta::Copied)
+            .then(|| delta.old_file().path())
+            .flatten()
+            .map(|p| p.to_string_lossy().replace('\\', "/"));
+        let lfs = lfs::is_tracked(repo, &path);
+        let binary = lfs || delta.flags().is_binary();
+        let mut hunks = Vec::new();
+        if !lfs {
+            for h in 0..patch.num_hunks() {
+                hunks.push(hunk_info(&patch, &path, h)?);
+            }
+        }
+        // Only ignored whitespace changed
+        let same_mode = delta.old_file().mode() == delta.new_file().mode();
+        if delta.status() == Delta::Modified && same_mode && !binary && hunks.is_empty() {
+            continue;
+        }
+        files.push(FileDiff {
+            old_path,
+            language: highlight::language(&path),
+            binary,
+            lfs,
+            path,
+            hunks,
+        });
+    }
+    Ok(files)
+}
+
+fn hunk_info(patch: &Patch, path: &str, h: usize) -> Result<HunkInfo> {
+    let (hunk, line_count) = patch.hunk(h)?;
+    let header = String::from_utf8_lossy(hunk.header()).trim_end().to_string();
+    let mut lines = Vec::with_capacity(line_count);
+    let mut raw = format!("{path}\n{header}\n").into_bytes();
+    for l in 0..line_count {
+        let line = patch.line_in_hunk(h, l)?;
+        raw.push(line.origin() as u8);
+        raw.extend_from_slice(line.content());
+        lines.push(LineInfo {
+            origin: line.origin(),
+            old_lineno: line.old_lineno(),
+            new_lineno: line.new_lineno(),
+            content: String::from_utf8_lossy(line.content()).into_owned(),
+            tokens: None,
+        });
+    }
+    let id = Oid::hash_object(ObjectType::Blob, &raw)?.to_string()[..12].to_string();
+    Ok(HunkInfo {
+        id,
+        header,
+        old_start: hunk.old_start(),
+        old_lines: hunk.old_lines(),
+        new_start: hunk.new_start(),
+        new_lines: hunk.new_lines(),
+        lines,
+    })
+}
+
+fn find_hunk(repo: &Repository, diff: &Diff, hunk_id: &str) -> Result<Coords> {
+    file_diffs(repo, diff)?
+        .into_iter()
+        .flat_map(|f| f.hunks)
+        .find(|h| h.id == hunk_id)
+        .map(|h| (h.old_start, h.old_lines, h.new_start, h.new_lines))
+        .ok_or_else(|| {
+            PiError::new(ErrorCode::NotFound, format!("hunk not found: {hunk_id}"))
+                .with_detail("the file changed since the diff was loaded")
+                .into()
+        })
+}
+
+/// Apply only the hunk at `coords` from `diff` to the index.
+fn apply_hunk(repo: &Repository, diff: &Diff, coords: Coords) -> Result<()> {
+    let mut opts = ApplyOptions::new();
+    opts.hunk_callback(|hunk| {
+        hunk.is_some_and(|h| {
+            (h.old_start(), h.old_lines(), h.new_start(), h.new_lines()) == coords
+        })
+    });
+    repo.apply(diff, ApplyLocation::Index, Some(&mut opts))
+        .context("apply hunk to index")
+}
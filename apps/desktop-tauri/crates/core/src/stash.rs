@@ -0,0 +1,112 @@
+//! Stashes of the main checkout.
+//!
+//! Merging or rebasing into the base branch needs a clean main checkout.
+//! Users park their own in-progress changes there with `save`, untracked
+//! files included by default, and bring them back with `apply` or `pop`
+//! once the agents' work has landed. Stashes are git's own, so `git stash
+//! list` shows the same entries; `index` is the `n` of `stash@{n}`.
+//!
+//! A stash that conflicts with the checkout fails with a merge conflict;
+//! `pop` only drops a stash that applied.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    worktree,
+};
+use anyhow::{Context, Result};
+use git2::{Oid, Repository, StashApplyOptions, StashFlags};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StashEntry {
+    pub index: usize,
+    pub message: String,
+    pub commit: String,
+    /// Unix seconds.
+    pub created_at: i64,
+}
+
+/// Stash the main checkout's changes. Returns the new stash, which is
+/// `stash@{0}`.
+pub fn save(repo_path: &str, message: Option<&str>, include_untracked: bool) -> Result<StashEntry> {
+    let mut repo = Repository::open(repo_path).context("open repo")?;
+    let sig = worktree::signature(&repo);
+    let mut flags = StashFlags::DEFAULT;
+    if include_untracked {
+        flags |= StashFlags::INCLUDE_UNTRACKED;
+    }
+    let message = message.filter(|m| !m.trim().is_empty());
+    let id = repo.stash_save2(&sig, message, Some(flags)).map_err(|e| {
+        if e.code() == git2::ErrorCode::NotFound {
+            PiError::invalid_input("no local changes to stash").into()
+        } else {
+            anyhow::Error::from(e)
+        }
+    })?;
+    entry(&repo, id, 0)
+}
+
+/// Stashes, newest first.
+pub fn list(repo_path: &str) -> Result<Vec<StashEntry>> {
+    let mut repo = Repository::open(repo_path).context("open repo")?;
+    let mut found = Vec::new();
+    repo.stash_foreach(|index, _, id| {
+        found.push((index, *id));
+        true
+    })?;
+    found.into_iter().map(|(index, id)| entry(&repo, id, index)).collect()
+}
+
+/// Apply `stash@{index}` to the main checkout; `pop` drops it if it
+/// applied.
+pub fn apply(repo_path: &str, index: usize, pop: bool) -> Result<()> {
+    let mut repo = Repository::open(repo_path).context("open repo")?;
+    check_index(&mut repo, index)?;
+    let mut opts = StashApplyOptions::new();
+    let result = if pop {
+        repo.stash_pop(index, Some(&mut opts))
+    } else {
+        repo.stash_apply(index, Some(&mut opts))
+    };
+    result.map_err(|e| match e.code() {
+        git2::ErrorCode::Conflict | git2::ErrorCode::MergeConflict => PiError::new(
+            ErrorCode::MergeConflict,
+            format!("stash@{{{index}}} conflicts with the checkout"),
+        )
+        .with_detail(e.message().to_string())
+        .into(),
+        _ => anyhow::Error::from(e),
+    })
+}
+
+/// Delete `stash@{index}` without applying it.
+pub fn drop(repo_path: &str, index: usize) -> Result<()> {
+    let mut repo = Repository::open(repo_path).context("open repo")?;
+    check_index(&mut repo, index)?;
+    repo.stash_drop(index)?;
+    Ok(())
+}
+
+fn check_index(repo: &mut Repository, index: usize) -> Result<()> {
+    let mut count = 0;
+    repo.stash_foreach(|_, _, _| {
+        count += 1;
+        true
+    })?;
+    if index >= count {
+        let message = format!("no stash@{{{index}}}");
+        return Err(PiError::new(ErrorCode::NotFound, message).into());
+    }
+    Ok(())
+}
+
+fn entry(repo: &Repository, id: Oid, index: usize) -> Result<StashEntry> {
+    let commit = repo.find_commit(id)?;
+    Ok(StashEntry {
+        index,
+        message: commit.summary().unwrap_or_default().to_string(),
+        commit: id.to_string(),
+        created_at: commit.time().seconds(),
+    })
+}
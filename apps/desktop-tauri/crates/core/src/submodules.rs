@@ -0,0 +1,154 @@
+//! Submodule checkout for fresh worktrees.
+//!
+//! `git worktree add` leaves submodule directories empty, so builds in a new
+//! agent worktree fail straight away. After creating one we initialize and
+//! update every submodule, recursively. libgit2 is tried first so fetch
+//! progress can be streamed as `worktree://submodule/<name>`; if it fails
+//! (typically SSH auth it can't do), the `git` CLI gets a go with the user's
+//! own credential setup.
+
+use crate::events::SharedSink;
+use anyhow::{bail, Context, Result};
+use git2::{FetchOptions, RemoteCallbacks, Repository, SubmoduleUpdateOptions};
+use serde::Serialize;
+use std::{path::Path, process::Command};
+
+#[derive(Debug, Serialize)]
+pub struct SubmoduleReport {
+    /// Path relative to the worktree root.
+    pub path: String,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FetchProgress {
+    pub received_objects: usize,
+    pub total_objects: usize,
+    pub received_bytes: usize,
+}
+
+/// Whether the checkout at `wt_path` declares submodules.
+pub fn has_submodules(wt_path: &Path) -> bool {
+    wt_path.join(".gitmodules").is_file()
+}
+
+/// Initialize and update all submodules under `wt_path`, emitting fetch
+/// progress for worktree `name`. A failing submodule doesn't stop the rest.
+pub fn update_with_events(
+    wt_path: &Path,
+    name: &str,
+    events: &SharedSink,
+) -> Vec<SubmoduleReport> {
+    let mut reports = Vec::new();
+    let repo = match Repository::open(wt_path).context("open worktree") {
+        Ok(repo) => repo,
+        Err(e) => {
+            reports.push(failed(String::new(), format!("{e:#}")));
+            return reports;
+        }
+    };
+    let mut on_progress = |submodule: &str, p: FetchProgress| {
+        events.emit(
+            &format!("worktree://submodule/{name}"),
+            serde_json::json!({
+                "worktree": name,
+                "submodule": submodule,
+                "receivedObjects": p.received_objects,
+                "totalObjects": p.total_objects,
+                "receivedBytes": p.received_bytes,
+            }),
+        );
+    };
+    update_repo(&repo, "", &mut on_progress, &mut reports);
+    reports
+}
+
+fn update_repo(
+    repo: &Repository,
+    prefix: &str,
+    on_progress: &mut dyn FnMut(&str, FetchProgress),
+    reports: &mut Vec<SubmoduleReport>,
+) {
+    let submodules = match repo.submodules() {
+        Ok(s) => s,
+        Err(e) => {
+            reports.push(failed(prefix.trim_end_matches('/').to_string(), e.to_string()));
+            return;
+        }
+    };
+    for mut sm in submodules {
+        let rel = sm.path().to_string_lossy().replace('\\', "/");
+        let path = format!("{prefix}{rel}");
+        let via_git2 = update_one(&mut sm, &path, on_progress);
+        let result = match via_git2 {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                log::info!("libgit2 submodule update of {path} failed ({e:#}), trying git");
+                update_with_cli(repo, &rel).map(|()| false)
+            }
+        };
+        match result {
+            Ok(recurse) => {
+                reports.push(SubmoduleReport { path: path.clone(), ok: true, error: None });
+                // The CLI already ran with --recursive
+                if recurse {
+                    if let Ok(sub) = sm.open() {
+                        update_repo(&sub, &format!("{path}/"), on_progress, reports);
+                    }
+                }
+            }
+            Err(e) => reports.push(failed(path, format!("{e:#}"))),
+        }
+    }
+}
+
+fn update_one(
+    sm: &mut git2::Submodule,
+    path: &str,
+    on_progress: &mut dyn FnMut(&str, FetchProgress),
+) -> Result<()> {
+    // Report whole percentages only; libgit2 calls back per object
+    let mut last_pct = None;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|p| {
+        let pct = (p.received_objects() * 100).checked_div(p.total_objects());
+        if pct != last_pct {
+            last_pct = pct;
+            on_progress(
+                path,
+                FetchProgress {
+                    received_objects: p.received_objects(),
+                    total_objects: p.total_objects(),
+                    received_bytes: p.received_bytes(),
+                },
+            );
+        }
+        true
+    });
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(callbacks);
+    let mut opts = SubmoduleUpdateOptions::new();
+    opts.fetch(fetch);
+    sm.update(true, Some(&mut opts)).context("update submodule")?;
+    Ok(())
+}
+
+fn failed(path: String, error: String) -> SubmoduleReport {
+    SubmoduleReport { path, ok: false, error: Some(error) }
+}
+
+fn update_with_cli(repo: &Repository, rel: &str) -> Result<()> {
+    let workdir = repo.workdir().context("bare repository")?;
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(workdir)
+        .args(["submodule", "update", "--init", "--recursive", "--"])
+        .arg(rel)
+        .output()
+        .context("run git submodule update")?;
+    if !out.status.success() {
+        bail!("git submodule update: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
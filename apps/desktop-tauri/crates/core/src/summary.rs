@@ -0,0 +1,135 @@
+//! What a session did, recorded when it exits.
+//!
+//! The summary of a finished session gives its exit code, how long it ran,
+//! how much output it wrote and, when it started in a git checkout, what
+//! changed there: the files that differ from the commit checked out at
+//! spawn, committed or not, and the commits made on top of it. Changes
+//! already uncommitted at spawn count as touched too.
+//!
+//! The summary goes out with `pty://exit` and is kept with the session's
+//! usage record in `usage.jsonl`, which `session_history` reads back.
+
+use crate::usage::UsageRecord;
+use anyhow::Result;
+use git2::{DiffOptions, Oid, Repository};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, path::Path};
+
+/// Files listed per summary; `file_count` has the rest.
+const MAX_FILES: usize = 200;
+
+/// Commits listed per summary; `commit_count` has the rest.
+const MAX_COMMITS: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    pub exit_code: u32,
+    pub duration_ms: u64,
+    /// Bytes read from the terminal, before redaction.
+    pub output_bytes: u64,
+    /// `None` if the session didn't start in a git checkout.
+    pub changes: Option<SessionChanges>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionChanges {
+    /// Paths relative to the checkout, up to `MAX_FILES`.
+    pub files: Vec<String>,
+    pub file_count: usize,
+    /// Newest first, up to `MAX_COMMITS`.
+    pub commits: Vec<SessionCommit>,
+    pub commit_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionCommit {
+    pub id: String,
+    pub summary: String,
+}
+
+/// Which finished sessions `history` returns; unset fields match all.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryFilter {
+    pub agent_id: Option<String>,
+    pub task_id: Option<String>,
+    pub worktree: Option<String>,
+    /// Only sessions that exited 0 (`true`) or didn't (`false`).
+    pub succeeded: Option<bool>,
+    /// Started at or after, Unix millis.
+    pub since: Option<u64>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &UsageRecord) -> bool {
+        let exit_code = record.summary.as_ref().map(|s| s.exit_code);
+        self.agent_id.as_ref().map_or(true, |a| *a == record.agent_id)
+            && self.task_id.as_ref().map_or(true, |t| record.task_id.as_ref() == Some(t))
+            && self.worktree.as_ref().map_or(true, |w| record.worktree.as_ref() == Some(w))
+            && self.succeeded.map_or(true, |ok| exit_code.is_some_and(|code| (code == 0) == ok))
+            && self.since.map_or(true, |since| record.started_at >= since)
+    }
+}
+
+/// Summarize a session that exited with `exit_code`. `baseline` is its
+/// cwd and the commit checked out there at spawn.
+pub fn summarize(
+    exit_code: u32,
+    duration_ms: u64,
+    output_bytes: u64,
+    baseline: Option<(&Path, &str)>,
+) -> SessionSummary {
+    let changes = baseline.and_then(|(cwd, base)| {
+        changes(cwd, base)
+            .map_err(|e| log::debug!("session changes in {}: {e}", cwd.display()))
+            .ok()
+    });
+    SessionSummary { exit_code, duration_ms, output_bytes, changes }
+}
+
+/// Finished sessions in `records` matching `filter`, newest first, at most
+/// `limit`.
+pub fn history(
+    records: impl IntoIterator<Item = UsageRecord>,
+    limit: usize,
+    filter: &HistoryFilter,
+) -> Vec<UsageRecord> {
+    let mut finished: Vec<UsageRecord> =
+        records.into_iter().filter(|r| r.ended_at.is_some() && filter.matches(r)).collect();
+    finished.sort_by_key(|r| Reverse(r.ended_at));
+    finished.truncate(limit);
+    finished
+}
+
+fn changes(cwd: &Path, base: &str) -> Result<SessionChanges> {
+    let repo = Repository::discover(cwd)?;
+    let base = repo.find_commit(Oid::from_str(base)?)?;
+    let mut opts = DiffOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&base.tree()?), Some(&mut opts))?;
+    let mut files: Vec<String> = diff
+        .deltas()
+        .filter_map(|d| d.new_file().path().or_else(|| d.old_file().path()))
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    let file_count = files.len();
+    files.truncate(MAX_FILES);
+
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.hide(base.id())?;
+    let mut commits = Vec::new();
+    let mut commit_count = 0;
+    for oid in walk {
+        commit_count += 1;
+        if commits.len() < MAX_COMMITS {
+            let commit = repo.find_commit(oid?)?;
+            let summary = commit.summary().unwrap_or_default().to_string();
+            commits.push(SessionCommit { id: commit.id().to_string(), summary });
+        }
+    }
+    Ok(SessionChanges { files, file_count, commits, commit_count })
+}
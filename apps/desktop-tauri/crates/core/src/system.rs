@@ -0,0 +1,234 @@
+//! Host metrics: CPU, memory, load and power.
+//!
+//! A monitor samples the host every few seconds and emits `system://metrics`
+//! with the sample. On laptops it includes the battery: whether the host
+//! runs on it, its charge and whether it is charging. The `system` setting
+//! can hold the task queue while on battery or while the 1-minute load per
+//! CPU is above a threshold; running tasks carry on, queued ones wait until
+//! the condition clears. The reason tasks are held is in the event as
+//! `tasksHeld`.
+//!
+//! A hold for load lifts once the load drops below 90% of the threshold, so
+//! a load hovering around it doesn't start and stop the queue every sample.
+//! Windows has no load average; CPU usage stands in for it there.
+//!
+//! Battery state comes from `/sys/class/power_supply` on Linux, `pmset` on
+//! macOS and `GetSystemPowerStatus` on Windows.
+
+use crate::{events::SharedSink, settings::Settings, tasks::Scheduler};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use sysinfo::System;
+
+/// How often the host is sampled.
+const METRICS_POLL: Duration = Duration::from_secs(5);
+
+/// Share of the load threshold the load must drop under to lift a hold.
+const LOAD_RELEASE: f64 = 0.9;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemPolicy {
+    /// Hold queued tasks while the host runs on battery.
+    pub pause_on_battery: bool,
+    /// Hold queued tasks while the 1-minute load average per CPU is above
+    /// this, e.g. `1.5`. `None` for no limit.
+    pub max_load_per_cpu: Option<f64>,
+}
+
+impl SystemPolicy {
+    /// Why queued tasks should wait, given a sample and whether they are
+    /// held already; `None` if they can start.
+    pub fn hold_reason(&self, metrics: &SystemMetrics, held: bool) -> Option<String> {
+        if self.pause_on_battery && metrics.power.as_ref().is_some_and(|p| p.on_battery) {
+            return Some("running on battery".into());
+        }
+        let max = self.max_load_per_cpu.filter(|m| *m > 0.0)?;
+        let limit = if held { max * LOAD_RELEASE } else { max };
+        let load = metrics.load_per_cpu();
+        (load > limit).then(|| format!("load {load:.2} per CPU is above {max:.2}"))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Power {
+    pub on_battery: bool,
+    /// Charge, 0–100, when known.
+    pub percent: Option<u8>,
+    pub charging: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemMetrics {
+    /// Across all CPUs, 0–100.
+    pub cpu_percent: f32,
+    pub cpus: usize,
+    pub memory_used: u64,
+    pub memory_total: u64,
+    /// 1, 5 and 15-minute load averages; zero where unsupported.
+    pub load: [f64; 3],
+    /// `None` without a battery.
+    pub power: Option<Power>,
+    /// Why the task queue is held, if it is.
+    pub tasks_held: Option<String>,
+}
+
+impl SystemMetrics {
+    /// The 1-minute load average per CPU, or CPU usage as a fraction where
+    /// there's no load average.
+    pub fn load_per_cpu(&self) -> f64 {
+        if cfg!(windows) {
+            return f64::from(self.cpu_percent) / 100.0;
+        }
+        self.load[0] / self.cpus.max(1) as f64
+    }
+}
+
+/// Takes samples; CPU usage is measured between consecutive ones.
+pub struct Sampler {
+    sys: System,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        let mut sys = System::new();
+        sys.refresh_cpu_usage();
+        Self { sys }
+    }
+}
+
+impl Sampler {
+    pub fn sample(&mut self) -> SystemMetrics {
+        self.sys.refresh_cpu_usage();
+        self.sys.refresh_memory();
+        let load = System::load_average();
+        SystemMetrics {
+            cpu_percent: self.sys.global_cpu_usage(),
+            cpus: self.sys.cpus().len(),
+            memory_used: self.sys.used_memory(),
+            memory_total: self.sys.total_memory(),
+            load: [load.one, load.five, load.fifteen],
+            power: power(),
+            tasks_held: None,
+        }
+    }
+}
+
+/// The last sample taken by the monitor.
+#[derive(Clone, Default)]
+pub struct SystemMonitor {
+    latest: Arc<Mutex<Option<SystemMetrics>>>,
+}
+
+impl SystemMonitor {
+    pub fn latest(&self) -> Option<SystemMetrics> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// Sample the host every few seconds, hold or release `tasks` by the
+/// `system` setting and emit `system://metrics`.
+pub fn spawn_monitor(
+    settings: Arc<Mutex<Settings>>,
+    tasks: Scheduler,
+    events: SharedSink,
+) -> SystemMonitor {
+    let monitor = SystemMonitor::default();
+    let latest = monitor.latest.clone();
+    thread::spawn(move || {
+        let mut sampler = Sampler::default();
+        loop {
+            thread::sleep(METRICS_POLL);
+            let mut metrics = sampler.sample();
+            let policy = settings.lock().unwrap().system.clone();
+            metrics.tasks_held = policy.hold_reason(&metrics, tasks.held().is_some());
+            tasks.hold(metrics.tasks_held.clone());
+            events.emit("system://metrics", serde_json::to_value(&metrics).unwrap_or_default());
+            *latest.lock().unwrap() = Some(metrics);
+        }
+    });
+    monitor
+}
+
+#[cfg(target_os = "linux")]
+fn power() -> Option<Power> {
+    use std::fs;
+
+    let mut battery = None;
+    let mut mains = None;
+    for entry in fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let dir = entry.path();
+        let read =
+            |file: &str| fs::read_to_string(dir.join(file)).ok().map(|s| s.trim().to_string());
+        match read("type").as_deref() {
+            // Peripherals like mice report a `Device` scope
+            Some("Battery") if read("scope").as_deref() != Some("Device") => {
+                battery.get_or_insert((read("status"), read("capacity")));
+            }
+            Some("Mains") => {
+                let online = read("online").as_deref() == Some("1");
+                mains = Some(mains.unwrap_or(false) || online);
+            }
+            _ => {}
+        }
+    }
+    let (status, capacity) = battery?;
+    let status = status.unwrap_or_default();
+    Some(Power {
+        on_battery: mains.map_or(status == "Discharging", |online| !online),
+        percent: capacity.and_then(|c| c.parse().ok()),
+        charging: status == "Charging",
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn power() -> Option<Power> {
+    // Now drawing from 'Battery Power'
+    //  -InternalBattery-0 (id=1234567)	85%; discharging; 3:12 remaining present: true
+    let out = std::process::Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    let line = text.lines().find(|l| l.contains("InternalBattery"))?;
+    let percent =
+        line.split_whitespace().find_map(|w| w.strip_suffix("%;")).and_then(|p| p.parse().ok());
+    Some(Power {
+        on_battery: text.contains("'Battery Power'"),
+        percent,
+        charging: line.contains("; charging;"),
+    })
+}
+
+#[cfg(windows)]
+fn power() -> Option<Power> {
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    /// `BatteryFlag` bits.
+    const CHARGING: u8 = 8;
+    const NO_BATTERY: u8 = 128;
+    const UNKNOWN: u8 = 255;
+
+    // SAFETY: plain-data struct, filled in by the call
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    // SAFETY: `status` is a valid out pointer
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    if status.BatteryFlag == UNKNOWN || status.BatteryFlag & NO_BATTERY != 0 {
+        return None;
+    }
+    Some(Power {
+        on_battery: status.ACLineStatus == 0,
+        percent: (status.BatteryLifePercent <= 100).then_some(status.BatteryLifePercent),
+        charging: status.BatteryFlag & CHARGING != 0,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn power() -> Option<Power> {
+    None
+}
@@ -0,0 +1,151 @@
+//! Where a PTY session runs.
+//!
+//! `native` runs the command directly. `wsl` / `wsl:<distro>` runs it inside
+//! a WSL distribution via `wsl.exe`, with the cwd translated from a Windows
+//! path (`C:\src\app`, `\\wsl$\Ubuntu\home\me`) to its Linux equivalent.
+//! Env vars are forwarded through `WSLENV`, which WSL otherwise drops.
+
+use crate::error::PiError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SpawnTarget {
+    #[default]
+    Native,
+    /// `None` uses the default distribution.
+    Wsl { distro: Option<String> },
+}
+
+impl FromStr for SpawnTarget {
+    type Err = PiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "native" => Ok(Self::Native),
+            "wsl" => Ok(Self::Wsl { distro: None }),
+            _ => match s.strip_prefix("wsl:") {
+                Some(distro) if !distro.is_empty() => {
+                    Ok(Self::Wsl { distro: Some(distro.to_string()) })
+                }
+                _ => Err(PiError::invalid_input(format!("unknown spawn target: {s}"))),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for SpawnTarget {
+    type Error = PiError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SpawnTarget> for String {
+    fn from(t: SpawnTarget) -> Self {
+        t.to_string()
+    }
+}
+
+impl fmt::Display for SpawnTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Native => f.write_str("native"),
+            Self::Wsl { distro: None } => f.write_str("wsl"),
+            Self::Wsl { distro: Some(d) } => write!(f, "wsl:{d}"),
+        }
+    }
+}
+
+/// A resolved command line for the target: argv, the cwd for the host
+/// process, extra env entries, and a command to run when the session is
+/// killed.
+pub struct Launch {
+    pub cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub cleanup: Option<Vec<String>>,
+}
+
+impl SpawnTarget {
+    /// Rewrite `cmd`/`cwd` to run on this target. An empty `cmd` stays empty
+    /// for native (default shell) and starts the distro's login shell for
+    /// WSL.
+    pub fn launch(
+        &self,
+        cmd: Vec<String>,
+        cwd: Option<String>,
+        env: &[(String, String)],
+    ) -> Result<Launch> {
+        let Self::Wsl { distro } = self else {
+            return Ok(Launch { cmd, cwd, env: Vec::new(), cleanup: None });
+        };
+        if !cfg!(windows) {
+            return Err(PiError::invalid_input("WSL targets are only available on Windows")
+                .into());
+        }
+
+        let mut argv = vec!["wsl.exe".to_string()];
+        if let Some(distro) = distro {
+            argv.extend(["-d".to_string(), distro.clone()]);
+        }
+        if let Some(dir) = &cwd {
+            argv.extend(["--cd".to_string(), to_wsl_path(dir)]);
+        }
+        if !cmd.is_empty() {
+            argv.push("--".into());
+            argv.extend(cmd);
+        }
+
+        let mut extra = Vec::new();
+        if !env.is_empty() {
+            let mut keys: Vec<String> = env.iter().map(|(k, _)| format!("{k}/u")).collect();
+            if let Ok(existing) = std::env::var("WSLENV") {
+                keys.insert(0, existing);
+            }
+            extra.push(("WSLENV".to_string(), keys.join(":")));
+        }
+        Ok(Launch { cmd: argv, cwd: None, env: extra, cleanup: None })
+    }
+}
+
+/// Translate a Windows path to the path WSL sees. Drive paths map under
+/// `/mnt/<drive>`; `\\wsl$\<distro>\...` and `\\wsl.localhost\<distro>\...`
+/// map to the distro's own root. Anything else is returned unchanged.
+pub fn to_wsl_path(path: &str) -> String {
+    let unified = path.replace('\\', "/");
+    for prefix in ["//wsl$/", "//wsl.localhost/"] {
+        if let Some(rest) = unified.strip_prefix(prefix) {
+            return match rest.split_once('/') {
+                Some((_distro, inner)) => format!("/{inner}"),
+                None => "/".into(),
+            };
+        }
+    }
+    let bytes = unified.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        let drive = (bytes[0] as char).to_ascii_lowercase();
+        let rest = unified[2..].trim_start_matches('/');
+        return if rest.is_empty() {
+            format!("/mnt/{drive}")
+        } else {
+            format!("/mnt/{drive}/{rest}")
+        };
+    }
+    unified
+}
+
+/// Translate a WSL path back to a Windows path reachable from the host.
+pub fn to_windows_path(path: &str, distro: &str) -> String {
+    if let Some(rest) = path.strip_prefix("/mnt/") {
+        let mut parts = rest.splitn(2, '/');
+        if let Some(drive) = parts.next().filter(|d| d.len() == 1) {
+            let inner = parts.next().unwrap_or_default().replace('/', "\\");
+            return format!("{}:\\{inner}", drive.to_ascii_uppercase());
+        }
+    }
+    format!(r"\\wsl.localhost\{distro}{}", path.replace('/', "\\"))
+}
@@ -0,0 +1,415 @@
+//! Task queue and scheduler.
+//!
+//! A task is "run this agent with this prompt against this repo". The
+//! scheduler keeps at most `max_concurrent_tasks` preparing or running;
+//! each gets a fresh worktree and PTY session, and is marked
+//! finished/failed from the session's exit code. Changes are emitted as
+//! `task://changed` events.
+//!
+//! The queue can be held (see `system.rs`): while it is, queued tasks wait
+//! and running ones carry on.
+
+use crate::{
+    agents::{self, PromptMode},
+    error::PiError,
+    events::SharedSink,
+    hooks::{self, HookPoint},
+    pty::{PtyManager, SessionLimits, SpawnOptions},
+    repo_config::RepoConfig,
+    settings::Settings,
+    lfs, submodules,
+    worktree::{self, BranchVars, WorktreeInfo},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaskSpec {
+    /// Agent profile id (see `agents`). Defaults to the repo's
+    /// `default_agent` (`.pi-builder.toml`).
+    #[serde(default)]
+    pub agent: String,
+    pub prompt: Option<String>,
+    /// Overrides the profile command when non-empty.
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    /// Target repo; defaults to the current repo.
+    pub repo: Option<String>,
+    /// Runtime and idle bounds for the task's session.
+    #[serde(default, flatten)]
+    pub limits: SessionLimits,
+    /// Paths to check out in the task's worktree; defaults to the repo's
+    /// `sparse`, and empty checks out everything. See `sparse.rs`.
+    #[serde(default)]
+    pub sparse: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Queued,
+    /// Getting its worktree ready: created, submodules checked out, LFS
+    /// files pulled.
+    Preparing,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Task {
+    pub id: String,
+    pub agent: String,
+    pub prompt: Option<String>,
+    pub cmd: Vec<String>,
+    pub repo: String,
+    #[serde(flatten)]
+    pub limits: SessionLimits,
+    pub sparse: Option<Vec<String>>,
+    pub status: TaskStatus,
+    pub session_id: Option<String>,
+    pub worktree: Option<String>,
+    pub exit_code: Option<u32>,
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+/// A task's worktree, ready for its agent to be spawned in.
+struct Prepared {
+    wt: WorktreeInfo,
+    opts: SpawnOptions,
+    /// Typed into the agent once it is up, for stdin-prompted profiles.
+    stdin_prompt: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Scheduler
+// ---------------------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct Scheduler {
+    tasks: Arc<Mutex<Vec<Task>>>,
+    pty: Arc<Mutex<PtyManager>>,
+    settings: Arc<Mutex<Settings>>,
+    events: SharedSink,
+    stopped: Arc<AtomicBool>,
+    /// Why queued tasks are held back, if they are.
+    held: Arc<Mutex<Option<String>>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        pty: Arc<Mutex<PtyManager>>,
+        settings: Arc<Mutex<Settings>>,
+        events: SharedSink,
+    ) -> Self {
+        Self {
+            tasks: Arc::default(),
+            pty,
+            settings,
+            events,
+            stopped: Arc::default(),
+            held: Arc::default(),
+        }
+    }
+
+    /// Stop launching queued tasks (used at shutdown).
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Hold queued tasks for `reason`, or with `None` let them start again.
+    pub fn hold(&self, reason: Option<String>) {
+        let released = {
+            let mut held = self.held.lock().unwrap();
+            let released = held.is_some() && reason.is_none();
+            if *held != reason {
+                match &reason {
+                    Some(reason) => log::info!("task queue held: {reason}"),
+                    None => log::info!("task queue released"),
+                }
+            }
+            *held = reason;
+            released
+        };
+        if released {
+            self.pump();
+        }
+    }
+
+    pub fn held(&self) -> Option<String> {
+        self.held.lock().unwrap().clone()
+    }
+
+    pub fn enqueue(&self, spec: TaskSpec, repo: String) -> Task {
+        let task = Task {
+            id: Uuid::new_v4().to_string(),
+            agent: spec.agent,
+            prompt: spec.prompt,
+            cmd: spec.cmd,
+            repo,
+            limits: spec.limits,
+            sparse: spec.sparse,
+            status: TaskStatus::Queued,
+            session_id: None,
+            worktree: None,
+            exit_code: None,
+            error: None,
+            created_at: now_ms(),
+            started_at: None,
+            finished_at: None,
+        };
+        self.tasks.lock().unwrap().push(task.clone());
+        self.changed(&task);
+        self.pump();
+        task
+    }
+
+    pub fn list(&self) -> Vec<Task> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    /// Cancel a queued or preparing task, or kill a running one. Finished
+    /// tasks are left untouched.
+    pub fn cancel(&self, task_id: &str) -> Result<()> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let task = tasks
+            .iter_mut()
+            .find(|t| t.id == task_id)
+            .ok_or_else(|| PiError::task_not_found(task_id))?;
+        match task.status {
+            TaskStatus::Queued | TaskStatus::Preparing => {}
+            TaskStatus::Running => {
+                if let Some(sid) = &task.session_id {
+                    self.pty.lock().unwrap().kill(sid);
+                }
+            }
+            _ => return Ok(()),
+        }
+        task.status = TaskStatus::Cancelled;
+        task.finished_at = Some(now_ms());
+        let task = task.clone();
+        drop(tasks);
+        self.changed(&task);
+        self.pump();
+        Ok(())
+    }
+
+    /// Start queued tasks until the concurrency limit is reached.
+    ///
+    /// A task's worktree is prepared, and its pre-spawn hooks run, with the
+    /// task list unlocked, as checking out submodules, pulling LFS files or
+    /// waiting on a hook can take a while. The list is locked again across
+    /// the spawn so an instantly exiting child can't mark the task finished
+    /// before it is marked running.
+    pub fn pump(&self) {
+        if self.stopped.load(Ordering::SeqCst) || self.held.lock().unwrap().is_some() {
+            return;
+        }
+        let max = self.settings.lock().unwrap().max_concurrent_tasks.max(1);
+        loop {
+            let queued = {
+                let mut tasks = self.tasks.lock().unwrap();
+                let busy = tasks
+                    .iter()
+                    .filter(|t| matches!(t.status, TaskStatus::Preparing | TaskStatus::Running))
+                    .count();
+                if busy >= max {
+                    break;
+                }
+                let Some(task) = tasks.iter_mut().find(|t| t.status == TaskStatus::Queued) else {
+                    break;
+                };
+                task.status = TaskStatus::Preparing;
+                self.changed(task);
+                task.clone()
+            };
+            let prepared = self.prepare(&queued);
+
+            let mut tasks = self.tasks.lock().unwrap();
+            let Some(task) = tasks.iter_mut().find(|t| t.id == queued.id) else { continue };
+            if task.status != TaskStatus::Preparing {
+                // Cancelled while it was being prepared.
+                drop(tasks);
+                if let Ok(prepared) = prepared {
+                    discard(&queued, &prepared.wt);
+                }
+                continue;
+            }
+            match prepared.and_then(|prepared| self.launch(task, prepared)) {
+                Ok(()) => {
+                    task.status = TaskStatus::Running;
+                    task.started_at = Some(now_ms());
+                }
+                Err(e) => {
+                    task.status = TaskStatus::Failed;
+                    task.error = Some(e.to_string());
+                    task.finished_at = Some(now_ms());
+                }
+            }
+            self.changed(task);
+        }
+    }
+
+    /// Create `task`'s worktree and get it ready: hooks run, submodules
+    /// checked out, LFS files pulled, spawn options built and passed through
+    /// the pre-spawn hooks. Runs with the task list unlocked.
+    fn prepare(&self, task: &Task) -> Result<Prepared> {
+        let (profile, root, template, size, hooks) = {
+            let settings = self.settings.lock().unwrap();
+            let profile = agents::resolve(&task.agent, &settings.agent_profiles)
+                .with_context(|| format!("unknown agent profile: {}", task.agent))?;
+            let root = settings.worktree_root.clone();
+            let hooks = settings.hooks.clone();
+            (profile, root, settings.branch_template.clone(), settings.terminal, hooks)
+        };
+        let config = RepoConfig::load_or_default(Path::new(&task.repo));
+        let template = config.branch_template.or(template);
+        let sparse = task.sparse.clone().unwrap_or(config.sparse);
+
+        let vars = BranchVars { agent: &profile.id, slug: task.prompt.as_deref() };
+        let wt = worktree::create_worktree(
+            &task.repo,
+            &task.id,
+            root.as_deref(),
+            template.as_deref(),
+            &vars,
+            None,
+            &sparse,
+        )?;
+        let input = serde_json::json!({
+            "repo": task.repo,
+            "worktree": wt.name,
+            "path": wt.path,
+            "branch": wt.branch,
+        });
+        if let Err(e) = hooks::run(&hooks, HookPoint::PostWorktreeCreate, input) {
+            discard(task, &wt);
+            return Err(e);
+        }
+        let wt_path = Path::new(&wt.path);
+        if submodules::has_submodules(wt_path) {
+            for failed in submodules::update_with_events(wt_path, &wt.name, &self.events)
+                .into_iter()
+                .filter(|r| !r.ok)
+            {
+                log::warn!("task {}: submodule {}: {:?}", task.id, failed.path, failed.error);
+            }
+        }
+        if lfs::uses_lfs(wt_path) {
+            if let Some(error) = lfs::pull(wt_path).error {
+                log::warn!("task {}: {error}", task.id);
+            }
+        }
+
+        let argv = if task.cmd.is_empty() {
+            profile.argv(task.prompt.as_deref())
+        } else {
+            task.cmd.clone()
+        };
+        let mut opts = SpawnOptions::new(profile.id.clone(), argv);
+        opts.cwd = Some(wt.path.clone());
+        opts.cols = size.cols;
+        opts.rows = size.rows;
+        opts.env = profile.env_pairs();
+        opts.ssh = profile.ssh.clone();
+        opts.usage_patterns = profile.usage_patterns();
+        opts.task_id = Some(task.id.clone());
+        opts.repo = Some(task.repo.clone());
+        opts.limits = task.limits;
+        let scheduler = self.clone();
+        let task_id = task.id.clone();
+        opts.on_exit = Some(Box::new(move |code| scheduler.finish(&task_id, code)));
+        if let Err(e) = hooks::pre_spawn(&hooks, &mut opts, Some(&wt.name)) {
+            discard(task, &wt);
+            return Err(e);
+        }
+        let stdin_prompt = match profile.prompt_mode {
+            PromptMode::Stdin => task.prompt.clone(),
+            PromptMode::Arg => None,
+        };
+        Ok(Prepared { wt, opts, stdin_prompt })
+    }
+
+    /// Spawn `task`'s agent in its prepared worktree.
+    fn launch(&self, task: &mut Task, prepared: Prepared) -> Result<()> {
+        let Prepared { wt, opts, stdin_prompt } = prepared;
+        let mut pty = self.pty.lock().unwrap();
+        let session_id = match pty.spawn(opts, self.events.clone()) {
+            Ok(session_id) => session_id,
+            Err(e) => {
+                drop(pty);
+                discard(task, &wt);
+                return Err(e);
+            }
+        };
+        if let Some(prompt) = stdin_prompt {
+            if let Err(e) = pty.write(&session_id, &format!("{prompt}\r")) {
+                // An agent that never got its prompt would idle forever.
+                pty.kill(&session_id);
+                drop(pty);
+                discard(task, &wt);
+                return Err(e.context("write the prompt to the agent"));
+            }
+        }
+
+        task.session_id = Some(session_id);
+        task.worktree = Some(wt.name);
+        Ok(())
+    }
+
+    fn finish(&self, task_id: &str, exit_code: u32) {
+        let task = {
+            let mut tasks = self.tasks.lock().unwrap();
+            let Some(task) = tasks.iter_mut().find(|t| t.id == task_id) else { return };
+            if task.status != TaskStatus::Running {
+                return;
+            }
+            task.status = if exit_code == 0 { TaskStatus::Finished } else { TaskStatus::Failed };
+            task.exit_code = Some(exit_code);
+            task.finished_at = Some(now_ms());
+            task.clone()
+        };
+        self.changed(&task);
+        self.pump();
+    }
+
+    fn changed(&self, task: &Task) {
+        self.events.emit(
+            "task://changed",
+            serde_json::to_value(task).unwrap_or_default(),
+        );
+    }
+}
+
+/// Remove the worktree created for `task`, which won't run in it: a hook
+/// refused it, the agent couldn't be spawned or given its prompt, or the
+/// task was cancelled meanwhile.
+fn discard(task: &Task, wt: &WorktreeInfo) {
+    if let Err(e) = worktree::remove_worktree(&task.repo, &wt.name) {
+        log::warn!("task {}: remove unused worktree: {e:#}", task.id);
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
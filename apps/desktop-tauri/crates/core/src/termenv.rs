@@ -0,0 +1,118 @@
+//! Terminal environment for spawned sessions.
+//!
+//! The app is often started from a desktop launcher, whose environment has
+//! no `TERM` at all, or from another terminal, whose `TERM` describes that
+//! terminal rather than ours. Either way full-screen programs fall back to
+//! a dumb terminal or draw for the wrong one. New sessions get `TERM`,
+//! `COLORTERM` and `TERM_PROGRAM` from the `terminal_env` setting instead;
+//! env given to the spawn still wins.
+//!
+//! Minimal systems (containers, Alpine, stripped-down servers) often ship
+//! only a few terminfo entries. For local sessions `TERM` falls back to the
+//! first of `xterm-256color`, `xterm` and `vt100` the terminfo database has,
+//! so programs don't refuse to start on an unknown terminal. Sessions in a
+//! container or over SSH get the configured `TERM` as is; their terminfo
+//! isn't ours to check.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Tried in order when the configured `TERM` has no terminfo entry.
+const FALLBACK_TERMS: &[&str] = &["xterm-256color", "xterm", "vt100"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TermEnv {
+    pub term: String,
+    /// Empty leaves `COLORTERM` unset.
+    pub colorterm: String,
+    /// Empty leaves `TERM_PROGRAM` and `TERM_PROGRAM_VERSION` unset.
+    pub term_program: String,
+    /// Fall back to a `TERM` the local terminfo database has.
+    pub check_terminfo: bool,
+}
+
+impl Default for TermEnv {
+    fn default() -> Self {
+        Self {
+            term: "xterm-256color".into(),
+            colorterm: "truecolor".into(),
+            term_program: "pi-builder".into(),
+            check_terminfo: true,
+        }
+    }
+}
+
+impl TermEnv {
+    /// Variables to set for a session; `local` when it runs on this host
+    /// outside a container, so the terminfo check applies.
+    pub fn vars(&self, local: bool) -> Vec<(String, String)> {
+        let mut vars = Vec::new();
+        let term = self.term.trim();
+        if !term.is_empty() {
+            let term = if local && self.check_terminfo { usable_term(term) } else { term.into() };
+            vars.push(("TERM".into(), term));
+        }
+        if !self.colorterm.trim().is_empty() {
+            vars.push(("COLORTERM".into(), self.colorterm.trim().into()));
+        }
+        if !self.term_program.trim().is_empty() {
+            vars.push(("TERM_PROGRAM".into(), self.term_program.trim().into()));
+            vars.push(("TERM_PROGRAM_VERSION".into(), env!("CARGO_PKG_VERSION").into()));
+        }
+        vars
+    }
+}
+
+/// `term`, or the first fallback with a terminfo entry when it has none.
+/// Kept as is without a terminfo database to look in, as programs with a
+/// built-in description still work then.
+pub fn usable_term(term: &str) -> String {
+    let dirs = terminfo_dirs();
+    if cfg!(windows) || dirs.is_empty() || has_entry(&dirs, term) {
+        return term.to_string();
+    }
+    match FALLBACK_TERMS.iter().find(|t| has_entry(&dirs, t)) {
+        Some(fallback) => {
+            log::warn!("no terminfo entry for {term}; sessions use TERM={fallback}");
+            fallback.to_string()
+        }
+        None => term.to_string(),
+    }
+}
+
+/// Whether one of `dirs` has an entry for `term`, filed under its first
+/// letter (`x/xterm`) or, as on macOS, that letter's hex code (`78/xterm`).
+pub fn has_entry(dirs: &[PathBuf], term: &str) -> bool {
+    let Some(first) = term.chars().next() else { return false };
+    dirs.iter().any(|dir| {
+        dir.join(first.to_string()).join(term).is_file()
+            || dir.join(format!("{:x}", first as u32)).join(term).is_file()
+    })
+}
+
+/// Existing terminfo directories, searched the way ncurses does.
+pub fn terminfo_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    if let Some(list) = std::env::var_os("TERMINFO_DIRS") {
+        dirs.extend(std::env::split_paths(&list).filter(|d| !d.as_os_str().is_empty()));
+    }
+    for dir in [
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+        "/usr/share/lib/terminfo",
+        "/usr/local/share/terminfo",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs.retain(|d| d.is_dir());
+    dirs
+}
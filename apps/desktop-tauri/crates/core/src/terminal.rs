@@ -0,0 +1,113 @@
+//! Terminal profiles: named defaults for new sessions.
+//!
+//! A profile sets what a session starts with when `pty_spawn` names it:
+//! size, how much scrollback the backend keeps for search and export,
+//! whether the session is recorded, env added to the agent's, where it
+//! starts, and whether it runs in a PTY of its own or as a window of the
+//! shared tmux session (see `detach.rs`). Font and color fields are hints
+//! the frontend applies to the session's xterm; the backend only stores
+//! them. Profiles live in settings. Anything a spawn gives explicitly wins
+//! over its profile, and a session in a worktree always starts there.
+
+use crate::{
+    detach::{self, SessionBackend},
+    error::PiError,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Most scrollback lines a profile may ask for.
+pub const MAX_SCROLLBACK_LINES: usize = 1_000_000;
+
+/// Where a session starts when it isn't in a worktree.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CwdPolicy {
+    /// The cwd the spawn gives, else the repo.
+    #[default]
+    Inherit,
+    /// The repo's main checkout, whatever cwd the spawn gives.
+    Repo,
+    /// The user's home directory.
+    Home,
+    /// This directory.
+    Path(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalProfile {
+    pub name: String,
+    pub cols: Option<u16>,
+    pub rows: Option<u16>,
+    /// Lines of scrollback kept in memory; `None` keeps `scrollback::MAX_LINES`.
+    #[serde(default)]
+    pub scrollback_lines: Option<usize>,
+    /// Record sessions (see `recording.rs`); `None` follows the log
+    /// settings.
+    #[serde(default)]
+    pub record: Option<bool>,
+    /// Added to the session's env; the agent profile's env wins.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: CwdPolicy,
+    #[serde(default)]
+    pub backend: SessionBackend,
+    #[serde(default)]
+    pub font_family: Option<String>,
+    #[serde(default)]
+    pub font_size: Option<f32>,
+    /// Color theme name, as the frontend knows it.
+    #[serde(default)]
+    pub theme: Option<String>,
+}
+
+impl TerminalProfile {
+    pub fn env_pairs(&self) -> Vec<(String, String)> {
+        self.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// The directory to start in, given the spawn's `cwd` and the repo.
+    pub fn cwd(&self, cwd: Option<String>, repo: Option<String>) -> Option<String> {
+        match &self.cwd {
+            CwdPolicy::Inherit => cwd.or(repo),
+            CwdPolicy::Repo => repo.or(cwd),
+            CwdPolicy::Home => {
+                let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+                home.map(|h| h.to_string_lossy().into_owned()).or(cwd)
+            }
+            CwdPolicy::Path(path) => Some(path.clone()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(PiError::invalid_input("terminal profile name is empty").into());
+        }
+        if self.cols == Some(0) || self.rows == Some(0) {
+            return Err(PiError::invalid_input("terminal size must be above 0").into());
+        }
+        if self.scrollback_lines.is_some_and(|n| n > MAX_SCROLLBACK_LINES) {
+            let message = format!("scrollback is limited to {MAX_SCROLLBACK_LINES} lines");
+            return Err(PiError::invalid_input(message).into());
+        }
+        if let CwdPolicy::Path(path) = &self.cwd {
+            if !std::path::Path::new(path).is_absolute() {
+                return Err(PiError::invalid_input("profile cwd must be absolute").into());
+            }
+        }
+        if self.backend == SessionBackend::Tmux && !detach::available() {
+            return Err(PiError::invalid_input("the tmux backend needs tmux on the PATH").into());
+        }
+        Ok(())
+    }
+}
+
+pub fn resolve<'a>(name: &str, profiles: &'a [TerminalProfile]) -> Result<&'a TerminalProfile> {
+    profiles
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| PiError::invalid_input(format!("no terminal profile named {name}")).into())
+}
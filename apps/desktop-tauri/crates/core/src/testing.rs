@@ -0,0 +1,222 @@
+//! In-process integration test harness (`testing` feature, and the crate's
+//! own unit tests).
+//!
+//! Lets plugin authors and downstream crates drive the PTY and worktree APIs
+//! without launching Tauri:
+//!
+//! ```ignore
+//! let sink = RecordingSink::new();
+//! let repo = TempRepo::new()?;
+//! let mut pty = PtyManager::default();
+//! let mut opts = SpawnOptions::new("test", Script::new().print("hello").exit(0).cmd());
+//! opts.cwd = Some(repo.path_str());
+//! let id = pty.spawn(opts, sink.clone())?;
+//! sink.wait_for(&format!("pty://exit/{id}"), Duration::from_secs(5)).unwrap();
+//! assert!(sink.output(&id).contains("hello"));
+//! ```
+
+use crate::events::EventSink;
+use anyhow::{Context, Result};
+use base64::Engine;
+use git2::{Repository, Signature};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+// ---------------------------------------------------------------------------
+// RecordingSink
+// ---------------------------------------------------------------------------
+
+/// Event sink that records every emitted event in order.
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<(String, Value)>>,
+    signal: Condvar,
+}
+
+impl RecordingSink {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Snapshot of all events recorded so far.
+    pub fn events(&self) -> Vec<(String, Value)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Payloads of events whose name starts with `prefix`.
+    pub fn named(&self, prefix: &str) -> Vec<Value> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+
+    /// Concatenated `pty://data` output for a session, decoded from
+    /// whichever encoding it was emitted in.
+    pub fn output(&self, session_id: &str) -> String {
+        let mut bytes = Vec::new();
+        for p in self.named(&format!("pty://data/{session_id}")) {
+            let data = p["data"].as_str().unwrap_or_default();
+            if p["encoding"] == "base64" {
+                let decoded = base64::engine::general_purpose::STANDARD.decode(data);
+                bytes.extend(decoded.unwrap_or_default());
+            } else {
+                bytes.extend_from_slice(data.as_bytes());
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Block until an event whose name starts with `prefix` arrives, or the
+    /// timeout elapses. Returns the first matching payload.
+    pub fn wait_for(&self, prefix: &str, timeout: Duration) -> Option<Value> {
+        let deadline = Instant::now() + timeout;
+        let mut events = self.events.lock().unwrap();
+        loop {
+            if let Some((_, payload)) = events.iter().find(|(name, _)| name.starts_with(prefix)) {
+                return Some(payload.clone());
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            events = self.signal.wait_timeout(events, remaining).unwrap().0;
+        }
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn emit(&self, event: &str, payload: Value) {
+        self.events.lock().unwrap().push((event.to_string(), payload));
+        self.signal.notify_all();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TempRepo
+// ---------------------------------------------------------------------------
+
+/// A throwaway git repository with one initial commit on `main`.
+/// Removed (along with its sibling `-agents` worktree dir) on drop.
+pub struct TempRepo {
+    path: PathBuf,
+    repo: Repository,
+}
+
+impl TempRepo {
+    pub fn new() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("pi-builder-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path)?;
+        let repo = Repository::init(&path).context("init repo")?;
+        repo.set_head("refs/heads/main")?;
+        let temp = Self { path, repo };
+        temp.commit_file("README.md", "test repo\n", "initial commit")?;
+        Ok(temp)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    pub fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// Write `name` with `content` and commit it on the current branch.
+    pub fn commit_file(&self, name: &str, content: &str, message: &str) -> Result<git2::Oid> {
+        let file = self.path.join(name);
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&file, content)?;
+
+        let mut index = self.repo.index()?;
+        index.add_path(Path::new(name))?;
+        index.write()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let sig = Signature::now("pi-builder test", "test@pi-builder.dev")?;
+        let parent = self.repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let oid = self.repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)?;
+        Ok(oid)
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+        let agents = crate::worktree::worktree_base_dir(&self.path_str(), None);
+        let _ = std::fs::remove_dir_all(agents);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Script
+// ---------------------------------------------------------------------------
+
+/// Builder for a scripted PTY child: prints lines, sleeps, and exits with a
+/// chosen code. `cmd()` yields an argv suitable for `PtyManager::spawn`.
+#[derive(Default)]
+pub struct Script {
+    steps: Vec<String>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn print(mut self, line: &str) -> Self {
+        if cfg!(windows) {
+            self.steps.push(format!("echo {line}"));
+        } else {
+            self.steps.push(format!("printf '%s\\n' '{}'", line.replace('\'', r"'\''")));
+        }
+        self
+    }
+
+    pub fn sleep_ms(mut self, ms: u64) -> Self {
+        if cfg!(windows) {
+            self.steps.push(format!("ping -n 1 -w {ms} 127.0.0.1 >NUL"));
+        } else {
+            self.steps.push(format!("sleep {}", ms as f64 / 1000.0));
+        }
+        self
+    }
+
+    /// Read one line of input and echo it back, for exercising `pty_input`.
+    pub fn echo_input(mut self) -> Self {
+        if cfg!(windows) {
+            self.steps.push("set /p line= & echo %line%".into());
+        } else {
+            self.steps.push("read line; printf '%s\\n' \"$line\"".into());
+        }
+        self
+    }
+
+    pub fn exit(mut self, code: i32) -> Self {
+        self.steps.push(format!("exit {code}"));
+        self
+    }
+
+    pub fn cmd(&self) -> Vec<String> {
+        if cfg!(windows) {
+            vec!["cmd.exe".into(), "/C".into(), self.steps.join(" & ")]
+        } else {
+            vec!["sh".into(), "-c".into(), self.steps.join("; ")]
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Per-task timeline of what happened, in order.
+//!
+//! What a task did is spread over many events: its status changes, its
+//! session spawning, printing, going idle and exiting, its worktree picking
+//! up changes and commits, checks run there. `TimelineSink` watches events
+//! go by and files the ones that belong to a task under it, so
+//! `task_timeline` returns them as one ordered list the UI can lay out as a
+//! Gantt-style view.
+//!
+//! Sessions belong to a task by the `taskId` of `pty://spawned` or the
+//! `session_id` of a running task; worktrees by the task's `worktree`.
+//! Timelines of the last `MAX_TASKS` tasks are kept in `timeline.jsonl` in
+//! the config dir.
+
+use crate::{
+    events::{EventSink, SharedSink},
+    summary::SessionSummary,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Tasks whose timelines are kept.
+const MAX_TASKS: usize = 200;
+
+/// Entries kept per task; past this only status changes and exits are.
+const MAX_ENTRIES_PER_TASK: usize = 1000;
+
+/// Lines the file may have before it is compacted, at the least.
+const MIN_COMPACT_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEntry {
+    pub task_id: String,
+    /// Unix millis.
+    pub at: u64,
+    /// The session it happened in; `None` for worktree changes.
+    pub session_id: Option<String>,
+    #[serde(flatten)]
+    pub event: TimelineEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TimelineEvent {
+    /// The task's status changed (queued, running, finished, ...).
+    Status { status: String },
+    /// A session started for the task.
+    Spawned {
+        #[serde(rename = "agentId")]
+        agent_id: String,
+    },
+    /// The session printed for the first time.
+    FirstOutput,
+    /// The worktree went from clean to having uncommitted changes.
+    FilesChanged,
+    /// New commits on the worktree's branch; `ahead` is all of them.
+    Commits { count: usize, ahead: usize },
+    /// A check finished in the worktree.
+    Check {
+        check: String,
+        passed: bool,
+        #[serde(rename = "exitCode")]
+        exit_code: u32,
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+    },
+    /// The session went quiet; it has been for `idleSecs` by `at`.
+    Idle {
+        #[serde(rename = "idleSecs")]
+        idle_secs: u64,
+    },
+    /// The session became active again after going idle.
+    Resumed,
+    /// The session exited.
+    Exit {
+        #[serde(rename = "exitCode")]
+        exit_code: u32,
+        summary: Option<SessionSummary>,
+    },
+}
+
+/// A task's session, as followed.
+struct TrackedSession {
+    task_id: String,
+    output_seen: bool,
+    idle: bool,
+}
+
+/// A task's worktree, with its status as last seen.
+struct TrackedWorktree {
+    task_id: String,
+    dirty: bool,
+    ahead: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Entries by task, oldest first.
+    tasks: HashMap<String, Vec<TimelineEntry>>,
+    /// Task ids, oldest first, for dropping old timelines.
+    order: VecDeque<String>,
+    /// Entries across all tasks.
+    total: usize,
+    sessions: HashMap<String, TrackedSession>,
+    /// By repo and worktree name.
+    worktrees: HashMap<(String, String), TrackedWorktree>,
+    file: Option<File>,
+    /// Lines in the file, compacted back to `total` at twice that.
+    lines: usize,
+}
+
+impl Inner {
+    /// Add `entry`; false if its task has no room for it.
+    fn push(&mut self, entry: TimelineEntry) -> bool {
+        if !self.tasks.contains_key(&entry.task_id) {
+            if self.order.len() == MAX_TASKS {
+                if let Some(old) = self.order.pop_front() {
+                    self.total -= self.tasks.remove(&old).map_or(0, |e| e.len());
+                    self.worktrees.retain(|_, w| w.task_id != old);
+                }
+            }
+            self.order.push_back(entry.task_id.clone());
+        }
+        let entries = self.tasks.entry(entry.task_id.clone()).or_default();
+        let kept = matches!(entry.event, TimelineEvent::Status { .. } | TimelineEvent::Exit { .. });
+        if entries.len() >= MAX_ENTRIES_PER_TASK && !kept {
+            return false;
+        }
+        entries.push(entry);
+        self.total += 1;
+        true
+    }
+
+    fn last_status(&self, task_id: &str) -> Option<&str> {
+        self.tasks.get(task_id)?.iter().rev().find_map(|e| match &e.event {
+            TimelineEvent::Status { status } => Some(status.as_str()),
+            _ => None,
+        })
+    }
+}
+
+pub struct Timelines {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl Timelines {
+    /// Load the timelines at `path`; unreadable lines are skipped.
+    pub fn load(path: PathBuf) -> Self {
+        let mut inner = Inner::default();
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                inner.lines += 1;
+                if let Ok(entry) = serde_json::from_str::<TimelineEntry>(&line) {
+                    inner.push(entry);
+                }
+            }
+        }
+        inner.file = open_append(&path).map_err(|e| log::warn!("task timelines: {e:#}")).ok();
+        Self { path, inner: Mutex::new(inner) }
+    }
+
+    /// Task `task_id`'s timeline, oldest first.
+    pub fn get(&self, task_id: &str) -> Vec<TimelineEntry> {
+        self.inner.lock().unwrap().tasks.get(task_id).cloned().unwrap_or_default()
+    }
+
+    /// File `event` under its task, if it has one.
+    pub fn observe(&self, event: &str, payload: &Value) {
+        if let Some((kind, session_id)) =
+            event.strip_prefix("pty://").and_then(|rest| rest.split_once('/'))
+        {
+            self.session_event(kind, session_id, payload);
+        } else if event == "task://changed" {
+            self.task_changed(payload);
+        } else if event == "worktree://status" {
+            self.worktree_status(payload);
+        } else if event == "check://result" {
+            self.check_result(payload);
+        }
+    }
+
+    fn session_event(&self, kind: &str, session_id: &str, payload: &Value) {
+        let mut inner = self.inner.lock().unwrap();
+        if kind == "spawned" {
+            let Some(task_id) = payload.get("taskId").and_then(Value::as_str) else { return };
+            let tracked =
+                TrackedSession { task_id: task_id.to_string(), output_seen: false, idle: false };
+            inner.sessions.insert(session_id.to_string(), tracked);
+            let agent_id = str_field(payload, "agentId").unwrap_or_default().to_string();
+            let event = TimelineEvent::Spawned { agent_id };
+            self.record(&mut inner, task_id.to_string(), Some(session_id), event);
+            return;
+        }
+        let Some(tracked) = inner.sessions.get_mut(session_id) else { return };
+        let event = match kind {
+            "data" if !tracked.output_seen => {
+                tracked.output_seen = true;
+                TimelineEvent::FirstOutput
+            }
+            "idle" => {
+                tracked.idle = true;
+                let idle_secs = payload.get("idleSecs").and_then(Value::as_u64).unwrap_or(0);
+                TimelineEvent::Idle { idle_secs }
+            }
+            "state" if tracked.idle && str_field(payload, "state") != Some("idle") => {
+                tracked.idle = false;
+                TimelineEvent::Resumed
+            }
+            "exit" => {
+                let exit_code = payload.get("exitCode").and_then(Value::as_u64).unwrap_or(0);
+                let summary = payload.get("summary").cloned().unwrap_or_default();
+                TimelineEvent::Exit {
+                    exit_code: exit_code as u32,
+                    summary: serde_json::from_value(summary).ok().flatten(),
+                }
+            }
+            _ => return,
+        };
+        let task_id = tracked.task_id.clone();
+        if kind == "exit" {
+            inner.sessions.remove(session_id);
+        }
+        self.record(&mut inner, task_id, Some(session_id), event);
+    }
+
+    fn task_changed(&self, task: &Value) {
+        let (Some(task_id), Some(status)) = (str_field(task, "id"), str_field(task, "status"))
+        else {
+            return;
+        };
+        let session_id = str_field(task, "session_id");
+        let mut inner = self.inner.lock().unwrap();
+        // Only while running: a finished task's session has exited already
+        if let (Some(session_id), "running") = (session_id, status) {
+            inner.sessions.entry(session_id.to_string()).or_insert_with(|| TrackedSession {
+                task_id: task_id.to_string(),
+                output_seen: false,
+                idle: false,
+            });
+        }
+        if let (Some(repo), Some(worktree)) = (str_field(task, "repo"), str_field(task, "worktree"))
+        {
+            let key = (repo.to_string(), worktree.to_string());
+            if inner.worktrees.get(&key).map_or(true, |w| w.task_id != task_id) {
+                let tracked =
+                    TrackedWorktree { task_id: task_id.to_string(), dirty: false, ahead: 0 };
+                inner.worktrees.insert(key, tracked);
+            }
+        }
+        if inner.last_status(task_id) != Some(status) {
+            let event = TimelineEvent::Status { status: status.to_string() };
+            self.record(&mut inner, task_id.to_string(), session_id, event);
+        }
+    }
+
+    fn worktree_status(&self, payload: &Value) {
+        let Some(repo) = str_field(payload, "repo") else { return };
+        let Some(list) = payload.get("worktrees").and_then(Value::as_array) else { return };
+        let mut inner = self.inner.lock().unwrap();
+        for info in list {
+            let Some(name) = str_field(info, "name") else { continue };
+            let Some(tracked) = inner.worktrees.get_mut(&(repo.to_string(), name.to_string()))
+            else {
+                continue;
+            };
+            let dirty = info.get("dirty").and_then(Value::as_bool).unwrap_or(false);
+            let ahead = info.get("ahead").and_then(Value::as_u64).unwrap_or(0) as usize;
+            let mut events = Vec::new();
+            if dirty && !tracked.dirty {
+                events.push(TimelineEvent::FilesChanged);
+            }
+            if ahead > tracked.ahead {
+                events.push(TimelineEvent::Commits { count: ahead - tracked.ahead, ahead });
+            }
+            tracked.dirty = dirty;
+            tracked.ahead = ahead;
+            let task_id = tracked.task_id.clone();
+            for event in events {
+                self.record(&mut inner, task_id.clone(), None, event);
+            }
+        }
+    }
+
+    fn check_result(&self, result: &Value) {
+        let (Some(repo), Some(worktree)) =
+            (str_field(result, "repo"), str_field(result, "worktree"))
+        else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let key = (repo.to_string(), worktree.to_string());
+        let Some(task_id) = inner.worktrees.get(&key).map(|w| w.task_id.clone()) else { return };
+        let event = TimelineEvent::Check {
+            check: str_field(result, "check").unwrap_or_default().to_string(),
+            passed: result.get("passed").and_then(Value::as_bool).unwrap_or(false),
+            exit_code: result.get("exitCode").and_then(Value::as_u64).unwrap_or(0) as u32,
+            duration_ms: result.get("durationMs").and_then(Value::as_u64).unwrap_or(0),
+        };
+        self.record(&mut inner, task_id, str_field(result, "sessionId"), event);
+    }
+
+    fn record(
+        &self,
+        inner: &mut Inner,
+        task_id: String,
+        session_id: Option<&str>,
+        event: TimelineEvent,
+    ) {
+        let entry =
+            TimelineEntry { task_id, at: now_ms(), session_id: session_id.map(Into::into), event };
+        let line = serde_json::to_string(&entry);
+        if !inner.push(entry) {
+            return;
+        }
+        if let (Some(file), Ok(line)) = (inner.file.as_mut(), line) {
+            let _ = writeln!(file, "{line}");
+        }
+        inner.lines += 1;
+        if inner.lines >= inner.total.max(MIN_COMPACT_LINES) * 2 {
+            if let Err(e) = self.compact(inner) {
+                log::warn!("compact task timelines: {e:#}");
+            }
+        }
+    }
+
+    /// Rewrite the file with only the timelines kept.
+    fn compact(&self, inner: &mut Inner) -> Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut out = String::new();
+        for task_id in &inner.order {
+            for entry in inner.tasks.get(task_id).into_iter().flatten() {
+                out.push_str(&serde_json::to_string(entry)?);
+                out.push('\n');
+            }
+        }
+        fs::write(&tmp, out).context("write task timelines")?;
+        inner.file = None;
+        fs::rename(&tmp, &self.path).context("replace task timelines")?;
+        inner.file = Some(open_append(&self.path)?);
+        inner.lines = inner.total;
+        Ok(())
+    }
+}
+
+/// Sink that files task events in the timelines before passing them on.
+pub struct TimelineSink {
+    inner: SharedSink,
+    timelines: Arc<Timelines>,
+}
+
+impl TimelineSink {
+    pub fn new(inner: SharedSink, timelines: Arc<Timelines>) -> Self {
+        Self { inner, timelines }
+    }
+}
+
+impl EventSink for TimelineSink {
+    fn emit(&self, event: &str, payload: Value) {
+        self.timelines.observe(event, &payload);
+        self.inner.emit(event, payload);
+    }
+}
+
+/// Location of the timelines inside the app config dir.
+pub fn timeline_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("timeline.jsonl")
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("create config dir")?;
+    }
+    OpenOptions::new().create(true).append(true).open(path).context("open task timelines")
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,173 @@
+//! Worktree file tree annotated with git status, for the file explorer.
+//!
+//! `list` returns one directory (or a few levels of it); the explorer
+//! expands further by asking for a child path. Statuses come from a
+//! `StatusSnapshot` that `RepoCache` keeps for a few seconds, so expanding
+//! folders doesn't run `git status` on the whole worktree every time.
+//! Directories are marked `changed` when anything below them has a status
+//! other than ignored. Ignored directories aren't descended into.
+
+use crate::files::{self, EntryKind};
+use anyhow::{Context, Result};
+use git2::{Repository, Status, StatusOptions};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// Deepest `depth` a single call may ask for.
+pub const MAX_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Ignored,
+    Conflicted,
+}
+
+impl FileStatus {
+    fn from_git(s: Status) -> Option<Self> {
+        Some(if s.is_conflicted() {
+            FileStatus::Conflicted
+        } else if s.is_ignored() {
+            FileStatus::Ignored
+        } else if s.is_wt_new() {
+            FileStatus::Untracked
+        } else if s.is_index_new() {
+            FileStatus::Added
+        } else if s.intersects(Status::INDEX_DELETED | Status::WT_DELETED) {
+            FileStatus::Deleted
+        } else if s.intersects(Status::INDEX_RENAMED | Status::WT_RENAMED) {
+            FileStatus::Renamed
+        } else if s.intersects(
+            Status::INDEX_MODIFIED
+                | Status::WT_MODIFIED
+                | Status::INDEX_TYPECHANGE
+                | Status::WT_TYPECHANGE,
+        ) {
+            FileStatus::Modified
+        } else {
+            return None;
+        })
+    }
+}
+
+/// Status of every changed, untracked or ignored path in a worktree.
+#[derive(Debug, Default)]
+pub struct StatusSnapshot {
+    files: HashMap<String, FileStatus>,
+    /// Untracked or ignored directories, reported whole by git.
+    whole_dirs: HashMap<String, FileStatus>,
+    /// Directories with a non-ignored change somewhere below.
+    changed_dirs: HashSet<String>,
+}
+
+impl StatusSnapshot {
+    pub fn read(repo: &Repository) -> Result<Self> {
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true)
+            .include_ignored(true)
+            .recurse_untracked_dirs(false)
+            .recurse_ignored_dirs(false)
+            .exclude_submodules(true);
+        let statuses = repo.statuses(Some(&mut opts)).context("git status")?;
+        let mut snapshot = Self::default();
+        for entry in statuses.iter() {
+            let (Some(path), Some(status)) = (entry.path(), FileStatus::from_git(entry.status()))
+            else {
+                continue;
+            };
+            let path = match path.strip_suffix('/') {
+                Some(dir) => {
+                    snapshot.whole_dirs.insert(dir.to_string(), status);
+                    dir
+                }
+                None => {
+                    snapshot.files.insert(path.to_string(), status);
+                    path
+                }
+            };
+            if status != FileStatus::Ignored {
+                let mut parent = path;
+                while let Some((dir, _)) = parent.rsplit_once('/') {
+                    if !snapshot.changed_dirs.insert(dir.to_string()) {
+                        break;
+                    }
+                    parent = dir;
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Status of `path`, inherited from an untracked or ignored ancestor.
+    pub fn status(&self, path: &str) -> Option<FileStatus> {
+        if let Some(s) = self.files.get(path).or_else(|| self.whole_dirs.get(path)) {
+            return Some(*s);
+        }
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if let Some(s) = self.whole_dirs.get(dir) {
+                return Some(*s);
+            }
+            parent = dir;
+        }
+        None
+    }
+
+    fn changed(&self, dir: &str) -> bool {
+        self.changed_dirs.contains(dir)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TreeEntry {
+    pub name: String,
+    /// Path relative to the worktree root, `/`-separated.
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub status: Option<FileStatus>,
+    /// Directory with changes below it.
+    pub changed: bool,
+    /// Listed when within the requested depth; `None` means not expanded.
+    pub children: Option<Vec<TreeEntry>>,
+}
+
+/// List `rel` inside `root` down to `depth` levels (1 = just `rel`).
+pub fn list(
+    root: &Path,
+    rel: &str,
+    depth: usize,
+    status: &StatusSnapshot,
+) -> Result<Vec<TreeEntry>> {
+    let depth = depth.clamp(1, MAX_DEPTH);
+    let mut entries = Vec::new();
+    for e in files::list_dir(root, rel)? {
+        let entry_status = status.status(&e.path);
+        let is_dir = e.kind == EntryKind::Dir;
+        // `.git` is a file in worktrees, but skip it either way
+        if e.name == ".git" {
+            continue;
+        }
+        let children = (is_dir && depth > 1 && entry_status != Some(FileStatus::Ignored))
+            .then(|| list(root, &e.path, depth - 1, status))
+            .transpose()?;
+        entries.push(TreeEntry {
+            changed: is_dir && status.changed(&e.path),
+            name: e.name,
+            path: e.path,
+            kind: e.kind,
+            size: e.size,
+            status: entry_status,
+            children,
+        });
+    }
+    Ok(entries)
+}
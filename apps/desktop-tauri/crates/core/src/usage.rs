@@ -0,0 +1,229 @@
+//! Token and cost usage parsed from agent output.
+//!
+//! Agent profiles carry `usage_patterns`: regexes run against each complete
+//! output line (ANSI stripped). Named groups say what a match means:
+//!
+//! - `input`, `output`, `cost`: usage of one request, added to the session
+//! - `total_input`, `total_output`, `total_cost`: running session totals,
+//!   which replace the current value
+//!
+//! Token counts may use `k`/`M` suffixes and thousands separators; costs are
+//! in dollars. When a session exits its usage is appended to `usage.jsonl`
+//! in the config dir and included in the `pty://exit` event, along with
+//! its summary (see `summary.rs`).
+
+use crate::{scrollback::LineTracker, summary::SessionSummary};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Longest line considered; usage summaries are short.
+const MAX_LINE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+/// Usage of one session, as persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    pub session_id: String,
+    pub agent_id: String,
+    pub task_id: Option<String>,
+    /// Linked worktree the session ran in.
+    #[serde(default)]
+    pub worktree: Option<String>,
+    /// Session that handed the worktree over to this one.
+    #[serde(default)]
+    pub handoff_from: Option<String>,
+    /// Unix millis.
+    pub started_at: u64,
+    /// `None` while the session is running.
+    pub ended_at: Option<u64>,
+    pub usage: Usage,
+    /// What the session did; `None` while it runs and in older records.
+    #[serde(default)]
+    pub summary: Option<SessionSummary>,
+}
+
+/// Sessions started within `[since, until)`, in Unix millis; open-ended
+/// when unset.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct UsageRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub total: Usage,
+    pub by_agent: BTreeMap<String, Usage>,
+    pub by_task: BTreeMap<String, Usage>,
+    pub sessions: Vec<UsageRecord>,
+}
+
+/// Parses one session's output for usage lines.
+pub struct UsageTracker {
+    patterns: Vec<Regex>,
+    line: LineTracker,
+    usage: Usage,
+}
+
+impl UsageTracker {
+    /// Invalid patterns are logged and skipped.
+    pub fn new(patterns: &[String]) -> Self {
+        let patterns = patterns
+            .iter()
+            .filter_map(|p| {
+                Regex::new(p).map_err(|e| log::warn!("usage pattern {p}: {e}")).ok()
+            })
+            .collect();
+        Self { patterns, line: LineTracker::new(MAX_LINE), usage: Usage::default() }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        if self.patterns.is_empty() {
+            return;
+        }
+        let Self { patterns, line, usage } = self;
+        line.feed(bytes, |text| {
+            for re in patterns.iter() {
+                if let Some(caps) = re.captures(&text) {
+                    apply(usage, &caps);
+                }
+            }
+        });
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+}
+
+fn apply(usage: &mut Usage, caps: &regex::Captures) {
+    let tokens = |name| caps.name(name).and_then(|m| parse_tokens(m.as_str()));
+    let cost = |name| caps.name(name).and_then(|m| m.as_str().replace(',', "").parse().ok());
+    if let Some(n) = tokens("input") {
+        usage.input_tokens += n;
+    }
+    if let Some(n) = tokens("output") {
+        usage.output_tokens += n;
+    }
+    if let Some(c) = cost("cost") {
+        usage.cost_usd += c;
+    }
+    if let Some(n) = tokens("total_input") {
+        usage.input_tokens = n;
+    }
+    if let Some(n) = tokens("total_output") {
+        usage.output_tokens = n;
+    }
+    if let Some(c) = cost("total_cost") {
+        usage.cost_usd = c;
+    }
+}
+
+/// `1,234` → 1234, `2.3k` → 2300, `1.1M` → 1100000.
+fn parse_tokens(s: &str) -> Option<u64> {
+    let s = s.trim().replace(',', "");
+    let (num, scale) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1_000.0),
+        'm' | 'M' => (&s[..s.len() - 1], 1_000_000.0),
+        _ => (s.as_str(), 1.0),
+    };
+    num.parse::<f64>().ok().map(|n| (n * scale).round() as u64)
+}
+
+/// Default patterns for the built-in agents' usage summaries.
+pub fn builtin_patterns(agent_id: &str) -> Vec<String> {
+    let patterns: &[&str] = match agent_id {
+        // Tokens: 2.3k sent, 150 received. Cost: $0.01 message, $0.05 session.
+        "aider" => &[concat!(
+            r"Tokens: (?P<input>[\d.,]+[kM]?) sent,.*?(?P<output>[\d.,]+[kM]?) received\.",
+            r".*?\$(?P<total_cost>[\d.,]+) session",
+        )],
+        // /cost: "Total cost: $0.55" ... "Usage: 1.2k input, 3.4k output, ..."
+        "claude" => &[
+            r"Total cost:\s+\$(?P<total_cost>[\d.,]+)",
+            concat!(
+                r"Usage:\s+(?P<total_input>[\d.,]+[kM]?) input, ",
+                r"(?P<total_output>[\d.,]+[kM]?) output",
+            ),
+        ],
+        // Token usage: total=1234 input=1000 (+ 200 cached) output=234
+        "codex" => &[concat!(
+            r"Token usage: total=[\d,]+ input=(?P<total_input>[\d,]+)",
+            r".*?output=(?P<total_output>[\d,]+)",
+        )],
+        _ => &[],
+    };
+    patterns.iter().map(|p| p.to_string()).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Persistence and reports
+// ---------------------------------------------------------------------------
+
+/// Location of the usage log inside the app config dir.
+pub fn usage_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("usage.jsonl")
+}
+
+pub fn append(path: &Path, record: &UsageRecord) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("create usage dir")?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("open usage log")?;
+    writeln!(file, "{}", serde_json::to_string(record)?).context("write usage log")?;
+    Ok(())
+}
+
+/// Records in the usage log; unreadable lines are skipped.
+pub fn load(path: &Path) -> Vec<UsageRecord> {
+    std::fs::read_to_string(path)
+        .map(|s| s.lines().filter_map(|l| serde_json::from_str(l).ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Totals over the records started within `range`.
+pub fn report(records: impl IntoIterator<Item = UsageRecord>, range: UsageRange) -> UsageReport {
+    let mut report = UsageReport::default();
+    for record in records {
+        if range.since.is_some_and(|s| record.started_at < s)
+            || range.until.is_some_and(|u| record.started_at >= u)
+        {
+            continue;
+        }
+        report.total.add(&record.usage);
+        report.by_agent.entry(record.agent_id.clone()).or_default().add(&record.usage);
+        if let Some(task) = &record.task_id {
+            report.by_task.entry(task.clone()).or_default().add(&record.usage);
+        }
+        report.sessions.push(record);
+    }
+    report
+}
@@ -0,0 +1,364 @@
+//! Outbound webhooks, for wiring the app into chat or dashboards.
+//!
+//! A webhook is a URL and the events it wants: a session exiting, a task
+//! finishing (or failing, or being cancelled), a worktree merging into
+//! the main checkout, and a worktree touching protected paths. They are
+//! saved in `webhooks.json` in the config dir. `WebhookSink` wraps the
+//! app's event sink and queues a delivery for each webhook an event
+//! matches; a background thread POSTs them, so a slow endpoint never holds
+//! up the session or command that emitted the event.
+//!
+//! The body is JSON: `{ id, event, at, source, data }`, where `source` is
+//! the app event's name and `data` its payload. With `secret` set (the
+//! name of a keychain secret, see `secrets.rs`) the body is signed with
+//! HMAC-SHA256 in `X-Pi-Builder-Signature: sha256=<hex>`. Network errors,
+//! 5xx and 429 are retried after `RETRY_DELAYS`; other responses are final.
+//! Deliveries still waiting when the app exits are dropped.
+
+use crate::{
+    error::{ErrorCode, PiError},
+    events::{EventSink, SharedSink},
+    secrets,
+};
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::{header, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use uuid::Uuid;
+
+/// Waits before each retry; a delivery is tried once more than this has
+/// entries.
+const RETRY_DELAYS: &[Duration] = &[
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(300),
+];
+
+/// Longest a single attempt may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    SessionExit,
+    TaskFinished,
+    MergeLanded,
+    Violation,
+}
+
+impl WebhookEvent {
+    /// The webhook event app event `event` is, if any.
+    fn of(event: &str, payload: &Value) -> Option<Self> {
+        if event.starts_with("pty://exit/") {
+            return Some(Self::SessionExit);
+        }
+        match event {
+            "task://changed" => {
+                matches!(payload["status"].as_str(), Some("finished" | "failed" | "cancelled"))
+                    .then_some(Self::TaskFinished)
+            }
+            "review://changed" => {
+                (payload["entry"]["state"] == "merged").then_some(Self::MergeLanded)
+            }
+            "worktree://violation" => payload["paths"]
+                .as_array()
+                .is_some_and(|paths| !paths.is_empty())
+                .then_some(Self::Violation),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::SessionExit => "session_exit",
+            Self::TaskFinished => "task_finished",
+            Self::MergeLanded => "merge_landed",
+            Self::Violation => "violation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub name: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Keychain secret to sign deliveries with.
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryResult {
+    /// HTTP status of the last attempt, if it got a response.
+    pub status: Option<u16>,
+    pub attempts: usize,
+    /// Why the delivery failed, if it did.
+    pub error: Option<String>,
+}
+
+struct Delivery {
+    url: String,
+    secret: Option<String>,
+    event: &'static str,
+    id: String,
+    body: Value,
+}
+
+/// Saved webhooks and the queue delivering to them.
+pub struct Webhooks {
+    path: PathBuf,
+    hooks: Mutex<Vec<Webhook>>,
+    /// Started with the first delivery.
+    queue: Mutex<Option<UnboundedSender<Delivery>>>,
+}
+
+impl Webhooks {
+    pub fn load(path: PathBuf) -> Self {
+        let hooks = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { path, hooks: Mutex::new(hooks), queue: Mutex::new(None) }
+    }
+
+    pub fn list(&self) -> Vec<Webhook> {
+        let mut hooks = self.hooks.lock().unwrap().clone();
+        hooks.sort_by(|a, b| a.name.cmp(&b.name));
+        hooks
+    }
+
+    pub fn get(&self, name: &str) -> Result<Webhook> {
+        let hooks = self.hooks.lock().unwrap();
+        let found = hooks.iter().find(|h| h.name == name).cloned();
+        let not_found = || PiError::new(ErrorCode::NotFound, format!("no webhook named {name}"));
+        Ok(found.ok_or_else(not_found)?)
+    }
+
+    /// Save `hook`, replacing one of the same name.
+    pub fn save(&self, mut hook: Webhook) -> Result<Webhook> {
+        hook.name = hook.name.trim().to_string();
+        hook.secret = hook.secret.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        validate(&hook)?;
+        {
+            let mut hooks = self.hooks.lock().unwrap();
+            hooks.retain(|h| h.name != hook.name);
+            hooks.push(hook.clone());
+        }
+        self.save_file()?;
+        Ok(hook)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.hooks.lock().unwrap().retain(|h| h.name != name);
+        self.save_file()
+    }
+
+    /// Queue `event` for every enabled webhook that wants `kind`.
+    fn dispatch(&self, kind: WebhookEvent, event: &str, payload: &Value) {
+        let hooks = self.hooks.lock().unwrap();
+        let targets: Vec<&Webhook> =
+            hooks.iter().filter(|h| h.enabled && h.events.contains(&kind)).collect();
+        if targets.is_empty() {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        let sender = queue.get_or_insert_with(spawn_delivery);
+        for hook in targets {
+            let id = Uuid::new_v4().to_string();
+            let body = envelope(&id, kind.name(), event, payload.clone());
+            let delivery = Delivery {
+                url: hook.url.clone(),
+                secret: hook.secret.clone(),
+                event: kind.name(),
+                id,
+                body,
+            };
+            if sender.send(delivery).is_err() {
+                log::warn!("webhook delivery thread is gone; {event} not sent");
+            }
+        }
+    }
+
+    fn save_file(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("create config dir")?;
+        }
+        let json = serde_json::to_string_pretty(&*self.hooks.lock().unwrap())?;
+        std::fs::write(&self.path, json).context("write webhooks")?;
+        Ok(())
+    }
+}
+
+pub fn webhooks_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("webhooks.json")
+}
+
+/// Send a `ping` to `hook` once, without retrying, to check the endpoint.
+pub async fn ping(hook: &Webhook) -> Result<DeliveryResult> {
+    let id = Uuid::new_v4().to_string();
+    let body = envelope(&id, "ping", "ping", serde_json::json!({ "webhook": hook.name }));
+    let delivery =
+        Delivery { url: hook.url.clone(), secret: hook.secret.clone(), event: "ping", id, body };
+    Ok(deliver(&client()?, &delivery, false).await)
+}
+
+/// Event sink that forwards everything to `inner` and queues webhook
+/// deliveries for the events webhooks want.
+pub struct WebhookSink {
+    inner: SharedSink,
+    webhooks: Arc<Webhooks>,
+}
+
+impl WebhookSink {
+    pub fn new(inner: SharedSink, webhooks: Arc<Webhooks>) -> Self {
+        Self { inner, webhooks }
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn emit(&self, event: &str, payload: Value) {
+        if let Some(kind) = WebhookEvent::of(event, &payload) {
+            self.webhooks.dispatch(kind, event, &payload);
+        }
+        self.inner.emit(event, payload);
+    }
+}
+
+fn validate(hook: &Webhook) -> Result<()> {
+    if hook.name.is_empty() {
+        return Err(PiError::invalid_input("webhook name is empty").into());
+    }
+    let url = Url::parse(&hook.url)
+        .map_err(|e| PiError::invalid_input(format!("invalid webhook URL: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(PiError::invalid_input("webhook URL must be http or https").into());
+    }
+    if hook.events.is_empty() {
+        return Err(PiError::invalid_input("webhook has no events").into());
+    }
+    Ok(())
+}
+
+fn envelope(id: &str, event: &str, source: &str, data: Value) -> Value {
+    serde_json::json!({ "id": id, "event": event, "at": now_ms(), "source": source, "data": data })
+}
+
+fn client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(concat!("pi-builder/", env!("CARGO_PKG_VERSION")))
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .context("build http client")
+}
+
+/// Start the thread that delivers what is sent to the returned queue,
+/// each delivery retrying on its own.
+fn spawn_delivery() -> UnboundedSender<Delivery> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Delivery>();
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::warn!("webhooks: start runtime: {e}");
+                return;
+            }
+        };
+        runtime.block_on(async move {
+            let http = match client() {
+                Ok(http) => http,
+                Err(e) => {
+                    log::warn!("webhooks: {e:#}");
+                    return;
+                }
+            };
+            while let Some(delivery) = rx.recv().await {
+                let http = http.clone();
+                tokio::spawn(async move {
+                    let result = deliver(&http, &delivery, true).await;
+                    if let Some(error) = result.error {
+                        log::warn!(
+                            "webhook {} to {} failed after {} attempts: {error}",
+                            delivery.event,
+                            delivery.url,
+                            result.attempts
+                        );
+                    }
+                });
+            }
+        });
+    });
+    tx
+}
+
+async fn deliver(http: &reqwest::Client, delivery: &Delivery, retry: bool) -> DeliveryResult {
+    let body = delivery.body.to_string();
+    let signature = match &delivery.secret {
+        Some(name) => match secrets::lookup(name) {
+            Ok(secret) => Some(sign(&secret, body.as_bytes())),
+            Err(e) => {
+                return DeliveryResult { status: None, attempts: 0, error: Some(format!("{e:#}")) }
+            }
+        },
+        None => None,
+    };
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut request = http
+            .post(&delivery.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Pi-Builder-Event", delivery.event)
+            .header("X-Pi-Builder-Delivery", &delivery.id)
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header("X-Pi-Builder-Signature", format!("sha256={signature}"));
+        }
+        let (status, error) = match request.send().await {
+            Ok(resp) if resp.status().is_success() => {
+                let status = Some(resp.status().as_u16());
+                return DeliveryResult { status, attempts, error: None };
+            }
+            Ok(resp) => (Some(resp.status()), format!("HTTP {}", resp.status())),
+            Err(e) => (None, e.to_string()),
+        };
+        let retryable =
+            status.map_or(true, |s| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS);
+        let delay = RETRY_DELAYS.get(attempts - 1).filter(|_| retry && retryable);
+        let Some(delay) = delay else {
+            let status = status.map(|s| s.as_u16());
+            return DeliveryResult { status, attempts, error: Some(error) };
+        };
+        tokio::time::sleep(*delay).await;
+    }
+}
+
+/// Hex HMAC-SHA256 of `body` with `secret`.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,106 @@
+//! Windows job objects, so killing a session ends everything it started.
+//!
+//! Terminating the ConPTY child leaves its own children (a dev server, a
+//! build) running. Each session's child is put in a job right after it
+//! starts, and killing the session terminates the whole job. Processes the
+//! child starts before it is assigned escape the job, but that window is
+//! the few microseconds after `CreateProcess` returns.
+
+use anyhow::{bail, Result};
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::{
+        JobObjects::{AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject},
+        Threading::{OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE},
+    },
+};
+
+pub struct Job(HANDLE);
+
+// The handle is only passed to kernel calls, which are thread-safe
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+impl Job {
+    /// A new job holding process `pid`.
+    pub fn for_process(pid: u32) -> Result<Self> {
+        // SAFETY: plain Win32 calls; every handle opened here is closed,
+        // the job's by `Drop`
+        unsafe {
+            let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if handle.is_null() {
+                bail!("CreateJobObject: {}", std::io::Error::last_os_error());
+            }
+            let job = Job(handle);
+            let process = OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid);
+            if process.is_null() {
+                bail!("OpenProcess {pid}: {}", std::io::Error::last_os_error());
+            }
+            let assigned = AssignProcessToJobObject(job.0, process);
+            let error = std::io::Error::last_os_error();
+            CloseHandle(process);
+            if assigned == 0 {
+                bail!("AssignProcessToJobObject {pid}: {error}");
+            }
+            Ok(job)
+        }
+    }
+
+    /// Terminate every process in the job with `exit_code`.
+    pub fn terminate(&self, exit_code: u32) {
+        // SAFETY: `self.0` is a live job handle
+        if unsafe { TerminateJobObject(self.0, exit_code) } == 0 {
+            log::warn!("TerminateJobObject: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: closed exactly once
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::Job;
+    use crate::proctree;
+    use std::{
+        process::Command,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Poll `check` every 50ms until it yields a value or 10s pass.
+    fn poll<T>(mut check: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if let Some(value) = check() {
+                return Some(value);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    #[test]
+    fn terminate_ends_the_whole_tree() {
+        let mut parent =
+            Command::new("cmd.exe").args(["/C", "ping -n 60 127.0.0.1 >NUL"]).spawn().unwrap();
+        let job = Job::for_process(parent.id()).unwrap();
+        let grandchild = poll(|| {
+            let node = proctree::tree(parent.id())?;
+            node.children.iter().find(|c| c.name.eq_ignore_ascii_case("ping.exe")).map(|c| c.pid)
+        })
+        .expect("cmd.exe never started ping");
+
+        job.terminate(7);
+
+        assert_eq!(parent.wait().unwrap().code(), Some(7));
+        assert!(
+            poll(|| proctree::tree(grandchild).is_none().then_some(())).is_some(),
+            "grandchild {grandchild} outlived the job"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Workspace bundles for sharing a pi-builder setup.
+//!
+//! A bundle is a versioned JSON document holding the repos in use and the
+//! full settings (agent profiles, setup commands, redaction, ...). Importing
+//! either replaces local settings wholesale or merges the bundle's
+//! collections into them, keeping local values for everything else.
+
+use crate::{error::PiError, settings::Settings};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Schema version written by this build. Bundles from newer builds are
+/// rejected rather than half-understood.
+pub const WORKSPACE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceBundle {
+    pub version: u32,
+    /// Repo paths; the first is made current on import.
+    #[serde(default)]
+    pub repos: Vec<String>,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add the bundle's profiles, setup commands and redaction patterns;
+    /// the bundle wins where ids or repos collide.
+    #[default]
+    Merge,
+    /// Take the bundle's settings and repo as they are.
+    Replace,
+}
+
+pub fn export(repo_path: Option<String>, settings: &Settings) -> WorkspaceBundle {
+    WorkspaceBundle {
+        version: WORKSPACE_VERSION,
+        repos: repo_path.into_iter().collect(),
+        settings: settings.clone(),
+    }
+}
+
+/// Check a bundle can be imported by this build.
+pub fn validate(bundle: &WorkspaceBundle) -> Result<()> {
+    if bundle.version == 0 || bundle.version > WORKSPACE_VERSION {
+        return Err(PiError::invalid_input(format!(
+            "unsupported workspace version {} (this build reads up to {WORKSPACE_VERSION})",
+            bundle.version
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Apply `incoming` settings to `local` according to `mode`.
+pub fn apply(local: &mut Settings, incoming: Settings, mode: ImportMode) {
+    if mode == ImportMode::Replace {
+        *local = incoming;
+        return;
+    }
+    for profile in incoming.agent_profiles {
+        local.agent_profiles.retain(|p| p.id != profile.id);
+        local.agent_profiles.push(profile);
+    }
+    for profile in incoming.terminal_profiles {
+        local.terminal_profiles.retain(|p| p.name != profile.name);
+        local.terminal_profiles.push(profile);
+    }
+    local.setup_commands.extend(incoming.setup_commands);
+    local.dependency_cache.extend(incoming.dependency_cache);
+    local.protected_paths.extend(incoming.protected_paths);
+    for pattern in incoming.redact_patterns {
+        if !local.redact_patterns.contains(&pattern) {
+            local.redact_patterns.push(pattern);
+        }
+    }
+}
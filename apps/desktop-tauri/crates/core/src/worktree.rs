@@ -0,0 +1,517 @@
+//! Git worktree management via git2.
+//!
+//! Each agent session gets its own worktree on a fresh branch so agents
+//! can work in parallel without stepping on each other. The main thread
+//! stays on the base branch; we track divergence for the UI.
+//!
+//! Worktrees live outside the repository (`<repo>-agents/` next to it, or a
+//! configured root) because many tools refuse to operate inside `.git`.
+//!
+//! Branch names come from a template (`agent/{id}` by default). The name
+//! actually created is recorded in the main repo's config as
+//! `pi-worktree.<name>.branch`, so removal deletes the right branch even if
+//! the template changed since.
+//!
+//! A worktree can also branch off another worktree's HEAD instead of the
+//! base, so one agent builds on another's partial work. Where it branched
+//! off is recorded next to its branch and listed as `parent`, for drawing
+//! the lineage of chained worktrees.
+
+use crate::{error::PiError, paths};
+use anyhow::{bail, Context, Result};
+use git2::{BranchType, Repository, Signature, WorktreeAddOptions, WorktreePruneOptions};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where worktrees were placed before the root became configurable.
+const LEGACY_DIR: &str = "worktrees-pi";
+
+/// Branch name template used when none is configured.
+pub const DEFAULT_BRANCH_TEMPLATE: &str = "agent/{id}";
+
+/// Longest `{slug}`, in chars.
+const MAX_SLUG: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorktreeInfo {
+    pub name: String,
+    pub path: String,
+    pub branch: String,
+    pub ahead: usize,
+    pub behind: usize,
+    pub dirty: bool,
+    /// Branch pi-builder created for this worktree, deleted along with it.
+    /// `None` for worktrees made before it was recorded.
+    #[serde(default)]
+    pub created_branch: Option<String>,
+    /// Upstream of the main checkout's branch, e.g. `origin/main`, as of
+    /// the last fetch.
+    #[serde(default)]
+    pub upstream: Option<String>,
+    /// Commits on this worktree's HEAD that aren't on `upstream`.
+    #[serde(default)]
+    pub upstream_ahead: usize,
+    /// Commits on `upstream` this worktree doesn't have yet.
+    #[serde(default)]
+    pub upstream_behind: usize,
+    /// Disk usage as last computed in the background; `None` until then.
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    /// Worktree this one branched off; `None` if it started from the base.
+    #[serde(default)]
+    pub parent: Option<WorktreeParent>,
+}
+
+/// Where a worktree created from another one branched off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorktreeParent {
+    /// May have been removed since.
+    pub worktree: String,
+    pub branch: String,
+    /// The parent's HEAD at the time, where the new branch starts.
+    pub commit: String,
+}
+
+/// Values substituted into the branch name template.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BranchVars<'a> {
+    /// `{agent}`: agent profile id.
+    pub agent: &'a str,
+    /// `{slug}`: short description such as a task prompt or session title;
+    /// falls back to the worktree name.
+    pub slug: Option<&'a str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub moved: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Directory holding this repo's worktrees.
+/// With a configured root: `<root>/<repo_name>`; otherwise `<repo>-agents`
+/// next to the repository.
+pub fn worktree_base_dir(repo_path: &str, root: Option<&str>) -> PathBuf {
+    let repo = Path::new(repo_path);
+    let repo_name = repo
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "repo".into());
+    match root {
+        Some(root) => Path::new(root).join(repo_name),
+        None => repo
+            .parent()
+            .unwrap_or(repo)
+            .join(format!("{repo_name}-agents")),
+    }
+}
+
+/// Create a new worktree for an agent session.
+/// Branch name: `template` (or `DEFAULT_BRANCH_TEMPLATE`) rendered with
+/// `vars`, suffixed `-2`, `-3`, ... if that branch already exists. The
+/// branch starts at `base` (a commit or other revision), or HEAD. With
+/// `sparse` paths only those are checked out; see `sparse.rs`.
+/// Worktree path: `<worktree_base_dir>/<session_id>`.
+pub fn create_worktree(
+    repo_path: &str,
+    session_id: &str,
+    root: Option<&str>,
+    template: Option<&str>,
+    vars: &BranchVars,
+    base: Option<&str>,
+    sparse: &[String],
+) -> Result<WorktreeInfo> {
+    paths::check(Path::new(repo_path), "repo")?;
+    let wt_path = worktree_base_dir(repo_path, root).join(session_id);
+    paths::check(&wt_path, "worktree")?;
+    let repo = Repository::open(repo_path).context("open repo")?;
+    paths::enable_long_paths(&repo).context("set core.longpaths")?;
+    let template = template.unwrap_or(DEFAULT_BRANCH_TEMPLATE);
+    let branch_name = unique_branch(&repo, &render_branch(template, session_id, vars)?);
+
+    let head = match base {
+        Some(rev) => repo
+            .revparse_single(rev)
+            .and_then(|o| o.peel_to_commit())
+            .with_context(|| format!("find commit {rev}"))?,
+        None => repo.head()?.peel_to_commit()?,
+    };
+    repo.branch(&branch_name, &head, false)?;
+
+    // libgit2 refuses to add a worktree at a path that already exists
+    if let Some(dir) = wt_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    if sparse.is_empty() {
+        let mut opts = WorktreeAddOptions::new();
+        let branch = repo.find_branch(&branch_name, BranchType::Local)?;
+        let branch_ref = branch.get().name().context("branch ref name")?;
+        // Note: git2 WorktreeAddOptions::reference takes an &Reference
+        // We re-find it to get the owned reference
+        let reference = repo.find_reference(branch_ref)?;
+        opts.reference(Some(&reference));
+
+        repo.worktree(session_id, &wt_path, Some(&opts))
+            .context("create worktree")?;
+    } else {
+        crate::sparse::add_worktree(repo_path, &wt_path, &branch_name, sparse)
+            .context("create sparse worktree")?;
+    }
+    let mut config = repo.config()?;
+    config.set_str(&branch_key(session_id), &branch_name).context("record worktree branch")?;
+    // Kept for merge commit messages; see `mergemsg.rs`
+    if !vars.agent.is_empty() {
+        config.set_str(&meta_key(session_id, "agent"), vars.agent)?;
+    }
+    if let Some(slug) = vars.slug.filter(|s| !s.trim().is_empty()) {
+        config.set_str(&meta_key(session_id, "description"), slug)?;
+    }
+
+    Ok(WorktreeInfo {
+        name: session_id.to_string(),
+        path: wt_path.to_string_lossy().to_string(),
+        branch: branch_name.clone(),
+        ahead: 0,
+        behind: 0,
+        dirty: false,
+        created_branch: Some(branch_name),
+        upstream: None,
+        upstream_ahead: 0,
+        upstream_behind: 0,
+        size_bytes: None,
+        parent: None,
+    })
+}
+
+/// Expand a branch template for worktree `name`. Placeholders: `{agent}`,
+/// `{slug}`, `{date}` (UTC `YYYY-MM-DD`), `{id}` (first 8 chars of the
+/// worktree name) and `{name}`. Fails if the result isn't a valid branch
+/// name.
+pub fn render_branch(template: &str, name: &str, vars: &BranchVars) -> Result<String> {
+    let slug = slugify(vars.slug.unwrap_or(name));
+    let branch = template
+        .replace("{agent}", &slugify(vars.agent))
+        .replace("{slug}", if slug.is_empty() { "work" } else { &slug })
+        .replace("{date}", &today())
+        .replace("{id}", &name[..8.min(name.len())])
+        .replace("{name}", name);
+    if branch.contains(['{', '}'])
+        || !git2::Reference::is_valid_name(&format!("refs/heads/{branch}"))
+    {
+        return Err(PiError::invalid_input(format!(
+            "branch template {template:?} gives invalid branch name {branch:?}"
+        ))
+        .into());
+    }
+    Ok(branch)
+}
+
+/// `base`, or `base-N` for the first N >= 2 with no local branch.
+fn unique_branch(repo: &Repository, base: &str) -> String {
+    let taken = |name: &str| repo.find_branch(name, BranchType::Local).is_ok();
+    if !taken(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{base}-{n}"))
+        .find(|name| !taken(name))
+        .expect("unbounded range")
+}
+
+/// Lowercase ASCII words joined by `-`, at most `MAX_SLUG` chars.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for word in text.split(|c: char| !c.is_ascii_alphanumeric()).filter(|w| !w.is_empty()) {
+        if slug.len() + word.len() + 1 > MAX_SLUG {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug
+}
+
+/// Today's UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Config key recording the branch created for worktree `name`.
+fn branch_key(name: &str) -> String {
+    format!("pi-worktree.{name}.branch")
+}
+
+fn meta_key(name: &str, field: &str) -> String {
+    format!("pi-worktree.{name}.{field}")
+}
+
+/// Branch created for worktree `name`, as recorded at creation.
+fn created_branch(repo: &Repository, name: &str) -> Option<String> {
+    repo.config().ok()?.get_string(&branch_key(name)).ok()
+}
+
+/// Agent profile id and description (task prompt or session title)
+/// worktree `name` was created with, where recorded.
+pub(crate) fn created_for(repo: &Repository, name: &str) -> (Option<String>, Option<String>) {
+    let Ok(config) = repo.config() else { return (None, None) };
+    let get = |field| config.get_string(&meta_key(name, field)).ok();
+    (get("agent"), get("description"))
+}
+
+/// Worktree `source`'s branch and HEAD, to branch a new worktree off.
+/// Changes it hasn't committed don't come along.
+pub fn parent_from(repo_path: &str, source: &str) -> Result<WorktreeParent> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let wt = repo.find_worktree(source).map_err(|_| PiError::worktree_not_found(source))?;
+    let wt_repo = Repository::open(wt.path()).context("open worktree")?;
+    let head = wt_repo.head().context("read worktree HEAD")?;
+    let branch = head.shorthand().unwrap_or("detached").to_string();
+    let commit = head.peel_to_commit()?.id().to_string();
+    Ok(WorktreeParent { worktree: source.to_string(), branch, commit })
+}
+
+/// Record `parent` as where worktree `name` branched off.
+pub fn record_parent(repo_path: &str, name: &str, parent: &WorktreeParent) -> Result<()> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let mut config = repo.config()?;
+    config.set_str(&meta_key(name, "parent"), &parent.worktree)?;
+    config.set_str(&meta_key(name, "parent-branch"), &parent.branch)?;
+    config.set_str(&meta_key(name, "parent-commit"), &parent.commit)?;
+    Ok(())
+}
+
+fn parent_of(repo: &Repository, name: &str) -> Option<WorktreeParent> {
+    let config = repo.config().ok()?;
+    let get = |field| config.get_string(&meta_key(name, field)).ok();
+    Some(WorktreeParent {
+        worktree: get("parent")?,
+        branch: get("parent-branch").unwrap_or_default(),
+        commit: get("parent-commit").unwrap_or_default(),
+    })
+}
+
+/// Whether a worktree with this name is registered in the repo.
+pub fn worktree_exists(repo_path: &str, name: &str) -> Result<bool> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let names = repo.worktrees()?;
+    Ok(names.iter().flatten().any(|n| n == name))
+}
+
+/// Filesystem path of a named worktree.
+pub fn worktree_path(repo_path: &str, name: &str) -> Result<PathBuf> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let wt = repo.find_worktree(name).map_err(|_| PiError::worktree_not_found(name))?;
+    Ok(wt.path().to_path_buf())
+}
+
+/// Get divergence stats for all worktrees (ahead/behind main, dirty status).
+pub fn list_worktrees(repo_path: &str) -> Result<Vec<WorktreeInfo>> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let mut result = Vec::new();
+    for wt_name in repo.worktrees()?.iter().flatten() {
+        if let Ok(info) = worktree_info(&repo, wt_name) {
+            result.push(info);
+        }
+    }
+    Ok(result)
+}
+
+/// Branch and divergence stats for one worktree of an open repo.
+pub fn worktree_info(repo: &Repository, name: &str) -> Result<WorktreeInfo> {
+    let wt = repo.find_worktree(name).map_err(|_| PiError::worktree_not_found(name))?;
+    let wt_repo = Repository::open(wt.path()).context("open worktree")?;
+    Ok(info_for(repo, &wt_repo, name, wt.path(), is_dirty(&wt_repo)))
+}
+
+/// Stats for a worktree whose repo is already open, with its dirty state
+/// from `is_dirty` or a cache of it.
+pub(crate) fn info_for(
+    repo: &Repository,
+    wt_repo: &Repository,
+    name: &str,
+    path: &Path,
+    dirty: bool,
+) -> WorktreeInfo {
+    let branch = wt_repo
+        .head()
+        .ok()
+        .and_then(|h| h.shorthand().map(str::to_string))
+        .unwrap_or_else(|| "detached".into());
+
+    let (ahead, behind) = divergence(wt_repo, repo).unwrap_or((0, 0));
+    let upstream = upstream_of(repo);
+    let (upstream_ahead, upstream_behind) = upstream
+        .as_ref()
+        .and_then(|(_, oid)| {
+            let head = wt_repo.head().ok()?.peel_to_commit().ok()?.id();
+            wt_repo.graph_ahead_behind(head, *oid).ok()
+        })
+        .unwrap_or((0, 0));
+
+    WorktreeInfo {
+        name: name.to_string(),
+        path: path.to_string_lossy().to_string(),
+        branch,
+        ahead,
+        behind,
+        dirty,
+        created_branch: created_branch(repo, name),
+        upstream: upstream.map(|(name, _)| name),
+        upstream_ahead,
+        upstream_behind,
+        size_bytes: None,
+        parent: parent_of(repo, name),
+    }
+}
+
+/// Remove a worktree and delete its branch.
+pub fn remove_worktree(repo_path: &str, name: &str) -> Result<()> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    remove_in(&repo, name)
+}
+
+/// `remove_worktree` against an already open repo.
+pub fn remove_in(repo: &Repository, name: &str) -> Result<()> {
+    let wt = repo.find_worktree(name).map_err(|_| PiError::worktree_not_found(name))?;
+    // Without `valid`, libgit2 only prunes worktrees whose directory is gone
+    wt.prune(Some(WorktreePruneOptions::new().valid(true).working_tree(true)))?;
+
+    // Worktrees made before the branch was recorded used `agent/<id>`
+    let branch_name = created_branch(repo, name)
+        .unwrap_or_else(|| format!("agent/{}", &name[..8.min(name.len())]));
+    if let Ok(mut branch) = repo.find_branch(&branch_name, BranchType::Local) {
+        let _ = branch.delete();
+    }
+    if let Ok(mut config) = repo.config() {
+        let _ = config.remove(&branch_key(name));
+        let _ = config.remove(&meta_key(name, "agent"));
+        let _ = config.remove(&meta_key(name, "description"));
+        for field in ["parent", "parent-branch", "parent-commit"] {
+            let _ = config.remove(&meta_key(name, field));
+        }
+    }
+    crate::snapshot::delete_all(repo, name);
+    Ok(())
+}
+
+/// Move worktrees still living under `<repo>/.git/worktrees-pi` to the
+/// configured location. Worktrees named in `in_use` (live sessions whose cwd
+/// points at the old path) are left alone and reported as skipped.
+pub fn migrate_legacy_worktrees(
+    repo_path: &str,
+    root: Option<&str>,
+    in_use: &[String],
+) -> Result<MigrationReport> {
+    let repo = Repository::open(repo_path).context("open repo")?;
+    let legacy = repo.path().join(LEGACY_DIR);
+    let base = worktree_base_dir(repo_path, root);
+    let mut report = MigrationReport::default();
+
+    for wt_name in repo.worktrees()?.iter().flatten() {
+        let Ok(wt) = repo.find_worktree(wt_name) else { continue };
+        if !wt.path().starts_with(&legacy) {
+            continue;
+        }
+        if in_use.iter().any(|s| s == wt_name) {
+            report.skipped.push(wt_name.to_string());
+            continue;
+        }
+        std::fs::create_dir_all(&base)?;
+        // git2 has no worktree move; the CLI fixes up both gitdir links.
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(repo_path)
+            .args(["worktree", "move"])
+            .arg(wt.path())
+            .arg(base.join(wt_name))
+            .output()
+            .context("run git worktree move")?;
+        if !out.status.success() {
+            bail!(
+                "move worktree {}: {}",
+                wt_name,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        report.moved.push(wt_name.to_string());
+    }
+
+    let _ = std::fs::remove_dir(&legacy);
+    Ok(report)
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+/// Open a named worktree of an already open repo.
+pub(crate) fn open_in(repo: &Repository, name: &str) -> Result<Repository> {
+    let wt = repo.find_worktree(name).map_err(|_| PiError::worktree_not_found(name))?;
+    Repository::open(wt.path()).context("open worktree")
+}
+
+fn divergence(wt_repo: &Repository, main_repo: &Repository) -> Result<(usize, usize)> {
+    let wt_head = wt_repo.head()?.peel_to_commit()?.id();
+    let main_head = main_repo.head()?.peel_to_commit()?.id();
+
+    let (ahead, behind) = wt_repo.graph_ahead_behind(wt_head, main_head)?;
+    Ok((ahead, behind))
+}
+
+/// Remote tracking branch of the repo's current branch and its commit.
+pub(crate) fn upstream_of(repo: &Repository) -> Option<(String, git2::Oid)> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    let upstream = git2::Branch::wrap(head).upstream().ok()?;
+    let name = upstream.name().ok()??.to_string();
+    let oid = upstream.get().target()?;
+    Some((name, oid))
+}
+
+/// Whether the checkout has changes to tracked files or untracked files.
+/// Ignored files don't count, and an untracked directory is one entry
+/// rather than a walk through everything under it (build output,
+/// `node_modules`), which is what made a scan slow on monorepos.
+pub(crate) fn is_dirty(repo: &Repository) -> bool {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true).include_ignored(false).recurse_untracked_dirs(false);
+    repo.statuses(Some(&mut opts))
+        .map(|s| s.iter().any(|e| e.status() != git2::Status::CURRENT))
+        .unwrap_or(false)
+}
+
+/// Like `is_dirty`, but untracked files don't count.
+pub(crate) fn has_tracked_changes(repo: &Repository) -> bool {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(false).include_ignored(false);
+    repo.statuses(Some(&mut opts))
+        .map(|s| s.iter().any(|e| e.status() != git2::Status::CURRENT))
+        .unwrap_or(false)
+}
+
+/// Committer for commits pi-builder makes itself: the repo's configured
+/// identity, or a fixed fallback.
+pub(crate) fn signature(repo: &Repository) -> Signature<'static> {
+    repo.signature()
+        .map(|s| s.to_owned())
+        .or_else(|_| Signature::now("pi-builder", "pi-builder@localhost"))
+        .expect("static signature")
+}
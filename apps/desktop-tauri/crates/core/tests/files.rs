@@ -0,0 +1,81 @@
+//! Worktree-scoped file access refusing paths that lead outside.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`.
+
+use pi_builder_core::{
+    error::{ErrorCode, PiError},
+    files::{self, EntryKind},
+    testing::TempRepo,
+};
+
+fn is_outside(result: anyhow::Result<impl std::fmt::Debug>) -> bool {
+    let error = result.expect_err("path should be refused");
+    error.downcast_ref::<PiError>().is_some_and(|e| e.code == ErrorCode::PathOutsideWorktree)
+}
+
+#[test]
+fn paths_inside_are_resolved() {
+    let repo = TempRepo::new().unwrap();
+    repo.commit_file("src/main.rs", "fn main() {}\n", "add main").unwrap();
+    let full = files::resolve(repo.path(), "src/../src/main.rs").unwrap();
+    assert!(full.ends_with("src/main.rs"));
+    let stat = files::stat(repo.path(), "src/main.rs").unwrap();
+    assert_eq!((stat.path.as_str(), stat.kind), ("src/main.rs", EntryKind::File));
+    assert_eq!(files::stat(repo.path(), "src").unwrap().kind, EntryKind::Dir);
+}
+
+#[test]
+fn parent_dir_escape_is_refused() {
+    let repo = TempRepo::new().unwrap();
+    repo.commit_file("src/main.rs", "fn main() {}\n", "add main").unwrap();
+    assert!(is_outside(files::resolve(repo.path(), "..")));
+    assert!(is_outside(files::resolve(repo.path(), "src/../..")));
+    assert!(is_outside(files::stat(repo.path(), "src/../..")));
+    assert!(is_outside(files::resolve_new(repo.path(), "../notes.md")));
+    assert!(is_outside(files::resolve_new(repo.path(), "a/../../notes.md")));
+    assert!(!repo.path().join("a").exists());
+}
+
+#[test]
+fn absolute_paths_are_refused() {
+    let repo = TempRepo::new().unwrap();
+    let inside = repo.path().join("README.md");
+    assert!(is_outside(files::resolve(repo.path(), &inside.to_string_lossy())));
+    assert!(is_outside(files::resolve_new(repo.path(), &inside.to_string_lossy())));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_out_of_the_worktree_are_refused() {
+    use std::os::unix::fs::symlink;
+
+    let repo = TempRepo::new().unwrap();
+    let outside = TempRepo::new().unwrap();
+    symlink(outside.path(), repo.path().join("out")).unwrap();
+    symlink(outside.path().join("README.md"), repo.path().join("readme-out")).unwrap();
+
+    assert!(is_outside(files::resolve(repo.path(), "out/README.md")));
+    assert!(is_outside(files::resolve(repo.path(), "readme-out")));
+    assert!(is_outside(files::stat(repo.path(), "readme-out")));
+    assert!(is_outside(files::resolve_new(repo.path(), "out/new.md")));
+    assert!(!outside.path().join("new.md").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_inside_are_reported_as_symlinks() {
+    let repo = TempRepo::new().unwrap();
+    std::os::unix::fs::symlink("README.md", repo.path().join("readme-link")).unwrap();
+    let stat = files::stat(repo.path(), "readme-link").unwrap();
+    assert_eq!((stat.path.as_str(), stat.kind), ("readme-link", EntryKind::Symlink));
+}
+
+#[cfg(unix)]
+#[test]
+fn resolve_new_refuses_an_existing_symlink() {
+    let repo = TempRepo::new().unwrap();
+    std::os::unix::fs::symlink("README.md", repo.path().join("readme-link")).unwrap();
+    assert!(is_outside(files::resolve_new(repo.path(), "readme-link")));
+    let fresh = files::resolve_new(repo.path(), "notes/today.md").unwrap();
+    assert!(fresh.ends_with("notes/today.md") && repo.path().join("notes").is_dir());
+}
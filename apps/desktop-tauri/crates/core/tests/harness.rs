@@ -0,0 +1,104 @@
+//! The `testing` harness end to end: a scripted child spawned in a fresh
+//! worktree of a temp repo, with its events recorded.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`.
+
+use pi_builder_core::{
+    pty::{PtyManager, SpawnOptions},
+    redact::MASK,
+    testing::{RecordingSink, Script, TempRepo},
+    worktree::{self, BranchVars, WorktreeInfo},
+};
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn create_worktree(repo: &TempRepo, name: &str) -> WorktreeInfo {
+    let vars = BranchVars { agent: "test", slug: None };
+    worktree::create_worktree(&repo.path_str(), name, None, None, &vars, None, &[]).unwrap()
+}
+
+#[test]
+fn scripted_child_output_and_exit_are_recorded() {
+    let repo = TempRepo::new().unwrap();
+    let wt = create_worktree(&repo, "scripted");
+    assert!(Path::new(&wt.path).join("README.md").is_file());
+
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    let script = Script::new().print("hello").sleep_ms(100).print("bye").exit(0);
+    let mut opts = SpawnOptions::new("test", script.cmd());
+    opts.cwd = Some(wt.path.clone());
+    let id = pty.spawn(opts, sink.clone()).unwrap();
+
+    let exit = sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+    assert_eq!(exit["sessionId"], id.as_str());
+    assert_eq!(exit["exitCode"], 0);
+    let output = sink.output(&id);
+    assert!(output.contains("hello") && output.contains("bye"), "{output:?}");
+
+    // All output is delivered before the exit is announced
+    let names: Vec<String> = sink.events().into_iter().map(|(name, _)| name).collect();
+    let exit_at = names.iter().position(|n| n.starts_with("pty://exit/")).unwrap();
+    let data = format!("pty://data/{id}");
+    assert!(names[exit_at + 1..].iter().all(|n| !n.starts_with(&data)), "{names:?}");
+}
+
+#[test]
+fn scripted_child_exit_code_is_recorded() {
+    let repo = TempRepo::new().unwrap();
+    let wt = create_worktree(&repo, "failing");
+
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    let mut opts = SpawnOptions::new("test", Script::new().print("failing").exit(3).cmd());
+    opts.cwd = Some(wt.path.clone());
+    let id = pty.spawn(opts, sink.clone()).unwrap();
+
+    let exit = sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+    assert_eq!(exit["exitCode"], 3);
+    assert!(sink.output(&id).contains("failing"));
+}
+
+#[test]
+fn input_can_be_written_more_than_once() {
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    let script = Script::new().echo_input().echo_input().exit(0);
+    let id = pty.spawn(SpawnOptions::new("test", script.cmd()), sink.clone()).unwrap();
+
+    pty.write(&id, "first\r").unwrap();
+    pty.write(&id, "second\r").unwrap();
+
+    let exit = sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+    assert_eq!(exit["exitCode"], 0);
+    let output = sink.output(&id);
+    assert!(output.contains("first") && output.contains("second"), "{output:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn held_back_secret_start_goes_out_while_the_child_waits() {
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    let cmd = vec!["sh".into(), "-c".into(), "printf 'token=sk-live-01'; read line".into()];
+    let mut opts = SpawnOptions::new("test", cmd);
+    opts.env = vec![("API_TOKEN".into(), "sk-live-0123456789abcdef".into())];
+    let id = pty.spawn(opts, sink.clone()).unwrap();
+
+    // The output stops inside what could be the secret; it isn't held
+    // back for good, and what is let out is masked
+    let deadline = Instant::now() + TIMEOUT;
+    while !sink.output(&id).contains("token=") && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    thread::sleep(Duration::from_millis(500));
+    let output = sink.output(&id);
+    assert!(output.ends_with(MASK), "{output:?}");
+    pty.write(&id, "\r").unwrap();
+    sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+}
@@ -0,0 +1,43 @@
+//! A pipe between two live sessions.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`.
+
+use pi_builder_core::{
+    pipe::Pipes,
+    pty::{PtyManager, SpawnOptions},
+    testing::{RecordingSink, Script},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+#[test]
+fn pipe_keeps_typing_after_its_first_batch() {
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    // Quiet gaps longer than the pipe's flush delay split the lines into
+    // two batches, typed into the target one after the other.
+    let source =
+        Script::new().sleep_ms(500).print("one").sleep_ms(1500).print("two").sleep_ms(1500);
+    let from = pty.spawn(SpawnOptions::new("test", source.cmd()), sink.clone()).unwrap();
+    let target = Script::new().echo_input().echo_input().exit(0);
+    let to = pty.spawn(SpawnOptions::new("test", target.cmd()), sink.clone()).unwrap();
+
+    let taps = pty.output_taps(&from).unwrap();
+    let pty = Arc::new(Mutex::new(pty));
+    let (writer, to_session) = (pty.clone(), to.clone());
+    let write = move |text: &str| {
+        writer.lock().unwrap().input(&to_session, &format!("{text}\r"))?;
+        Ok(())
+    };
+    Pipes::default().open(&from, &to, None, taps, write, sink.clone()).unwrap();
+
+    let exit = sink.wait_for(&format!("pty://exit/{to}"), TIMEOUT).expect("no exit event");
+    assert_eq!(exit["exitCode"], 0);
+    let output = sink.output(&to);
+    assert!(output.contains("one") && output.contains("two"), "{output:?}");
+    assert!(sink.named("pty://pipe-closed/").iter().all(|e| e["error"].is_null()));
+}
@@ -0,0 +1,72 @@
+//! Secrets split across PTY reads.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`.
+
+use pi_builder_core::redact::{Redactor, MASK};
+
+const SECRET: &str = "sk-live-0123456789abcdef";
+
+fn redactor() -> Redactor {
+    Redactor::new(vec![SECRET.to_string()], Vec::new())
+}
+
+/// Everything `chunks` come out as, flushed at the end of the stream.
+fn run(redactor: &mut Redactor, chunks: &[&str]) -> String {
+    let mut out: Vec<u8> = chunks.iter().flat_map(|c| redactor.filter(c.as_bytes())).collect();
+    out.extend(redactor.flush());
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn secret_in_one_chunk_is_masked() {
+    let out = run(&mut redactor(), &[&format!("token={SECRET}\n")]);
+    assert_eq!(out, format!("token={MASK}\n"));
+}
+
+#[test]
+fn secret_split_across_chunks_is_masked() {
+    for at in 1..SECRET.len() {
+        let (head, tail) = SECRET.split_at(at);
+        let out = run(&mut redactor(), &["token=", head, &format!("{tail}\n")]);
+        assert!(!out.contains(&SECRET[3..]), "split at {at}: {out:?}");
+        assert!(out.starts_with("token=") && out.ends_with(&format!("{MASK}\n")), "{out:?}");
+    }
+}
+
+#[test]
+fn secret_over_three_chunks_is_masked() {
+    let out = run(&mut redactor(), &["token=sk-li", "ve-01234", "56789abcdef done\n"]);
+    assert_eq!(out, format!("token={MASK} done\n"));
+}
+
+#[test]
+fn short_partial_is_not_held_back() {
+    let mut redactor = redactor();
+    assert_eq!(redactor.filter(b"$ sk"), b"$ sk");
+    assert!(!redactor.has_pending());
+}
+
+#[test]
+fn held_back_tail_goes_out_with_the_next_chunk() {
+    let mut redactor = redactor();
+    assert_eq!(redactor.filter(b"ask sk-live"), b"ask ");
+    assert!(redactor.has_pending());
+    assert_eq!(redactor.filter(b"ly\n"), b"sk-lively\n");
+    assert!(!redactor.has_pending());
+}
+
+#[test]
+fn held_back_tail_goes_out_masked_when_output_is_quiet() {
+    let mut redactor = redactor();
+    assert_eq!(redactor.filter(b"token=sk-live-01"), b"token=");
+    assert_eq!(redactor.flush_idle(), MASK.as_bytes());
+    assert!(!redactor.has_pending());
+    assert_eq!(redactor.filter(b"23456789abcdef\n"), b"\n");
+    assert!(redactor.flush_idle().is_empty());
+}
+
+#[test]
+fn unrelated_output_after_a_short_partial_is_untouched() {
+    let out = run(&mut redactor(), &["$ sk", "ip this\n"]);
+    assert_eq!(out, "$ skip this\n");
+}
@@ -0,0 +1,43 @@
+//! Files sent to a live shell session.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`. The
+//! `base64` mode needs a POSIX shell, so these only run on Unix.
+
+#![cfg(unix)]
+
+use pi_builder_core::{
+    pty::{PtyManager, SpawnOptions},
+    sendfile::{self, SendMode},
+    testing::{RecordingSink, TempRepo},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+#[test]
+fn base64_file_is_recreated_by_the_session() {
+    let dir = TempRepo::new().unwrap();
+    let name = format!("pi-builder-sendfile-{}.bin", std::process::id());
+    let path = dir.path().join(&name);
+    // Several read chunks, so the file takes many writes to the session
+    let content: Vec<u8> = (0..150 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(&path, &content).unwrap();
+
+    let sink = RecordingSink::new();
+    let mut pty = PtyManager::default();
+    let id = pty.spawn(SpawnOptions::new("test", vec!["sh".into()]), sink.clone()).unwrap();
+    let launch = pty.launch_snapshot(&id).unwrap();
+    let sent =
+        sendfile::send_file(&path, SendMode::Base64, &launch, false, |data| pty.write(&id, data))
+            .unwrap();
+    pty.write(&id, "exit\r").unwrap();
+
+    let exit = sink.wait_for(&format!("pty://exit/{id}"), TIMEOUT).expect("no exit event");
+    assert_eq!(exit["exitCode"], 0);
+    assert_eq!(sent.bytes, content.len() as u64);
+    let remote = sent.remote_path.unwrap();
+    assert_eq!(remote, format!("/tmp/{name}"));
+    let received = std::fs::read(&remote).unwrap();
+    let _ = std::fs::remove_file(&remote);
+    assert!(received == content, "got {} of {} bytes", received.len(), content.len());
+}
@@ -0,0 +1,28 @@
+//! Worktree lifecycle against a real repo.
+//!
+//! Run with `cargo test -p pi-builder-core --features testing`.
+
+use git2::BranchType;
+use pi_builder_core::{
+    testing::TempRepo,
+    worktree::{self, BranchVars},
+};
+use std::path::Path;
+
+#[test]
+fn created_worktree_is_removed_with_its_branch() {
+    let repo = TempRepo::new().unwrap();
+    let vars = BranchVars { agent: "test", slug: Some("remove me") };
+    let wt = worktree::create_worktree(&repo.path_str(), "doomed", None, None, &vars, None, &[])
+        .unwrap();
+    assert!(Path::new(&wt.path).join("README.md").is_file());
+    let listed = worktree::list_worktrees(&repo.path_str()).unwrap();
+    assert!(listed.iter().any(|w| w.name == "doomed"));
+
+    worktree::remove_worktree(&repo.path_str(), "doomed").unwrap();
+
+    let listed = worktree::list_worktrees(&repo.path_str()).unwrap();
+    assert!(listed.iter().all(|w| w.name != "doomed"), "{listed:?}");
+    assert!(!Path::new(&wt.path).exists());
+    assert!(repo.repo().find_branch(&wt.branch, BranchType::Local).is_err());
+}
@@ -1,53 +1,863 @@
 //! Tauri command bridge — frontend calls these via invoke().
 
-use crate::{pty::PtyManager, worktree};
+use crate::{
+    actions::{self, Action, ActionRun, ActionStep, ActionStore, Runs},
+    agents::{self, AgentProfile},
+    annotate,
+    audit::{self, AuditEntry, AuditFilter, AuditLog, AuditRange, AuditSink},
+    branches::{self, BranchInfo},
+    bulk::{self, BulkItem, BulkOp},
+    checks,
+    codec::{self, DataEncoding, ProtocolInfo},
+    comments::{self, CommentStore, ReviewComment},
+    compare::{self, Comparison},
+    container::ContainerSpec,
+    depcache::{self, CacheLinkReport, CacheRule},
+    detach::{self, SessionBackend},
+    disk::{self, DiskUsage},
+    doctor::{self, DoctorReport},
+    error::{ErrorCode, PiError},
+    events::SharedSink,
+    export::{self, ExportFormat, ExportResult},
+    files,
+    flow::{FlowSettings, QueueStats},
+    highlight,
+    guard::{Filtered, GuardRules, GuardSettings},
+    history::{self, BlameLine, BlameRange, CommitInfo},
+    hooks::{self, Hook, HookPoint, HookSink},
+    inputhistory::{self, HistoryEntry, HistoryMatch},
+    lfs::{self, LfsReport},
+    locks::WorktreeLocks,
+    integrations::github::{self, IssueContext, PullContext},
+    journal::{self, EventJournal, EventsSince, JournalSink},
+    logs::{self, LogSettings},
+    macros::{self, Macro, MacroStore, Playbacks, Recorder},
+    mainguard,
+    mergemsg,
+    mergequeue::{MergeEntry, MergeQueue, MergeQueueState},
+    notify::{NotificationPrefs, NotifyingSink, SharedNotifier},
+    paste::{self, PasteResult, PasteSettings, PendingPastes},
+    patch::{self, PatchReport},
+    paths,
+    pipe::{PipeInfo, Pipes},
+    playback::{self, PlaybackInfo},
+    proctree::{self, ProcessNode},
+    protect,
+    pty::{
+        ExitBehavior, PtyManager, SessionFilter, SessionLimits, SessionMeta, SessionRecord,
+        ShellConfig, SpawnOptions,
+    },
+    rebase::{self, RebaseOutcome, RebaseStatus},
+    repro::LaunchSnapshot,
+    screen::ScreenSnapshot,
+    scrollback::{ExportedText, LineRange, SearchResult},
+    search::{self, Cancellations, RepoSearchResult, SearchOptions},
+    secrets::{self, SecretStore},
+    sendfile::{self, SendMode, SentFile},
+    recording::{self, RecordingInfo},
+    redact,
+    remote::{self, CloneReport, FetchReport},
+    review::{self, MergeOutcome, ReviewEntry, ReviewState, ReviewStore},
+    repo_cache::{self, RepoCache},
+    repo_config::{self, RepoConfig},
+    report::{self, ReportFormat, ReportResult, TaskReport},
+    scale::{self, ScaleReport},
+    settings::{Settings, TerminalSize},
+    setup, shutdown,
+    snapshot::{self, Snapshot},
+    sparse,
+    spawnqueue::{ConcurrencySettings, QueuedSpawn, Slot, SpawnQueue},
+    ssh::SshTarget,
+    staging::{self, DiffView, FileDiff},
+    stash::{self, StashEntry},
+    submodules::{self, SubmoduleReport},
+    summary::{self, HistoryFilter},
+    system::{self, SystemMetrics, SystemMonitor, SystemPolicy},
+    terminal::{self, TerminalProfile},
+    target::SpawnTarget,
+    tasks::{Scheduler, Task, TaskSpec},
+    timeline::{self, TimelineEntry, TimelineSink, Timelines},
+    tree::{self, TreeEntry},
+    usage::{self, UsageRange, UsageRecord, UsageReport},
+    webhooks::{self, DeliveryResult, Webhook, WebhookSink, Webhooks},
+    workspace::{self, ImportMode, WorkspaceBundle},
+    worktree::{self, BranchVars},
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
-use tauri::{AppHandle, State};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    time::Duration,
+};
+use tauri::State;
 
 pub struct AppState {
-    pub pty: Mutex<PtyManager>,
-    pub repo_path: Mutex<Option<String>>,
+    pub pty: Arc<Mutex<PtyManager>>,
+    pub repo_path: Arc<Mutex<Option<String>>>,
+    /// Open repo handles and the latest worktree status scan.
+    pub repo_cache: Arc<RepoCache>,
+    /// Running `repo_search` calls, for `repo_search_cancel`.
+    pub searches: Cancellations,
+    /// Pastes held for confirmation, for `pty_paste_confirm`.
+    pub pastes: PendingPastes,
+    /// Spawns waiting for a free session slot.
+    pub spawn_queue: SpawnQueue<SpawnArgs>,
+    /// Review state of agent branches.
+    pub reviews: Arc<ReviewStore>,
+    /// Inline review comments on worktree diffs.
+    pub comments: CommentStore,
+    /// Approved branches waiting to be landed.
+    pub merge_queue: MergeQueue,
+    /// Names of the secrets kept in the OS keychain.
+    pub secrets: SecretStore,
+    /// Saved input macros.
+    pub macros: MacroStore,
+    /// Input recordings of sessions, for `macro_save`.
+    pub recordings: Recorder,
+    pub playbacks: Playbacks,
+    /// Saved shortcut actions.
+    pub actions: ActionStore,
+    /// Sessions an action is running in.
+    pub action_runs: Runs,
+    /// Sessions' output forwarded into other sessions.
+    pub pipes: Pipes,
+    /// Recordings open for playback.
+    pub recording_playbacks: playback::Playbacks,
+    /// Worktrees an operation is changing; see `locks.rs`.
+    pub worktree_locks: WorktreeLocks,
+    pub settings: Arc<Mutex<Settings>>,
+    pub settings_path: PathBuf,
+    pub log_dir: PathBuf,
+    pub tasks: Scheduler,
+    /// Latest host metrics sample.
+    pub system: SystemMonitor,
+    pub events: SharedSink,
+    /// Journal of state events for `events_since`.
+    pub journal: Arc<EventJournal>,
+    pub notifications: Arc<NotifyingSink>,
+    /// Record of invoked commands, for `audit_query`.
+    pub audit: Arc<AuditLog>,
+    /// Outbound webhooks; see `webhooks.rs`.
+    pub webhooks: Arc<Webhooks>,
+    /// Per-task timelines for `task_timeline`.
+    pub timelines: Arc<Timelines>,
+    /// Where sessions are recorded at shutdown.
+    pub sessions_path: PathBuf,
+    /// Usage log of finished sessions.
+    pub usage_path: PathBuf,
+    /// Sessions that were open when the app last exited.
+    pub previous_sessions: Vec<SessionRecord>,
+    pub shut_down: AtomicBool,
+}
+
+impl AppState {
+    pub fn new(
+        settings_path: PathBuf,
+        log_dir: PathBuf,
+        events: SharedSink,
+        notifier: SharedNotifier,
+    ) -> Self {
+        let settings = Settings::load(&settings_path);
+        logs::prune(&log_dir, &settings.logs);
+        let config_dir = settings_path.parent().unwrap_or(Path::new("."));
+        let sessions_path = shutdown::sessions_file(config_dir);
+        let usage_path = usage::usage_file(config_dir);
+        let reviews = Arc::new(ReviewStore::load(review::reviews_file(config_dir)));
+        let comments = CommentStore::load(comments::comments_file(config_dir));
+        let secrets = SecretStore::load(secrets::secrets_file(config_dir));
+        let macros = MacroStore::load(macros::macros_file(config_dir));
+        let actions = ActionStore::load(actions::actions_file(config_dir));
+        let mut pty = PtyManager::default();
+        configure_pty(&mut pty, &settings, &log_dir);
+        pty.configure_usage_log(usage_path.clone());
+        let pty = Arc::new(Mutex::new(pty));
+        let settings = Arc::new(Mutex::new(settings));
+        let notifications = Arc::new(NotifyingSink::new(events, notifier, settings.clone()));
+        let journal = Arc::new(EventJournal::load(journal::journal_file(config_dir)));
+        let events: SharedSink = Arc::new(JournalSink::new(notifications.clone(), journal.clone()));
+        let audit = Arc::new(AuditLog::open(audit::audit_file(config_dir)));
+        let events: SharedSink = Arc::new(AuditSink::new(events, audit.clone()));
+        let webhooks = Arc::new(Webhooks::load(webhooks::webhooks_file(config_dir)));
+        let events: SharedSink = Arc::new(WebhookSink::new(events, webhooks.clone()));
+        let events: SharedSink = Arc::new(HookSink::new(events, settings.clone()));
+        let timelines = Arc::new(Timelines::load(timeline::timeline_file(config_dir)));
+        let events: SharedSink = Arc::new(TimelineSink::new(events, timelines.clone()));
+        let repo_path: Arc<Mutex<Option<String>>> = Arc::default();
+        let repo_cache: Arc<RepoCache> = Arc::default();
+        repo_cache::spawn_status_monitor(repo_cache.clone(), repo_path.clone(), events.clone());
+        protect::spawn_monitor(
+            repo_cache.clone(),
+            repo_path.clone(),
+            settings.clone(),
+            events.clone(),
+        );
+        let worktree_locks = WorktreeLocks::default();
+        remote::spawn_auto_fetch(
+            repo_path.clone(),
+            settings.clone(),
+            repo_cache.clone(),
+            worktree_locks.clone(),
+            events.clone(),
+        );
+        let tasks = Scheduler::new(pty.clone(), settings.clone(), events.clone());
+        let system = system::spawn_monitor(settings.clone(), tasks.clone(), events.clone());
+        let merge_queue = MergeQueue::new(
+            pty.clone(),
+            settings.clone(),
+            repo_cache.clone(),
+            reviews.clone(),
+            worktree_locks.clone(),
+            events.clone(),
+        );
+        let state = Self {
+            tasks,
+            system,
+            merge_queue,
+            pty,
+            repo_path,
+            repo_cache,
+            searches: Cancellations::default(),
+            pastes: PendingPastes::default(),
+            spawn_queue: SpawnQueue::default(),
+            reviews,
+            comments,
+            secrets,
+            macros,
+            recordings: Recorder::default(),
+            playbacks: Playbacks::default(),
+            actions,
+            action_runs: Runs::default(),
+            pipes: Pipes::default(),
+            recording_playbacks: playback::Playbacks::default(),
+            worktree_locks,
+            settings,
+            settings_path,
+            log_dir,
+            events,
+            journal,
+            notifications,
+            audit,
+            webhooks,
+            timelines,
+            previous_sessions: shutdown::load_sessions(&sessions_path),
+            sessions_path,
+            usage_path,
+            shut_down: AtomicBool::new(false),
+        };
+        reattach_detached(&state);
+        state
+    }
+
+    /// The configured repo, or `RepoNotConfigured`.
+    fn repo(&self) -> Result<String, PiError> {
+        self.repo_path.lock().unwrap().clone().ok_or_else(PiError::repo_not_configured)
+    }
+
+    fn worktree_root(&self) -> Option<String> {
+        self.settings.lock().unwrap().worktree_root.clone()
+    }
+
+    fn terminal_size(&self) -> TerminalSize {
+        self.settings.lock().unwrap().terminal
+    }
+
+    /// Change settings, save them, apply them to the running subsystems and
+    /// emit `settings://changed`. PTY-level changes affect sessions spawned
+    /// afterwards.
+    fn update_settings(&self, change: impl FnOnce(&mut Settings)) -> Result<(), PiError> {
+        let updated = {
+            let mut settings = self.settings.lock().unwrap();
+            change(&mut settings);
+            settings.save(&self.settings_path)?;
+            settings.clone()
+        };
+        configure_pty(&mut self.pty.lock().unwrap(), &updated, &self.log_dir);
+        // A raised concurrency limit may let queued tasks start right away
+        self.tasks.pump();
+        self.events.emit(
+            "settings://changed",
+            serde_json::to_value(&updated).unwrap_or_default(),
+        );
+        Ok(())
+    }
+}
+
+/// Push the settings that live inside the PTY manager.
+fn configure_pty(pty: &mut PtyManager, settings: &Settings, log_dir: &Path) {
+    pty.configure_redaction(
+        redact::compile_patterns(&settings.redact_patterns),
+        settings.redact_env_secrets,
+    );
+    pty.configure_idle(idle_after(&settings.notifications));
+    pty.configure_shell(settings.shell.clone());
+    pty.configure_logging(log_dir.to_path_buf(), settings.logs.clone());
+    pty.configure_flow(settings.output.clone());
+    pty.configure_scrollback_spill(settings.scrollback_spill.clone());
+    pty.configure_term_env(settings.terminal_env.clone());
+    pty.configure_guard(GuardRules::compile(&settings.guard));
+    pty.configure_annotators(settings.annotate_output.then(annotate::builtin_set));
+}
+
+fn idle_after(prefs: &NotificationPrefs) -> Option<Duration> {
+    (prefs.idle_secs > 0).then(|| Duration::from_secs(prefs.idle_secs))
+}
+
+// ---------------------------------------------------------------------------
+// Settings commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn settings_get(state: State<'_, AppState>) -> Settings {
+    state.settings.lock().unwrap().clone()
+}
+
+/// Replace all settings at once.
+#[tauri::command]
+pub fn settings_set(settings: Settings, state: State<'_, AppState>) -> Result<(), PiError> {
+    validate_redaction(&settings.redact_patterns)?;
+    hooks::validate(&settings.hooks)?;
+    state.update_settings(|s| *s = settings)
+}
+
+/// Repos and settings as a bundle another install can import.
+#[tauri::command]
+pub fn workspace_export(state: State<'_, AppState>) -> WorkspaceBundle {
+    let repo = state.repo_path.lock().unwrap().clone();
+    workspace::export(repo, &state.settings.lock().unwrap())
+}
+
+/// Restore a bundle from `workspace_export`, merging into or replacing the
+/// local setup (merge by default).
+#[tauri::command]
+pub fn workspace_import(
+    bundle: WorkspaceBundle,
+    mode: Option<ImportMode>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    workspace::validate(&bundle)?;
+    validate_redaction(&bundle.settings.redact_patterns)?;
+    let mode = mode.unwrap_or_default();
+    if let Some(repo) = bundle.repos.first() {
+        let mut current = state.repo_path.lock().unwrap();
+        if mode == ImportMode::Replace || current.is_none() {
+            *current = Some(repo.clone());
+        }
+    }
+    state.update_settings(|s| workspace::apply(s, bundle.settings, mode))
+}
+
+fn validate_redaction(patterns: &[String]) -> Result<(), PiError> {
+    if redact::compile_patterns(patterns).len() != patterns.len() {
+        return Err(PiError::invalid_input("invalid redaction pattern"));
+    }
+    Ok(())
 }
 
 // ---------------------------------------------------------------------------
 // PTY commands
 // ---------------------------------------------------------------------------
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
 pub struct SpawnArgs {
+    /// Defaults to the repo's `default_agent` (`.pi-builder.toml`).
+    #[serde(default)]
     pub agent_id: String,
     pub cmd: Vec<String>,
     pub cwd: Option<String>,
+    /// Run inside this worktree, creating it first if needed. Takes
+    /// precedence over `cwd`.
+    pub worktree: Option<String>,
+    /// Commit to create `worktree` at instead of the repo's HEAD.
+    pub base: Option<String>,
     pub cols: Option<u16>,
     pub rows: Option<u16>,
+    /// Encoding for `pty://data` payloads; `utf8` when omitted.
+    #[serde(default)]
+    pub encoding: DataEncoding,
+    /// `native` (default), `wsl`, or `wsl:<distro>`.
+    #[serde(default)]
+    pub target: SpawnTarget,
+    /// Run inside a Docker/Podman container with the cwd bind-mounted.
+    pub container: Option<ContainerSpec>,
+    /// Run over SSH; defaults to the agent profile's `ssh` config.
+    pub ssh: Option<SshTarget>,
+    /// Hold output until the frontend acks it with `pty_ack`.
+    #[serde(default)]
+    pub flow_control: bool,
+    /// Confirm dangerous input lines; see `pty_confirm`.
+    #[serde(default)]
+    pub guarded: bool,
+    /// Wait for a free slot instead of failing when over a session limit.
+    #[serde(default)]
+    pub queue: bool,
+    /// Run under tmux so the session survives restarting the app.
+    #[serde(default)]
+    pub detached: bool,
+    /// `pty` or `tmux` (a window of the shared `pi` tmux session);
+    /// defaults to the terminal profile's.
+    pub backend: Option<SessionBackend>,
+    /// Initial title, tags and group.
+    #[serde(default, flatten)]
+    pub meta: SessionMeta,
+    /// Don't check out submodules when creating `worktree`.
+    #[serde(default)]
+    pub skip_submodules: bool,
+    /// Paths to check out when creating `worktree`, instead of the repo's
+    /// `sparse`; empty checks out everything.
+    pub sparse: Option<Vec<String>>,
+    /// `max_runtime_secs` / `max_idle_secs`; the session is stopped and
+    /// `pty://timeout/<id>` emitted when one runs out.
+    #[serde(default, flatten)]
+    pub limits: SessionLimits,
+    /// Terminal profile whose size, scrollback, recording, env, cwd policy
+    /// and backend fill in what isn't given here.
+    pub profile: Option<String>,
+    /// Start in the main checkout although `main_checkout_guard` asks for
+    /// confirmation.
+    #[serde(default)]
+    pub allow_main_checkout: bool,
+    /// Set by `session_handoff`.
+    #[serde(skip)]
+    pub handoff_from: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct SpawnResult {
-    pub session_id: String,
+    /// `None` when the spawn was queued.
+    pub session_id: Option<String>,
+    /// Worktree the session runs in, when spawned with `worktree`.
+    pub worktree_path: Option<String>,
+    /// Setup hook session, if the worktree had to be created.
+    pub setup_session_id: Option<String>,
+    /// Set when the spawn waits for a free slot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_id: Option<String>,
 }
 
+/// Start a session. Over a session limit this fails with `session_limit`,
+/// or with `queue` waits: `pty://queued/<queueId>` is emitted now and
+/// `pty://started/<queueId>` once the session runs.
 #[tauri::command]
 pub async fn pty_spawn(
     args: SpawnArgs,
     state: State<'_, AppState>,
-    app: AppHandle,
-) -> Result<SpawnResult, String> {
-    let mut mgr = state.pty.lock().unwrap();
-    let cwd = args.cwd.or_else(|| {
-        state.repo_path.lock().unwrap().clone()
+) -> Result<SpawnResult, PiError> {
+    let mut args = args;
+    if args.agent_id.is_empty() {
+        let config = state.repo().ok().map(|repo| RepoConfig::load_or_default(Path::new(&repo)));
+        let Some(agent) = config.and_then(|c| c.default_agent) else {
+            return Err(PiError::invalid_input(format!(
+                "no agent_id given and no default_agent in {}",
+                repo_config::CONFIG_FILE
+            )));
+        };
+        if args.cmd.is_empty() {
+            let settings = state.settings.lock().unwrap();
+            if let Some(profile) = agents::resolve(&agent, &settings.agent_profiles) {
+                args.cmd = profile.argv(None);
+            }
+        }
+        args.agent_id = agent;
+    }
+
+    let repo = state.repo_path.lock().unwrap().clone();
+    let _slot = match reserve_slot(&state, repo.as_deref()) {
+        Ok(slot) => slot,
+        Err(reason) => {
+            if !args.queue {
+                return Err(PiError::new(ErrorCode::SessionLimit, reason));
+            }
+            let agent_id = args.agent_id.clone();
+            let queued = state.spawn_queue.push(repo, &agent_id, args);
+            let mut payload = serde_json::to_value(&queued).unwrap_or_default();
+            payload["reason"] = reason.into();
+            state.events.emit(&format!("pty://queued/{}", queued.queue_id), payload);
+            return Ok(SpawnResult {
+                session_id: None,
+                worktree_path: None,
+                setup_session_id: None,
+                queue_id: Some(queued.queue_id),
+            });
+        }
+    };
+    spawn_session(args, &state)
+}
+
+/// What `session_id` was launched with, including a shell command line
+/// that repeats it. Also works for sessions of the previous run.
+#[tauri::command]
+pub fn pty_launch_snapshot(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<LaunchSnapshot, PiError> {
+    launch_snapshot(&state, &session_id)
+}
+
+/// Launch the agent of `session_id` again, as it was started, in a new
+/// session. A session that ran in a worktree gets a new worktree at the
+/// commit it started from. Session limits apply as for `pty_spawn`.
+#[tauri::command]
+pub async fn pty_respawn(
+    session_id: String,
+    queue: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<SpawnResult, PiError> {
+    let snapshot = launch_snapshot(&state, &session_id)?;
+    let mut args = SpawnArgs {
+        agent_id: snapshot.agent_id,
+        cmd: snapshot.cmd,
+        cwd: snapshot.cwd,
+        cols: Some(snapshot.cols),
+        rows: Some(snapshot.rows),
+        target: snapshot.target,
+        container: snapshot.container,
+        ssh: snapshot.ssh,
+        queue: queue.unwrap_or(false),
+        ..Default::default()
+    };
+    if let Some(git) = snapshot.git.filter(|g| g.worktree.is_some()) {
+        let repo = state.repo()?;
+        if snapshot.repo.as_deref().is_some_and(|r| r != repo) {
+            return Err(PiError::invalid_input(
+                "the session ran in another repo; open that repo to respawn it",
+            ));
+        }
+        args.worktree = Some(uuid::Uuid::new_v4().to_string());
+        args.base = Some(git.commit);
+    }
+    pty_spawn(args, state).await
+}
+
+/// Switch the agent working in a session's worktree: stop `old_session`,
+/// then start `new_agent_profile` in the same worktree (or cwd) with the
+/// same size, title, tags, group, target and container. The old session
+/// is killed, or with `detach` a detached one keeps running under tmux.
+/// Nothing in the worktree is touched. The new session's launch snapshot
+/// and usage record name the old one as `handoffFrom`, and
+/// `pty://handoff/<old id>` is emitted with both ids. Session limits don't
+/// apply, as a slot is freed first.
+#[tauri::command]
+pub async fn session_handoff(
+    old_session: String,
+    new_agent_profile: String,
+    detach: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<SpawnResult, PiError> {
+    let (launch, meta, detached, backend) = {
+        let pty = state.pty.lock().unwrap();
+        let session = pty.session(&old_session)?;
+        let meta = session.meta.lock().unwrap().clone();
+        (session.launch.clone(), meta, session.detached, session.backend)
+    };
+    let profile = {
+        let settings = state.settings.lock().unwrap();
+        agents::resolve(&new_agent_profile, &settings.agent_profiles)
+    };
+    let Some(profile) = profile else {
+        return Err(PiError::invalid_input(format!("no agent profile {new_agent_profile}")));
+    };
+    let worktree = launch.git.and_then(|g| g.worktree);
+    let _lock = match &worktree {
+        Some(name) => {
+            let repo = state.repo()?;
+            if launch.repo.as_deref().is_some_and(|r| r != repo) {
+                return Err(PiError::invalid_input(
+                    "the session runs in another repo; open that repo to hand it off",
+                ));
+            }
+            Some(state.worktree_locks.worktree(&repo, name, "handoff")?)
+        }
+        None => None,
+    };
+
+    if detach.unwrap_or(false) {
+        state.pty.lock().unwrap().detach(&old_session)?;
+    } else {
+        state.pty.lock().unwrap().kill(&old_session);
+    }
+    state.playbacks.stop(&old_session);
+    state.recordings.forget(&old_session);
+    state.pipes.close_session(&old_session);
+
+    let args = SpawnArgs {
+        agent_id: profile.id.clone(),
+        cmd: profile.argv(None),
+        cwd: launch.cwd,
+        worktree,
+        cols: Some(launch.cols),
+        rows: Some(launch.rows),
+        target: launch.target,
+        container: launch.container,
+        detached,
+        backend: Some(backend),
+        meta,
+        handoff_from: Some(old_session.clone()),
+        ..Default::default()
+    };
+    let result = spawn_session(args, &state)?;
+    state.events.emit(
+        &format!("pty://handoff/{old_session}"),
+        serde_json::json!({
+            "oldSessionId": old_session,
+            "newSessionId": result.session_id,
+            "agentId": profile.id,
+        }),
+    );
+    Ok(result)
+}
+
+fn launch_snapshot(state: &AppState, session_id: &str) -> Result<LaunchSnapshot, PiError> {
+    if let Ok(snapshot) = state.pty.lock().unwrap().launch_snapshot(session_id) {
+        return Ok(snapshot);
+    }
+    state
+        .previous_sessions
+        .iter()
+        .find(|r| r.session_id == session_id)
+        .and_then(|r| r.launch.clone())
+        .ok_or_else(|| PiError::session_not_found(session_id))
+}
+
+/// Take a session slot in `repo`, or say why the session limits don't
+/// allow another session there now. Hold it until the session is spawned.
+fn reserve_slot<'a>(state: &'a AppState, repo: Option<&str>) -> Result<Slot<'a>, String> {
+    state.spawn_queue.reserve(repo, |starting, starting_in_repo| {
+        let (running, in_repo) = state.pty.lock().unwrap().running(repo);
+        let (running, in_repo) = (running + starting, in_repo + starting_in_repo);
+        state.settings.lock().unwrap().concurrency.refusal(running, in_repo, repo)
+    })
+}
+
+/// Start queued spawns that fit within the session limits now, emitting
+/// `pty://started/<queueId>` with the result or error of each.
+pub fn start_queued(state: &State<'_, AppState>) {
+    loop {
+        // Held until the session is spawned
+        let mut slot = None;
+        let ready = state.spawn_queue.pop_ready(|repo| {
+            slot = reserve_slot(state, repo).ok();
+            slot.is_some()
+        });
+        let Some((queued, args)) = ready else { break };
+        let payload = match spawn_session(args, state) {
+            Ok(result) => serde_json::json!({
+                "queueId": queued.queue_id,
+                "sessionId": result.session_id,
+                "worktreePath": result.worktree_path,
+                "setupSessionId": result.setup_session_id,
+            }),
+            Err(e) => serde_json::json!({ "queueId": queued.queue_id, "error": e }),
+        };
+        state.events.emit(&format!("pty://started/{}", queued.queue_id), payload);
+    }
+}
+
+fn spawn_session(args: SpawnArgs, state: &State<'_, AppState>) -> Result<SpawnResult, PiError> {
+    let term = match &args.profile {
+        Some(name) => {
+            let settings = state.settings.lock().unwrap();
+            let profile = terminal::resolve(name, &settings.terminal_profiles);
+            Some(profile.map_err(PiError::from)?.clone())
+        }
+        None => None,
+    };
+    let mut worktree_path = None;
+    let mut setup_session_id = None;
+    // The repo of a worktree created here, to remove if the spawn fails.
+    let mut created_in = None;
+    if let Some(name) = &args.worktree {
+        let repo = state.repo()?;
+        if worktree::worktree_exists(&repo, name)? {
+            let path = worktree::worktree_path(&repo, name)?;
+            worktree_path = Some(path.to_string_lossy().to_string());
+        } else {
+            let vars = BranchVars { agent: &args.agent_id, slug: args.meta.title.as_deref() };
+            let created = create_worktree_with_setup(
+                &state,
+                &repo,
+                name,
+                &vars,
+                args.base.as_deref(),
+                !args.skip_submodules,
+                args.sparse.clone(),
+            )?;
+            worktree_path = Some(created.info.path);
+            setup_session_id = created.setup_session_id;
+            created_in = Some(repo);
+        }
+    }
+
+    let mut opts = SpawnOptions::new(args.agent_id, args.cmd);
+    if worktree_path.is_some() {
+        opts.env = shared_cache_env(&state)?;
+    }
+    let repo_path = state.repo_path.lock().unwrap().clone();
+    opts.cwd = worktree_path.clone().or_else(|| match &term {
+        Some(term) => term.cwd(args.cwd, repo_path),
+        None => args.cwd.or(repo_path),
     });
-    mgr.spawn(
-        args.agent_id,
-        args.cmd,
-        cwd,
-        args.cols.unwrap_or(220),
-        args.rows.unwrap_or(50),
-        app,
-    )
-    .map(|session_id| SpawnResult { session_id })
-    .map_err(|e| e.to_string())
+    let size = state.terminal_size();
+    opts.cols = args.cols.or(term.as_ref().and_then(|t| t.cols)).unwrap_or(size.cols);
+    opts.rows = args.rows.or(term.as_ref().and_then(|t| t.rows)).unwrap_or(size.rows);
+    if let Some(term) = &term {
+        opts.env.extend(term.env_pairs());
+        opts.scrollback_lines = term.scrollback_lines;
+        opts.record = term.record;
+    }
+    opts.encoding = args.encoding;
+    opts.target = args.target;
+    opts.container = args.container;
+    opts.flow_control = args.flow_control;
+    opts.guarded = args.guarded;
+    opts.detached = args.detached;
+    opts.backend = args.backend.or(term.as_ref().map(|t| t.backend)).unwrap_or_default();
+    opts.limits = args.limits;
+    opts.meta.title = args.meta.title;
+    opts.meta.group = args.meta.group;
+    opts.meta.set_tags(args.meta.tags);
+    let profile = {
+        let settings = state.settings.lock().unwrap();
+        agents::resolve(&opts.agent_id, &settings.agent_profiles)
+    };
+    if let Some(profile) = &profile {
+        opts.env.extend(profile.env_pairs());
+    }
+    opts.ssh = args.ssh.or_else(|| profile.as_ref().and_then(|p| p.ssh.clone()));
+    opts.usage_patterns = profile.map(|p| p.usage_patterns()).unwrap_or_default();
+    opts.repo = state.repo_path.lock().unwrap().clone();
+    opts.handoff_from = args.handoff_from;
+    let worktree = args.worktree.as_deref();
+    let session_id = match spawn_checked(state, opts, worktree, args.allow_main_checkout) {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            if let Some(id) = &setup_session_id {
+                state.pty.lock().unwrap().kill(id);
+            }
+            if let (Some(repo), Some(name)) = (&created_in, worktree) {
+                if let Err(e) = worktree::remove_worktree(repo, name) {
+                    log::warn!("remove unused worktree {name}: {e:#}");
+                }
+                state.repo_cache.mark_stale(repo);
+            }
+            return Err(e);
+        }
+    };
+    Ok(SpawnResult {
+        session_id: Some(session_id),
+        worktree_path,
+        setup_session_id,
+        queue_id: None,
+    })
+}
+
+/// Run the pre-spawn hooks on `opts`, check it against the main checkout
+/// guard and spawn it.
+fn spawn_checked(
+    state: &AppState,
+    mut opts: SpawnOptions,
+    worktree: Option<&str>,
+    allow_main_checkout: bool,
+) -> Result<String, PiError> {
+    let (hooks, guard) = {
+        let settings = state.settings.lock().unwrap();
+        (settings.hooks.clone(), settings.main_checkout_guard)
+    };
+    hooks::pre_spawn(&hooks, &mut opts, worktree)?;
+    mainguard::check(
+        guard,
+        &opts.agent_id,
+        opts.cwd.as_deref(),
+        opts.repo.as_deref(),
+        allow_main_checkout,
+    )?;
+    Ok(state.pty.lock().unwrap().spawn(opts, state.events.clone())?)
+}
+
+/// Attach to the detached sessions that outlived the last run, under their
+/// old ids.
+fn reattach_detached(state: &AppState) {
+    let size = state.terminal_size();
+    for record in state.previous_sessions.iter().filter(|r| r.detached) {
+        let running = match record.backend {
+            SessionBackend::Pty => detach::is_running(&record.session_id),
+            SessionBackend::Tmux => detach::is_running_shared(&record.session_id),
+        };
+        if !running {
+            continue;
+        }
+        let mut opts = SpawnOptions::new(record.agent_id.clone(), record.cmd.clone());
+        opts.cwd = record.cwd.clone();
+        opts.cols = size.cols;
+        opts.rows = size.rows;
+        opts.meta = record.meta.clone();
+        opts.repo = record.repo.clone();
+        opts.detached = true;
+        opts.backend = record.backend;
+        opts.session_id = Some(record.session_id.clone());
+        opts.handoff_from = record.launch.as_ref().and_then(|l| l.handoff_from.clone());
+        let profile = {
+            let settings = state.settings.lock().unwrap();
+            agents::resolve(&record.agent_id, &settings.agent_profiles)
+        };
+        opts.usage_patterns = profile.map(|p| p.usage_patterns()).unwrap_or_default();
+        if let Err(e) = state.pty.lock().unwrap().spawn(opts, state.events.clone()) {
+            log::warn!("re-attach session {}: {e:#}", record.session_id);
+        }
+    }
+}
+
+/// Spawns waiting for a free session slot, oldest first.
+#[tauri::command]
+pub fn pty_queue_list(state: State<'_, AppState>) -> Vec<QueuedSpawn> {
+    state.spawn_queue.list()
+}
+
+/// Drop a queued spawn. Returns false if it already started.
+#[tauri::command]
+pub fn pty_queue_cancel(queue_id: String, state: State<'_, AppState>) -> bool {
+    state.spawn_queue.cancel(&queue_id)
+}
+
+/// Set the global and per-repo session limits. Lowering them stops no
+/// running session.
+#[tauri::command]
+pub fn set_concurrency(
+    concurrency: ConcurrencySettings,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.update_settings(|s| s.concurrency = concurrency)
+}
+
+/// The latest host metrics sample; `None` until the first is taken.
+#[tauri::command]
+pub fn system_metrics(state: State<'_, AppState>) -> Option<SystemMetrics> {
+    state.system.latest()
+}
+
+/// Set when queued tasks wait for the host: on battery or under load.
+/// Takes effect at the next sample.
+#[tauri::command]
+pub fn set_system_policy(policy: SystemPolicy, state: State<'_, AppState>) -> Result<(), PiError> {
+    if policy.max_load_per_cpu.is_some_and(|m| !m.is_finite() || m < 0.0) {
+        return Err(PiError::invalid_input("load threshold must be a positive number"));
+    }
+    state.update_settings(|s| s.system = policy)
+}
+
+/// Check git, the shell, PTYs, the worktree location, the keychain and
+/// every agent profile's binary, with a fix for each problem found.
+#[tauri::command]
+pub async fn doctor(state: State<'_, AppState>) -> Result<DoctorReport, PiError> {
+    let settings = state.settings.lock().unwrap().clone();
+    let repo = state.repo_path.lock().unwrap().clone();
+    Ok(doctor::run(&settings, repo.as_deref()))
+}
+
+/// Data protocol version and supported encodings, for negotiation.
+#[tauri::command]
+pub fn pty_protocol() -> ProtocolInfo {
+    codec::protocol_info()
 }
 
 #[tauri::command]
@@ -55,8 +865,361 @@ pub fn pty_input(
     session_id: String,
     data: String,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    state.pty.lock().unwrap().write(&session_id, &data).map_err(|e| e.to_string())
+) -> Result<(), PiError> {
+    let filtered = state.pty.lock().unwrap().input(&session_id, &data)?;
+    state.recordings.record(&session_id, &data);
+    emit_guard(&state.events, &session_id, &filtered);
+    Ok(())
+}
+
+/// Lines typed into a session, newest first, for up-arrow recall. Input
+/// for password prompts isn't kept.
+#[tauri::command]
+pub fn pty_input_history(
+    session_id: String,
+    limit: Option<usize>,
+    state: State<'_, AppState>,
+) -> Result<Vec<HistoryEntry>, PiError> {
+    let limit = limit.unwrap_or(inputhistory::MAX_ENTRIES);
+    Ok(state.pty.lock().unwrap().input_history(&session_id, limit)?)
+}
+
+/// Lines typed into any session containing `query`, ignoring case, newest
+/// first.
+#[tauri::command]
+pub fn pty_input_history_search(
+    query: String,
+    limit: Option<usize>,
+    state: State<'_, AppState>,
+) -> Vec<HistoryMatch> {
+    let limit = limit.unwrap_or(100);
+    state.pty.lock().unwrap().search_input_history(&query, limit)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastResult {
+    pub session_id: String,
+    /// Why the input didn't reach the session.
+    pub error: Option<String>,
+}
+
+/// Type the same input into several sessions, each through its guard.
+/// One failing doesn't stop the others.
+#[tauri::command]
+pub fn pty_broadcast(
+    session_ids: Vec<String>,
+    data: String,
+    state: State<'_, AppState>,
+) -> Vec<BroadcastResult> {
+    session_ids
+        .into_iter()
+        .map(|session_id| {
+            let result = state.pty.lock().unwrap().input(&session_id, &data);
+            let error = match result {
+                Ok(filtered) => {
+                    state.recordings.record(&session_id, &data);
+                    emit_guard(&state.events, &session_id, &filtered);
+                    None
+                }
+                Err(e) => Some(PiError::from(e).message),
+            };
+            BroadcastResult { session_id, error }
+        })
+        .collect()
+}
+
+/// Forward `from_session`'s output lines, those matching the `filter`
+/// regex if given, into `to_session`: pasted in batches once the output
+/// goes quiet, each followed by Enter. See `pipe.rs`.
+#[tauri::command]
+pub fn pty_pipe(
+    from_session: String,
+    to_session: String,
+    filter: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<PipeInfo, PiError> {
+    let taps = {
+        let pty = state.pty.lock().unwrap();
+        pty.bracketed_paste(&to_session)?;
+        pty.output_taps(&from_session)?
+    };
+    let (pty, events, to) = (state.pty.clone(), state.events.clone(), to_session.clone());
+    let write = move |text: &str| {
+        let pty = pty.lock().unwrap();
+        let bracketed = pty.bracketed_paste(&to)?;
+        let filtered = pty.input(&to, &format!("{}\r", paste::encode(text, bracketed)))?;
+        emit_guard(&events, &to, &filtered);
+        Ok(())
+    };
+    let events = state.events.clone();
+    state.pipes.open(&from_session, &to_session, filter, taps, write, events).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn pty_pipes(state: State<'_, AppState>) -> Vec<PipeInfo> {
+    state.pipes.list()
+}
+
+#[tauri::command]
+pub fn pty_unpipe(pipe_id: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.pipes.close(&pipe_id).map_err(PiError::from)
+}
+
+/// Answer a `pty://confirm` event: submit the held line, or drop it along
+/// with the input typed after it.
+#[tauri::command]
+pub fn pty_confirm(
+    session_id: String,
+    token: String,
+    allow: bool,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let filtered = state.pty.lock().unwrap().confirm_input(&session_id, &token, allow)?;
+    emit_guard(&state.events, &session_id, &filtered);
+    Ok(())
+}
+
+/// Turn guarded mode on or off for a running session.
+#[tauri::command]
+pub fn pty_set_guarded(
+    session_id: String,
+    guarded: bool,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.pty.lock().unwrap().set_guarded(&session_id, guarded).map_err(PiError::from)
+}
+
+/// `pty://confirm/<id>` for a line the guard held (with a `token` to pass
+/// to `pty_confirm`) or refused (`denied`, no token).
+fn emit_guard(events: &SharedSink, session_id: &str, filtered: &Filtered) {
+    let event = format!("pty://confirm/{session_id}");
+    if let Some(held) = &filtered.held {
+        events.emit(
+            &event,
+            serde_json::json!({
+                "sessionId": session_id,
+                "token": held.token,
+                "line": held.line,
+                "reasons": held.reasons,
+                "denied": false,
+            }),
+        );
+    } else if let Some(denied) = &filtered.denied {
+        events.emit(
+            &event,
+            serde_json::json!({
+                "sessionId": session_id,
+                "token": null,
+                "line": denied.line,
+                "reasons": denied.reasons,
+                "denied": true,
+            }),
+        );
+    }
+}
+
+/// Paste `data` into a session, bracketed if the application enabled
+/// bracketed paste. When the paste guard flags it, emits
+/// `pty://paste-confirm/<id>` and waits for `pty_paste_confirm`; a paste
+/// that is declined or not answered in time is dropped.
+#[tauri::command]
+pub async fn pty_paste(
+    session_id: String,
+    data: String,
+    state: State<'_, AppState>,
+) -> Result<PasteResult, PiError> {
+    // Fail fast on an unknown session rather than after a confirmation
+    state.pty.lock().unwrap().bracketed_paste(&session_id)?;
+    let settings = state.settings.lock().unwrap().paste.clone();
+    let flagged = if settings.confirm { paste::check(&data, &settings) } else { Vec::new() };
+    if !flagged.is_empty() {
+        let paste_id = uuid::Uuid::new_v4().to_string();
+        let answer = state.pastes.wait(&paste_id);
+        state.events.emit(
+            &format!("pty://paste-confirm/{session_id}"),
+            serde_json::json!({
+                "sessionId": session_id,
+                "pasteId": paste_id,
+                "lines": data.lines().count(),
+                "reasons": flagged,
+            }),
+        );
+        let timeout = Duration::from_secs(settings.confirm_timeout_secs);
+        let accepted = matches!(tokio::time::timeout(timeout, answer).await, Ok(Ok(true)));
+        state.pastes.forget(&paste_id);
+        if !accepted {
+            return Ok(PasteResult { pasted: false, bracketed: false, flagged });
+        }
+    }
+    let pty = state.pty.lock().unwrap();
+    let bracketed = pty.bracketed_paste(&session_id)?;
+    pty.write(&session_id, &paste::encode(&data, bracketed))?;
+    state.recordings.record(&session_id, &data);
+    Ok(PasteResult { pasted: true, bracketed, flagged })
+}
+
+/// Answer a `pty://paste-confirm` event. Returns false if the paste already
+/// timed out.
+#[tauri::command]
+pub fn pty_paste_confirm(paste_id: String, accept: bool, state: State<'_, AppState>) -> bool {
+    state.pastes.answer(&paste_id, accept)
+}
+
+/// Feed a file to a session, e.g. one dropped onto its terminal: type its
+/// path, paste its text, or recreate it in the session from base64 (see
+/// `sendfile.rs`). The file is streamed in chunks without holding up other
+/// sessions.
+#[tauri::command]
+pub async fn pty_send_file(
+    session_id: String,
+    path: String,
+    mode: SendMode,
+    state: State<'_, AppState>,
+) -> Result<SentFile, PiError> {
+    let (session, launch, bracketed) = {
+        let pty = state.pty.lock().unwrap();
+        let bracketed = pty.bracketed_paste(&session_id)?;
+        (pty.session(&session_id)?, pty.launch_snapshot(&session_id)?, bracketed)
+    };
+    sendfile::send_file(Path::new(&path), mode, &launch, bracketed, |data| session.write(data))
+        .map_err(PiError::from)
+}
+
+/// Start recording a session's input, dropping its last recording, or
+/// stop and keep what was recorded for `macro_save`.
+#[tauri::command]
+pub fn macro_record(
+    session_id: String,
+    recording: bool,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    // Fail on an unknown session rather than record nothing
+    state.pty.lock().unwrap().bracketed_paste(&session_id)?;
+    state.recordings.set(&session_id, recording);
+    Ok(())
+}
+
+/// Save what `from_session` recorded as the macro `name`, replacing one of
+/// that name.
+#[tauri::command]
+pub fn macro_save(
+    name: String,
+    from_session: String,
+    state: State<'_, AppState>,
+) -> Result<Macro, PiError> {
+    let steps = state.recordings.steps(&from_session);
+    state.macros.save(&name, steps).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn macro_list(state: State<'_, AppState>) -> Vec<Macro> {
+    state.macros.list()
+}
+
+#[tauri::command]
+pub fn macro_delete(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.macros.delete(&name).map_err(PiError::from)
+}
+
+/// Type a macro into a session at its recorded pace times `speed` (default
+/// 1; 2 plays twice as fast). Returns once playback has started; emits
+/// `macro://played/<id>` with `completed`, false if it was stopped or a
+/// write failed.
+#[tauri::command]
+pub fn macro_play(
+    session_id: String,
+    name: String,
+    speed: Option<f64>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let played = state.macros.get(&name)?;
+    state.pty.lock().unwrap().bracketed_paste(&session_id)?;
+    let (pty, events) = (state.pty.clone(), state.events.clone());
+    let id = session_id.clone();
+    let write = move |data: &str| {
+        let filtered = pty.lock().unwrap().input(&id, data)?;
+        emit_guard(&events, &id, &filtered);
+        Ok(())
+    };
+    let (events, id) = (state.events.clone(), session_id.clone());
+    let done = move |result: anyhow::Result<bool>| {
+        let error = result.as_ref().err().map(|e| format!("{e:#}"));
+        events.emit(
+            &format!("macro://played/{id}"),
+            serde_json::json!({
+                "sessionId": id,
+                "name": name,
+                "completed": matches!(result, Ok(true)),
+                "error": error,
+            }),
+        );
+    };
+    state.playbacks.play(&session_id, played.steps, speed.unwrap_or(1.0), write, done)?;
+    Ok(())
+}
+
+/// Stop a session's macro playback. Returns whether one was playing.
+#[tauri::command]
+pub fn macro_stop(session_id: String, state: State<'_, AppState>) -> bool {
+    state.playbacks.stop(&session_id)
+}
+
+/// Save an action, replacing one of the same name.
+#[tauri::command]
+pub fn action_save(action: Action, state: State<'_, AppState>) -> Result<Action, PiError> {
+    state.actions.save(action).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn action_list(state: State<'_, AppState>) -> Vec<Action> {
+    state.actions.list()
+}
+
+#[tauri::command]
+pub fn action_delete(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.actions.delete(&name).map_err(PiError::from)
+}
+
+/// Run the action `name` in a session, step by step, holding the session's
+/// worktree lock throughout; see `actions.rs`. Step failures are reported
+/// in the result rather than failing the call.
+#[tauri::command]
+pub async fn action_run(
+    name: String,
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<ActionRun, PiError> {
+    let action = state.actions.get(&name)?;
+    let launch = state.pty.lock().unwrap().launch_snapshot(&session_id)?;
+    let target = launch.repo.zip(launch.git.and_then(|g| g.worktree));
+    let _lock = match &target {
+        Some((repo, worktree)) => Some(state.worktree_locks.worktree(repo, worktree, "action")?),
+        None => None,
+    };
+    let input = |data: &str| -> anyhow::Result<Option<String>> {
+        let filtered = state.pty.lock().unwrap().input(&session_id, data)?;
+        emit_guard(&state.events, &session_id, &filtered);
+        Ok(None)
+    };
+    let run = state.action_runs.run(&action, &session_id, |step| match step {
+        ActionStep::Snapshot { label } => {
+            let Some((repo, worktree)) = &target else {
+                return Err(PiError::invalid_input("session isn't in a worktree").into());
+            };
+            let label = label.as_deref().unwrap_or(&action.name);
+            Ok(Some(snapshot::snapshot(repo, worktree, label)?.id))
+        }
+        ActionStep::Send { text, enter } => {
+            input(&if *enter { format!("{text}\r") } else { text.clone() })
+        }
+        ActionStep::Interrupt => input("\x03"),
+        ActionStep::Wait { ms } => {
+            std::thread::sleep(Duration::from_millis(*ms));
+            Ok(None)
+        }
+    })?;
+    Ok(run)
 }
 
 #[tauri::command]
@@ -65,66 +1228,1688 @@ pub fn pty_resize(
     cols: u16,
     rows: u16,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    state.pty.lock().unwrap().resize(&session_id, cols, rows).map_err(|e| e.to_string())
+) -> Result<(), PiError> {
+    state.pty.lock().unwrap().resize(&session_id, cols, rows).map_err(PiError::from)
 }
 
 #[tauri::command]
 pub fn pty_kill(session_id: String, state: State<'_, AppState>) {
     state.pty.lock().unwrap().kill(&session_id);
+    state.playbacks.stop(&session_id);
+    state.recordings.forget(&session_id);
+    state.pipes.close_session(&session_id);
+}
+
+/// The session's child process and everything it spawned, or `None` once
+/// it has exited.
+#[tauri::command]
+pub fn pty_process_tree(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Option<ProcessNode>, PiError> {
+    let pid = state.pty.lock().unwrap().pid(&session_id)?;
+    Ok(proctree::tree(pid))
 }
 
+/// Terminate one process spawned under a session (SIGKILL with `force`),
+/// leaving the session running.
 #[tauri::command]
-pub fn pty_list(state: State<'_, AppState>) -> Vec<serde_json::Value> {
-    state.pty.lock().unwrap().list()
+pub fn pty_kill_process(
+    session_id: String,
+    pid: u32,
+    force: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let root = state.pty.lock().unwrap().pid(&session_id)?;
+    proctree::kill_descendant(root, pid, force.unwrap_or(false)).map_err(PiError::from)
 }
 
-// ---------------------------------------------------------------------------
-// Worktree commands
-// ---------------------------------------------------------------------------
+/// Acknowledge `bytes` of output rendered from a session spawned with
+/// `flow_control`, letting the emitter send more.
+#[tauri::command]
+pub fn pty_ack(
+    session_id: String,
+    bytes: usize,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.pty.lock().unwrap().ack(&session_id, bytes).map_err(PiError::from)
+}
 
+/// Find text in a session's scrollback, ANSI codes stripped. `query` is
+/// literal unless `regex`; matching ignores case unless `case_sensitive`.
+/// Returns the newest `max_results` matches (default 500).
 #[tauri::command]
-pub fn worktree_create(
+pub fn pty_search(
     session_id: String,
+    query: String,
+    regex: bool,
+    case_sensitive: Option<bool>,
+    max_results: Option<usize>,
     state: State<'_, AppState>,
-) -> Result<worktree::WorktreeInfo, String> {
-    let repo = state
-        .repo_path
-        .lock()
-        .unwrap()
-        .clone()
-        .ok_or("no repo configured")?;
-    worktree::create_worktree(&repo, &session_id).map_err(|e| e.to_string())
+) -> Result<SearchResult, PiError> {
+    // Spilled scrollback can take a while; don't hold up other sessions
+    let scrollback = state.pty.lock().unwrap().scrollback(&session_id)?;
+    let scrollback = scrollback.lock().unwrap();
+    scrollback
+        .search(&query, regex, case_sensitive.unwrap_or(false), max_results.unwrap_or(500))
+        .map_err(PiError::from)
+}
+
+/// The session's current screen: cell grid, cursor, and escape sequences
+/// that redraw it, for restoring a terminal without replaying its output.
+#[tauri::command]
+pub fn pty_screen(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<ScreenSnapshot, PiError> {
+    state.pty.lock().unwrap().screen(&session_id).map_err(PiError::from)
+}
+
+/// A session's scrollback as plain text, escape sequences applied and
+/// removed. With `path`, the text is written to that file (absolute)
+/// instead of being returned.
+#[tauri::command]
+pub fn pty_export_text(
+    session_id: String,
+    range: Option<LineRange>,
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ExportedText, PiError> {
+    let scrollback = state.pty.lock().unwrap().scrollback(&session_id)?;
+    let mut exported = scrollback.lock().unwrap().export(range.unwrap_or_default())?;
+    if let Some(path) = path {
+        if !Path::new(&path).is_absolute() {
+            return Err(PiError::invalid_input("export path must be absolute"));
+        }
+        std::fs::write(&path, std::mem::take(&mut exported.text))?;
+        exported.path = Some(path);
+    }
+    Ok(exported)
+}
+
+/// Output queue depth and drop/spill counters for a session.
+#[tauri::command]
+pub fn pty_stats(session_id: String, state: State<'_, AppState>) -> Result<QueueStats, PiError> {
+    state.pty.lock().unwrap().stats(&session_id).map_err(PiError::from)
+}
+
+/// Token and cost totals per agent and task for sessions started within
+/// `range`, running ones included.
+#[tauri::command]
+pub fn usage_report(range: Option<UsageRange>, state: State<'_, AppState>) -> UsageReport {
+    let records = state.pty.lock().unwrap().usage_records();
+    usage::report(records, range.unwrap_or_default())
+}
+
+/// Finished sessions matching `filter`, newest first, with their exit
+/// summaries. Returns at most `limit` (default 100).
+#[tauri::command]
+pub fn session_history(
+    limit: Option<usize>,
+    filter: Option<HistoryFilter>,
+    state: State<'_, AppState>,
+) -> Vec<UsageRecord> {
+    let records = state.pty.lock().unwrap().usage_records();
+    summary::history(records, limit.unwrap_or(100), &filter.unwrap_or_default())
+}
+
+/// Sessions matching `filter`, or all of them.
+#[tauri::command]
+pub fn pty_list(
+    filter: Option<SessionFilter>,
+    state: State<'_, AppState>,
+) -> Vec<serde_json::Value> {
+    state.pty.lock().unwrap().list(&filter.unwrap_or_default())
 }
 
+/// Set or clear (`None`) a session's display title.
 #[tauri::command]
-pub fn worktree_list(state: State<'_, AppState>) -> Result<Vec<worktree::WorktreeInfo>, String> {
-    let repo = state
-        .repo_path
+pub fn pty_rename(
+    session_id: String,
+    title: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, PiError> {
+    let title = title.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    state
+        .pty
         .lock()
         .unwrap()
-        .clone()
-        .ok_or("no repo configured")?;
-    worktree::list_worktrees(&repo).map_err(|e| e.to_string())
+        .update_meta(&session_id, |m| m.title = title, &state.events)
+        .map_err(PiError::from)
 }
 
+/// Replace a session's tags, and its group when `group` is given (an empty
+/// string clears it).
 #[tauri::command]
-pub fn worktree_remove(
-    name: String,
+pub fn pty_tag(
+    session_id: String,
+    tags: Vec<String>,
+    group: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let repo = state
-        .repo_path
+) -> Result<serde_json::Value, PiError> {
+    state
+        .pty
         .lock()
         .unwrap()
-        .clone()
-        .ok_or("no repo configured")?;
-    worktree::remove_worktree(&repo, &name).map_err(|e| e.to_string())
+        .update_meta(
+            &session_id,
+            |m| {
+                m.set_tags(tags);
+                if let Some(group) = group {
+                    let group = group.trim();
+                    m.group = (!group.is_empty()).then(|| group.to_string());
+                }
+            },
+            &state.events,
+        )
+        .map_err(PiError::from)
+}
+
+/// Output log file of a session, or `None` if it isn't being logged.
+#[tauri::command]
+pub fn pty_log_path(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Option<String>, PiError> {
+    let path = state.pty.lock().unwrap().log_path(&session_id)?;
+    Ok(path.map(|p| p.to_string_lossy().to_string()))
+}
+
+#[tauri::command]
+pub fn get_log_settings(state: State<'_, AppState>) -> LogSettings {
+    state.settings.lock().unwrap().logs.clone()
+}
+
+/// Replace output logging settings. Applies to sessions spawned after the
+/// call.
+#[tauri::command]
+pub fn set_log_settings(logs: LogSettings, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.update_settings(|s| s.logs = logs)
+}
+
+/// Timed session recordings, most recent first.
+#[tauri::command]
+pub fn pty_recordings(state: State<'_, AppState>) -> Vec<RecordingInfo> {
+    recording::list(&state.log_dir)
+}
+
+/// Open a session recording for playback, paused at its start.
+#[tauri::command]
+pub fn pty_playback_open(
+    recording_id: String,
+    state: State<'_, AppState>,
+) -> Result<PlaybackInfo, PiError> {
+    state.recording_playbacks.open(&state.log_dir, &recording_id).map_err(PiError::from)
+}
+
+/// Move a playback to `t` ms into its recording; returns the screen then.
+#[tauri::command]
+pub fn playback_seek(
+    playback_id: String,
+    t: u64,
+    state: State<'_, AppState>,
+) -> Result<ScreenSnapshot, PiError> {
+    let events = state.events.clone();
+    state.recording_playbacks.seek(&playback_id, t, events).map_err(PiError::from)
+}
+
+/// Play from the current position at `speed` times real time (default:
+/// the last speed, initially 1).
+#[tauri::command]
+pub fn playback_play(
+    playback_id: String,
+    speed: Option<f64>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let events = state.events.clone();
+    state.recording_playbacks.play(&playback_id, speed, events).map_err(PiError::from)
+}
+
+/// Pause a playback; returns its position in ms.
+#[tauri::command]
+pub fn playback_pause(playback_id: String, state: State<'_, AppState>) -> Result<u64, PiError> {
+    state.recording_playbacks.pause(&playback_id).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn playback_close(playback_id: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.recording_playbacks.close(&playback_id).map_err(PiError::from)
+}
+
+/// Replace the output queue bound and overflow policy. Applies to sessions
+/// spawned after the call.
+#[tauri::command]
+pub fn set_output_settings(
+    output: FlowSettings,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.update_settings(|s| s.output = output)
+}
+
+#[tauri::command]
+pub fn set_paste_settings(
+    paste: PasteSettings,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.update_settings(|s| s.paste = paste)
+}
+
+/// Replace the confirm/deny patterns of guarded mode. Applies to sessions
+/// guarded after the call.
+#[tauri::command]
+pub fn set_guard_settings(
+    guard: GuardSettings,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    guard.validate().map_err(|e| PiError::invalid_input(format!("{e:#}")))?;
+    state.update_settings(|s| s.guard = guard)
+}
+
+/// Audit log entries recorded within `range` that match `filter`, oldest
+/// first.
+#[tauri::command]
+pub fn audit_query(
+    range: Option<AuditRange>,
+    filter: Option<AuditFilter>,
+    state: State<'_, AppState>,
+) -> Vec<AuditEntry> {
+    state.audit.query(range.unwrap_or_default(), &filter.unwrap_or_default())
+}
+
+/// Sessions that were open when the app last exited.
+#[tauri::command]
+pub fn pty_previous_sessions(state: State<'_, AppState>) -> Vec<SessionRecord> {
+    state.previous_sessions.clone()
+}
+
+#[tauri::command]
+pub fn set_exit_behavior(
+    behavior: ExitBehavior,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.update_settings(|s| s.exit_behavior = behavior)
+}
+
+// ---------------------------------------------------------------------------
+// Worktree commands
+// ---------------------------------------------------------------------------
+
+#[derive(Serialize)]
+pub struct WorktreeCreated {
+    #[serde(flatten)]
+    pub info: worktree::WorktreeInfo,
+    /// PTY session running the repo's setup hook, if it has one.
+    pub setup_session_id: Option<String>,
+    /// Submodules checked out in the new worktree.
+    pub submodules: Vec<SubmoduleReport>,
+    /// Result of `git lfs pull`, when the repo uses LFS.
+    pub lfs: Option<LfsReport>,
+    /// Cache directories linked from the main checkout.
+    pub shared_caches: Vec<CacheLinkReport>,
+    /// File counts and size warnings; `None` if they couldn't be taken.
+    pub scale: Option<ScaleReport>,
+}
+
+/// Create a worktree, check out its submodules (unless `skip_submodules`)
+/// and start the repo's setup hook. Submodule fetches emit
+/// `worktree://submodule/<name>` progress. `agent_id` and `slug` fill the
+/// branch name template. `sparse` paths replace the repo's `sparse` list;
+/// an empty one checks out everything.
+#[tauri::command]
+pub async fn worktree_create(
+    session_id: String,
+    skip_submodules: Option<bool>,
+    agent_id: Option<String>,
+    slug: Option<String>,
+    sparse: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<WorktreeCreated, PiError> {
+    let repo = state.repo()?;
+    let vars = BranchVars { agent: agent_id.as_deref().unwrap_or("agent"), slug: slug.as_deref() };
+    let init_submodules = !skip_submodules.unwrap_or(false);
+    create_worktree_with_setup(&state, &repo, &session_id, &vars, None, init_submodules, sparse)
+}
+
+/// Create worktree `name` on a branch off worktree `source_worktree`'s HEAD
+/// rather than the base, so an agent can build on another's committed work,
+/// and record the source as its `parent`. Otherwise as `worktree_create`.
+#[tauri::command]
+pub async fn worktree_create_from(
+    name: String,
+    source_worktree: String,
+    skip_submodules: Option<bool>,
+    agent_id: Option<String>,
+    slug: Option<String>,
+    sparse: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<WorktreeCreated, PiError> {
+    let repo = state.repo()?;
+    let parent = worktree::parent_from(&repo, &source_worktree)?;
+    let vars = BranchVars { agent: agent_id.as_deref().unwrap_or("agent"), slug: slug.as_deref() };
+    let init_submodules = !skip_submodules.unwrap_or(false);
+    let base = Some(parent.commit.as_str());
+    let mut created =
+        create_worktree_with_setup(&state, &repo, &name, &vars, base, init_submodules, sparse)?;
+    worktree::record_parent(&repo, &name, &parent)?;
+    state.repo_cache.mark_stale(&repo);
+    created.info.parent = Some(parent);
+    Ok(created)
+}
+
+/// Create a worktree at `base` (default HEAD), check out its submodules if
+/// asked, and start the repo's setup hook in it, if any. Only `sparse`
+/// paths, or without it the repo's, are checked out.
+fn create_worktree_with_setup(
+    state: &State<'_, AppState>,
+    repo: &str,
+    name: &str,
+    vars: &BranchVars,
+    base: Option<&str>,
+    init_submodules: bool,
+    sparse: Option<Vec<String>>,
+) -> Result<WorktreeCreated, PiError> {
+    let config = RepoConfig::load_or_default(Path::new(repo));
+    let (root, template) = {
+        let settings = state.settings.lock().unwrap();
+        let template = config.branch_template.clone().or(settings.branch_template.clone());
+        (settings.worktree_root.clone(), template)
+    };
+    let sparse = sparse.unwrap_or_else(|| config.sparse.clone());
+    sparse::validate(&sparse)?;
+    let info = worktree::create_worktree(
+        repo,
+        name,
+        root.as_deref(),
+        template.as_deref(),
+        vars,
+        base,
+        &sparse,
+    )?;
+    state.repo_cache.mark_stale(repo);
+    let hooks = state.settings.lock().unwrap().hooks.clone();
+    let input = serde_json::json!({
+        "repo": repo,
+        "worktree": name,
+        "path": info.path,
+        "branch": info.branch,
+    });
+    if let Err(e) = hooks::run(&hooks, HookPoint::PostWorktreeCreate, input) {
+        if let Err(e) = worktree::remove_worktree(repo, name) {
+            log::warn!("remove refused worktree {name}: {e:#}");
+        }
+        state.repo_cache.mark_stale(repo);
+        return Err(e.into());
+    }
+    let wt_path = Path::new(&info.path);
+    let submodules = if init_submodules && submodules::has_submodules(wt_path) {
+        submodules::update_with_events(wt_path, name, &state.events)
+    } else {
+        Vec::new()
+    };
+    let lfs = lfs::uses_lfs(wt_path).then(|| lfs::pull(wt_path));
+
+    let (configured, cache_rules) = {
+        let settings = state.settings.lock().unwrap();
+        (
+            settings.setup_commands.get(repo).cloned().unwrap_or(config.setup),
+            settings.dependency_cache.get(repo).cloned().unwrap_or(config.cache),
+        )
+    };
+    let shared_caches = depcache::apply(Path::new(repo), wt_path, &cache_rules);
+    let scale = check_scale(&state, repo, name, wt_path);
+    let setup_session_id = match setup::setup_command(wt_path, &configured) {
+        Some(cmd) => {
+            let mut opts = SpawnOptions::new(setup::SETUP_AGENT_ID, cmd);
+            let size = state.terminal_size();
+            opts.cwd = Some(info.path.clone());
+            opts.cols = size.cols;
+            opts.rows = size.rows;
+            opts.env = setup::setup_env(repo, &info.path, &info.name);
+            opts.env.extend(depcache::shared_env(Path::new(repo), &cache_rules));
+            let id = state.pty.lock().unwrap().spawn(opts, state.events.clone())?;
+            Some(id)
+        }
+        None => None,
+    };
+
+    Ok(WorktreeCreated { info, setup_session_id, submodules, lfs, shared_caches, scale })
+}
+
+/// Count a worktree's files against the scale limits and degrade its
+/// status checks if it is over them.
+fn check_scale(
+    state: &State<'_, AppState>,
+    repo: &str,
+    name: &str,
+    path: &Path,
+) -> Option<ScaleReport> {
+    let limits = state.settings.lock().unwrap().scale_limits.clone();
+    let report = scale::check(path, &limits)
+        .map_err(|e| log::warn!("counting files in {name}: {e:#}"))
+        .ok()?;
+    state.repo_cache.set_degraded(repo, name, report.degraded);
+    Some(report)
+}
+
+/// Tracked and untracked file counts of a worktree, with warnings when it
+/// is too large for quick status scans and the ignore rules that would
+/// help. Over the limits, the worktree's status checks skip untracked
+/// files until a later count finds it back under them.
+#[tauri::command]
+pub async fn worktree_scale(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<ScaleReport, PiError> {
+    let repo = state.repo()?;
+    let path = worktree::worktree_path(&repo, &name)?;
+    let limits = state.settings.lock().unwrap().scale_limits.clone();
+    let report = scale::check(&path, &limits)?;
+    state.repo_cache.set_degraded(&repo, &name, report.degraded);
+    Ok(report)
+}
+
+/// `shared_dir` environment for sessions in a worktree of the current repo.
+fn shared_cache_env(state: &State<'_, AppState>) -> Result<Vec<(String, String)>, PiError> {
+    let repo = state.repo()?;
+    let rules = match state.settings.lock().unwrap().dependency_cache.get(&repo) {
+        Some(rules) => rules.clone(),
+        None => RepoConfig::load_or_default(Path::new(&repo)).cache,
+    };
+    Ok(depcache::shared_env(Path::new(&repo), &rules))
+}
+
+#[tauri::command]
+pub fn worktree_list(state: State<'_, AppState>) -> Result<Vec<worktree::WorktreeInfo>, PiError> {
+    let repo = state.repo()?;
+    state.repo_cache.list(&repo).map_err(PiError::from)
+}
+
+/// One worktree's status as `worktree_list` has it, or with `refresh`
+/// rechecked now, e.g. right after an agent finished writing.
+#[tauri::command]
+pub fn worktree_status(
+    name: String,
+    refresh: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<worktree::WorktreeInfo, PiError> {
+    let repo = state.repo()?;
+    state.repo_cache.status(&repo, &name, refresh.unwrap_or(false)).map_err(PiError::from)
+}
+
+/// Size of a worktree broken down by top-level entry, with what deleting
+/// build output and dependencies would free. Walks the tree now; use
+/// `size_bytes` from `worktree_list` for the cached total.
+#[tauri::command]
+pub async fn worktree_disk_usage(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<DiskUsage, PiError> {
+    let repo = state.repo()?;
+    let path = worktree::worktree_path(&repo, &name)?;
+    let usage = disk::usage(&path);
+    state.repo_cache.sizes.store(&path, usage.total_bytes);
+    Ok(usage)
+}
+
+#[tauri::command]
+pub fn worktree_remove(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "remove")?;
+    let removed = state.repo_cache.with_repo(&repo, |r| worktree::remove_in(r, &name));
+    state.repo_cache.mark_stale(&repo);
+    removed?;
+    state.reviews.forget(&repo, &name);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn worktree_snapshot(
+    name: String,
+    label: String,
+    state: State<'_, AppState>,
+) -> Result<Snapshot, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "snapshot")?;
+    snapshot::snapshot(&repo, &name, &label).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn worktree_snapshots(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<Snapshot>, PiError> {
+    let repo = state.repo()?;
+    snapshot::list(&repo, &name).map_err(PiError::from)
+}
+
+/// Restore a snapshot; returns the safety snapshot of the pre-rollback state.
+#[tauri::command]
+pub fn worktree_rollback(
+    name: String,
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<Snapshot, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "rollback")?;
+    snapshot::rollback(&repo, &name, &snapshot_id).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn worktree_snapshot_delete(
+    name: String,
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "snapshot delete")?;
+    snapshot::delete(&repo, &name, &snapshot_id).map_err(PiError::from)
+}
+
+/// Commits on a worktree's branch, newest first. `limit` defaults to 100.
+#[tauri::command]
+pub fn worktree_log(
+    name: String,
+    limit: Option<usize>,
+    since: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<CommitInfo>, PiError> {
+    let repo = state.repo()?;
+    history::log(&repo, &name, limit.unwrap_or(100), since).map_err(PiError::from)
+}
+
+/// Last commit, author and age of each line of a worktree file, optionally
+/// only lines `range.start..=range.end`.
+#[tauri::command]
+pub fn worktree_blame(
+    name: String,
+    path: String,
+    range: Option<BlameRange>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BlameLine>, PiError> {
+    let repo = state.repo()?;
+    history::blame(&repo, &name, &path, range).map_err(PiError::from)
+}
+
+/// Rebase a worktree's branch onto the current base tip. Emits
+/// `worktree://conflict` if it stops on conflicts.
+#[tauri::command]
+pub fn worktree_rebase(name: String, state: State<'_, AppState>) -> Result<RebaseOutcome, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "rebase")?;
+    let sign = state.settings.lock().unwrap().sign_commits;
+    let outcome = state.repo_cache.with_repo(&repo, |r| rebase::rebase_in(r, &name, sign));
+    state.repo_cache.mark_stale(&repo);
+    let outcome = outcome?;
+    report_conflicts(&state, &name, &outcome);
+    Ok(outcome)
+}
+
+#[tauri::command]
+pub fn worktree_rebase_continue(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<RebaseOutcome, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "rebase")?;
+    let sign = state.settings.lock().unwrap().sign_commits;
+    let outcome = rebase::continue_rebase(&repo, &name, sign);
+    state.repo_cache.mark_stale(&repo);
+    let outcome = outcome?;
+    report_conflicts(&state, &name, &outcome);
+    Ok(outcome)
+}
+
+#[tauri::command]
+pub fn worktree_rebase_abort(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "rebase abort")?;
+    let aborted = rebase::abort(&repo, &name);
+    state.repo_cache.mark_stale(&repo);
+    aborted.map_err(PiError::from)
+}
+
+/// The current repo's checks, by name, with their commands.
+#[tauri::command]
+pub fn worktree_checks(state: State<'_, AppState>) -> Result<BTreeMap<String, String>, PiError> {
+    let repo = state.repo()?;
+    Ok(checks::list(&repo, &state.settings.lock().unwrap()))
+}
+
+/// Run the repo's check `check_name` in worktree `name` as a session
+/// tagged `check`. Returns the session id; `check://result` reports
+/// whether it passed when it exits.
+#[tauri::command]
+pub fn worktree_run_check(
+    name: String,
+    check_name: String,
+    state: State<'_, AppState>,
+) -> Result<String, PiError> {
+    let repo = state.repo()?;
+    let command = checks::command(&repo, &state.settings.lock().unwrap(), &check_name)?;
+    let size = state.terminal_size();
+    checks::spawn(&state.pty, state.events.clone(), &repo, &name, &check_name, &command, size)
+        .map_err(PiError::from)
+}
+
+/// Run `op` on several worktrees in parallel. Emits `worktree://bulk` as
+/// each one finishes; per-worktree failures are reported in the results
+/// rather than failing the call.
+#[tauri::command]
+pub async fn worktree_bulk(
+    op: BulkOp,
+    names: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkItem>, PiError> {
+    let repo = state.repo()?;
+    let total = names.len();
+    let sign = state.settings.lock().unwrap().sign_commits;
+    let items = bulk::run(&repo, op, &names, &state.worktree_locks, sign, |item, done| {
+        state.events.emit(
+            "worktree://bulk",
+            serde_json::json!({
+                "op": op,
+                "name": item.name,
+                "ok": item.ok,
+                "done": done,
+                "total": total,
+            }),
+        );
+    })?;
+    state.repo_cache.mark_stale(&repo);
+    for item in &items {
+        if let Some(outcome) = &item.rebase {
+            report_conflicts(&state, &item.name, outcome);
+        }
+    }
+    Ok(items)
+}
+
+fn report_conflicts(state: &State<'_, AppState>, worktree: &str, outcome: &RebaseOutcome) {
+    if outcome.status == RebaseStatus::Conflict {
+        state.events.emit(
+            &format!("worktree://conflict/{worktree}"),
+            serde_json::json!({ "worktree": worktree, "files": outcome.conflicts }),
+        );
+    }
+}
+
+/// Unstaged changes in a worktree, with hunk ids for staging. With
+/// `highlight`, lines come with syntax highlighting tokens. `view` can
+/// hide whitespace and line-ending changes and detect renames; hunks are
+/// then for reading only, see `staging.rs`.
+#[tauri::command]
+pub fn worktree_diff(
+    name: String,
+    highlight: Option<bool>,
+    view: Option<DiffView>,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileDiff>, PiError> {
+    let repo = state.repo()?;
+    let mut files = staging::unstaged_diff(&repo, &name, &view.unwrap_or_default())?;
+    if highlight.unwrap_or(false) {
+        highlight::annotate(&mut files);
+    }
+    Ok(files)
+}
+
+/// Line up two worktrees' branches against their merge base: which files
+/// each changed, which both did and which of those conflict, and the diff
+/// from `name_a` to `name_b`.
+#[tauri::command]
+pub fn worktree_compare(
+    name_a: String,
+    name_b: String,
+    state: State<'_, AppState>,
+) -> Result<Comparison, PiError> {
+    let repo = state.repo()?;
+    state
+        .repo_cache
+        .with_repo(&repo, |r| compare::compare(r, &name_a, &name_b))
+        .map_err(PiError::from)
+}
+
+/// Staged changes in a worktree; `highlight` and `view` as for
+/// `worktree_diff`.
+#[tauri::command]
+pub fn worktree_staged_diff(
+    name: String,
+    highlight: Option<bool>,
+    view: Option<DiffView>,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileDiff>, PiError> {
+    let repo = state.repo()?;
+    let mut files = staging::staged_diff(&repo, &name, &view.unwrap_or_default())?;
+    if highlight.unwrap_or(false) {
+        highlight::annotate(&mut files);
+    }
+    Ok(files)
+}
+
+/// Apply a unified diff to a worktree's files. Hunks are checked one by one
+/// and reported; the patch lands whole or not at all unless `three_way`,
+/// which lets git merge it and leave conflict markers.
+#[tauri::command]
+pub fn worktree_apply_patch(
+    name: String,
+    unified_diff: String,
+    three_way: bool,
+    state: State<'_, AppState>,
+) -> Result<PatchReport, PiError> {
+    let repo = state.repo()?;
+    let report = state
+        .repo_cache
+        .with_repo(&repo, |r| patch::apply(r, &name, &unified_diff, three_way));
+    state.repo_cache.mark_stale(&repo);
+    report.map_err(PiError::from)
+}
+
+/// Write a worktree's work to `path` as a tarball, one squashed patch or a
+/// `git format-patch` series (into a directory).
+#[tauri::command]
+pub fn worktree_export(
+    name: String,
+    format: ExportFormat,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<ExportResult, PiError> {
+    let repo = state.repo()?;
+    state
+        .repo_cache
+        .with_repo(&repo, |r| export::export(r, &name, format, Path::new(&path)))
+        .map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn worktree_stage_hunk(
+    name: String,
+    file: String,
+    hunk_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    staging::stage_hunk(&repo, &name, &file, &hunk_id).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn worktree_unstage_hunk(
+    name: String,
+    file: String,
+    hunk_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    staging::unstage_hunk(&repo, &name, &file, &hunk_id).map_err(PiError::from)
+}
+
+/// Move worktrees from the legacy `.git/worktrees-pi` location to the
+/// configured root. Worktrees backing live sessions are skipped.
+#[tauri::command]
+pub fn worktree_migrate(state: State<'_, AppState>) -> Result<worktree::MigrationReport, PiError> {
+    let repo = state.repo()?;
+    let in_use = state.pty.lock().unwrap().live_session_ids();
+    let root = state.worktree_root();
+    let report = worktree::migrate_legacy_worktrees(&repo, root.as_deref(), &in_use);
+    // Moved worktrees invalidate the cached handles for them
+    state.repo_cache.invalidate(&repo);
+    report.map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn set_worktree_root(path: Option<String>, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.update_settings(|s| s.worktree_root = path)
+}
+
+/// Configure post-create setup commands for the current repo. An empty list
+/// falls back to `.pi-builder/setup.sh`.
+#[tauri::command]
+pub fn set_setup_commands(
+    commands: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    state.update_settings(|s| {
+        if commands.is_empty() {
+            s.setup_commands.remove(&repo);
+        } else {
+            s.setup_commands.insert(repo, commands);
+        }
+    })
+}
+
+/// The current repo's `.pi-builder.toml`, or `None` if it has none.
+#[tauri::command]
+pub fn repo_config(state: State<'_, AppState>) -> Result<Option<RepoConfig>, PiError> {
+    let repo = state.repo()?;
+    RepoConfig::load(Path::new(&repo)).map_err(PiError::from)
+}
+
+/// Set the current repo's protected paths (gitignore syntax), added to
+/// those in its `.pi-builder.toml`. An empty list clears them.
+#[tauri::command]
+pub fn set_protected_paths(
+    patterns: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    protect::Protected::new(&patterns).map_err(|e| PiError::invalid_input(format!("{e:#}")))?;
+    let repo = state.repo()?;
+    state.update_settings(|s| {
+        if patterns.is_empty() {
+            s.protected_paths.remove(&repo);
+        } else {
+            s.protected_paths.insert(repo, patterns);
+        }
+    })
+}
+
+/// Protected files a worktree changed since it forked off the main branch.
+#[tauri::command]
+pub fn worktree_violations(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, PiError> {
+    let repo = state.repo()?;
+    protected_violations(&state, &repo, &name)
+}
+
+fn protected_violations(state: &AppState, repo: &str, name: &str) -> Result<Vec<String>, PiError> {
+    let patterns = protect::patterns(repo, &state.settings.lock().unwrap());
+    let protected = protect::Protected::new(&patterns)?;
+    Ok(state.repo_cache.with_repo(repo, |r| protect::violations(r, name, &protected))?)
+}
+
+/// Configure which cache directories new worktrees of the current repo
+/// share with the main checkout. An empty list turns sharing off.
+#[tauri::command]
+pub fn set_dependency_cache(
+    rules: Vec<CacheRule>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    depcache::validate(&rules)?;
+    let repo = state.repo()?;
+    state.update_settings(|s| {
+        if rules.is_empty() {
+            s.dependency_cache.remove(&repo);
+        } else {
+            s.dependency_cache.insert(repo, rules);
+        }
+    })
+}
+
+#[tauri::command]
+pub fn get_worktree_root(state: State<'_, AppState>) -> Option<String> {
+    state.worktree_root()
+}
+
+/// Fetch `remote` (default: the base branch's upstream remote) and
+/// refresh worktree upstream divergence.
+#[tauri::command]
+pub async fn repo_fetch(
+    remote: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<FetchReport, PiError> {
+    let repo = state.repo()?;
+    let report = remote::fetch(&repo, remote.as_deref())?;
+    state.repo_cache.mark_stale(&repo);
+    Ok(report)
+}
+
+/// Stash the current repo's uncommitted changes, untracked files too
+/// unless `include_untracked` is false, so merges into its branch can run.
+#[tauri::command]
+pub fn repo_stash_save(
+    message: Option<String>,
+    include_untracked: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<StashEntry, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.main_checkout(&repo, "stash")?;
+    let entry = stash::save(&repo, message.as_deref(), include_untracked.unwrap_or(true));
+    state.repo_cache.mark_stale(&repo);
+    entry.map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn repo_stash_list(state: State<'_, AppState>) -> Result<Vec<StashEntry>, PiError> {
+    let repo = state.repo()?;
+    stash::list(&repo).map_err(PiError::from)
+}
+
+/// Apply `stash@{index}` (default 0) to the current repo's checkout and
+/// keep it.
+#[tauri::command]
+pub fn repo_stash_apply(index: Option<usize>, state: State<'_, AppState>) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.main_checkout(&repo, "stash apply")?;
+    let result = stash::apply(&repo, index.unwrap_or(0), false);
+    state.repo_cache.mark_stale(&repo);
+    result.map_err(PiError::from)
+}
+
+/// Apply `stash@{index}` (default 0) and drop it if it applied.
+#[tauri::command]
+pub fn repo_stash_pop(index: Option<usize>, state: State<'_, AppState>) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.main_checkout(&repo, "stash pop")?;
+    let result = stash::apply(&repo, index.unwrap_or(0), true);
+    state.repo_cache.mark_stale(&repo);
+    result.map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn repo_stash_drop(index: Option<usize>, state: State<'_, AppState>) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.main_checkout(&repo, "stash drop")?;
+    stash::drop(&repo, index.unwrap_or(0)).map_err(PiError::from)
+}
+
+/// Local and remote branches of the current repo, with ahead/behind
+/// against their upstreams and their last commits.
+#[tauri::command]
+pub fn repo_branches(state: State<'_, AppState>) -> Result<Vec<BranchInfo>, PiError> {
+    let repo = state.repo()?;
+    branches::list(&repo).map_err(PiError::from)
+}
+
+/// Check out `branch` in the current repo's main checkout, making it the
+/// base new worktrees fork from. A remote branch like `origin/dev` gets a
+/// local `dev` tracking it. Fails on uncommitted changes.
+#[tauri::command]
+pub fn repo_checkout(branch: String, state: State<'_, AppState>) -> Result<BranchInfo, PiError> {
+    let repo = state.repo()?;
+    let _lock = state.worktree_locks.main_checkout(&repo, "checkout")?;
+    let result = branches::checkout(&repo, &branch);
+    state.repo_cache.mark_stale(&repo);
+    result.map_err(PiError::from)
+}
+
+/// Clone `url` into `dest` (shallow with `depth`) and make it the current
+/// repo. Emits `repo://clone-progress` while receiving objects and checking
+/// out files.
+#[tauri::command]
+pub async fn repo_clone(
+    url: String,
+    dest: String,
+    depth: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<CloneReport, PiError> {
+    let report = remote::clone(&url, Path::new(&dest), depth, |progress| {
+        state.events.emit(
+            "repo://clone-progress",
+            serde_json::json!({ "url": url, "dest": dest, "progress": progress }),
+        );
+    })?;
+    state.repo_cache.invalidate(&report.path);
+    *state.repo_path.lock().unwrap() = Some(report.path.clone());
+    Ok(report)
+}
+
+/// Background fetch interval in seconds (at least a minute); `None`
+/// turns background fetching off.
+#[tauri::command]
+pub fn set_fetch_interval(secs: Option<u64>, state: State<'_, AppState>) -> Result<(), PiError> {
+    let secs = secs.map(|s| s.max(remote::MIN_FETCH_INTERVAL_SECS));
+    state.update_settings(|s| s.fetch_interval_secs = secs)
+}
+
+/// Set the branch name template for new worktrees; `None` restores
+/// `agent/{id}`. Placeholders: `{agent}`, `{slug}`, `{date}`, `{id}`,
+/// `{name}`.
+#[tauri::command]
+pub fn set_branch_template(
+    template: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let template = template.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    if let Some(t) = &template {
+        let sample = BranchVars { agent: "agent", slug: Some("sample") };
+        worktree::render_branch(t, "0123456789abcdef", &sample)?;
+    }
+    state.update_settings(|s| s.branch_template = template)
+}
+
+// ---------------------------------------------------------------------------
+// File commands (read-only, scoped to a worktree)
+// ---------------------------------------------------------------------------
+
+fn worktree_root_path(worktree: &str, state: &State<'_, AppState>) -> Result<PathBuf, PiError> {
+    let repo = state.repo()?;
+    worktree::worktree_path(&repo, worktree).map_err(PiError::from)
+}
+
+/// Search the files of worktree `worktree`, or the main checkout, for
+/// `query`. Pass a `search_id` to be able to stop it with
+/// `repo_search_cancel`; a cancelled search returns what it found so far.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn repo_search(
+    query: String,
+    worktree: Option<String>,
+    globs: Option<Vec<String>>,
+    regex: Option<bool>,
+    case_sensitive: Option<bool>,
+    max_results: Option<usize>,
+    search_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<RepoSearchResult, PiError> {
+    let root = match &worktree {
+        Some(name) => worktree_root_path(name, &state)?,
+        None => PathBuf::from(state.repo()?),
+    };
+    let opts = SearchOptions {
+        regex: regex.unwrap_or(false),
+        case_sensitive: case_sensitive.unwrap_or(false),
+        globs: globs.unwrap_or_default(),
+        max_results: max_results.unwrap_or(1000),
+    };
+    let cancel = match &search_id {
+        Some(id) => state.searches.start(id),
+        None => Arc::default(),
+    };
+    let result = search::search(&root, &query, &opts, &cancel);
+    if let Some(id) = &search_id {
+        state.searches.finish(id);
+    }
+    result.map_err(PiError::from)
+}
+
+/// Stop a running `repo_search`. Returns false if it already finished.
+#[tauri::command]
+pub fn repo_search_cancel(search_id: String, state: State<'_, AppState>) -> bool {
+    state.searches.cancel(&search_id)
+}
+
+#[tauri::command]
+pub fn fs_read_file(
+    worktree: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<files::FileContent, PiError> {
+    let root = worktree_root_path(&worktree, &state)?;
+    files::read_file(&root, &path).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn fs_list_dir(
+    worktree: String,
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<files::DirEntryInfo>, PiError> {
+    let root = worktree_root_path(&worktree, &state)?;
+    files::list_dir(&root, path.as_deref().unwrap_or(".")).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn fs_stat(
+    worktree: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<files::FileStat, PiError> {
+    let root = worktree_root_path(&worktree, &state)?;
+    files::stat(&root, &path).map_err(PiError::from)
+}
+
+/// Directory listing of a worktree annotated with git status, `depth`
+/// levels deep (default 1). Expand a directory by calling again with its
+/// `path`.
+#[tauri::command]
+pub fn worktree_tree(
+    name: String,
+    path: Option<String>,
+    depth: Option<usize>,
+    state: State<'_, AppState>,
+) -> Result<Vec<TreeEntry>, PiError> {
+    let repo = state.repo()?;
+    let root = worktree_root_path(&name, &state)?;
+    let status = state.repo_cache.file_status(&repo, &name)?;
+    tree::list(&root, path.as_deref().unwrap_or("."), depth.unwrap_or(1), &status)
+        .map_err(PiError::from)
+}
+
+// ---------------------------------------------------------------------------
+// Review commands
+// ---------------------------------------------------------------------------
+
+/// Review state of every worktree, plus merged/discarded ones still on
+/// record.
+#[tauri::command]
+pub fn review_list(state: State<'_, AppState>) -> Result<Vec<ReviewEntry>, PiError> {
+    let repo = state.repo()?;
+    let names: Vec<String> = state.repo_cache.list(&repo)?.into_iter().map(|w| w.name).collect();
+    Ok(state.reviews.list(&repo, &names))
+}
+
+/// Move a worktree to `in_progress`, `ready_for_review` or `approved`.
+/// Merging and discarding go through `review_merge` / `review_discard`.
+#[tauri::command]
+pub fn review_transition(
+    name: String,
+    to: ReviewState,
+    note: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ReviewEntry, PiError> {
+    if matches!(to, ReviewState::Merged | ReviewState::Discarded) {
+        return Err(PiError::invalid_input("use review_merge or review_discard"));
+    }
+    let repo = state.repo()?;
+    let head = state.repo_cache.with_repo(&repo, |r| review::worktree_head(r, &name))?;
+    let entry = state.reviews.transition(&repo, &name, to, note, Some(head))?;
+    emit_review(&state, &repo, &entry);
+    Ok(entry)
+}
+
+/// Merge an approved worktree's branch into the main checkout's branch,
+/// then remove the worktree if `remove_worktree`. Fails unless the branch
+/// is approved and unchanged since, and, without `allow_protected`, when
+/// it changed protected paths.
+#[tauri::command]
+pub fn review_merge(
+    name: String,
+    remove_worktree: Option<bool>,
+    allow_protected: Option<bool>,
+    comments_path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<MergeOutcome, PiError> {
+    let repo = state.repo()?;
+    let entry = state.reviews.get(&repo, &name);
+    review::check_transition(entry.state, ReviewState::Merged)?;
+    // Refuse a bad comments path now rather than after the merge lands
+    if let Some(path) = &comments_path {
+        files::check_relative(path)?;
+    }
+    if !allow_protected.unwrap_or(false) {
+        let violations = protected_violations(&state, &repo, &name)?;
+        if !violations.is_empty() {
+            return Err(PiError::new(ErrorCode::ProtectedPaths, "worktree changed protected paths")
+                .with_detail(violations.join("\n")));
+        }
+    }
+    let hooks = state.settings.lock().unwrap().hooks.clone();
+    let input = serde_json::json!({ "repo": repo, "worktree": name });
+    hooks::run(&hooks, HookPoint::PreMerge, input)?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "merge")?;
+    let _main = state.worktree_locks.main_checkout(&repo, "merge")?;
+    let reviewed = entry.reviewed_head.unwrap_or_default();
+    let (template, sign) = {
+        let settings = state.settings.lock().unwrap();
+        (mergemsg::template(&repo, &settings), settings.sign_commits)
+    };
+    let sessions = state.pty.lock().unwrap().usage_records();
+    let outcome = state
+        .repo_cache
+        .with_repo(&repo, |r| review::merge(r, &name, &reviewed, &template, &sessions, sign))?;
+    let entry = state.reviews.transition(
+        &repo,
+        &name,
+        ReviewState::Merged,
+        None,
+        Some(outcome.commit.clone()),
+    )?;
+    emit_review(&state, &repo, &entry);
+    if let Some(path) = comments_path {
+        if let Err(e) = write_comments(&state, &repo, &name, &path) {
+            log::warn!("exporting review comments of {name}: {e:#}");
+        }
+    }
+    if remove_worktree.unwrap_or(false) {
+        state.repo_cache.with_repo(&repo, |r| worktree::remove_in(r, &name))?;
+    }
+    state.repo_cache.mark_stale(&repo);
+    Ok(outcome)
+}
+
+/// Abandon a worktree that hasn't been merged: remove it and its branch.
+#[tauri::command]
+pub fn review_discard(
+    name: String,
+    note: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ReviewEntry, PiError> {
+    let repo = state.repo()?;
+    review::check_transition(state.reviews.get(&repo, &name).state, ReviewState::Discarded)?;
+    let _lock = state.worktree_locks.worktree(&repo, &name, "remove")?;
+    let removed = state.repo_cache.with_repo(&repo, |r| worktree::remove_in(r, &name));
+    state.repo_cache.mark_stale(&repo);
+    removed?;
+    let entry = state.reviews.transition(&repo, &name, ReviewState::Discarded, note, None)?;
+    emit_review(&state, &repo, &entry);
+    Ok(entry)
+}
+
+/// Queue an approved worktree to be rebased, checked and merged after the
+/// ones already queued. Progress is emitted as `merge_queue://changed`.
+#[tauri::command]
+pub fn merge_queue_enqueue(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<MergeEntry, PiError> {
+    let repo = state.repo()?;
+    state.merge_queue.enqueue(&repo, &name).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn merge_queue_list(state: State<'_, AppState>) -> MergeQueueState {
+    state.merge_queue.list()
+}
+
+/// Take a waiting entry out of the merge queue.
+#[tauri::command]
+pub fn merge_queue_cancel(id: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.merge_queue.cancel(&id).map_err(PiError::from)
+}
+
+/// Continue landing queued branches after a failure halted the queue.
+#[tauri::command]
+pub fn merge_queue_resume(state: State<'_, AppState>) {
+    state.merge_queue.resume();
+}
+
+/// Set the command the merge queue runs in the current repo's worktrees
+/// before merging them. `None` falls back to `.pi-builder.toml`.
+#[tauri::command]
+pub fn set_merge_check(command: Option<String>, state: State<'_, AppState>) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    state.update_settings(|s| match command {
+        Some(command) if !command.trim().is_empty() => {
+            s.merge_checks.insert(repo, command);
+        }
+        _ => {
+            s.merge_checks.remove(&repo);
+        }
+    })
+}
+
+/// Set the merge commit message template for the current repo. `None`
+/// falls back to `.pi-builder.toml`; see `mergemsg.rs` for placeholders.
+#[tauri::command]
+pub fn set_merge_message(
+    template: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    if let Some(template) = &template {
+        mergemsg::validate(template)?;
+    }
+    state.update_settings(|s| match template {
+        Some(template) if !template.trim().is_empty() => {
+            s.merge_messages.insert(repo, template);
+        }
+        _ => {
+            s.merge_messages.remove(&repo);
+        }
+    })
+}
+
+/// The commit message merging a worktree would get, with the template
+/// given or the repo's.
+#[tauri::command]
+pub fn review_merge_message(
+    name: String,
+    template: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, PiError> {
+    let repo = state.repo()?;
+    let template = match template {
+        Some(template) => {
+            mergemsg::validate(&template)?;
+            template
+        }
+        None => mergemsg::template(&repo, &state.settings.lock().unwrap()),
+    };
+    let sessions = state.pty.lock().unwrap().usage_records();
+    state
+        .repo_cache
+        .with_repo(&repo, |r| review::merge_message(r, &name, &template, &sessions))
+        .map_err(PiError::from)
+}
+
+fn emit_review(state: &State<'_, AppState>, repo: &str, entry: &ReviewEntry) {
+    state.events.emit(
+        "review://changed",
+        serde_json::json!({ "repo": repo, "entry": entry }),
+    );
+}
+
+/// Leave a comment on `line` of `file` in `worktree`'s diff. Emits
+/// `review://comments` with the worktree's comments.
+#[tauri::command]
+pub fn review_comment_add(
+    worktree: String,
+    file: String,
+    line: u32,
+    body: String,
+    state: State<'_, AppState>,
+) -> Result<ReviewComment, PiError> {
+    let repo = state.repo()?;
+    let head = state.repo_cache.with_repo(&repo, |r| review::worktree_head(r, &worktree)).ok();
+    let comment = state.comments.add(&repo, &worktree, &file, line, &body, head)?;
+    emit_comments(&state, &repo, &worktree);
+    Ok(comment)
+}
+
+#[tauri::command]
+pub fn review_comment_list(
+    worktree: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<ReviewComment>, PiError> {
+    let repo = state.repo()?;
+    Ok(state.comments.list(&repo, &worktree))
+}
+
+#[tauri::command]
+pub fn review_comment_delete(
+    worktree: String,
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    let repo = state.repo()?;
+    if !state.comments.delete(&repo, &worktree, &id)? {
+        return Err(PiError::new(ErrorCode::NotFound, format!("no comment {id}")));
+    }
+    emit_comments(&state, &repo, &worktree);
+    Ok(())
+}
+
+/// `worktree`'s comments as Markdown, e.g. for a PR description. Also
+/// written to `path` when given, which must be relative to the main
+/// checkout and stay inside it.
+#[tauri::command]
+pub fn review_comment_export(
+    worktree: String,
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, PiError> {
+    let repo = state.repo()?;
+    match path {
+        Some(path) => write_comments(&state, &repo, &worktree, &path).map_err(PiError::from),
+        None => Ok(comments::markdown(&worktree, &state.comments.list(&repo, &worktree))),
+    }
+}
+
+/// Write `worktree`'s comments as Markdown to `path` inside the main
+/// checkout, returning them.
+fn write_comments(
+    state: &State<'_, AppState>,
+    repo: &str,
+    worktree: &str,
+    path: &str,
+) -> anyhow::Result<String> {
+    let path = files::resolve_new(Path::new(repo), path)?;
+    let markdown = comments::markdown(worktree, &state.comments.list(repo, worktree));
+    std::fs::write(&path, &markdown)?;
+    Ok(markdown)
+}
+
+fn emit_comments(state: &State<'_, AppState>, repo: &str, worktree: &str) {
+    state.events.emit(
+        "review://comments",
+        serde_json::json!({
+            "repo": repo,
+            "worktree": worktree,
+            "comments": state.comments.list(repo, worktree),
+        }),
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Integration commands
+// ---------------------------------------------------------------------------
+
+/// Issue title, body and comments from `repo` (`owner/name`).
+#[tauri::command]
+pub async fn github_fetch_issue(repo: String, number: u64) -> Result<IssueContext, PiError> {
+    github::fetch_issue(&repo, number).await.map_err(PiError::from)
+}
+
+/// Pull request title, body, comments and diff from `repo` (`owner/name`).
+#[tauri::command]
+pub async fn github_fetch_pr(repo: String, number: u64) -> Result<PullContext, PiError> {
+    github::fetch_pull(&repo, number).await.map_err(PiError::from)
+}
+
+// ---------------------------------------------------------------------------
+// Task commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn task_enqueue(spec: TaskSpec, state: State<'_, AppState>) -> Result<Task, PiError> {
+    let mut spec = spec;
+    let repo = spec
+        .repo
+        .clone()
+        .or_else(|| state.repo_path.lock().unwrap().clone())
+        .ok_or_else(PiError::repo_not_configured)?;
+    if let Some(paths) = &spec.sparse {
+        sparse::validate(paths)?;
+    }
+    if spec.agent.is_empty() {
+        let config = RepoConfig::load(Path::new(&repo))?.unwrap_or_default();
+        spec.agent = config.default_agent.ok_or_else(|| {
+            PiError::invalid_input(format!(
+                "no agent given and no default_agent in {}",
+                repo_config::CONFIG_FILE
+            ))
+        })?;
+    }
+    Ok(state.tasks.enqueue(spec, repo))
+}
+
+#[tauri::command]
+pub fn task_list(state: State<'_, AppState>) -> Vec<Task> {
+    state.tasks.list()
+}
+
+/// A report of one task run (see `report.rs`) as Markdown or JSON, written
+/// to `dest`, an absolute path, or returned inline.
+#[tauri::command]
+pub fn task_report(
+    task_id: String,
+    format: ReportFormat,
+    dest: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ReportResult, PiError> {
+    let task = state.tasks.list().into_iter().find(|t| t.id == task_id);
+    let task = task.ok_or_else(|| PiError::new(ErrorCode::NotFound, "no such task"))?;
+    let changes = task.worktree.as_deref().and_then(|name| {
+        let changes = state.repo_cache.with_repo(&task.repo, |r| report::branch_changes(r, name));
+        changes.map_err(|e| log::debug!("task report changes: {e:#}")).ok()
+    });
+    let checks: Vec<MergeEntry> = state
+        .merge_queue
+        .list()
+        .entries
+        .into_iter()
+        .filter(|e| e.repo == task.repo && task.worktree.as_ref() == Some(&e.worktree))
+        .collect();
+    let (usage, log_path) = {
+        let pty = state.pty.lock().unwrap();
+        let log_path = task.session_id.as_deref().and_then(|id| pty.log_path(id).ok().flatten());
+        (report::task_usage(&task.id, pty.usage_records()), log_path)
+    };
+    let recording = task
+        .session_id
+        .clone()
+        .map(|id| (recording::cast_path(&state.log_dir, &id), id))
+        .filter(|(path, _)| path.exists());
+    let report = TaskReport::new(task, usage, changes, checks, log_path, recording);
+    report::output(&report, format, dest.as_deref().map(Path::new)).map_err(PiError::from)
+}
+
+/// What happened in task `task_id`, oldest first: status changes, session
+/// activity, worktree changes and checks. See `timeline.rs`.
+#[tauri::command]
+pub fn task_timeline(
+    task_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<TimelineEntry>, PiError> {
+    let entries = state.timelines.get(&task_id);
+    if entries.is_empty() && !state.tasks.list().iter().any(|t| t.id == task_id) {
+        return Err(PiError::new(ErrorCode::NotFound, "no such task"));
+    }
+    Ok(entries)
+}
+
+#[tauri::command]
+pub fn task_cancel(task_id: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.tasks.cancel(&task_id).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn task_set_max_concurrency(max: usize, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.update_settings(|s| s.max_concurrent_tasks = max.max(1))
+}
+
+/// Replace the output redaction config. Applies to sessions spawned after
+/// the call.
+#[tauri::command]
+pub fn set_redaction(
+    patterns: Vec<String>,
+    redact_env_secrets: bool,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    validate_redaction(&patterns)?;
+    state.update_settings(|s| {
+        s.redact_patterns = patterns;
+        s.redact_env_secrets = redact_env_secrets;
+    })
+}
+
+#[tauri::command]
+pub fn get_shell_config(state: State<'_, AppState>) -> ShellConfig {
+    state.settings.lock().unwrap().shell.clone()
+}
+
+/// Replace the default shell config. Applies to sessions spawned after the
+/// call.
+#[tauri::command]
+pub fn set_shell_config(shell: ShellConfig, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.update_settings(|s| s.shell = shell)
+}
+
+#[tauri::command]
+pub fn get_hooks(state: State<'_, AppState>) -> Vec<Hook> {
+    state.settings.lock().unwrap().hooks.clone()
+}
+
+/// Replace the lifecycle hooks. Applies to operations started after the
+/// call.
+#[tauri::command]
+pub fn set_hooks(hooks: Vec<Hook>, state: State<'_, AppState>) -> Result<(), PiError> {
+    hooks::validate(&hooks)?;
+    state.update_settings(|s| s.hooks = hooks)
+}
+
+#[tauri::command]
+pub fn get_notification_prefs(state: State<'_, AppState>) -> NotificationPrefs {
+    state.settings.lock().unwrap().notifications.clone()
+}
+
+/// Replace notification preferences. The idle threshold applies to sessions
+/// spawned after the call.
+#[tauri::command]
+pub fn set_notification_prefs(
+    prefs: NotificationPrefs,
+    state: State<'_, AppState>,
+) -> Result<(), PiError> {
+    state.update_settings(|s| s.notifications = prefs)
+}
+
+#[tauri::command]
+pub fn agent_profiles(state: State<'_, AppState>) -> Vec<AgentProfile> {
+    agents::all_profiles(&state.settings.lock().unwrap().agent_profiles)
+}
+
+#[tauri::command]
+pub fn terminal_profile_list(state: State<'_, AppState>) -> Vec<TerminalProfile> {
+    let mut profiles = state.settings.lock().unwrap().terminal_profiles.clone();
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    profiles
+}
+
+/// Save `profile`, replacing one of the same name. Sessions already
+/// running keep what they started with.
+#[tauri::command]
+pub fn terminal_profile_save(
+    mut profile: TerminalProfile,
+    state: State<'_, AppState>,
+) -> Result<TerminalProfile, PiError> {
+    profile.name = profile.name.trim().to_string();
+    profile.validate().map_err(PiError::from)?;
+    let saved = profile.clone();
+    state.update_settings(|s| {
+        s.terminal_profiles.retain(|p| p.name != profile.name);
+        s.terminal_profiles.push(profile);
+    })?;
+    Ok(saved)
+}
+
+#[tauri::command]
+pub fn terminal_profile_delete(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.update_settings(|s| s.terminal_profiles.retain(|p| p.name != name))
+}
+
+/// Store a secret in the OS keychain. Profiles reference it in env values
+/// as `${secret:NAME}`.
+#[tauri::command]
+pub fn secret_set(name: String, value: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.secrets.set(&name, &value).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn secret_delete(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.secrets.delete(&name).map_err(PiError::from)
+}
+
+/// Names of stored secrets; values never leave the backend.
+#[tauri::command]
+pub fn secret_list(state: State<'_, AppState>) -> Vec<String> {
+    state.secrets.list()
+}
+
+#[tauri::command]
+pub fn webhook_list(state: State<'_, AppState>) -> Vec<Webhook> {
+    state.webhooks.list()
+}
+
+/// Save `webhook`, replacing one of the same name. `secret` names a
+/// secret stored with `secret_set` to sign deliveries with.
+#[tauri::command]
+pub fn webhook_save(webhook: Webhook, state: State<'_, AppState>) -> Result<Webhook, PiError> {
+    state.webhooks.save(webhook).map_err(PiError::from)
+}
+
+#[tauri::command]
+pub fn webhook_delete(name: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    state.webhooks.delete(&name).map_err(PiError::from)
+}
+
+/// POST a signed `ping` to webhook `name` once and report how it went.
+#[tauri::command]
+pub async fn webhook_test(
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<DeliveryResult, PiError> {
+    let hook = state.webhooks.get(&name)?;
+    webhooks::ping(&hook).await.map_err(PiError::from)
+}
+
+/// Journaled events after `seq`, for a reloaded webview to catch up. If
+/// `complete` is false some were already dropped and state must be
+/// reloaded in full.
+#[tauri::command]
+pub fn events_since(seq: u64, state: State<'_, AppState>) -> EventsSince {
+    state.journal.since(seq)
 }
 
 #[tauri::command]
-pub fn set_repo_path(path: String, state: State<'_, AppState>) {
+pub fn set_repo_path(path: String, state: State<'_, AppState>) -> Result<(), PiError> {
+    paths::check(Path::new(&path), "repo")?;
+    // Reopen from scratch in case the repo was re-cloned in place
+    state.repo_cache.invalidate(&path);
     *state.repo_path.lock().unwrap() = Some(path);
+    Ok(())
 }
 
 #[tauri::command]
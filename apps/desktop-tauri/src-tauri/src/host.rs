@@ -0,0 +1,30 @@
+//! Tauri implementations of the core's `EventSink` and `Notifier`.
+
+use pi_builder_core::{events::EventSink, notify::Notifier};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Runtime};
+use tauri_plugin_notification::NotificationExt;
+
+/// Emits events to every webview of the app.
+pub struct TauriSink<R: Runtime>(pub AppHandle<R>);
+
+impl<R: Runtime> EventSink for TauriSink<R> {
+    fn emit(&self, event: &str, payload: Value) {
+        let _ = Emitter::emit(&self.0, event, payload);
+    }
+}
+
+/// Shows notifications through the notification plugin.
+pub struct TauriNotifier<R: Runtime>(pub AppHandle<R>);
+
+impl<R: Runtime> Notifier for TauriNotifier<R> {
+    fn notify(&self, title: &str, body: &str, sound: bool) {
+        let mut builder = self.0.notification().builder().title(title).body(body);
+        if sound {
+            builder = builder.sound("default");
+        }
+        if let Err(e) = builder.show() {
+            log::warn!("notification failed: {e}");
+        }
+    }
+}
@@ -1,36 +1,305 @@
 pub mod commands;
-pub mod pty;
-pub mod worktree;
+pub mod host;
+pub mod shutdown;
+#[cfg(unix)]
+pub mod socket;
 
+// Core modules stay reachable under `crate::`, as before the split.
+pub use pi_builder_core::{
+    actions, activity, agents, annotate, audit, basesync, branches, bulk, checks, codec, comments,
+    compare, container, depcache, detach, disk, doctor, error, events, export, files, flow, guard,
+    highlight, history, hooks, inputhistory, integrations, ipc, journal, lfs, locks, logs, macros,
+    mainguard, mergemsg, mergequeue, notify, paste, patch, paths, pipe, playback, proctree, protect,
+    pty, rebase, recording, redact, remote, repo_cache, repo_config, report, repro, review, rpc,
+    scale, screen, scrollback, search, secrets, sendfile, settings, setup, signing, snapshot,
+    sparse, spawnqueue, ssh, staging, stash, submodules, summary, system, target, tasks, termenv,
+    terminal, timeline, tree, usage, webhooks, workspace, worktree,
+};
+#[cfg(feature = "testing")]
+pub use pi_builder_core::testing;
+
+use audit::AuditSource;
 use commands::{
     AppState,
-    get_repo_path, set_repo_path,
-    pty_spawn, pty_input, pty_resize, pty_kill, pty_list,
-    worktree_create, worktree_list, worktree_remove,
+    settings_get, settings_set,
+    workspace_export, workspace_import,
+    get_repo_path, set_repo_path, events_since,
+    get_worktree_root, set_worktree_root, set_setup_commands, set_dependency_cache, repo_config,
+    set_protected_paths, worktree_violations,
+    set_branch_template,
+    repo_fetch, set_fetch_interval, repo_clone,
+    repo_stash_save, repo_stash_list, repo_stash_apply, repo_stash_pop, repo_stash_drop,
+    repo_branches, repo_checkout,
+    pty_spawn, pty_respawn, session_handoff, pty_launch_snapshot, pty_input, pty_resize, pty_kill,
+    pty_list,
+    pty_input_history, pty_input_history_search,
+    pty_protocol,
+    pty_rename, pty_tag,
+    pty_paste, pty_paste_confirm, set_paste_settings, pty_send_file,
+    macro_record, macro_save, macro_list, macro_delete, macro_play, macro_stop,
+    action_save, action_list, action_delete, action_run,
+    pty_confirm, pty_set_guarded, set_guard_settings,
+    pty_broadcast, pty_pipe, pty_pipes, pty_unpipe,
+    pty_queue_list, pty_queue_cancel, set_concurrency, system_metrics, set_system_policy,
+    doctor,
+    pty_process_tree, pty_kill_process,
+    pty_previous_sessions, set_exit_behavior,
+    pty_log_path, get_log_settings, set_log_settings,
+    pty_recordings, pty_playback_open, playback_seek, playback_play, playback_pause, playback_close,
+    pty_ack, pty_stats, set_output_settings, pty_search, pty_export_text, pty_screen,
+    usage_report, session_history,
+    worktree_create, worktree_create_from, worktree_list, worktree_status, worktree_remove,
+    worktree_migrate, worktree_disk_usage, worktree_scale,
+    task_enqueue, task_list, task_report, task_timeline, task_cancel, task_set_max_concurrency,
+    agent_profiles, set_redaction,
+    terminal_profile_list, terminal_profile_save, terminal_profile_delete,
+    get_notification_prefs, set_notification_prefs,
+    get_shell_config, set_shell_config,
+    get_hooks, set_hooks,
+    fs_read_file, fs_list_dir, fs_stat, worktree_tree,
+    repo_search, repo_search_cancel,
+    github_fetch_issue, github_fetch_pr,
+    worktree_snapshot, worktree_snapshots, worktree_rollback, worktree_snapshot_delete,
+    worktree_diff, worktree_compare,
+    worktree_staged_diff, worktree_stage_hunk, worktree_unstage_hunk,
+    worktree_apply_patch, worktree_export,
+    worktree_log, worktree_blame, worktree_rebase, worktree_rebase_continue, worktree_rebase_abort,
+    worktree_bulk, worktree_checks, worktree_run_check,
+    review_list, review_transition, review_merge, review_discard,
+    review_comment_add, review_comment_list, review_comment_delete, review_comment_export,
+    merge_queue_enqueue, merge_queue_list, merge_queue_cancel, merge_queue_resume, set_merge_check,
+    set_merge_message, review_merge_message,
+    secret_set, secret_delete, secret_list,
+    webhook_list, webhook_save, webhook_delete, webhook_test,
+    audit_query,
+};
+use host::{TauriNotifier, TauriSink};
+use std::{sync::Arc, time::Duration};
+use tauri::{
+    ipc::{Invoke, InvokeBody},
+    Manager, RunEvent, Runtime,
 };
-use pty::PtyManager;
-use std::sync::Mutex;
+
+/// How often queued spawns are checked against the session limits.
+const SPAWN_QUEUE_POLL: Duration = Duration::from_secs(1);
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
-        .manage(AppState {
-            pty: Mutex::new(PtyManager::default()),
-            repo_path: Mutex::new(None),
+        .plugin(tauri_plugin_notification::init())
+        .setup(|app| {
+            let config_dir = app.path().app_config_dir()?;
+            let log_dir = app.path().app_data_dir()?.join("logs");
+            let events = Arc::new(TauriSink(app.handle().clone()));
+            let notifier = Arc::new(TauriNotifier(app.handle().clone()));
+            app.manage(AppState::new(
+                settings::settings_file(&config_dir),
+                log_dir,
+                events,
+                notifier,
+            ));
+            #[cfg(unix)]
+            socket::serve(app.handle().clone());
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut tick = tokio::time::interval(SPAWN_QUEUE_POLL);
+                loop {
+                    tick.tick().await;
+                    let state = handle.state::<AppState>();
+                    if !state.spawn_queue.is_empty() {
+                        commands::start_queued(&state);
+                    }
+                }
+            });
+            Ok(())
         })
-        .invoke_handler(tauri::generate_handler![
+        .invoke_handler(audited(tauri::generate_handler![
+            settings_get,
+            settings_set,
+            workspace_export,
+            workspace_import,
             pty_spawn,
+            pty_respawn,
+            session_handoff,
+            pty_launch_snapshot,
             pty_input,
+            pty_input_history,
+            pty_input_history_search,
+            pty_paste,
+            pty_paste_confirm,
+            set_paste_settings,
+            pty_send_file,
+            macro_record,
+            macro_save,
+            macro_list,
+            macro_delete,
+            macro_play,
+            macro_stop,
+            action_save,
+            action_list,
+            action_delete,
+            action_run,
+            pty_confirm,
+            pty_set_guarded,
+            pty_broadcast,
+            pty_pipe,
+            pty_pipes,
+            pty_unpipe,
+            set_guard_settings,
+            pty_queue_list,
+            pty_queue_cancel,
+            set_concurrency,
+            system_metrics,
+            set_system_policy,
+            doctor,
             pty_resize,
             pty_kill,
             pty_list,
+            pty_rename,
+            pty_tag,
+            pty_protocol,
+            pty_previous_sessions,
+            set_exit_behavior,
+            pty_log_path,
+            get_log_settings,
+            pty_recordings,
+            pty_playback_open,
+            playback_seek,
+            playback_play,
+            playback_pause,
+            playback_close,
+            set_log_settings,
+            pty_ack,
+            pty_stats,
+            pty_search,
+            pty_export_text,
+            pty_screen,
+            pty_process_tree,
+            pty_kill_process,
+            set_output_settings,
+            usage_report,
+            session_history,
             worktree_create,
+            worktree_create_from,
             worktree_list,
+            worktree_status,
             worktree_remove,
+            worktree_disk_usage,
+            worktree_scale,
+            worktree_migrate,
+            set_worktree_root,
+            set_branch_template,
+            repo_fetch,
+            repo_stash_save,
+            repo_stash_list,
+            repo_stash_apply,
+            repo_stash_pop,
+            repo_stash_drop,
+            repo_branches,
+            repo_checkout,
+            repo_clone,
+            set_fetch_interval,
+            get_worktree_root,
+            set_setup_commands,
+            set_dependency_cache,
+            repo_config,
+            set_protected_paths,
+            worktree_violations,
+            task_enqueue,
+            task_list,
+            task_report,
+            task_timeline,
+            task_cancel,
+            task_set_max_concurrency,
+            agent_profiles,
+            terminal_profile_list,
+            terminal_profile_save,
+            terminal_profile_delete,
+            set_redaction,
+            get_notification_prefs,
+            set_notification_prefs,
+            get_shell_config,
+            set_shell_config,
+            get_hooks,
+            set_hooks,
+            fs_read_file,
+            fs_list_dir,
+            fs_stat,
+            worktree_tree,
+            repo_search,
+            repo_search_cancel,
+            github_fetch_issue,
+            github_fetch_pr,
+            worktree_snapshot,
+            worktree_snapshots,
+            worktree_rollback,
+            worktree_snapshot_delete,
+            worktree_diff,
+            worktree_compare,
+            worktree_staged_diff,
+            worktree_stage_hunk,
+            worktree_apply_patch,
+            worktree_export,
+            worktree_unstage_hunk,
+            worktree_log,
+            worktree_blame,
+            worktree_rebase,
+            worktree_rebase_continue,
+            worktree_rebase_abort,
+            worktree_bulk,
+            worktree_checks,
+            worktree_run_check,
+            secret_set,
+            secret_delete,
+            secret_list,
+            webhook_list,
+            webhook_save,
+            webhook_delete,
+            webhook_test,
+            review_list,
+            review_transition,
+            review_merge,
+            review_discard,
+            review_comment_add,
+            review_comment_list,
+            review_comment_delete,
+            review_comment_export,
+            merge_queue_enqueue,
+            merge_queue_list,
+            merge_queue_cancel,
+            merge_queue_resume,
+            set_merge_check,
+            set_merge_message,
+            review_merge_message,
             set_repo_path,
             get_repo_path,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error running pi-builder desktop");
+            events_since,
+            audit_query,
+        ]))
+        .build(tauri::generate_context!())
+        .expect("error building pi-builder desktop")
+        .run(|app, event| {
+            if matches!(event, RunEvent::ExitRequested { .. } | RunEvent::Exit) {
+                if let Some(state) = app.try_state::<AppState>() {
+                    shutdown::shutdown(&state);
+                }
+            }
+        });
+}
+
+/// Record each webview invocation in the audit log before running it.
+fn audited<R: Runtime>(
+    handler: impl Fn(Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        if let Some(state) = invoke.message.webview().try_state::<AppState>() {
+            let args = match invoke.message.payload() {
+                InvokeBody::Json(args) => args.clone(),
+                InvokeBody::Raw(_) => serde_json::Value::Null,
+            };
+            state.audit.record(AuditSource::Webview, invoke.message.command(), &args);
+        }
+        handler(invoke)
+    }
 }
@@ -0,0 +1,70 @@
+//! Graceful shutdown.
+//!
+//! On exit the scheduler stops launching tasks, notifications are muted,
+//! and every PTY child is killed (or left running, per `exit_behavior`),
+//! except detached sessions, which always keep running. A record of the
+//! sessions is written to `sessions.json` so the next launch can show what
+//! was running when the app closed and re-attach the detached ones.
+
+use crate::{
+    commands::AppState,
+    pty::{ExitBehavior, SessionRecord},
+};
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+/// How long to wait for killed children before giving up on them.
+pub const KILL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Location of the session record inside the app config dir.
+pub fn sessions_file(config_dir: &Path) -> PathBuf {
+    config_dir.join("sessions.json")
+}
+
+/// Sessions recorded by the previous shutdown; empty if there is no record.
+pub fn load_sessions(path: &Path) -> Vec<SessionRecord> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_sessions(path: &Path, sessions: &[SessionRecord]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("create config dir")?;
+    }
+    let json = serde_json::to_string_pretty(sessions)?;
+    std::fs::write(path, json).context("write sessions")?;
+    Ok(())
+}
+
+/// Tear down all sessions. Safe to call more than once; only the first call
+/// does anything.
+pub fn shutdown(state: &AppState) {
+    if state.shut_down.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    state.tasks.stop();
+    state.merge_queue.stop();
+    state.notifications.mute();
+
+    let behavior = state.settings.lock().unwrap().exit_behavior;
+    let pty = state.pty.lock().unwrap();
+    let records = pty.records();
+    if behavior == ExitBehavior::Kill {
+        let remaining = pty.kill_all(KILL_TIMEOUT);
+        if remaining > 0 {
+            log::warn!("{remaining} PTY session(s) still running at exit");
+        }
+    }
+    if let Err(e) = save_sessions(&state.sessions_path, &records) {
+        log::warn!("failed to persist sessions: {e:#}");
+    }
+    state.audit.flush();
+    #[cfg(unix)]
+    crate::socket::close();
+}
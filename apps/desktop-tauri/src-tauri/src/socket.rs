@@ -0,0 +1,380 @@
+//! Local socket for the `pi` CLI (protocol in `ipc.rs` in the core).
+//! Unix only for now.
+//!
+//! Requests are dispatched to the same command functions the webview
+//! invokes, so CLI and GUI share state: a session spawned from a script
+//! shows up in the sidebar. Lines that are JSON-RPC 2.0 (see `rpc.rs` in
+//! the core) are answered in kind.
+
+use crate::{
+    audit::{AuditFilter, AuditRange, AuditSource},
+    commands::{self, AppState, SpawnArgs},
+    error::PiError,
+    export::ExportFormat,
+    ipc::{self, Request, Response},
+    pty::SessionFilter,
+    review::ReviewState,
+    rpc::{self, RpcResponse},
+    scrollback::LineRange,
+    summary::HistoryFilter,
+    tasks::TaskSpec,
+    usage::UsageRange,
+};
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    os::unix::fs::PermissionsExt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+/// Whether this instance owns the socket file.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+/// Start listening; failures are logged, the app runs without the socket.
+pub fn serve<R: Runtime>(app: AppHandle<R>) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = listen(app).await {
+            log::warn!("cli socket: {e:#}");
+        }
+    });
+}
+
+/// Remove the socket file at exit, unless another instance owns it.
+pub fn close() {
+    if LISTENING.swap(false, Ordering::SeqCst) {
+        let _ = std::fs::remove_file(ipc::socket_path());
+    }
+}
+
+async fn listen<R: Runtime>(app: AppHandle<R>) -> anyhow::Result<()> {
+    let path = ipc::socket_path();
+    ipc::prepare_socket_dir(&path)?;
+    if UnixStream::connect(&path).await.is_ok() {
+        anyhow::bail!("another instance is listening on {}", path.display());
+    }
+    // Left behind by a previous run that didn't exit cleanly
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).with_context(|| format!("bind {}", path.display()))?;
+    LISTENING.store(true, Ordering::SeqCst);
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .context("restrict socket permissions")?;
+    log::info!("cli socket listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await.context("accept")?;
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let answer = match serde_json::from_str::<Value>(&line) {
+                    Ok(message) if rpc::is_rpc(&message) => rpc_message(&app, message).await,
+                    Ok(message) => Some(plain(&app, message).await),
+                    // Unparseable; answer in the protocol the client seems to speak
+                    Err(e) if line.contains("\"jsonrpc\"") => {
+                        let error = RpcResponse::err(Value::Null, rpc::PARSE_ERROR, e.to_string());
+                        serde_json::to_value(error).ok()
+                    }
+                    Err(e) => {
+                        let error = PiError::invalid_input(format!("bad request: {e}"));
+                        serde_json::to_value(Response::err(error)).ok()
+                    }
+                };
+                // Notifications aren't answered
+                let Some(answer) = answer else { continue };
+                let Ok(mut out) = serde_json::to_string(&answer) else { break };
+                out.push('\n');
+                if write.write_all(out.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// A request in the plain protocol of `ipc.rs`.
+async fn plain<R: Runtime>(app: &AppHandle<R>, message: Value) -> Value {
+    let response = match serde_json::from_value::<Request>(message) {
+        Ok(request) => match dispatch(app, &request.method, request.params).await {
+            Ok(result) => Response::ok(result),
+            Err(e) => Response::err(e),
+        },
+        Err(e) => Response::err(PiError::invalid_input(format!("bad request: {e}"))),
+    };
+    serde_json::to_value(response).unwrap_or_default()
+}
+
+/// A JSON-RPC request or batch; `None` if nothing needs an answer.
+async fn rpc_message<R: Runtime>(app: &AppHandle<R>, message: Value) -> Option<Value> {
+    let batch = match message {
+        Value::Array(batch) => batch,
+        request => return rpc_call(app, request).await,
+    };
+    if batch.is_empty() {
+        let error = RpcResponse::err(Value::Null, rpc::INVALID_REQUEST, "empty batch");
+        return serde_json::to_value(error).ok();
+    }
+    let mut responses = Vec::new();
+    for request in batch {
+        responses.extend(rpc_call(app, request).await);
+    }
+    (!responses.is_empty()).then_some(Value::Array(responses))
+}
+
+async fn rpc_call<R: Runtime>(app: &AppHandle<R>, request: Value) -> Option<Value> {
+    let call = match rpc::parse_call(&request) {
+        Ok(call) => call,
+        Err(response) => return serde_json::to_value(response).ok(),
+    };
+    let response = if call.method == rpc::DISCOVER {
+        RpcResponse::ok(call.id?, rpc::discover())
+    } else if rpc::find(&call.method).is_none() {
+        let message = format!("unknown method: {}", call.method);
+        RpcResponse::err(call.id?, rpc::METHOD_NOT_FOUND, message)
+    } else {
+        let result = dispatch(app, &call.method, call.params).await;
+        RpcResponse::from_result(call.id?, result)
+    };
+    serde_json::to_value(response).ok()
+}
+
+/// Deserialize named params into a tuple:
+/// `let (name, limit) = args!(params, name: String, limit: Option<usize>);`
+macro_rules! args {
+    ($params:expr, $($name:ident: $ty:ty),+ $(,)?) => {{
+        #[derive(Deserialize)]
+        struct P {
+            $($name: $ty),+
+        }
+        let P { $($name),+ } = parse($params)?;
+        ($($name),+,)
+    }};
+}
+
+async fn dispatch<R: Runtime>(
+    app: &AppHandle<R>,
+    method: &str,
+    params: Value,
+) -> Result<Value, PiError> {
+    let state = || app.state::<AppState>();
+    state().audit.record(AuditSource::Cli, method, &params);
+    match method {
+        "get_repo_path" => to_value(commands::get_repo_path(state())),
+        "set_repo_path" => {
+            let (path,) = args!(params, path: String);
+            to_value(commands::set_repo_path(path, state())?)
+        }
+        "agent_profiles" => to_value(commands::agent_profiles(state())),
+        "pty_spawn" => {
+            let (args,) = args!(params, args: SpawnArgs);
+            to_value(commands::pty_spawn(args, state()).await?)
+        }
+        "pty_respawn" => {
+            let (session_id, queue) = args!(params, session_id: String, queue: Option<bool>);
+            to_value(commands::pty_respawn(session_id, queue, state()).await?)
+        }
+        "pty_launch_snapshot" => {
+            let (session_id,) = args!(params, session_id: String);
+            to_value(commands::pty_launch_snapshot(session_id, state())?)
+        }
+        "pty_input" => {
+            let (session_id, data) = args!(params, session_id: String, data: String);
+            to_value(commands::pty_input(session_id, data, state())?)
+        }
+        "pty_input_history" => {
+            let (session_id, limit) = args!(params, session_id: String, limit: Option<usize>);
+            to_value(commands::pty_input_history(session_id, limit, state())?)
+        }
+        "pty_resize" => {
+            let (session_id, cols, rows) = args!(params, session_id: String, cols: u16, rows: u16);
+            to_value(commands::pty_resize(session_id, cols, rows, state())?)
+        }
+        "pty_kill" => {
+            let (session_id,) = args!(params, session_id: String);
+            to_value(commands::pty_kill(session_id, state()))
+        }
+        "pty_list" => {
+            let (filter,) = args!(params, filter: Option<SessionFilter>);
+            to_value(commands::pty_list(filter, state()))
+        }
+        "pty_screen" => {
+            let (session_id,) = args!(params, session_id: String);
+            to_value(commands::pty_screen(session_id, state())?)
+        }
+        "pty_export_text" => {
+            let (session_id, range, path) = args!(
+                params,
+                session_id: String,
+                range: Option<LineRange>,
+                path: Option<String>,
+            );
+            to_value(commands::pty_export_text(session_id, range, path, state())?)
+        }
+        "pty_rename" => {
+            let (session_id, title) = args!(params, session_id: String, title: Option<String>);
+            to_value(commands::pty_rename(session_id, title, state())?)
+        }
+        "worktree_list" => to_value(commands::worktree_list(state())?),
+        "worktree_create" => {
+            let (session_id, skip_submodules, agent_id, slug, sparse) = args!(
+                params,
+                session_id: String,
+                skip_submodules: Option<bool>,
+                agent_id: Option<String>,
+                slug: Option<String>,
+                sparse: Option<Vec<String>>,
+            );
+            let created = commands::worktree_create(
+                session_id,
+                skip_submodules,
+                agent_id,
+                slug,
+                sparse,
+                state(),
+            )
+            .await?;
+            to_value(created)
+        }
+        "worktree_create_from" => {
+            let (name, source_worktree, skip_submodules, agent_id, slug, sparse) = args!(
+                params,
+                name: String,
+                source_worktree: String,
+                skip_submodules: Option<bool>,
+                agent_id: Option<String>,
+                slug: Option<String>,
+                sparse: Option<Vec<String>>,
+            );
+            let created = commands::worktree_create_from(
+                name,
+                source_worktree,
+                skip_submodules,
+                agent_id,
+                slug,
+                sparse,
+                state(),
+            )
+            .await?;
+            to_value(created)
+        }
+        "worktree_remove" => {
+            let (name,) = args!(params, name: String);
+            to_value(commands::worktree_remove(name, state())?)
+        }
+        "worktree_diff" => {
+            let (name, highlight) = args!(params, name: String, highlight: Option<bool>);
+            to_value(commands::worktree_diff(name, highlight, state())?)
+        }
+        "worktree_log" => {
+            let (name, limit, since) =
+                args!(params, name: String, limit: Option<usize>, since: Option<i64>);
+            to_value(commands::worktree_log(name, limit, since, state())?)
+        }
+        "worktree_rebase" => {
+            let (name,) = args!(params, name: String);
+            to_value(commands::worktree_rebase(name, state())?)
+        }
+        "worktree_checks" => to_value(commands::worktree_checks(state())?),
+        "worktree_run_check" => {
+            let (name, check_name) = args!(params, name: String, check_name: String);
+            to_value(commands::worktree_run_check(name, check_name, state())?)
+        }
+        "worktree_export" => {
+            let (name, format, path) =
+                args!(params, name: String, format: ExportFormat, path: String);
+            to_value(commands::worktree_export(name, format, path, state())?)
+        }
+        "review_list" => to_value(commands::review_list(state())?),
+        "review_transition" => {
+            let (name, to, note) =
+                args!(params, name: String, to: ReviewState, note: Option<String>);
+            to_value(commands::review_transition(name, to, note, state())?)
+        }
+        "review_merge" => {
+            let (name, remove_worktree, allow_protected, comments_path) = args!(
+                params,
+                name: String,
+                remove_worktree: Option<bool>,
+                allow_protected: Option<bool>,
+                comments_path: Option<String>,
+            );
+            to_value(commands::review_merge(
+                name,
+                remove_worktree,
+                allow_protected,
+                comments_path,
+                state(),
+            )?)
+        }
+        "review_discard" => {
+            let (name, note) = args!(params, name: String, note: Option<String>);
+            to_value(commands::review_discard(name, note, state())?)
+        }
+        "review_comment_add" => {
+            let (worktree, file, line, body) =
+                args!(params, worktree: String, file: String, line: u32, body: String);
+            to_value(commands::review_comment_add(worktree, file, line, body, state())?)
+        }
+        "review_comment_list" => {
+            let (worktree,) = args!(params, worktree: String);
+            to_value(commands::review_comment_list(worktree, state())?)
+        }
+        "merge_queue_enqueue" => {
+            let (name,) = args!(params, name: String);
+            to_value(commands::merge_queue_enqueue(name, state())?)
+        }
+        "merge_queue_list" => to_value(commands::merge_queue_list(state())),
+        "merge_queue_cancel" => {
+            let (id,) = args!(params, id: String);
+            to_value(commands::merge_queue_cancel(id, state())?)
+        }
+        "task_enqueue" => {
+            let (spec,) = args!(params, spec: TaskSpec);
+            to_value(commands::task_enqueue(spec, state())?)
+        }
+        "task_list" => to_value(commands::task_list(state())),
+        "task_timeline" => {
+            let (task_id,) = args!(params, task_id: String);
+            to_value(commands::task_timeline(task_id, state())?)
+        }
+        "task_cancel" => {
+            let (task_id,) = args!(params, task_id: String);
+            to_value(commands::task_cancel(task_id, state())?)
+        }
+        "events_since" => {
+            let (seq,) = args!(params, seq: u64);
+            to_value(commands::events_since(seq, state()))
+        }
+        "usage_report" => {
+            let (range,) = args!(params, range: Option<UsageRange>);
+            to_value(commands::usage_report(range, state()))
+        }
+        "session_history" => {
+            let (limit, filter) =
+                args!(params, limit: Option<usize>, filter: Option<HistoryFilter>);
+            to_value(commands::session_history(limit, filter, state()))
+        }
+        "audit_query" => {
+            let (range, filter) =
+                args!(params, range: Option<AuditRange>, filter: Option<AuditFilter>);
+            to_value(commands::audit_query(range, filter, state()))
+        }
+        other => Err(PiError::invalid_input(format!("unknown method: {other}"))),
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, PiError> {
+    // Methods without arguments may be sent without `params`
+    let params = if params.is_null() { Value::Object(Default::default()) } else { params };
+    serde_json::from_value(params).map_err(|e| PiError::invalid_input(format!("bad params: {e}")))
+}
+
+fn to_value(value: impl Serialize) -> Result<Value, PiError> {
+    serde_json::to_value(value).map_err(|e| PiError::from(anyhow::Error::from(e)))
+}
@@ -9,6 +9,7 @@
 
 import { invoke } from '@tauri-apps/api/core'
 import { useCallback, useRef, useState } from 'react'
+import { isPiError } from './errors'
 import { TerminalPane } from './TerminalPane'
 import { usePty, type PtySessionInfo } from './usePty'
 import { useWorktrees } from './useWorktrees'
@@ -64,8 +65,13 @@ export default function App() {
     // TODO: use Tauri dialog when tauri-plugin-dialog is added
     const path = window.prompt('Repository path:', repoPath)
     if (!path) return
-    setRepoPath(path)
-    await invoke('set_repo_path', { path })
+    try {
+      await invoke('set_repo_path', { path })
+      setRepoPath(path)
+    } catch (e) {
+      // e.g. a path Windows or git can't use
+      window.alert(isPiError(e) ? e.message : String(e))
+    }
   }, [repoPath])
 
   return (
@@ -0,0 +1,31 @@
+/**
+ * Shape of errors rejected by every Tauri command (see `error.rs`).
+ * Branch on `code`, not `message`.
+ */
+
+export type PiErrorCode =
+  | 'repo_not_configured'
+  | 'session_not_found'
+  | 'worktree_not_found'
+  | 'task_not_found'
+  | 'snapshot_not_found'
+  | 'not_found'
+  | 'path_outside_worktree'
+  | 'invalid_path'
+  | 'invalid_input'
+  | 'invalid_transition'
+  | 'merge_conflict'
+  | 'git_auth'
+  | 'git'
+  | 'io'
+  | 'internal'
+
+export interface PiError {
+  code: PiErrorCode
+  message: string
+  detail: string | null
+}
+
+export function isPiError(e: unknown): e is PiError {
+  return typeof e === 'object' && e !== null && 'code' in e && 'message' in e
+}
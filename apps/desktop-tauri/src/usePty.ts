@@ -15,17 +15,81 @@ export interface PtySessionInfo {
   alive: boolean
   cols: number
   rows: number
+  title?: string | null
+  tags?: string[]
+  group?: string | null
+  state?: PtySessionState
+  /** Runs under tmux and survives restarting the app. */
+  detached?: boolean
+  /** 'tmux' for a window of the shared `pi` tmux session. */
+  backend?: 'pty' | 'tmux'
+}
+
+export type PtySessionState = 'running' | 'awaiting_input' | 'idle'
+
+export interface PtyStateEvent {
+  sessionId: string
+  agentId: string
+  state: PtySessionState
+  /** The prompt line, when `state` is `awaiting_input`. */
+  prompt: string | null
 }
 
 export interface PtyDataEvent {
+  /** Payload protocol version (see `pty_protocol`). */
+  v?: number
   sessionId: string
   agentId: string
+  /** `base64` when the session was spawned with `encoding: 'base64'`. */
+  encoding?: 'utf8' | 'base64'
   data: string
+  /** Raw output bytes in this event; what `pty_ack` counts. */
+  bytes?: number
+}
+
+export interface ContainerSpec {
+  runtime?: 'docker' | 'podman'
+  image: string
+  mounts?: { source: string; target: string; readonly?: boolean }[]
+  env?: Record<string, string>
+  workdir?: string
+  network?: string
+  args?: string[]
+}
+
+export interface SshTarget {
+  host: string
+  user?: string
+  port?: number
+  identity_file?: string
+  remote_cwd?: string
+  options?: string[]
+}
+
+export interface TokenUsage {
+  inputTokens: number
+  outputTokens: number
+  costUsd: number
 }
 
 export interface PtyExitEvent {
   sessionId: string
   exitCode: number
+  usage?: TokenUsage
+}
+
+export interface PtyTimeoutEvent {
+  sessionId: string
+  agentId: string
+  reason: 'max_runtime' | 'max_idle'
+  limitSecs: number
+}
+
+export interface PasteResult {
+  /** False when the paste guard's confirmation was declined or timed out */
+  pasted: boolean
+  bracketed: boolean
+  flagged: string[]
 }
 
 export function usePty() {
@@ -35,7 +99,33 @@ export function usePty() {
   const spawn = useCallback(async (
     agentId: string,
     cmd: string[],
-    opts: { cwd?: string; cols?: number; rows?: number } = {},
+    opts: {
+      cwd?: string
+      worktree?: string
+      /** Don't check out submodules when `worktree` has to be created. */
+      skip_submodules?: boolean
+      /** `native`, `wsl`, or `wsl:<distro>`. */
+      target?: string
+      /** Run inside Docker/Podman with the cwd mounted at `workdir`. */
+      container?: ContainerSpec
+      /** Run on a remote host; defaults to the agent profile's config. */
+      ssh?: SshTarget
+      cols?: number
+      rows?: number
+      /** Ack each event after `onData` so a slow view throttles output. */
+      flow_control?: boolean
+      /** Run under tmux so the session survives restarting the app. */
+      detached?: boolean
+      /** Defaults to the terminal profile's. */
+      backend?: 'pty' | 'tmux'
+      title?: string
+      tags?: string[]
+      group?: string
+      /** Stop the session after this long; emits `pty://timeout/<id>`. */
+      max_runtime_secs?: number
+      /** Stop the session after this long without output. */
+      max_idle_secs?: number
+    } = {},
     onData: (e: PtyDataEvent) => void,
     onExit: (e: PtyExitEvent) => void,
   ): Promise<string> => {
@@ -46,7 +136,12 @@ export function usePty() {
     // Subscribe to data + exit events for this session
     const unlistenData = await listen<PtyDataEvent>(
       `pty://data/${session_id}`,
-      (event) => onData(event.payload),
+      (event) => {
+        onData(event.payload)
+        if (opts.flow_control && event.payload.bytes) {
+          void invoke('pty_ack', { sessionId: session_id, bytes: event.payload.bytes })
+        }
+      },
     )
     const unlistenExit = await listen<PtyExitEvent>(
       `pty://exit/${session_id}`,
@@ -58,13 +153,27 @@ export function usePty() {
       },
     )
 
-    unlisteners.current.set(session_id, [unlistenData, unlistenExit])
+    const unlistenState = await listen<PtyStateEvent>(
+      `pty://state/${session_id}`,
+      (event) => {
+        setSessions(prev => prev.map(s =>
+          s.sessionId === session_id ? { ...s, state: event.payload.state } : s
+        ))
+      },
+    )
+
+    unlisteners.current.set(session_id, [unlistenData, unlistenExit, unlistenState])
     setSessions(prev => [...prev, {
       sessionId: session_id,
       agentId,
       alive: true,
       cols: opts.cols ?? 220,
       rows: opts.rows ?? 50,
+      title: opts.title ?? null,
+      tags: opts.tags ?? [],
+      group: opts.group ?? null,
+      state: 'running',
+      detached: opts.detached ?? false,
     }])
 
     return session_id
@@ -74,10 +183,24 @@ export function usePty() {
     void invoke('pty_input', { sessionId, data })
   }, [])
 
+  const paste = useCallback((sessionId: string, data: string) => {
+    return invoke<PasteResult>('pty_paste', { sessionId, data })
+  }, [])
+
   const resize = useCallback((sessionId: string, cols: number, rows: number) => {
     void invoke('pty_resize', { sessionId, cols, rows })
   }, [])
 
+  const rename = useCallback(async (sessionId: string, title: string | null) => {
+    const info = await invoke<PtySessionInfo>('pty_rename', { sessionId, title })
+    setSessions(prev => prev.map(s => s.sessionId === sessionId ? { ...s, ...info } : s))
+  }, [])
+
+  const tag = useCallback(async (sessionId: string, tags: string[], group?: string) => {
+    const info = await invoke<PtySessionInfo>('pty_tag', { sessionId, tags, group })
+    setSessions(prev => prev.map(s => s.sessionId === sessionId ? { ...s, ...info } : s))
+  }, [])
+
   const kill = useCallback((sessionId: string) => {
     invoke('pty_kill', { sessionId }).then(() => {
       const fns = unlisteners.current.get(sessionId)
@@ -96,5 +219,5 @@ export function usePty() {
     }
   }, [])
 
-  return { sessions, spawn, writeInput, resize, kill }
+  return { sessions, spawn, writeInput, paste, resize, rename, tag, kill }
 }
@@ -1,9 +1,11 @@
 /**
- * useWorktrees — polls Tauri worktree_list every 5 s.
+ * useWorktrees — loads worktree_list, then follows the backend's
+ * "worktree://status" pushes. Polls every 5 s as a fallback.
  * Returns divergence stats for each agent worktree.
  */
 
 import { invoke } from '@tauri-apps/api/core'
+import { listen } from '@tauri-apps/api/event'
 import { useEffect, useState } from 'react'
 
 export interface WorktreeInfo {
@@ -13,6 +15,27 @@ export interface WorktreeInfo {
   ahead: number
   behind: number
   dirty: boolean
+  /** Branch created for this worktree; null for older worktrees */
+  created_branch?: string | null
+  /** Base branch's upstream, e.g. origin/main, as of the last fetch */
+  upstream?: string | null
+  upstream_ahead?: number
+  upstream_behind?: number
+  /** Disk usage from the last background scan; null until computed */
+  size_bytes?: number | null
+  /** Worktree this one branched off (worktree_create_from); null if the base */
+  parent?: WorktreeParent | null
+}
+
+export interface WorktreeParent {
+  worktree: string
+  branch: string
+  commit: string
+}
+
+export interface WorktreeStatusEvent {
+  repo: string
+  worktrees: WorktreeInfo[]
 }
 
 export function useWorktrees(repoPath: string | null, intervalMs = 5000) {
@@ -32,9 +55,17 @@ export function useWorktrees(repoPath: string | null, intervalMs = 5000) {
       }
     }
 
+    const unlisten = listen<WorktreeStatusEvent>('worktree://status', (event) => {
+      if (!cancelled && event.payload.repo === repoPath) setWorktrees(event.payload.worktrees)
+    })
+
     void poll()
     const id = setInterval(() => { void poll() }, intervalMs)
-    return () => { cancelled = true; clearInterval(id) }
+    return () => {
+      cancelled = true
+      clearInterval(id)
+      void unlisten.then(fn => fn())
+    }
   }, [repoPath, intervalMs])
 
   return worktrees